@@ -0,0 +1,57 @@
+//! Times `Skeleton::calculate_global_poses` on a skeleton of many root joints (e.g. a crowd of
+//! single-joint props), against the same accumulation concatenating each root with an identity
+//! parent. Run with `cargo bench --bench global_poses`.
+
+extern crate skeletal_animation;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use skeletal_animation::{Skeleton, Transform};
+use skeletal_animation::math::{Matrix4, mat4_id};
+use skeletal_animation::skeleton::{Joint, ROOT_JOINT_PARENT_INDEX};
+
+const ROOT_COUNT: usize = 4096;
+const ITERATIONS: u32 = 1000;
+
+/// Average time per call of `f` over `ITERATIONS` calls, after one warm-up call
+fn time_per_call<F: FnMut()>(mut f: F) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0 .. ITERATIONS {
+        f();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+
+    let skeleton = Skeleton::from_joints((0 .. ROOT_COUNT).map(|i| {
+        Joint::new(&format!("root_{}", i), ROOT_JOINT_PARENT_INDEX, mat4_id())
+    }).collect());
+
+    let local_poses: Vec<Matrix4<f32>> = (0 .. ROOT_COUNT).map(|i| {
+        let mut pose = mat4_id();
+        pose.set_translation([i as f32, 0.0, 0.0]);
+        pose
+    }).collect();
+
+    let mut global_poses = vec![mat4_id(); ROOT_COUNT];
+    let fast_path = time_per_call(|| {
+        skeleton.calculate_global_poses(black_box(&local_poses[..]), &mut global_poses[..]);
+        black_box(&global_poses);
+    });
+
+    let mut concat_poses = vec![mat4_id(); ROOT_COUNT];
+    let identity_concat = time_per_call(|| {
+        for (global_pose, &local_pose) in concat_poses.iter_mut().zip(black_box(&local_poses).iter()) {
+            *global_pose = black_box(Matrix4::identity()).concat(local_pose);
+        }
+        black_box(&concat_poses);
+    });
+
+    assert_eq!(global_poses, concat_poses);
+
+    println!("calculate_global_poses, {} roots: {:?} per call", ROOT_COUNT, fast_path);
+    println!("identity parent concat, {} roots: {:?} per call", ROOT_COUNT, identity_concat);
+}
@@ -0,0 +1,81 @@
+//! Times `math::lerp_quaternion`, which normalizes with the exact `math::inv_sqrt`, against the
+//! same blend normalized with the `0x5f3759df` approximation it replaced, over the joints of a
+//! long chain. Run with `cargo bench --bench inv_sqrt`.
+
+extern crate skeletal_animation;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use skeletal_animation::math::{Quaternion, lerp_quaternion, quaternion};
+
+const JOINT_COUNT: usize = 4096;
+const ITERATIONS: u32 = 1000;
+
+/// Average time per call of `f` over `ITERATIONS` calls, after one warm-up call
+fn time_per_call<F: FnMut()>(mut f: F) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0 .. ITERATIONS {
+        f();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+/// The approximate inverse square root that `math::inv_sqrt` used to compute, with one
+/// Newton-Raphson step
+fn approx_inv_sqrt(x: f32) -> f32 {
+    let x2 = x * 0.5;
+    let y = f32::from_bits(0x5f3759df - (x.to_bits() >> 1));
+    y * (1.5 - (x2 * y * y))
+}
+
+/// `math::lerp_quaternion`, normalized with `approx_inv_sqrt`
+fn approx_lerp_quaternion(q1: &Quaternion<f32>, q2: &Quaternion<f32>, blend_factor: &f32) -> Quaternion<f32> {
+
+    let dot = q1.0 * q2.0 + q1.1[0] * q2.1[0] + q1.1[1] * q2.1[1] + q1.1[2] * q2.1[2];
+
+    let s = 1.0 - blend_factor;
+    let t: f32 = if dot > 0.0 { *blend_factor } else { -blend_factor };
+
+    let w = s * q1.0 + t * q2.0;
+    let x = s * q1.1[0] + t * q2.1[0];
+    let y = s * q1.1[1] + t * q2.1[1];
+    let z = s * q1.1[2] + t * q2.1[2];
+
+    let inv_sqrt_len = approx_inv_sqrt(w * w + x * x + y * y + z * z);
+    (w * inv_sqrt_len, [x * inv_sqrt_len, y * inv_sqrt_len, z * inv_sqrt_len])
+}
+
+fn main() {
+
+    // The rotations of each joint in two poses of the chain, as blended by a LerpNode
+    let poses: Vec<(Quaternion<f32>, Quaternion<f32>)> = (0 .. JOINT_COUNT).map(|i| {
+        let angle = i as f32 * 0.01;
+        (quaternion::axis_angle([0.0, 1.0, 0.0], angle), quaternion::axis_angle([1.0, 0.0, 0.0], -angle))
+    }).collect();
+
+    let mut exact = vec![quaternion::id(); JOINT_COUNT];
+    let exact_time = time_per_call(|| {
+        for (output, &(q1, q2)) in exact.iter_mut().zip(black_box(&poses).iter()) {
+            *output = lerp_quaternion(&q1, &q2, black_box(&0.3));
+        }
+        black_box(&exact);
+    });
+
+    let mut approx = vec![quaternion::id(); JOINT_COUNT];
+    let approx_time = time_per_call(|| {
+        for (output, &(q1, q2)) in approx.iter_mut().zip(black_box(&poses).iter()) {
+            *output = approx_lerp_quaternion(&q1, &q2, black_box(&0.3));
+        }
+        black_box(&approx);
+    });
+
+    let max_error = exact.iter().zip(approx.iter()).map(|(q1, q2)| {
+        (q1.0 - q2.0).abs().max((0 .. 3).map(|i| (q1.1[i] - q2.1[i]).abs()).fold(0.0, f32::max))
+    }).fold(0.0, f32::max);
+
+    println!("lerp_quaternion, exact inv_sqrt, {} joints: {:?} per call", JOINT_COUNT, exact_time);
+    println!("lerp_quaternion, 0x5f3759df inv_sqrt, {} joints: {:?} per call", JOINT_COUNT, approx_time);
+    println!("largest component difference: {}", max_error);
+}
@@ -1,15 +1,45 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use collada::document::ColladaDocument;
 use collada;
+use rustc_serialize::{Decodable, Decoder};
 use float::Radians;
 
 use math::*;
-use skeleton::Skeleton;
+use skeleton::{Skeleton, JointIndex};
 use transform::Transform;
 
+/// Tolerance used when comparing joint poses between samples
+const POSE_EPSILON: f32 = 0.0001;
+
+/// Height above the ground plane within which a foot is considered planted, for `foot_skate`
+pub const FOOT_CONTACT_HEIGHT: f32 = 0.05;
+
+/// How sampling times beyond the end (or before the start) of a clip are mapped into it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    /// Wrap around, interpolating from the last sample back to the first over the final frame
+    Loop,
+    /// Hold the first or last sample
+    Clamp,
+    /// Play forwards then backwards, reflecting the time at each end of the clip
+    PingPong,
+}
+
+impl Decodable for LoopMode {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<LoopMode, D::Error> {
+        match &decoder.read_str()?[..] {
+            "loop" => Ok(LoopMode::Loop),
+            "clamp" => Ok(LoopMode::Clamp),
+            "ping_pong" => Ok(LoopMode::PingPong),
+            loop_mode => Err(decoder.error(&format!("Unknown loop mode: {}", loop_mode))),
+        }
+    }
+}
+
 /// A single skeletal pose
 #[derive(Debug)]
 pub struct AnimationSample<T: Transform>
@@ -28,30 +58,152 @@ pub struct AnimationClip<T: Transform> {
     /// The sequence of skeletal poses
     pub samples: Vec<AnimationSample<T>>,
 
-    /// Sample rate for the clip. Assumes a constant sample rate.
+    /// Sample rate for the clip. Assumes a constant sample rate, unless
+    /// `sample_times` is present, in which case this is the average sample rate.
     pub samples_per_second: f32,
 
+    /// Optional time (in seconds) of each sample, for clips with non-uniform keyframe spacing.
+    /// The last sample time is taken as the duration of the clip.
+    pub sample_times: Option<Vec<f32>>,
+
+    /// Author-defined tags, e.g. for filtering clips in tools
+    pub tags: Vec<String>,
+
+    /// How `get_pose_at_time` maps times outside the clip into it. A `Loop` clip interpolates
+    /// from its last sample back to its first over the final frame, while a `Clamp` (one-shot)
+    /// clip holds its last sample. Root motion and events follow the same mode.
+    pub loop_mode: LoopMode,
+
+    /// Named events (e.g. footsteps), each at a time in seconds from the start of the clip
+    pub events: Vec<(f32, String)>,
+
+    /// Names of the joints whose scale was removed when loading the clip, with the
+    /// `strip_scale` option of its `AnimationClipDef`
+    pub scale_stripped_joints: Vec<String>,
+
 }
 
+/// Identifies files written by `AnimationClip::save_to_path`
+const CLIP_FILE_MAGIC: &[u8; 4] = b"SKAC";
+
+/// Version of the `AnimationClip::save_to_path` layout
+const CLIP_FILE_VERSION: u32 = 2;
+
 #[derive(Debug, RustcDecodable)]
 pub struct AnimationClipDef {
     pub name: String,
     pub source: String,
     pub duration: f32,
     pub rotate_z: f32,
+    pub preserve_sample_times: Option<bool>,
+    pub tags: Option<Vec<String>>,
+
+    /// Optional loop mode, one of "loop" (the default), "clamp" or "ping_pong"
+    pub loop_mode: Option<LoopMode>,
+
+    /// Alias for `loop_mode`, as "loop" if true or "clamp" if false. Ignored if `loop_mode`
+    /// is present.
+    pub looping: Option<bool>,
+
+    /// Optional name of the animation take to load, for source files holding several takes.
+    /// Matches an `<animation_clip>` by id or name, or else `<animation>` ids prefixed by it.
+    pub take: Option<String>,
+
+    /// Optionally remove the root translation drift over each loop (see `fix_root_loop_seam`)
+    pub fix_root_loop_seam: Option<bool>,
+
+    /// Optionally strip scale from the joint poses, for transforms that can't represent it.
+    /// By default scale is passed through to `Transform::from_matrix`. Joints whose scale was
+    /// stripped are listed in the clip's `scale_stripped_joints`.
+    pub strip_scale: Option<bool>,
+
+    /// Optional COLLADA file (relative to the same folder as `source`) with the skeleton to
+    /// load the clip for, matching joints by name (see `remap_joints`). Allows blending clips
+    /// authored for overlapping rigs, e.g. with and without twist bones.
+    pub target_skeleton: Option<String>,
+
+    /// Optional named events (e.g. footsteps) to fire as the clip plays past their times
+    pub events: Option<Vec<ClipEventDef>>,
 }
 
-#[derive(Debug, RustcDecodable)]
+/// A named event at a time in seconds from the start of an AnimationClip
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct ClipEventDef {
+    pub time: f32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, RustcDecodable)]
 pub struct DifferenceClipDef {
     pub name: String,
     pub source_clip: String,
     pub reference_clip: String,
 }
 
+/// Mirror settings for `AnimationClip::retarget`, for opposite-handed characters
+#[derive(Debug, Clone)]
+pub struct MirrorConfig {
+
+    /// Normal of the mirror plane, which passes through the model-space origin
+    pub plane_normal: Vector3<f32>,
+
+    /// Pairs of joint names that swap under the mirror, e.g. ("hand_l", "hand_r")
+    pub joint_pairs: Vec<(String, String)>,
+
+}
+
+impl MirrorConfig {
+
+    /// Return the name of the joint paired with the given joint, or the name itself if unpaired
+    pub fn paired_joint_name<'a>(&'a self, joint_name: &'a str) -> &'a str {
+        for (a, b) in self.joint_pairs.iter() {
+            if a == joint_name {
+                return &b[..];
+            } else if b == joint_name {
+                return &a[..];
+            }
+        }
+        joint_name
+    }
+
+    /// Reflect a local joint pose across the mirror plane. The result is the reflection conjugated
+    /// with the pose, so it remains a proper rigid transform.
+    fn mirror_pose<T: Transform>(&self, mut pose: T) -> T {
+        let normal = vec3_normalized(self.plane_normal);
+        let (w, v) = pose.get_rotation();
+        pose.set_rotation((w, vec3_sub(vec3_scale(normal, 2.0 * vec3_dot(v, normal)), v)));
+        let translation = mirror_point(pose.get_translation(), [0.0, 0.0, 0.0], normal);
+        pose.set_translation(translation);
+        pose
+    }
+}
+
 impl<T: Transform> AnimationClip<T> {
 
-    /// `parent_folder` is the folder to search for the `AnimationClip`'s source file
-    pub fn from_def(clip_def: &AnimationClipDef, parent_folder: PathBuf) -> AnimationClip<T> {
+    /// Create an untagged clip from the given samples, at a constant sample rate
+    pub fn new(samples: Vec<AnimationSample<T>>, samples_per_second: f32) -> AnimationClip<T> {
+        AnimationClip {
+            samples,
+            samples_per_second,
+            sample_times: None,
+            tags: Vec::new(),
+            loop_mode: LoopMode::Loop,
+            events: Vec::new(),
+            scale_stripped_joints: Vec::new(),
+        }
+    }
+
+    /// Create a single-sample clip of the skeleton's local bind pose, e.g. as the reference clip
+    /// for a difference clip that should be relative to the bind pose
+    pub fn from_bind_pose(skeleton: &Skeleton) -> AnimationClip<T> {
+        AnimationClip::new(vec![AnimationSample { local_poses: skeleton.bind_pose_local() }], 1.0)
+    }
+
+    /// `parent_folder` is the folder to search for the `AnimationClip`'s source file. Returns a
+    /// description of the problem if a COLLADA file can't be loaded or has no usable animation.
+    pub fn from_def(clip_def: &AnimationClipDef, parent_folder: PathBuf) -> Result<AnimationClip<T>, String> {
+
+        let mut target_skeleton_path = parent_folder.clone();
 
         // Wacky. Shouldn't it be an error if the struct field isn't present?
         // FIXME - use an Option
@@ -64,23 +216,243 @@ impl<T: Transform> AnimationClip<T> {
         // FIXME - load skeleton separately?
         let mut source_path = parent_folder;
         source_path.push(&clip_def.source);
-        let collada_document = ColladaDocument::from_path(&source_path).unwrap();
-        let animations = collada_document.get_animations().unwrap();
-        let skeleton_set = collada_document.get_skeletons().unwrap();
-        let skeleton = Skeleton::from_collada(&skeleton_set[0]);
+        let collada_document = load_collada_document(&source_path)?;
+        let mut animations = collada_document.get_animations().unwrap_or_default();
+        if let Some(ref take) = clip_def.take {
+            animations = select_take(&collada_document, animations, take);
+            if animations.is_empty() {
+                return Err(format!("No animations found for take '{}' in {:?}", take, source_path));
+            }
+        }
+        if animations.is_empty() {
+            return Err(format!("No animations found in {:?}", source_path));
+        }
+        let scale_stripped_joints = if clip_def.strip_scale == Some(true) {
+            strip_scale(&mut animations)
+        } else {
+            Vec::new()
+        };
+        let mut skeleton = load_skeleton(&collada_document, &source_path)?;
 
         let mut clip = AnimationClip::from_collada(&skeleton, &animations, &adjust);
+        clip.scale_stripped_joints = scale_stripped_joints;
+
+        if let Some(ref target_skeleton) = clip_def.target_skeleton {
+            target_skeleton_path.push(target_skeleton);
+            let target_document = load_collada_document(&target_skeleton_path)?;
+            let target_skeleton = load_skeleton(&target_document, &target_skeleton_path)?;
+            clip = AnimationClip::remap_joints(&clip, &skeleton, &target_skeleton);
+            skeleton = target_skeleton;
+        }
+
+        if clip_def.preserve_sample_times == Some(true) {
+            let sample_times = &animations[0].sample_times;
+            if let Some(animation) = animations.iter().find(|animation| animation.sample_times != *sample_times) {
+                return Err(format!("Can't preserve sample times in {:?}, as those of '{}' differ from '{}'",
+                                   source_path, animation.target, animations[0].target));
+            }
+            clip.set_sample_times(sample_times.clone())
+                .map_err(|e| format!("Invalid sample times in {:?}: {}", source_path, e))?;
+        }
 
         if !clip_def.duration.is_nan() {
             clip.set_duration(clip_def.duration);
         }
-        clip
 
+        if let Some(ref tags) = clip_def.tags {
+            clip.tags = tags.clone();
+        }
+
+        if let Some(loop_mode) = clip_def.loop_mode {
+            clip.loop_mode = loop_mode;
+        } else if let Some(looping) = clip_def.looping {
+            clip.loop_mode = if looping { LoopMode::Loop } else { LoopMode::Clamp };
+        }
+
+        if let Some(ref events) = clip_def.events {
+            clip.events = events.iter().map(|event| (event.time, event.name.clone())).collect();
+        }
+
+        if clip_def.fix_root_loop_seam == Some(true) {
+            clip.fix_root_loop_seam(&skeleton);
+        }
+
+        Ok(clip)
+
+    }
+
+    /// Write the clip's samples and timing to a binary file at `path`, so that it can be loaded
+    /// again with `load_from_path` without re-reading its COLLADA source. Tags and events
+    /// aren't saved, as they come from the clip's `AnimationClipDef`.
+    pub fn save_to_path(&self, path: &Path) -> Result<(), String> {
+        let joint_count = self.samples.first().map_or(0, |s| s.local_poses.len());
+
+        let mut bytes = CLIP_FILE_MAGIC.to_vec();
+        for &value in &[CLIP_FILE_VERSION, T::FLOAT_COUNT as u32, joint_count as u32, self.samples.len() as u32] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.samples_per_second.to_le_bytes());
+        bytes.push(match self.loop_mode {
+            LoopMode::Clamp => 0,
+            LoopMode::Loop => 1,
+            LoopMode::PingPong => 2,
+        });
+        bytes.push(self.sample_times.is_some() as u8);
+        bytes.push(T::LAYOUT_ID);
+
+        let mut floats = self.sample_times.clone().unwrap_or_default();
+        for sample in &self.samples {
+            for &pose in &sample.local_poses {
+                pose.write_floats(&mut floats);
+            }
+        }
+        for value in floats {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        fs::write(path, bytes).map_err(|e| format!("{} {:?}", e, path))
+    }
+
+    /// Load a clip written by `save_to_path`, checking that its transforms were written by the
+    /// same `Transform` implementation as `T` and that it has one transform per joint of `skeleton`.
+    pub fn load_from_path(path: &Path, skeleton: &Skeleton) -> Result<AnimationClip<T>, String> {
+        let bytes = fs::read(path).map_err(|e| format!("{} {:?}", e, path))?;
+
+        let header_len = CLIP_FILE_MAGIC.len() + 23;
+        if bytes.len() < header_len || bytes[.. CLIP_FILE_MAGIC.len()] != CLIP_FILE_MAGIC[..] {
+            return Err(format!("Not a clip file {:?}", path));
+        }
+        let read_u32 = |offset: usize| {
+            let mut word = [0; 4];
+            word.copy_from_slice(&bytes[offset .. offset + 4]);
+            u32::from_le_bytes(word)
+        };
+        let header = CLIP_FILE_MAGIC.len();
+        if read_u32(header) != CLIP_FILE_VERSION {
+            return Err(format!("Unsupported clip file version {} in {:?}", read_u32(header), path));
+        }
+        if bytes[header + 22] != T::LAYOUT_ID {
+            return Err(format!("Clip file {:?} has transform layout {}, expected {}",
+                               path, bytes[header + 22], T::LAYOUT_ID));
+        }
+        if read_u32(header + 4) as usize != T::FLOAT_COUNT {
+            return Err(format!("Clip file {:?} has {} floats per transform, expected {}",
+                               path, read_u32(header + 4), T::FLOAT_COUNT));
+        }
+        let joint_count = read_u32(header + 8) as usize;
+        if joint_count != skeleton.joints.len() {
+            return Err(format!("Clip file {:?} has {} joints, but the skeleton has {}",
+                               path, joint_count, skeleton.joints.len()));
+        }
+        let sample_count = read_u32(header + 12) as usize;
+        let samples_per_second = f32::from_bits(read_u32(header + 16));
+        let loop_mode = match bytes[header + 20] {
+            0 => LoopMode::Clamp,
+            1 => LoopMode::Loop,
+            2 => LoopMode::PingPong,
+            loop_mode => return Err(format!("Unknown loop mode {} in clip file {:?}", loop_mode, path)),
+        };
+        let has_sample_times = bytes[header + 21] != 0;
+
+        let time_count = if has_sample_times { sample_count } else { 0 };
+        let float_count = time_count + sample_count * joint_count * T::FLOAT_COUNT;
+        if bytes.len() != header_len + float_count * 4 {
+            return Err(format!("Truncated clip file {:?}", path));
+        }
+        let floats: Vec<f32> = (0 .. float_count).map(|i| f32::from_bits(read_u32(header_len + i * 4))).collect();
+
+        let (times, poses) = floats.split_at(time_count);
+        let samples = if joint_count == 0 {
+            (0 .. sample_count).map(|_| AnimationSample { local_poses: Vec::new() }).collect()
+        } else {
+            poses.chunks(joint_count * T::FLOAT_COUNT).map(|sample| {
+                AnimationSample { local_poses: sample.chunks(T::FLOAT_COUNT).map(T::read_floats).collect() }
+            }).collect()
+        };
+
+        let mut clip = AnimationClip::new(samples, samples_per_second);
+        clip.loop_mode = loop_mode;
+        if has_sample_times {
+            clip.set_sample_times(times.to_vec())
+                .map_err(|e| format!("Invalid sample times in clip file {:?}: {}", path, e))?;
+        }
+        Ok(clip)
     }
 
     /// Overrides the sampling rate of the clip to give the given duration (in seconds).
     pub fn set_duration(&mut self, duration: f32) {
+        let scale = duration / self.get_duration();
+        if let Some(ref mut sample_times) = self.sample_times {
+            for time in sample_times.iter_mut() {
+                *time *= scale;
+            }
+        }
+        for event in self.events.iter_mut() {
+            event.0 *= scale;
+        }
+        self.samples_per_second = self.samples.len() as f32 / duration;
+    }
+
+    /// Remove the drift in root joint translation over each loop of the clip, so a looping clip
+    /// whose endpoints don't match doesn't jerk its root back at the loop seam.
+    ///
+    /// The drift is the difference between the root translation at the end of the clip (the
+    /// last sample, extrapolated over the final frame for uniformly sampled clips) and the
+    /// first sample. It is subtracted from each sample in proportion to the sample's time.
+    pub fn fix_root_loop_seam(&mut self, skeleton: &Skeleton) {
+
+        let sample_count = self.samples.len();
+        let duration = self.get_duration();
+
+        let sample_times: Vec<f32> = match self.sample_times {
+            Some(ref sample_times) => sample_times.clone(),
+            None => (0 .. sample_count).map(|i| i as f32 / self.samples_per_second).collect(),
+        };
+
+        for (joint_index, joint) in skeleton.joints.iter().enumerate() {
+
+            if !joint.is_root() {
+                continue;
+            }
+
+            let first = self.samples[0].local_poses[joint_index].get_translation();
+            let last = self.samples[sample_count - 1].local_poses[joint_index].get_translation();
+
+            let end = if self.sample_times.is_none() && sample_count > 1 {
+                let previous = self.samples[sample_count - 2].local_poses[joint_index].get_translation();
+                vec3_add(last, vec3_sub(last, previous))
+            } else {
+                last
+            };
+
+            let drift = vec3_sub(end, first);
+
+            for (sample, &time) in self.samples.iter_mut().zip(sample_times.iter()) {
+                let pose = &mut sample.local_poses[joint_index];
+                let translation = vec3_sub(pose.get_translation(), vec3_scale(drift, time / duration));
+                pose.set_translation(translation);
+            }
+        }
+    }
+
+    /// Use the given per-sample times (in seconds) for interpolation instead of assuming a
+    /// constant sample rate. Returns a description of the problem, leaving the clip unchanged,
+    /// unless there is one time for each sample, and the times are strictly ascending and end
+    /// after time 0.
+    pub fn set_sample_times(&mut self, sample_times: Vec<f32>) -> Result<(), String> {
+        if sample_times.len() != self.samples.len() {
+            return Err(format!("Expected {} sample times, one per sample, got {}", self.samples.len(), sample_times.len()));
+        }
+        let duration = match sample_times.last() {
+            Some(&duration) if duration > 0.0 && duration.is_finite() => duration,
+            _ => return Err("Sample times must end after time 0".to_string()),
+        };
+        if !sample_times.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err("Sample times must be strictly ascending".to_string());
+        }
         self.samples_per_second = self.samples.len() as f32 / duration;
+        self.sample_times = Some(sample_times);
+        Ok(())
     }
 
     /// Return the duration of the clip in seconds
@@ -96,16 +468,14 @@ impl<T: Transform> AnimationClip<T> {
     /// * `blended_poses` - The output array slice of joint transforms that will be populated
     ///                     for each joint in the skeleton.
     pub fn get_pose_at_time(&self, elapsed_time: f32, blended_poses: &mut [T]) {
+        self.get_pose_at_time_with_mode(elapsed_time, self.loop_mode, blended_poses);
+    }
 
-        let interpolated_index = elapsed_time * self.samples_per_second;
-
-        let index_1 = interpolated_index.floor() as usize;
-        let index_2 = interpolated_index.ceil() as usize;
-
-        let blend_factor = interpolated_index - index_1 as f32;
+    /// Obtains the interpolated skeletal pose at the given sampling time, as with
+    /// `get_pose_at_time`, but with the given loop mode rather than the clip's own.
+    pub fn get_pose_at_time_with_mode(&self, elapsed_time: f32, loop_mode: LoopMode, blended_poses: &mut [T]) {
 
-        let index_1 = index_1 % self.samples.len();
-        let index_2 = index_2 % self.samples.len();
+        let (index_1, index_2, blend_factor) = self.get_sample_indices_with_mode(elapsed_time, loop_mode);
 
         let sample_1 = &self.samples[index_1];
         let sample_2 = &self.samples[index_2];
@@ -122,141 +492,733 @@ impl<T: Transform> AnimationClip<T> {
 
     }
 
-    /// Create a difference clip from a source and reference clip for additive blending.
-    pub fn as_difference_clip(source_clip: &AnimationClip<T>, reference_clip: &AnimationClip<T>) -> AnimationClip<T> {
-
-        let samples = (0 .. source_clip.samples.len()).map(|sample_index| {
+    /// Samples the clip at `count` evenly spaced times, e.g. for a contact sheet. Times run from
+    /// the start of the clip and exclude the end (which coincides with the start of the next
+    /// loop), so sample `i` is taken at `i / count` of the clip duration.
+    pub fn sample_uniform(&self, count: usize) -> Vec<Vec<T>> {
+        let joint_count = self.samples.first().map_or(0, |s| s.local_poses.len());
+        let duration = self.get_duration();
+        (0 .. count).map(|i| {
+            let mut poses = vec![T::identity(); joint_count];
+            self.get_pose_at_time(duration * i as f32 / count as f32, &mut poses);
+            poses
+        }).collect()
+    }
 
-            let ref source_sample = source_clip.samples[sample_index];
+    /// Creates a uniformly sampled copy of the clip at the given sample rate, e.g. to normalize
+    /// clips from different sources to one rate. The rate is adjusted slightly if needed, to fit a
+    /// whole number of samples into the clip's duration.
+    pub fn resample(&self, target_fps: f32) -> AnimationClip<T> {
+        let duration = self.get_duration();
+        let count = ((duration * target_fps).round() as usize).max(1);
+        let samples = self.sample_uniform(count).into_iter().map(|local_poses| AnimationSample { local_poses }).collect();
 
-            // Extrapolate reference clip by wrapping, if reference clip is shorter than source clip
-            let ref reference_sample = reference_clip.samples[sample_index % reference_clip.samples.len()];
+        let mut clip = AnimationClip::new(samples, count as f32 / duration);
+        clip.tags = self.tags.clone();
+        clip.loop_mode = self.loop_mode;
+        clip.events = self.events.clone();
+        clip
+    }
 
-            let difference_poses = (0 .. source_sample.local_poses.len()).map(|joint_index| {
-                let source_pose = source_sample.local_poses[joint_index];
-                let reference_pose = reference_sample.local_poses[joint_index];
-                reference_pose.inverse().concat(source_pose)
-            }).collect();
+    /// Creates a clip of the time range `[start, end]` (in seconds) of this clip, e.g. to slice
+    /// one of several animations out of a long take. Keeps the samples strictly inside the
+    /// range, adding samples at `start` and `end` (sampled without looping) so the boundary
+    /// poses are exact, and gives the new clip explicit sample times with a duration of
+    /// `end - start`. Events in the range are kept, relative to `start`.
+    ///
+    /// Panics if the range is reversed or empty, or not within `[0, get_duration()]`.
+    pub fn sub_clip(&self, start: f32, end: f32) -> AnimationClip<T> {
 
-            AnimationSample {
-                local_poses: difference_poses,
-            }
+        let duration = self.get_duration();
+        assert!(start >= 0.0 && end <= duration, "Sub-clip range {}..{} is outside the clip duration {}", start, end, duration);
+        assert!(start < end, "Sub-clip range {}..{} is empty or reversed", start, end);
 
-        }).collect();
+        let sample_at = |time: f32| {
+            let mut local_poses = self.samples[0].local_poses.clone();
+            self.get_pose_at_time_with_mode(time, LoopMode::Clamp, &mut local_poses);
+            AnimationSample { local_poses }
+        };
 
-        AnimationClip {
-            samples_per_second: source_clip.samples_per_second,
-            samples: samples,
+        let mut samples = vec![sample_at(start)];
+        let mut sample_times = vec![0.0];
+        for (i, sample) in self.samples.iter().enumerate() {
+            let time = match self.sample_times {
+                Some(ref sample_times) => sample_times[i],
+                None => i as f32 / self.samples_per_second,
+            };
+            if time > start && time < end {
+                samples.push(AnimationSample { local_poses: sample.local_poses.clone() });
+                sample_times.push(time - start);
+            }
         }
+        samples.push(sample_at(end));
+        sample_times.push(end - start);
+
+        let mut clip = AnimationClip::new(samples, 1.0);
+        clip.set_sample_times(sample_times).expect("Sub-clip sample times are ascending from a non-empty range");
+        clip.tags = self.tags.clone();
+        clip.loop_mode = self.loop_mode;
+        clip.events = self.events.iter()
+            .filter(|&&(time, _)| time >= start && time <= end)
+            .map(|&(time, ref name)| (time - start, name.clone()))
+            .collect();
+        clip
     }
 
-    /// Creates an `AnimationClip` from a collection of `collada::Animation`.
+    /// Obtains the interpolated local pose of a single joint at the given sampling time,
+    /// without sampling the rest of the skeleton.
     ///
     /// # Arguments
     ///
-    /// * `skeleton` - The `Skeleton` that the `AnimationClip` will be created for.
-    /// * `animations` - The collection of `collada::Animation`s that will be converted into an
-    ///                  `AnimationClip`, using the given `Skeleton`.
-    /// * `transform` - An offset transform to apply to the root pose of each animation sample,
-    ///                 useful for applying rotation, translation, or scaling when loading an
-    ///                 animation.
-    pub fn from_collada(skeleton: &Skeleton, animations: &Vec<collada::Animation>, transform: &Matrix4<f32>) -> AnimationClip<T> {
-        use std::f32::consts::PI;
+    /// * `joint` - The index of the joint to sample
+    /// * `time` - The time to sample with, relative to the start of the animation
+    pub fn get_joint_pose_at_time(&self, joint: JointIndex, elapsed_time: f32) -> T {
+        let (index_1, index_2, blend_factor) = self.get_sample_indices(elapsed_time);
+        let pose_1 = self.samples[index_1].local_poses[joint as usize];
+        let pose_2 = self.samples[index_2].local_poses[joint as usize];
+        pose_1.lerp(pose_2, blend_factor)
+    }
 
-        // Z-axis is 'up' in COLLADA, so need to rotate root pose about x-axis so y-axis is 'up'
-        let rotate_on_x =
-            [
-                [1.0, 0.0, 0.0, 0.0],
-                [0.0, (PI/2.0).cos(), (PI/2.0).sin(), 0.0],
-                [0.0, (-PI/2.0).sin(), (PI/2.0).cos(), 0.0],
-                [0.0, 0.0, 0.0, 1.0],
-            ];
+    /// Root motion: the translation of the root joint between the given local times. For a
+    /// `Loop` clip, each loop crossed contributes the translation from the first to the last
+    /// sample, rather than the jump back to the start over the final frame.
+    pub fn root_motion_between(&self, previous_time: f32, time: f32) -> Vector3<f32> {
 
-        let transform = row_mat4_mul(rotate_on_x, *transform);
+        let sample_mode = self.root_motion_sample_mode();
+        let root_translation = |time: f32| {
+            let (index_1, index_2, blend_factor) = self.get_sample_indices_with_mode(time, sample_mode);
+            let pose_1 = self.samples[index_1].local_poses[0];
+            let pose_2 = self.samples[index_2].local_poses[0];
+            pose_1.lerp(pose_2, blend_factor).get_translation()
+        };
 
-        // Build an index of joint names to anims
-        let mut joint_animations = HashMap::new();
-        for anim in animations.iter() {
-            let joint_name = anim.target.split('/').next().unwrap();
-            joint_animations.insert(joint_name, anim);
+        let duration = self.get_duration();
+        if self.loop_mode != LoopMode::Loop || duration <= 0.0 {
+            return vec3_sub(root_translation(time), root_translation(previous_time));
         }
 
-        // Assuming all ColladaAnims have the same number of samples..
-        let sample_count = animations[0].sample_times.len();
+        let previous_loop = (previous_time / duration).floor();
+        let current_loop = (time / duration).floor();
+        let previous_time = previous_time - previous_loop * duration;
+        let time = time - current_loop * duration;
 
-        // Assuming all ColladaAnims have the same duration..
-        let duration = *animations[0].sample_times.last().unwrap();
+        if previous_loop == current_loop {
+            return vec3_sub(root_translation(time), root_translation(previous_time));
+        }
 
-        // Assuming constant sample rate
-        let samples_per_second = sample_count as f32 / duration;
+        let start = root_translation(0.0);
+        let end = root_translation(duration);
+        let whole_loops = current_loop - previous_loop - 1.0;
+        vec3_add(vec3_add(vec3_sub(end, root_translation(previous_time)), vec3_scale(vec3_sub(end, start), whole_loops)),
+                 vec3_sub(root_translation(time), start))
+    }
 
-        let samples = (0 .. sample_count).map(|sample_index| {
+    /// Root rotation: the rotation `q` of the root joint between the given local times, such
+    /// that its rotation at `time` is `q` applied after its rotation at `previous_time`. Loops
+    /// are crossed as with `root_motion_between`.
+    pub fn root_rotation_between(&self, previous_time: f32, time: f32) -> Quaternion<f32> {
 
-            // Grab local poses for each joint from COLLADA animation if available,
-            // falling back to identity matrix
-            let local_poses: Vec<Matrix4<f32>> = skeleton.joints.iter().map(|joint| {
-                match joint_animations.get(&joint.name[..]) {
-                    Some(a) if joint.is_root() => row_mat4_mul(transform, a.sample_poses[sample_index]),
-                    Some(a) => a.sample_poses[sample_index],
-                    None => mat4_id(),
-                }
-            }).collect();
+        let sample_mode = self.root_motion_sample_mode();
+        let root_rotation = |time: f32| {
+            let (index_1, index_2, blend_factor) = self.get_sample_indices_with_mode(time, sample_mode);
+            let pose_1 = self.samples[index_1].local_poses[0];
+            let pose_2 = self.samples[index_2].local_poses[0];
+            pose_1.lerp(pose_2, blend_factor).get_rotation()
+        };
+        let rotation_from_to = |from: f32, to: f32| quaternion_mul(root_rotation(to), quaternion_conj(root_rotation(from)));
 
-            // Convert local poses to Transforms (for interpolation)
-            let local_poses: Vec<T> = local_poses.iter().map(|pose_matrix| {
-                T::from_matrix(*pose_matrix)
-            }).collect();
+        let duration = self.get_duration();
+        if self.loop_mode != LoopMode::Loop || duration <= 0.0 {
+            return rotation_from_to(previous_time, time);
+        }
 
-            AnimationSample {
-                local_poses: local_poses,
+        let previous_loop = (previous_time / duration).floor();
+        let current_loop = (time / duration).floor();
+        let previous_time = previous_time - previous_loop * duration;
+        let time = time - current_loop * duration;
+
+        if previous_loop == current_loop {
+            return rotation_from_to(previous_time, time);
+        }
+
+        let whole_loop = rotation_from_to(0.0, duration);
+        let whole_loops = current_loop - previous_loop - 1.0;
+        if whole_loops >= 0.0 {
+            let mut rotation = rotation_from_to(previous_time, duration);
+            for _ in 0 .. whole_loops as usize {
+                rotation = quaternion_mul(whole_loop, rotation);
             }
-        }).collect();
+            quaternion_mul(rotation_from_to(0.0, time), rotation)
+        } else {
+            // Playing in reverse: back to the start of the loop, undoing any whole loops
+            // skipped over, then back from the end of the loop
+            let mut rotation = rotation_from_to(previous_time, 0.0);
+            for _ in 0 .. (-whole_loops - 2.0) as usize {
+                rotation = quaternion_mul(quaternion_conj(whole_loop), rotation);
+            }
+            quaternion_mul(rotation_from_to(duration, time), rotation)
+        }
+    }
 
-        AnimationClip {
-            samples_per_second: samples_per_second,
-            samples: samples,
+    /// Loop mode for sampling root motion within a loop: `Loop` clips have their loops crossed
+    /// separately, so are sampled without wrapping, while `PingPong` clips move back and forth
+    fn root_motion_sample_mode(&self) -> LoopMode {
+        match self.loop_mode {
+            LoopMode::Loop | LoopMode::Clamp => LoopMode::Clamp,
+            LoopMode::PingPong => LoopMode::PingPong,
         }
     }
 
-}
+    /// Average speed of the root joint over the clip (its distance from the first to the last
+    /// sample, over the time between them), e.g. the ground speed of a walk cycle. Zero for a
+    /// clip with a single sample.
+    pub fn root_motion_speed(&self) -> f32 {
+        let last_time = self.last_sample_time();
+        if last_time > 0.0 {
+            let first = self.samples[0].local_poses[0].get_translation();
+            let last = self.samples[self.samples.len() - 1].local_poses[0].get_translation();
+            vec3_len(vec3_sub(last, first)) / last_time
+        } else {
+            0.0
+        }
+    }
 
-/// An instance of an AnimationClip which tracks playback parameters
-pub struct ClipInstance<T: Transform> {
-    /// Shared clip reference
-    pub clip: Rc<AnimationClip<T>>,
+    /// Time of the last sample, i.e. the duration less any final frame wrapping to the first
+    fn last_sample_time(&self) -> f32 {
+        match self.sample_times {
+            Some(ref sample_times) => sample_times[sample_times.len() - 1],
+            None => (self.samples.len() - 1) as f32 / self.samples_per_second,
+        }
+    }
 
-    /// Controller clock time at animation start
-    pub start_time: f32,
+    /// Returns `(index_1, index_2, blend_factor)` for the pair of samples `get_pose_at_time`
+    /// would blend between at the given time, after looping or clamping, e.g. for debugging
+    /// timing issues in tools.
+    pub fn sample_info(&self, elapsed_time: f32) -> (usize, usize, f32) {
+        self.get_sample_indices(elapsed_time)
+    }
 
-    /// Playback rate modifier, where 1.0 is original speed
-    pub playback_rate: f32,
+    /// Find the pair of samples bracketing the given time, and the blend factor between them
+    fn get_sample_indices(&self, elapsed_time: f32) -> (usize, usize, f32) {
+        self.get_sample_indices_with_mode(elapsed_time, self.loop_mode)
+    }
 
-    /// Used to account for changes in playback rate
-    pub time_offset: f32,
-}
+    fn get_sample_indices_with_mode(&self, elapsed_time: f32, loop_mode: LoopMode) -> (usize, usize, f32) {
 
-impl<T: Transform> ClipInstance<T> {
+        let (elapsed_time, looping) = match loop_mode {
+            LoopMode::Loop => (elapsed_time, true),
+            LoopMode::Clamp => (elapsed_time, false),
+            LoopMode::PingPong => {
+                // Reflect into the span from the first to the last sample, then clamp
+                let last_time = self.last_sample_time();
+                if last_time > 0.0 {
+                    let time = elapsed_time.abs() % (2.0 * last_time);
+                    (if time > last_time { 2.0 * last_time - time } else { time }, false)
+                } else {
+                    (0.0, false)
+                }
+            }
+        };
 
-    pub fn new(clip: Rc<AnimationClip<T>>) -> ClipInstance<T> {
-        ClipInstance {
-            clip: clip,
-            start_time: 0.0,
-            playback_rate: 1.0,
-            time_offset: 0.0,
+        match self.sample_times {
+            Some(ref sample_times) => self.get_sample_indices_from_times(sample_times, elapsed_time, looping),
+            None => {
+                let mut interpolated_index = elapsed_time * self.samples_per_second;
+
+                if !looping {
+                    // Hold the last sample over the final frame, rather than wrapping to the first
+                    interpolated_index = interpolated_index.max(0.0).min((self.samples.len() - 1) as f32);
+                } else {
+                    // Wrap negative times (e.g. from reverse playback) back into the clip
+                    interpolated_index = interpolated_index.rem_euclid(self.samples.len() as f32);
+                }
+
+                let index_1 = interpolated_index.floor() as usize;
+                let index_2 = interpolated_index.ceil() as usize;
+
+                let blend_factor = interpolated_index - index_1 as f32;
+
+                (index_1 % self.samples.len(), index_2 % self.samples.len(), blend_factor)
+            }
         }
     }
 
-    /// Adjust the playback rate of the clip without affecting the
-    /// value of get_local_time for a given global time.
-    pub fn set_playback_rate(&mut self, global_time: f32, new_rate: f32) {
-        if self.playback_rate != new_rate {
-            let local_time = self.get_local_time(global_time);
-            self.time_offset = local_time - (global_time - self.start_time) * new_rate;
-            self.playback_rate = new_rate;
+    /// Find the pair of samples bracketing the given time, and the blend factor between them,
+    /// from explicit sample times. Before the first sample time, blends from the last sample
+    /// (treated as coinciding with the start of the next loop) to the first, or holds the first
+    /// sample if the clip isn't looping.
+    fn get_sample_indices_from_times(&self, sample_times: &[f32], elapsed_time: f32, looping: bool) -> (usize, usize, f32) {
+
+        let duration = self.get_duration();
+        let time = if looping {
+            let time = elapsed_time % duration;
+            if time < 0.0 { time + duration } else { time }
+        } else {
+            elapsed_time.max(0.0).min(duration)
+        };
+
+        let last_index = sample_times.len() - 1;
+
+        // Index of the first sample after the sampling time
+        let next_index = sample_times.partition_point(|&t| t <= time);
+
+        if next_index == 0 && !looping {
+            (0, 0, 0.0)
+        } else if next_index == 0 {
+            let blend_factor = if sample_times[0] > 0.0 { time / sample_times[0] } else { 0.0 };
+            (last_index, 0, blend_factor)
+        } else if next_index > last_index {
+            (last_index, last_index, 0.0)
+        } else {
+            let time_1 = sample_times[next_index - 1];
+            let time_2 = sample_times[next_index];
+            if time == time_1 {
+                (next_index - 1, next_index - 1, 0.0)
+            } else {
+                (next_index - 1, next_index, (time - time_1) / (time_2 - time_1))
+            }
         }
     }
 
-    pub fn get_pose_at_time(&self, global_time: f32, blended_poses: &mut [T]) {
+    /// Measures foot sliding, as the total horizontal (xz) distance travelled by the given foot
+    /// joint between consecutive samples in which it is planted, i.e. within
+    /// `FOOT_CONTACT_HEIGHT` of the ground plane at `ground_y`.
+    pub fn foot_skate(&self, skeleton: &Skeleton, foot_joint: JointIndex, ground_y: f32) -> f32 {
+
+        let mut global_poses = vec![mat4_id(); skeleton.joints.len()];
+
+        let foot_positions: Vec<Vector3<f32>> = self.samples.iter().map(|sample| {
+            skeleton.calculate_global_poses(&sample.local_poses, &mut global_poses);
+            global_poses[foot_joint as usize].transform_vector([0.0, 0.0, 0.0])
+        }).collect();
+
+        let is_planted = |p: &Vector3<f32>| p[1] - ground_y <= FOOT_CONTACT_HEIGHT;
+
+        foot_positions.windows(2).filter(|pair| {
+            is_planted(&pair[0]) && is_planted(&pair[1])
+        }).map(|pair| {
+            let dx = pair[1][0] - pair[0][0];
+            let dz = pair[1][2] - pair[0][2];
+            (dx * dx + dz * dz).sqrt()
+        }).sum()
+    }
+
+    /// Measures how cleanly the clip loops, as the difference between each joint's local pose in
+    /// the first and last samples: the rotation angle (in radians) plus the translation distance.
+    /// Near-zero for all joints if the clip loops seamlessly.
+    pub fn loop_error(&self) -> Vec<(JointIndex, f32)> {
+        let (first, last) = match (self.samples.first(), self.samples.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Vec::new(),
+        };
+        first.local_poses.iter().zip(last.local_poses.iter()).enumerate().map(|(joint_index, (&first_pose, &last_pose))| {
+            let angle = quaternion_angle_between(first_pose.get_rotation(), last_pose.get_rotation());
+            let distance = vec3_len(vec3_sub(last_pose.get_translation(), first_pose.get_translation()));
+            (joint_index as JointIndex, angle + distance)
+        }).collect()
+    }
+
+    /// Returns the joints whose local pose varies across the clip's samples, i.e. the joints
+    /// that are actually animated by this clip.
+    pub fn animated_joints(&self) -> Vec<JointIndex> {
+        let joint_count = self.samples.first().map_or(0, |s| s.local_poses.len());
+        (0 .. joint_count).filter(|&joint_index| {
+            self.is_joint_animated(joint_index, POSE_EPSILON)
+        }).map(|joint_index| joint_index as JointIndex).collect()
+    }
+
+    /// Returns the joints whose local pose stays within `epsilon` (in any matrix component) of
+    /// its pose in the first sample throughout the clip, the complement of `animated_joints`.
+    /// Useful for flagging bones that were never keyed on export.
+    pub fn static_joints(&self, epsilon: f32) -> Vec<JointIndex> {
+        let joint_count = self.samples.first().map_or(0, |s| s.local_poses.len());
+        (0 .. joint_count).filter(|&joint_index| {
+            !self.is_joint_animated(joint_index, epsilon)
+        }).map(|joint_index| joint_index as JointIndex).collect()
+    }
+
+    /// Returns true if the joint's local pose in any sample differs from the first sample
+    /// by more than `epsilon` in any matrix component
+    fn is_joint_animated(&self, joint_index: usize, epsilon: f32) -> bool {
+        let first_pose = self.samples[0].local_poses[joint_index].to_matrix();
+        self.samples.iter().skip(1).any(|sample| {
+            let pose = sample.local_poses[joint_index].to_matrix();
+            (0 .. 4).any(|i| (0 .. 4).any(|j| (pose[i][j] - first_pose[i][j]).abs() > epsilon))
+        })
+    }
+
+    /// Creates a copy of the clip with keyframe reduction applied, dropping samples that can be
+    /// reconstructed by interpolating between their neighbours to within `tolerance`, in any
+    /// matrix component of any joint's local pose. The surviving samples are stored with
+    /// explicit sample times.
+    pub fn compress(&self, tolerance: f32) -> AnimationClip<T> {
+
+        let copy_sample = |sample: &AnimationSample<T>| AnimationSample { local_poses: sample.local_poses.clone() };
+
+        // Gather samples with their times, closing a uniform clip with a copy of the sample it
+        // ends on (the first for a looping clip) at the clip duration
+        let (times, samples): (Vec<f32>, Vec<&AnimationSample<T>>) = match self.sample_times {
+            Some(ref sample_times) => (sample_times.clone(), self.samples.iter().collect()),
+            None => {
+                let times = (0 ..= self.samples.len()).map(|i| i as f32 / self.samples_per_second).collect();
+                let mut samples: Vec<&AnimationSample<T>> = self.samples.iter().collect();
+                samples.push(if self.loop_mode == LoopMode::Loop { &self.samples[0] } else { &self.samples[self.samples.len() - 1] });
+                (times, samples)
+            }
+        };
+
+        let is_within_tolerance = |anchor: usize, candidate: usize| {
+            (anchor + 1 .. candidate).all(|k| {
+                let blend_factor = (times[k] - times[anchor]) / (times[candidate] - times[anchor]);
+                samples[k].local_poses.iter().enumerate().all(|(joint_index, pose)| {
+                    let original = pose.to_matrix();
+                    let interpolated = samples[anchor].local_poses[joint_index]
+                        .lerp(samples[candidate].local_poses[joint_index], blend_factor)
+                        .to_matrix();
+                    (0 .. 4).all(|i| (0 .. 4).all(|j| (original[i][j] - interpolated[i][j]).abs() <= tolerance))
+                })
+            })
+        };
+
+        // Greedily extend each segment from the last kept sample as far as tolerance allows
+        let mut kept = vec![0];
+        let mut anchor = 0;
+        let mut candidate = 2;
+        while candidate < samples.len() {
+            if is_within_tolerance(anchor, candidate) {
+                candidate += 1;
+            } else {
+                anchor = candidate - 1;
+                kept.push(anchor);
+                candidate = anchor + 2;
+            }
+        }
+        if samples.len() > 1 {
+            kept.push(samples.len() - 1);
+        }
+
+        let mut clip = AnimationClip::new(kept.iter().map(|&i| copy_sample(samples[i])).collect(), self.samples_per_second);
+        clip.set_sample_times(kept.iter().map(|&i| times[i]).collect()).expect("Kept sample times are a subset of the clip's");
+        clip.tags = self.tags.clone();
+        clip.loop_mode = self.loop_mode;
+        clip.events = self.events.clone();
+        clip
+    }
+
+    /// Returns the largest difference of any matrix component of any joint's local pose from
+    /// the identity, across all samples. For a difference clip, this bounds the clip's effect
+    /// when applied additively.
+    pub fn max_pose_delta(&self) -> f32 {
+        let identity: Matrix4<f32> = mat4_id();
+        let mut max_delta = 0.0f32;
+        for pose in self.samples.iter().flat_map(|sample| sample.local_poses.iter()) {
+            let pose = pose.to_matrix();
+            for i in 0 .. 4 {
+                for j in 0 .. 4 {
+                    max_delta = max_delta.max((pose[i][j] - identity[i][j]).abs());
+                }
+            }
+        }
+        max_delta
+    }
+
+    /// Create a difference clip from a source and reference clip for additive blending.
+    pub fn as_difference_clip(source_clip: &AnimationClip<T>, reference_clip: &AnimationClip<T>) -> AnimationClip<T> {
+        let joint_count = source_clip.samples.first().map_or(0, |s| s.local_poses.len());
+        AnimationClip::as_mixed_difference_clip(source_clip, &[reference_clip], &vec![0; joint_count])
+    }
+
+    /// Create a difference clip from a source clip for additive blending, where each joint is
+    /// differenced against its own reference clip, e.g. arms against an aim pose and legs against
+    /// an idle. `joint_references[j]` is the index into `reference_clips` for joint `j`.
+    pub fn as_mixed_difference_clip(source_clip: &AnimationClip<T>, reference_clips: &[&AnimationClip<T>], joint_references: &[usize]) -> AnimationClip<T> {
+
+        let samples = (0 .. source_clip.samples.len()).map(|sample_index| {
+
+            let ref source_sample = source_clip.samples[sample_index];
+
+            let difference_poses = (0 .. source_sample.local_poses.len()).map(|joint_index| {
+                let reference_clip = reference_clips[joint_references[joint_index]];
+
+                // Extrapolate reference clip by wrapping, if reference clip is shorter than source clip
+                let reference_sample = &reference_clip.samples[sample_index % reference_clip.samples.len()];
+
+                let source_pose = source_sample.local_poses[joint_index];
+                let reference_pose = reference_sample.local_poses[joint_index];
+                reference_pose.inverse().concat(source_pose)
+            }).collect();
+
+            AnimationSample {
+                local_poses: difference_poses,
+            }
+
+        }).collect();
+
+        AnimationClip {
+            samples_per_second: source_clip.samples_per_second,
+            samples: samples,
+            sample_times: source_clip.sample_times.clone(),
+            tags: source_clip.tags.clone(),
+            loop_mode: source_clip.loop_mode,
+            events: source_clip.events.clone(),
+            scale_stripped_joints: source_clip.scale_stripped_joints.clone(),
+        }
+    }
+
+    /// Creates an `AnimationClip` for `target_skeleton` from a clip authored for `source_skeleton`,
+    /// matching joints by name. Joint rotations are taken from the source clip, while the
+    /// translations of non-root joints are rebased onto the target's bind pose, to account for
+    /// differing bone lengths. Target joints with no matching source joint hold their bind pose.
+    ///
+    /// If a `MirrorConfig` is given, the motion is also mirrored across its plane in the same
+    /// pass, with each paired joint driven by its counterpart in the source clip.
+    pub fn retarget(
+        source_clip: &AnimationClip<T>,
+        source_skeleton: &Skeleton,
+        target_skeleton: &Skeleton,
+        mirror: Option<&MirrorConfig>,
+    ) -> AnimationClip<T> {
+
+        let source_joints: Vec<Option<usize>> = target_skeleton.joints.iter().map(|joint| {
+            let source_name = match mirror {
+                Some(mirror) => mirror.paired_joint_name(&joint.name[..]),
+                None => &joint.name[..],
+            };
+            source_skeleton.get_joint_index(source_name).map(|index| index as usize)
+        }).collect();
+
+        let bind_poses: Vec<Matrix4<f32>> = target_skeleton.joints.iter().map(|joint| {
+            let global_bind_pose = mat4_inv(joint.inverse_bind_pose);
+            if joint.is_root() {
+                global_bind_pose
+            } else {
+                let parent = &target_skeleton.joints[joint.parent_index as usize];
+                row_mat4_mul(parent.inverse_bind_pose, global_bind_pose)
+            }
+        }).collect();
+
+        let samples = source_clip.samples.iter().map(|source_sample| {
+
+            let local_poses = target_skeleton.joints.iter().enumerate().map(|(joint_index, joint)| {
+                match source_joints[joint_index] {
+                    Some(source_index) => {
+                        let mut pose = source_sample.local_poses[source_index];
+
+                        if let Some(mirror) = mirror {
+                            pose = mirror.mirror_pose(pose);
+                        }
+
+                        if !joint.is_root() {
+                            let bind_pose = bind_poses[joint_index];
+                            pose.set_translation([bind_pose[0][3], bind_pose[1][3], bind_pose[2][3]]);
+                        }
+
+                        pose
+                    }
+                    None => T::from_matrix(bind_poses[joint_index]),
+                }
+            }).collect();
+
+            AnimationSample { local_poses }
+
+        }).collect();
+
+        AnimationClip {
+            samples,
+            samples_per_second: source_clip.samples_per_second,
+            sample_times: source_clip.sample_times.clone(),
+            tags: source_clip.tags.clone(),
+            loop_mode: source_clip.loop_mode,
+            events: source_clip.events.clone(),
+            scale_stripped_joints: source_clip.scale_stripped_joints.clone(),
+        }
+    }
+
+    /// Maps a clip authored for one skeleton into the joint index space of another, matching
+    /// joints by name, so that clips for overlapping rigs can be blended. Unlike `retarget`,
+    /// poses are copied unchanged. Target joints with no matching source joint hold their local
+    /// bind pose.
+    pub fn remap_joints(source_clip: &AnimationClip<T>, source_skeleton: &Skeleton, target_skeleton: &Skeleton) -> AnimationClip<T> {
+
+        let source_joints: Vec<Option<JointIndex>> = target_skeleton.joints.iter().map(|joint| {
+            source_skeleton.get_joint_index(&joint.name[..])
+        }).collect();
+
+        let bind_poses: Vec<T> = target_skeleton.bind_pose_local();
+
+        let samples = source_clip.samples.iter().map(|source_sample| {
+            let local_poses = source_joints.iter().zip(bind_poses.iter()).map(|(source_joint, &bind_pose)| {
+                match *source_joint {
+                    Some(source_index) => source_sample.local_poses[source_index as usize],
+                    None => bind_pose,
+                }
+            }).collect();
+            AnimationSample { local_poses }
+        }).collect();
+
+        AnimationClip {
+            samples,
+            samples_per_second: source_clip.samples_per_second,
+            sample_times: source_clip.sample_times.clone(),
+            tags: source_clip.tags.clone(),
+            loop_mode: source_clip.loop_mode,
+            events: source_clip.events.clone(),
+            scale_stripped_joints: source_clip.scale_stripped_joints.clone(),
+        }
+    }
+
+    /// Creates an `AnimationClip` from a collection of `collada::Animation`.
+    ///
+    /// # Arguments
+    ///
+    /// * `skeleton` - The `Skeleton` that the `AnimationClip` will be created for.
+    /// * `animations` - The collection of `collada::Animation`s that will be converted into an
+    ///                  `AnimationClip`, using the given `Skeleton`.
+    /// * `transform` - An offset transform to apply to the root pose of each animation sample,
+    ///                 useful for applying rotation, translation, or scaling when loading an
+    ///                 animation.
+    pub fn from_collada(skeleton: &Skeleton, animations: &Vec<collada::Animation>, transform: &Matrix4<f32>) -> AnimationClip<T> {
+        use std::f32::consts::PI;
+
+        // Z-axis is 'up' in COLLADA, so need to rotate root pose about x-axis so y-axis is 'up'
+        let rotate_on_x =
+            [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, (PI/2.0).cos(), (PI/2.0).sin(), 0.0],
+                [0.0, (-PI/2.0).sin(), (PI/2.0).cos(), 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ];
+
+        let transform = row_mat4_mul(rotate_on_x, *transform);
+
+        // Build an index of joint names to anims
+        let mut joint_animations = HashMap::new();
+        for anim in animations.iter() {
+            let joint_name = anim.target.split('/').next().unwrap();
+            joint_animations.insert(joint_name, anim);
+        }
+
+        // Assuming all ColladaAnims have the same number of samples..
+        let sample_count = animations[0].sample_times.len();
+
+        // Assuming all ColladaAnims have the same duration..
+        let duration = *animations[0].sample_times.last().unwrap();
+
+        // Assuming constant sample rate
+        let samples_per_second = sample_count as f32 / duration;
+
+        let samples = (0 .. sample_count).map(|sample_index| {
+
+            // Grab local poses for each joint from COLLADA animation if available,
+            // falling back to identity matrix
+            let local_poses: Vec<Matrix4<f32>> = skeleton.joints.iter().map(|joint| {
+                match joint_animations.get(&joint.name[..]) {
+                    Some(a) if joint.is_root() => row_mat4_mul(transform, a.sample_poses[sample_index]),
+                    Some(a) => a.sample_poses[sample_index],
+                    None => mat4_id(),
+                }
+            }).collect();
+
+            // Convert local poses to Transforms (for interpolation)
+            let local_poses: Vec<T> = local_poses.iter().map(|pose_matrix| {
+                T::from_matrix(*pose_matrix)
+            }).collect();
+
+            AnimationSample {
+                local_poses: local_poses,
+            }
+        }).collect();
+
+        AnimationClip {
+            samples_per_second: samples_per_second,
+            samples: samples,
+            sample_times: None,
+            tags: Vec::new(),
+            loop_mode: LoopMode::Loop,
+            events: Vec::new(),
+            scale_stripped_joints: Vec::new(),
+        }
+    }
+
+}
+
+/// An instance of an AnimationClip which tracks playback parameters
+pub struct ClipInstance<T: Transform> {
+    /// Shared clip reference
+    pub clip: Rc<AnimationClip<T>>,
+
+    /// Controller clock time at animation start
+    pub start_time: f32,
+
+    /// Playback rate modifier, where 1.0 is original speed. Set by blend tree synchronization.
+    pub playback_rate: f32,
+
+    /// User-set speed multiplier, applied on top of `playback_rate` and left untouched by
+    /// synchronization
+    pub user_speed: f32,
+
+    /// Speed multiplier from the SpeedNodes above the clip in its blend tree, applied on top
+    /// of `playback_rate` and `user_speed`
+    pub node_speed: f32,
+
+    /// Used to account for changes in playback rate
+    pub time_offset: f32,
+}
+
+impl<T: Transform> ClipInstance<T> {
+
+    pub fn new(clip: Rc<AnimationClip<T>>) -> ClipInstance<T> {
+        ClipInstance {
+            clip: clip,
+            start_time: 0.0,
+            playback_rate: 1.0,
+            user_speed: 1.0,
+            node_speed: 1.0,
+            time_offset: 0.0,
+        }
+    }
+
+    /// Adjust the playback rate of the clip without affecting the
+    /// value of get_local_time for a given global time.
+    pub fn set_playback_rate(&mut self, global_time: f32, new_rate: f32) {
+        if self.playback_rate != new_rate {
+            let local_time = self.get_local_time(global_time);
+            self.time_offset = local_time - (global_time - self.start_time) * new_rate * self.user_speed * self.node_speed;
+            self.playback_rate = new_rate;
+        }
+    }
+
+    /// Adjust the user speed multiplier of the clip without affecting the
+    /// value of get_local_time for a given global time.
+    pub fn set_user_speed(&mut self, global_time: f32, new_speed: f32) {
+        if self.user_speed != new_speed {
+            let local_time = self.get_local_time(global_time);
+            self.time_offset = local_time - (global_time - self.start_time) * self.playback_rate * new_speed * self.node_speed;
+            self.user_speed = new_speed;
+        }
+    }
+
+    /// Adjust the blend tree's speed multiplier for the clip without affecting the
+    /// value of get_local_time for a given global time.
+    pub fn set_node_speed(&mut self, global_time: f32, new_speed: f32) {
+        if self.node_speed != new_speed {
+            let local_time = self.get_local_time(global_time);
+            self.time_offset = local_time - (global_time - self.start_time) * self.playback_rate * self.user_speed * new_speed;
+            self.node_speed = new_speed;
+        }
+    }
+
+    /// Set the user speed multiplier so that the clip's root motion moves at the given speed,
+    /// e.g. to play a walk cycle at the character's movement speed without its feet sliding.
+    /// Clips without root motion are left unchanged.
+    pub fn match_root_motion_speed(&mut self, global_time: f32, speed: f32) {
+        let clip_speed = self.clip.root_motion_speed();
+        if clip_speed > 0.0 {
+            self.set_user_speed(global_time, speed / clip_speed);
+        }
+    }
+
+    pub fn get_pose_at_time(&self, global_time: f32, blended_poses: &mut [T]) {
         self.clip.get_pose_at_time(self.get_local_time(global_time), blended_poses);
     }
 
@@ -264,7 +1226,921 @@ impl<T: Transform> ClipInstance<T> {
         self.clip.get_duration()
     }
 
-    fn get_local_time(&self, global_time: f32) -> f32 {
-        (global_time - self.start_time) * self.playback_rate + self.time_offset
+    /// Return the local time within the clip for the given global time
+    pub fn get_local_time(&self, global_time: f32) -> f32 {
+        (global_time - self.start_time) * self.playback_rate * self.user_speed * self.node_speed + self.time_offset
+    }
+
+    /// Return the names of the clip's events crossed as the local time advances from
+    /// `previous_local_time` (exclusive) to `local_time` (inclusive), in the order they're
+    /// crossed. Events of a `Loop` clip fire once per loop, and those of a `PingPong` clip
+    /// both forwards and backwards.
+    pub fn get_events_between(&self, previous_local_time: f32, local_time: f32) -> Vec<&str> {
+
+        let mut crossed: Vec<(f32, &str)> = Vec::new();
+
+        for (event_time, name) in self.clip.events.iter() {
+
+            // Times of the event within the first period of the clip's playback
+            let (period, event_times) = match self.clip.loop_mode {
+                LoopMode::Loop => (self.clip.get_duration(), vec![*event_time]),
+                LoopMode::Clamp => (0.0, vec![*event_time]),
+                LoopMode::PingPong => {
+                    let last_time = self.clip.last_sample_time();
+                    if 0.0 < *event_time && *event_time < last_time {
+                        (2.0 * last_time, vec![*event_time, 2.0 * last_time - event_time])
+                    } else {
+                        // Crossed once at a turning point
+                        (2.0 * last_time, vec![*event_time])
+                    }
+                }
+            };
+
+            for event_time in event_times {
+                if period > 0.0 {
+                    // First occurrence of the event after the previous time
+                    let mut time = event_time + (((previous_local_time - event_time) / period).floor() + 1.0) * period;
+                    while time <= local_time {
+                        crossed.push((time, &name[..]));
+                        time += period;
+                    }
+                } else if previous_local_time < event_time && event_time <= local_time {
+                    crossed.push((event_time, &name[..]));
+                }
+            }
+        }
+
+        crossed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        crossed.into_iter().map(|(_, name)| name).collect()
+    }
+}
+
+fn load_collada_document(path: &Path) -> Result<ColladaDocument, String> {
+    ColladaDocument::from_path(path).map_err(|e| format!("{} {:?}", e, path))
+}
+
+/// Load the first skeleton in the document, checking that its joint names are unique
+fn load_skeleton(collada_document: &ColladaDocument, path: &Path) -> Result<Skeleton, String> {
+    let skeleton = match collada_document.get_skeletons() {
+        Some(ref skeletons) if !skeletons.is_empty() => Skeleton::from_collada(&skeletons[0]),
+        _ => return Err(format!("No skeleton found in {:?}", path)),
+    };
+    skeleton.validate().map_err(|names| format!("Duplicate joint names {:?} in {:?}", names, path))?;
+    Ok(skeleton)
+}
+
+/// Select the animations belonging to the named take. `animations` must be in document order,
+/// as returned by `ColladaDocument::get_animations`.
+fn select_take(collada_document: &ColladaDocument, animations: Vec<collada::Animation>, take: &str) -> Vec<collada::Animation> {
+
+    let root = &collada_document.root_element;
+    let ns = root.ns.as_ref().map(|ns| &ns[..]);
+
+    let animation_ids: Vec<&str> = match root.get_child("library_animations", ns) {
+        Some(library_animations) => library_animations.get_children("animation", ns)
+            .map(|animation| animation.get_attribute("id", None).unwrap_or(""))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let take_clip = root.get_child("library_animation_clips", ns)
+        .and_then(|library_clips| library_clips.get_children("animation_clip", ns).find(|clip| {
+            clip.get_attribute("id", None) == Some(take) || clip.get_attribute("name", None) == Some(take)
+        }));
+
+    let in_take = |id: &str| match take_clip {
+        Some(clip) => clip.get_children("instance_animation", ns)
+            .any(|instance| instance.get_attribute("url", None).map(|url| url.trim_start_matches('#')) == Some(id)),
+        None => id.starts_with(take),
+    };
+
+    animations.into_iter()
+        .zip(animation_ids)
+        .filter(|&(_, id)| in_take(id))
+        .map(|(animation, _)| animation)
+        .collect()
+}
+
+/// Remove any scale from the animations' sample poses, by normalizing the axes of each pose.
+/// Returns the names of the joints that had non-unit scale in any sample.
+fn strip_scale(animations: &mut [collada::Animation]) -> Vec<String> {
+    let mut scaled_joints = Vec::new();
+    for animation in animations.iter_mut() {
+        let mut scaled = false;
+        for pose in animation.sample_poses.iter_mut() {
+            for axis in 0 .. 3 {
+                let scale = vec3_len([pose[0][axis], pose[1][axis], pose[2][axis]]);
+                if (scale - 1.0).abs() > POSE_EPSILON && scale > 0.0 {
+                    scaled = true;
+                    for row in pose.iter_mut().take(3) {
+                        row[axis] /= scale;
+                    }
+                }
+            }
+        }
+        if scaled {
+            scaled_joints.push(animation.target.split('/').next().unwrap().to_string());
+        }
+    }
+    scaled_joints
+}
+
+#[cfg(test)]
+mod test {
+
+    use collada::document::ColladaDocument;
+    use collada;
+
+    use std::path::Path;
+    use std::rc::Rc;
+
+    use super::{AnimationClip, AnimationSample, ClipInstance, LoopMode, MirrorConfig, POSE_EPSILON,
+                load_skeleton, select_take, strip_scale};
+    use math::*;
+    use skeleton::{Joint, JointIndex, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::{Transform, QVTransform};
+
+    static EPSILON: f32 = 0.000001;
+
+    fn translation_x(x: f32) -> QVTransform {
+        let mut pose = QVTransform::identity();
+        pose.set_translation([x, 0.0, 0.0]);
+        pose
+    }
+
+    fn single_joint_clip(xs: &[f32], samples_per_second: f32) -> AnimationClip<QVTransform> {
+        AnimationClip::new(xs.iter().map(|&x| AnimationSample { local_poses: vec![translation_x(x)] }).collect(), samples_per_second)
+    }
+
+    #[test]
+    fn test_non_uniform_sample_times() {
+
+        let mut clip = single_joint_clip(&[0.0, 1.0, 2.0], 3.0);
+        clip.set_sample_times(vec![0.0, 0.1, 1.0]).unwrap();
+
+        assert!((clip.get_duration() - 1.0).abs() < EPSILON);
+
+        let mut pose = [QVTransform::identity()];
+
+        // Halfway between the second and last samples
+        clip.get_pose_at_time(0.55, &mut pose);
+        assert!((pose[0].get_translation()[0] - 1.5).abs() < EPSILON);
+
+        // Exactly on the second sample
+        clip.get_pose_at_time(0.1, &mut pose);
+        assert!((pose[0].get_translation()[0] - 1.0).abs() < EPSILON);
+
+        // Halfway between the first and second samples
+        clip.get_pose_at_time(0.05, &mut pose);
+        assert!((pose[0].get_translation()[0] - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_invalid_sample_times() {
+
+        let mut clip = single_joint_clip(&[0.0, 1.0, 2.0], 3.0);
+
+        assert!(clip.set_sample_times(vec![0.0, 1.0]).is_err());
+        assert!(clip.set_sample_times(vec![0.0, 0.0, 0.0]).is_err());
+        assert!(clip.set_sample_times(vec![0.0, 0.5, 0.5]).is_err());
+        assert!(clip.set_sample_times(vec![0.0, 0.6, 0.3]).is_err());
+        assert!(clip.set_sample_times(vec![0.0, 0.5, f32::NAN]).is_err());
+        assert_eq!(clip.sample_times, None);
+        assert!((clip.get_duration() - 1.0).abs() < EPSILON);
+
+        let mut empty_clip = single_joint_clip(&[], 3.0);
+        assert!(empty_clip.set_sample_times(Vec::new()).is_err());
+
+        let mut single_sample_clip = single_joint_clip(&[1.0], 3.0);
+        assert!(single_sample_clip.set_sample_times(vec![0.0]).is_err());
+        assert!(single_sample_clip.set_sample_times(vec![0.5]).is_ok());
+    }
+
+    #[test]
+    fn test_sample_info() {
+
+        let mut clip = single_joint_clip(&[0.0, 1.0, 2.0, 3.0], 4.0);
+
+        // On a frame
+        assert_eq!(clip.sample_info(0.5), (2, 2, 0.0));
+
+        // Between frames, including across the loop
+        let (index_1, index_2, blend_factor) = clip.sample_info(0.3125);
+        assert_eq!((index_1, index_2), (1, 2));
+        assert!((blend_factor - 0.25).abs() < EPSILON);
+
+        let (index_1, index_2, blend_factor) = clip.sample_info(1.875);
+        assert_eq!((index_1, index_2), (3, 0));
+        assert!((blend_factor - 0.5).abs() < EPSILON);
+
+        // Explicit sample times
+        clip.set_sample_times(vec![0.0, 0.1, 0.4, 1.0]).unwrap();
+        assert_eq!(clip.sample_info(0.4), (2, 2, 0.0));
+
+        let (index_1, index_2, blend_factor) = clip.sample_info(0.7);
+        assert_eq!((index_1, index_2), (2, 3));
+        assert!((blend_factor - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_clip_instance_events() {
+
+        let mut clip = AnimationClip::new((0 .. 4).map(|_| AnimationSample { local_poses: vec![mat4_id()] }).collect(), 4.0);
+        clip.events = vec![(0.5, "footstep".to_string())];
+        let instance = ClipInstance::new(Rc::new(clip));
+
+        assert_eq!(instance.get_events_between(0.0, 0.4), Vec::<&str>::new());
+        assert_eq!(instance.get_events_between(0.4, 0.6), vec!["footstep"]);
+        assert_eq!(instance.get_events_between(0.6, 1.4), Vec::<&str>::new());
+
+        // Once per loop, across the loop seam
+        assert_eq!(instance.get_events_between(0.6, 2.6), vec!["footstep", "footstep"]);
+
+        // Events at a NaN time, e.g. from a malformed asset file, are never crossed
+        let mut clip = AnimationClip::new((0 .. 4).map(|_| AnimationSample { local_poses: vec![mat4_id()] }).collect(), 4.0);
+        clip.events = vec![(f32::NAN, "bad".to_string()), (0.5, "footstep".to_string()), (0.25, "step".to_string())];
+        let instance = ClipInstance::new(Rc::new(clip));
+        assert_eq!(instance.get_events_between(0.0, 1.6), vec!["step", "footstep", "step", "footstep"]);
+
+        // Ping-pong reflects at the last sample, at 0.75s, so the event is crossed again at 1s
+        let mut clip = AnimationClip::new((0 .. 4).map(|_| AnimationSample { local_poses: vec![mat4_id()] }).collect(), 4.0);
+        clip.events = vec![(0.5, "footstep".to_string())];
+        clip.loop_mode = LoopMode::PingPong;
+        let instance = ClipInstance::new(Rc::new(clip));
+
+        assert_eq!(instance.get_events_between(0.0, 1.2), vec!["footstep", "footstep"]);
+        assert_eq!(instance.get_events_between(1.2, 1.9), Vec::<&str>::new());
+        assert_eq!(instance.get_events_between(1.9, 2.1), vec!["footstep"]);
+    }
+
+    #[test]
+    fn test_clip_instance_user_speed() {
+
+        let mut instance = ClipInstance::new(Rc::new(single_joint_clip(&[0.0, 1.0], 1.0)));
+
+        instance.set_user_speed(0.0, 1.5);
+        assert!((instance.get_local_time(1.0) - 1.5).abs() < EPSILON);
+
+        // Synchronization changes the playback rate, and the user speed still applies on top
+        instance.set_playback_rate(1.0, 2.0);
+        assert!((instance.user_speed - 1.5).abs() < EPSILON);
+        assert!((instance.get_local_time(1.0) - 1.5).abs() < EPSILON);
+        assert!((instance.get_local_time(2.0) - 4.5).abs() < EPSILON);
+
+        // Changing the user speed doesn't jump the local time
+        instance.set_user_speed(2.0, 1.0);
+        assert!((instance.get_local_time(2.0) - 4.5).abs() < EPSILON);
+        assert!((instance.get_local_time(3.0) - 6.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_match_root_motion_speed() {
+
+        // A walk covering 2m in 1s
+        let walk = Rc::new(single_joint_clip(&[0.0, 0.5, 1.0, 1.5, 2.0], 4.0));
+        assert!((walk.root_motion_speed() - 2.0).abs() < EPSILON);
+
+        let mut instance = ClipInstance::new(walk.clone());
+        instance.match_root_motion_speed(0.0, 3.0);
+        assert!((instance.user_speed - 1.5).abs() < EPSILON);
+
+        // The root now moves at 3m/s, so the feet don't slide
+        let distance = walk.root_motion_between(instance.get_local_time(0.0), instance.get_local_time(0.5));
+        assert!((distance[0] - 1.5).abs() < EPSILON);
+
+        let mut still = ClipInstance::new(Rc::new(single_joint_clip(&[1.0, 1.0], 1.0)));
+        still.match_root_motion_speed(0.0, 3.0);
+        assert!((still.user_speed - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_root_motion_reverse_across_loop() {
+
+        // Walking forward along z at 1 unit per second, and turning about y at 1 radian per second
+        let clip = AnimationClip::new((0 .. 21).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([0.0, 0.0, i as f32 / 10.0]);
+            pose.set_rotation(quaternion::axis_angle([0.0, 1.0, 0.0], i as f32 / 10.0));
+            AnimationSample { local_poses: vec![pose] }
+        }).collect(), 10.0);
+
+        let expect_motion = |previous_time: f32, time: f32, distance: f32| {
+            let translation = clip.root_motion_between(previous_time, time);
+            assert!(vec3_len(vec3_sub(translation, [0.0, 0.0, distance])) < 0.001, "{:?}", translation);
+            let rotation = clip.root_rotation_between(previous_time, time);
+            let expected_rotation = quaternion::axis_angle([0.0, 1.0, 0.0], distance);
+            assert!(quaternion_angle_between(rotation, expected_rotation) < 0.001, "{:?}", rotation);
+        };
+
+        // Playing backward across the loop boundary undoes the forward motion
+        expect_motion(2.0, 2.2, 0.1);
+        expect_motion(2.2, 2.0, -0.1);
+
+        // ... including across whole loops
+        expect_motion(2.0, 4.4, 2.2);
+        expect_motion(4.4, 2.0, -2.2);
+    }
+
+    #[test]
+    fn test_fix_root_loop_seam() {
+
+        let skeleton = Skeleton::from_joints(vec![
+            Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("spine", 0, mat4_id()),
+        ]);
+
+        // A walk with forward root motion, with a longer final step
+        let mut clip = AnimationClip::new([0.0, 1.0, 2.0, 3.2].iter().map(|&x| {
+            AnimationSample { local_poses: vec![translation_x(x), translation_x(x)] }
+        }).collect(), 4.0);
+
+        let root_x = |clip: &AnimationClip<QVTransform>, time: f32| {
+            let mut poses = [QVTransform::identity(); 2];
+            clip.get_pose_at_time(time, &mut poses);
+            poses[0].get_translation()[0]
+        };
+
+        // Without the fix, the root jumps back over the final frame
+        assert!(root_x(&clip, 0.75) - root_x(&clip, 0.99) > 3.0);
+
+        clip.fix_root_loop_seam(&skeleton);
+
+        // Now the root stays close to its start, across the seam
+        for i in 0 .. 40 {
+            let time = 0.5 + i as f32 * 0.025;
+            assert!(root_x(&clip, time).abs() < 0.25);
+            assert!((root_x(&clip, time + 0.025) - root_x(&clip, time)).abs() < 0.1);
+        }
+
+        // Non-root joints are untouched
+        assert!((clip.get_joint_pose_at_time(1, 0.75).get_translation()[0] - 3.2).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_loop_error() {
+
+        let pose = |angle: f32, x: f32| {
+            let mut pose = QVTransform::identity();
+            pose.set_rotation(quaternion::axis_angle([0.0, 1.0, 0.0], angle));
+            pose.set_translation([x, 0.0, 0.0]);
+            pose
+        };
+
+        // Joint 0 returns to where it started, joint 1 ends rotated and offset
+        let samples = vec![
+            AnimationSample { local_poses: vec![pose(0.0, 0.0), pose(0.0, 0.0)] },
+            AnimationSample { local_poses: vec![pose(1.0, 2.0), pose(0.5, 1.0)] },
+            AnimationSample { local_poses: vec![pose(0.0, 0.0), pose(1.0, 2.0)] },
+        ];
+        let clip = AnimationClip::new(samples, 1.0);
+
+        let error = clip.loop_error();
+        assert_eq!(error.len(), 2);
+        assert_eq!(error[0].0, 0);
+        assert!(error[0].1 < 0.001);
+        assert_eq!(error[1].0, 1);
+        assert!((error[1].1 - 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_mixed_difference_clip() {
+
+        let clip = |xs: [f32; 2]| AnimationClip::new(vec![AnimationSample { local_poses: vec![translation_x(xs[0]), translation_x(xs[1])] }], 1.0);
+
+        let source = clip([5.0, 7.0]);
+        let idle = clip([1.0, 1.0]);
+        let aim = clip([2.0, 3.0]);
+
+        // Joint 0 against idle, joint 1 against aim
+        let difference = AnimationClip::as_mixed_difference_clip(&source, &[&idle, &aim], &[0, 1]);
+        let difference_x = |joint: JointIndex| difference.get_joint_pose_at_time(joint, 0.0).get_translation()[0];
+        assert!((difference_x(0) - 4.0).abs() < EPSILON);
+        assert!((difference_x(1) - 4.0).abs() < EPSILON);
+
+        // Adding the difference to each joint's reference reproduces the source
+        let composed_0 = idle.get_joint_pose_at_time(0, 0.0).concat(difference.get_joint_pose_at_time(0, 0.0));
+        let composed_1 = aim.get_joint_pose_at_time(1, 0.0).concat(difference.get_joint_pose_at_time(1, 0.0));
+        assert!((composed_0.get_translation()[0] - 5.0).abs() < 0.0001);
+        assert!((composed_1.get_translation()[0] - 7.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_resample() {
+
+        let xs: Vec<f32> = (0 .. 24).map(|i| (i * i) as f32).collect();
+        let clip = single_joint_clip(&xs, 24.0);
+
+        let resampled = clip.resample(30.0);
+        assert_eq!(resampled.samples.len(), 30);
+        assert!((resampled.samples_per_second - 30.0).abs() < EPSILON);
+        assert!((resampled.get_duration() - clip.get_duration()).abs() < EPSILON);
+
+        // Frames shared by both rates, every sixth of a second
+        for i in 0 .. 6 {
+            let time = i as f32 / 6.0;
+            let original = clip.get_joint_pose_at_time(0, time).get_translation()[0];
+            let resampled = resampled.get_joint_pose_at_time(0, time).get_translation()[0];
+            assert!((original - resampled).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_sub_clip() {
+
+        let xs: Vec<f32> = (0 .. 11).map(|i| i as f32).collect();
+        let mut clip = single_joint_clip(&xs, 10.0);
+        clip.events = vec![(0.2, "early".to_string()), (0.4, "step".to_string())];
+
+        let sub_clip = clip.sub_clip(0.25, 0.75);
+        assert!((sub_clip.get_duration() - 0.5).abs() < EPSILON);
+        assert_eq!(sub_clip.events, vec![(0.4 - 0.25, "step".to_string())]);
+
+        // Interpolated boundary poses, with the original samples at 0.3 .. 0.7 in between
+        let xs: Vec<f32> = sub_clip.samples.iter().map(|sample| sample.local_poses[0].get_translation()[0]).collect();
+        let expected = [2.5, 3.0, 4.0, 5.0, 6.0, 7.0, 7.5];
+        assert_eq!(xs.len(), expected.len());
+        for (x, expected) in xs.iter().zip(expected.iter()) {
+            assert!((x - expected).abs() < EPSILON, "{:?}", xs);
+        }
+
+        // Sampling the sub-clip matches the original, offset by the start
+        for &time in [0.0, 0.1, 0.33, 0.5].iter() {
+            let mut pose = [QVTransform::identity()];
+            sub_clip.get_pose_at_time_with_mode(time, LoopMode::Clamp, &mut pose);
+            assert!((pose[0].get_translation()[0] - clip.get_joint_pose_at_time(0, time + 0.25).get_translation()[0]).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "is empty or reversed")]
+    fn test_sub_clip_reversed() {
+        let clip = single_joint_clip(&[0.0, 1.0, 2.0, 3.0], 4.0);
+        clip.sub_clip(0.75, 0.25);
+    }
+
+    #[test]
+    fn test_one_shot_holds_last_sample() {
+
+        let mut clip = single_joint_clip(&[0.0, 1.0, 2.0, 3.0], 4.0);
+        let mut pose = [QVTransform::identity()];
+
+        // Looping clips blend towards the first sample over the final frame
+        clip.get_pose_at_time(0.875, &mut pose);
+        assert!((pose[0].get_translation()[0] - 1.5).abs() < EPSILON);
+
+        clip.loop_mode = LoopMode::Clamp;
+
+        // Just before the end, stays on the last sample rather than heading back to the first
+        clip.get_pose_at_time(0.999, &mut pose);
+        assert!((pose[0].get_translation()[0] - 3.0).abs() < EPSILON);
+
+        // Interpolation between the last two samples is unaffected
+        clip.get_pose_at_time(0.625, &mut pose);
+        assert!((pose[0].get_translation()[0] - 2.5).abs() < EPSILON);
+
+        // Times beyond the clip are clamped
+        clip.get_pose_at_time(1.5, &mut pose);
+        assert!((pose[0].get_translation()[0] - 3.0).abs() < EPSILON);
+        clip.get_pose_at_time(-0.5, &mut pose);
+        assert!((pose[0].get_translation()[0] - 0.0).abs() < EPSILON);
+
+        // Likewise with explicit sample times
+        clip.set_sample_times(vec![0.1, 0.2, 0.4, 1.0]).unwrap();
+        clip.get_pose_at_time(0.05, &mut pose);
+        assert!((pose[0].get_translation()[0] - 0.0).abs() < EPSILON);
+        clip.get_pose_at_time(1.5, &mut pose);
+        assert!((pose[0].get_translation()[0] - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_compress() {
+
+        // A linear stretch followed by a curve
+        let xs: Vec<f32> = (0 .. 40).map(|i| {
+            if i < 30 { i as f32 } else { 30.0 + ((i - 30) * (i - 30)) as f32 * 0.5 }
+        }).collect();
+
+        let clip = single_joint_clip(&xs, 10.0);
+        let tolerance = 0.01;
+        let compressed = clip.compress(tolerance);
+
+        assert!(compressed.samples.len() < clip.samples.len() / 2);
+        assert!((compressed.get_duration() - clip.get_duration()).abs() < EPSILON);
+
+        let mut original_pose = [QVTransform::identity()];
+        let mut compressed_pose = [QVTransform::identity()];
+        for i in 0 .. 160 {
+            let time = i as f32 * 0.025;
+            clip.get_pose_at_time(time, &mut original_pose);
+            compressed.get_pose_at_time(time, &mut compressed_pose);
+            let error = (original_pose[0].get_translation()[0] - compressed_pose[0].get_translation()[0]).abs();
+            assert!(error <= tolerance + 0.0001, "error {} at time {}", error, time);
+        }
+    }
+
+    #[test]
+    fn test_set_duration_scales_sample_times() {
+
+        let mut clip = single_joint_clip(&[0.0, 1.0, 2.0], 3.0);
+        clip.set_sample_times(vec![0.0, 0.1, 1.0]).unwrap();
+        clip.set_duration(2.0);
+
+        let mut pose = [QVTransform::identity()];
+        clip.get_pose_at_time(1.1, &mut pose);
+        assert!((pose[0].get_translation()[0] - 1.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_sample_uniform() {
+
+        let clip = single_joint_clip(&[0.0, 1.0, 2.0, 3.0], 2.0);
+
+        let xs: Vec<f32> = clip.sample_uniform(4).iter().map(|poses| poses[0].get_translation()[0]).collect();
+        assert_eq!(xs, vec![0.0, 1.0, 2.0, 3.0]);
+
+        // The last sample blends from the last keyframe back towards the first
+        let xs: Vec<f32> = clip.sample_uniform(8).iter().map(|poses| poses[0].get_translation()[0]).collect();
+        assert_eq!(xs, vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 1.5]);
+
+        assert!(clip.sample_uniform(0).is_empty());
+    }
+
+    #[test]
+    fn test_get_joint_pose_at_time() {
+
+        let clip = AnimationClip::new((0 .. 4).map(|i| {
+            AnimationSample { local_poses: (0 .. 3).map(|j| translation_x((i * j) as f32)).collect() }
+        }).collect(), 4.0);
+
+        let mut poses = [QVTransform::identity(); 3];
+
+        for &time in [0.0, 0.3, 0.6, 0.9].iter() {
+            clip.get_pose_at_time(time, &mut poses);
+            for (joint, pose) in poses.iter().enumerate() {
+                let joint_pose = clip.get_joint_pose_at_time(joint as JointIndex, time);
+                assert_eq!(joint_pose.get_translation(), pose.get_translation());
+                assert_eq!(joint_pose.get_rotation(), pose.get_rotation());
+            }
+        }
+    }
+
+    #[test]
+    fn test_foot_skate() {
+
+        let skeleton = Skeleton::from_joints(vec![
+            Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("foot", 0, mat4_id()),
+        ]);
+
+        let clip_from = |positions: &[Vector3<f32>]| AnimationClip::new(positions.iter().map(|&p| {
+            let mut foot = QVTransform::identity();
+            foot.set_translation(p);
+            AnimationSample { local_poses: vec![QVTransform::identity(), foot] }
+        }).collect(), 30.0);
+
+        // Foot stays planted while the body moves, then lifts and steps forward
+        let clean = clip_from(&[[0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 0.5, 0.5], [0.0, 0.0, 1.0], [0.0, 0.0, 1.0]]);
+        assert!(clean.foot_skate(&skeleton, 1, 0.0) < EPSILON);
+
+        // Foot slides along the ground
+        let sliding = clip_from(&[[0.0, 0.0, 0.0], [0.0, 0.0, 0.3], [0.4, 0.0, 0.3], [0.4, 0.5, 1.0]]);
+        assert!((sliding.foot_skate(&skeleton, 1, 0.0) - 0.7).abs() < 0.0001);
+
+        // Nothing is planted relative to a lower ground plane
+        assert!(sliding.foot_skate(&skeleton, 1, -1.0) < EPSILON);
+    }
+
+    #[test]
+    fn test_animated_joints() {
+
+        let samples = (0 .. 4).map(|i| {
+            AnimationSample {
+                local_poses: vec![translation_x(1.0), translation_x(i as f32), translation_x(2.0)],
+            }
+        }).collect();
+
+        let clip = AnimationClip::new(samples, 4.0);
+
+        assert_eq!(clip.animated_joints(), vec![1]);
+    }
+
+    #[test]
+    fn test_static_joints() {
+
+        // The head (joint 2) was never keyed, and the spine (joint 1) barely moves
+        let samples = (0 .. 4).map(|i| {
+            AnimationSample {
+                local_poses: vec![translation_x(i as f32), translation_x(1.0 + 0.001 * i as f32), translation_x(2.0)],
+            }
+        }).collect();
+
+        let clip = AnimationClip::new(samples, 4.0);
+
+        assert_eq!(clip.static_joints(POSE_EPSILON), vec![2]);
+        assert_eq!(clip.static_joints(0.01), vec![1, 2]);
+    }
+
+    fn translation(x: f32) -> DualQuaternion<f32> {
+        dual_quaternion::from_rotation_and_translation(quaternion_id(), [x, 0.0, 0.0])
+    }
+
+    /// A root joint with a hand joint on each side, offset along x
+    fn two_handed_skeleton() -> Skeleton {
+        let joint = |name: &str, parent_index, x: f32| {
+            Joint::new(name, parent_index, translation(x).inverse().to_matrix())
+        };
+        Skeleton::from_joints(vec![
+            joint("root", ROOT_JOINT_PARENT_INDEX, 0.0),
+            joint("hand_r", 0, 1.0),
+            joint("hand_l", 0, -1.0),
+        ])
+    }
+
+    #[test]
+    fn test_save_and_load_clip() {
+
+        let skeleton = two_handed_skeleton();
+        let pose = |angle: f32, x: f32| QVTransform {
+            translation: [x, 2.0, -3.0],
+            scale: 1.5,
+            rotation: quaternion::axis_angle([0.0, 0.0, 1.0], angle),
+        };
+        let mut clip = AnimationClip::new((0 .. 3).map(|i| {
+            let i = i as f32;
+            AnimationSample { local_poses: vec![pose(0.1 * i, i), pose(0.2 * i, -i), pose(0.3, 0.5 * i)] }
+        }).collect(), 1.0);
+        clip.set_sample_times(vec![0.25, 1.0, 1.75]).unwrap();
+        clip.loop_mode = LoopMode::PingPong;
+
+        let mut path = ::std::env::temp_dir();
+        path.push(format!("skeletal_animation_clip_{}.bin", ::std::process::id()));
+        clip.save_to_path(&path).unwrap();
+
+        let loaded = AnimationClip::<QVTransform>::load_from_path(&path, &skeleton).unwrap();
+        assert_eq!(loaded.samples_per_second, clip.samples_per_second);
+        assert_eq!(loaded.sample_times, clip.sample_times);
+        assert_eq!(loaded.loop_mode, LoopMode::PingPong);
+        assert_eq!(loaded.samples.len(), 3);
+        for (loaded_sample, sample) in loaded.samples.iter().zip(clip.samples.iter()) {
+            for (a, b) in loaded_sample.local_poses.iter().zip(sample.local_poses.iter()) {
+                assert_eq!(a.translation, b.translation);
+                assert_eq!(a.scale, b.scale);
+                assert_eq!(a.rotation, b.rotation);
+            }
+        }
+
+        // Joint count and transform layout are checked
+        let one_joint = Skeleton::from_joints(vec![Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id())]);
+        let error = AnimationClip::<QVTransform>::load_from_path(&path, &one_joint).unwrap_err();
+        assert!(error.contains("has 3 joints, but the skeleton has 1"), "{}", error);
+        let error = AnimationClip::<Matrix4<f32>>::load_from_path(&path, &skeleton).unwrap_err();
+        assert!(error.contains("transform layout 1, expected 3"), "{}", error);
+        // Same float count, different layout
+        let error = AnimationClip::<DualQuaternion<f32>>::load_from_path(&path, &skeleton).unwrap_err();
+        assert!(error.contains("transform layout 1, expected 2"), "{}", error);
+
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_retarget_mirrored() {
+
+        let skeleton = two_handed_skeleton();
+
+        // Right hand waves, rotating a quarter turn about z
+        let wave = dual_quaternion::from_rotation_and_translation(
+            quaternion::axis_angle([0.0, 0.0, 1.0], ::std::f32::consts::PI / 2.0), [1.0, 0.0, 0.0]);
+        let clip = AnimationClip::new(vec![
+            AnimationSample { local_poses: vec![DualQuaternion::identity(), wave, translation(-1.0)] },
+        ], 1.0);
+
+        let mirror = MirrorConfig {
+            plane_normal: [1.0, 0.0, 0.0],
+            joint_pairs: vec![("hand_l".to_string(), "hand_r".to_string())],
+        };
+
+        let retargeted = AnimationClip::retarget(&clip, &skeleton, &skeleton, Some(&mirror));
+        let mut global_poses = [DualQuaternion::identity(); 3];
+        skeleton.calculate_global_poses(&retargeted.samples[0].local_poses, &mut global_poses);
+
+        let close = |a: Vector3<f32>, b: Vector3<f32>| vec3_len(vec3_sub(a, b)) < 0.0001;
+
+        // Right hand is now at rest, rebased onto its bind position
+        assert!(close(global_poses[1].transform_vector([0.0, 0.0, 0.0]), [1.0, 0.0, 0.0]));
+        assert!(close(global_poses[1].transform_vector([0.0, 1.0, 0.0]), [1.0, 1.0, 0.0]));
+
+        // Left hand waves, mirroring the original right hand wave (which swung up towards -x)
+        assert!(close(global_poses[2].transform_vector([0.0, 0.0, 0.0]), [-1.0, 0.0, 0.0]));
+        assert!(close(global_poses[2].transform_vector([0.0, 1.0, 0.0]), [0.0, 0.0, 0.0]));
+    }
+
+    /// A COLLADA document holding the given `(id, target)` animations, and optionally `(name, animation ids)` clips
+    fn takes_document(animations: &[(&str, &str)], clips: &[(&str, &[&str])]) -> ColladaDocument {
+
+        let identity = "1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1";
+        let animation_elements: String = animations.iter().map(|&(id, target)| format!(r##"
+            <animation id="{id}">
+              <source id="{id}-input"><float_array id="{id}-input-array" count="1">0</float_array></source>
+              <source id="{id}-output"><float_array id="{id}-output-array" count="16">{identity}</float_array></source>
+              <sampler id="{id}-sampler">
+                <input semantic="INPUT" source="#{id}-input"/>
+                <input semantic="OUTPUT" source="#{id}-output"/>
+              </sampler>
+              <channel source="#{id}-sampler" target="{target}"/>
+            </animation>"##, id = id, target = target, identity = identity)).collect();
+
+        let clip_elements: String = clips.iter().map(|&(name, ids)| {
+            let instances: String = ids.iter().map(|id| format!(r##"<instance_animation url="#{}"/>"##, id)).collect();
+            format!(r#"<animation_clip id="{0}-clip" name="{0}">{1}</animation_clip>"#, name, instances)
+        }).collect();
+
+        let library_clips = if clips.is_empty() {
+            String::new()
+        } else {
+            format!("<library_animation_clips>{}</library_animation_clips>", clip_elements)
+        };
+
+        ColladaDocument::from_str(&format!(r#"<?xml version="1.0" encoding="utf-8"?>
+            <COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">
+              <library_animations>{}</library_animations>
+              {}
+            </COLLADA>"#, animation_elements, library_clips)).unwrap()
+    }
+
+    fn selected_targets(document: &ColladaDocument, take: &str) -> Vec<String> {
+        select_take(document, document.get_animations().unwrap(), take).into_iter().map(|a| a.target).collect()
+    }
+
+    #[test]
+    fn test_select_take() {
+
+        let animations = [("walk_hip", "hip/transform"), ("walk_knee", "knee/transform"),
+                          ("run_hip", "hip/transform"), ("run_knee", "knee/transform")];
+
+        // Selected through the take's animation clip, by name or id
+        let document = takes_document(&animations, &[("walk", &["walk_hip", "walk_knee"]), ("run", &["run_hip"])]);
+        assert_eq!(selected_targets(&document, "run"), vec!["hip/transform"]);
+        assert_eq!(selected_targets(&document, "walk-clip"), vec!["hip/transform", "knee/transform"]);
+
+        // Without animation clips, falls back to animation id prefixes
+        let document = takes_document(&animations, &[]);
+        assert_eq!(selected_targets(&document, "run"), vec!["hip/transform", "knee/transform"]);
+        assert!(selected_targets(&document, "jump").is_empty());
+    }
+
+    /// A document with a skinned mesh whose skeleton has the given chain of joint ids
+    fn skeleton_document(joint_ids: &[&str]) -> ColladaDocument {
+
+        let identity = "1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1";
+        let inverse_bind_poses: Vec<&str> = joint_ids.iter().map(|_| identity).collect();
+        let joint_nodes: String = joint_ids.iter().map(|id| {
+            format!(r#"<node id="{}" type="JOINT"><matrix>{}</matrix>"#, id, identity)
+        }).collect();
+        let joint_node_ends: String = joint_ids.iter().map(|_| "</node>").collect();
+
+        ColladaDocument::from_str(&format!(r##"<?xml version="1.0" encoding="utf-8"?>
+            <COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">
+              <library_controllers>
+                <controller id="skin" name="armature">
+                  <skin source="#mesh">
+                    <source id="joints"><Name_array count="{count}">{names}</Name_array></source>
+                    <source id="poses"><float_array count="{pose_count}">{poses}</float_array></source>
+                    <source id="weights"><float_array count="1">1</float_array></source>
+                    <joints>
+                      <input semantic="JOINT" source="#joints"/>
+                      <input semantic="INV_BIND_MATRIX" source="#poses"/>
+                    </joints>
+                    <vertex_weights count="1">
+                      <input semantic="JOINT" source="#joints" offset="0"/>
+                      <input semantic="WEIGHT" source="#weights" offset="1"/>
+                      <vcount>1</vcount>
+                      <v>0 0</v>
+                    </vertex_weights>
+                  </skin>
+                </controller>
+              </library_controllers>
+              <library_visual_scenes>
+                <visual_scene id="scene">
+                  {nodes}{node_ends}
+                  <node id="body"><instance_controller url="#skin"><skeleton>#{root}</skeleton></instance_controller></node>
+                </visual_scene>
+              </library_visual_scenes>
+            </COLLADA>"##,
+            count = joint_ids.len(), names = joint_ids.join(" "),
+            pose_count = joint_ids.len() * 16, poses = inverse_bind_poses.join(" "),
+            nodes = joint_nodes, node_ends = joint_node_ends, root = joint_ids[0])).unwrap()
+    }
+
+    #[test]
+    fn test_load_skeleton() {
+
+        let path = Path::new("character.dae");
+
+        let skeleton = load_skeleton(&skeleton_document(&["hip", "spine", "neck"]), path).unwrap();
+        assert_eq!(skeleton.joints.len(), 3);
+
+        let error = load_skeleton(&skeleton_document(&["hip", "spine", "spine"]), path).unwrap_err();
+        assert!(error.contains("Duplicate joint names [\"spine\"]"), "{}", error);
+    }
+
+    #[test]
+    fn test_loop_modes() {
+
+        // Samples at x = 0, 1, 2, 3 over 4 seconds
+        let clip = AnimationClip::new((0 .. 4).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([i as f32, 0.0, 0.0]);
+            AnimationSample { local_poses: vec![pose] }
+        }).collect(), 1.0);
+
+        let x = |time: f32, loop_mode: LoopMode| {
+            let mut poses = [QVTransform::identity()];
+            clip.get_pose_at_time_with_mode(time, loop_mode, &mut poses);
+            poses[0].get_translation()[0]
+        };
+
+        // Looping blends back to the first sample over the final frame, and wraps
+        assert_eq!(x(3.5, LoopMode::Loop), 1.5);
+        assert_eq!(x(5.0, LoopMode::Loop), 1.0);
+
+        // Clamping holds the last sample
+        assert_eq!(x(3.5, LoopMode::Clamp), 3.0);
+        assert_eq!(x(10.0, LoopMode::Clamp), 3.0);
+        assert_eq!(x(-1.0, LoopMode::Clamp), 0.0);
+
+        // Ping-pong reflects at the last sample, and again at the first
+        assert_eq!(x(2.5, LoopMode::PingPong), 2.5);
+        assert_eq!(x(4.0, LoopMode::PingPong), 2.0);
+        assert_eq!(x(7.0, LoopMode::PingPong), 1.0);
+
+        // The existing method loops by default
+        let mut poses = [QVTransform::identity()];
+        clip.get_pose_at_time(3.5, &mut poses);
+        assert_eq!(poses[0].get_translation()[0], 1.5);
+        assert_eq!(clip.loop_mode, LoopMode::Loop);
+    }
+
+    #[test]
+    fn test_remap_joints() {
+
+        let translated = |x: f32| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([x, 0.0, 0.0]);
+            pose
+        };
+
+        let base_skeleton = Skeleton::from_joints(vec![
+            Joint::new("upper_arm", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("hand", 0, mat4_id()),
+        ]);
+
+        let mut twist_bind_pose = mat4_id();
+        twist_bind_pose.set_translation([0.0, -1.0, 0.0]);
+        let twist_skeleton = Skeleton::from_joints(vec![
+            Joint::new("upper_arm", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("twist", 0, twist_bind_pose),
+            Joint::new("hand", 0, mat4_id()),
+        ]);
+
+        let base_clip = AnimationClip::new(vec![AnimationSample { local_poses: vec![translated(1.0), translated(2.0)] }], 1.0);
+        let twist_clip = AnimationClip::new(vec![AnimationSample { local_poses: vec![translated(3.0), translated(5.0), translated(4.0)] }], 1.0);
+
+        let remapped = AnimationClip::remap_joints(&base_clip, &base_skeleton, &twist_skeleton);
+        let remapped_pose = |joint| remapped.get_joint_pose_at_time(joint, 0.0).get_translation();
+        assert_eq!(remapped_pose(0), [1.0, 0.0, 0.0]);
+        assert_eq!(remapped_pose(1), [0.0, 1.0, 0.0]);
+        assert_eq!(remapped_pose(2), [2.0, 0.0, 0.0]);
+
+        // Blending the clips, the twist bone blends from its bind pose
+        let mut base_poses = [QVTransform::identity(); 3];
+        let mut twist_poses = [QVTransform::identity(); 3];
+        remapped.get_pose_at_time(0.0, &mut base_poses);
+        twist_clip.get_pose_at_time(0.0, &mut twist_poses);
+        let blended: Vec<Vector3<f32>> = base_poses.iter().zip(twist_poses.iter()).map(|(a, b)| a.lerp(*b, 0.5).get_translation()).collect();
+        assert_eq!(blended, vec![[2.0, 0.0, 0.0], [2.5, 0.5, 0.0], [3.0, 0.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_strip_scale() {
+
+        let pose = |scale: f32| {
+            let mut pose = mat4_id();
+            pose[0][0] = scale;
+            pose[1][1] = scale;
+            pose[2][2] = scale;
+            pose[0][3] = 2.0;
+            pose
+        };
+
+        let mut animations = vec![
+            collada::Animation { target: "hip/transform".to_string(), sample_times: vec![0.0, 1.0], sample_poses: vec![pose(1.0), pose(1.0)] },
+            collada::Animation { target: "knee/transform".to_string(), sample_times: vec![0.0, 1.0], sample_poses: vec![pose(1.0), pose(1.5)] },
+        ];
+
+        assert_eq!(strip_scale(&mut animations), vec!["knee".to_string()]);
+        assert_eq!(animations[1].sample_poses[1], pose(1.0));
+
+        // Nothing left to strip
+        assert!(strip_scale(&mut animations).is_empty());
     }
 }
@@ -1,12 +1,18 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::time::Duration;
 
+use gfx;
+use gfx_debug_draw;
 use rustc_serialize::{Decodable, Decoder};
+use interpolation;
 
-use animation::{AnimationClip, ClipInstance};
+use animation::{AnimationClip, AnimationSample, ClipInstance};
 use skeleton::{Skeleton, JointIndex};
+use pose::PoseBuffer;
 
-use transform::Transform;
+use transform::{Transform, TranslationSpace};
 use math::*;
 
 /// Identifier for an AnimationClip within a BlendTreeNodeDef
@@ -15,13 +21,330 @@ pub type ClipId = String;
 /// Identifier for animation controller parameter, within a LerpNode
 pub type ParamId = String;
 
+/// Default bone length below which an IKNode treats its chain as degenerate, and leaves its
+/// input pose unchanged
+pub const DEFAULT_IK_BONE_LENGTH_EPSILON: f32 = 0.0001;
+
+/// Default direction, in the local space of an IKNode's root joint, in which the middle joint
+/// bends when neither the bend direction parameters nor a bent input pose give one
+pub const DEFAULT_IK_BEND_AXIS: Vector3<f32> = [0.0, 0.0, 1.0];
+
+/// Default local axis about which a TwistNode measures its source joint's roll: a bone's length,
+/// for skeletons whose bones extend along +Y
+pub const DEFAULT_TWIST_AXIS: Vector3<f32> = [0.0, 1.0, 0.0];
+
+/// Default local axis that a LookAtNode points at its target. Matches the skeleton's debug
+/// drawing, where bones extend along +Y.
+pub const DEFAULT_LOOK_AT_AXIS: Vector3<f32> = [0.0, 1.0, 0.0];
+
+/// Deepest nesting of nodes that `AnimBlendTree::from_def` accepts, as building (and
+/// evaluating) a tree recurses once per level
+pub const DEFAULT_MAX_BLEND_TREE_DEPTH: usize = 64;
+
 /// Definition of a blend tree, used by AnimationController to construct an AnimBlendTree
 #[derive(Debug, Clone)]
 pub enum BlendTreeNodeDef {
-    LerpNode(Box<BlendTreeNodeDef>, Box<BlendTreeNodeDef>, ParamId),
-    AdditiveNode(Box<BlendTreeNodeDef>, Box<BlendTreeNodeDef>, ParamId),
-    IKNode(Box<BlendTreeNodeDef>, String, ParamId, ParamId, ParamId, ParamId, ParamId, ParamId, ParamId),
+    LerpNode(Box<BlendTreeNodeDef>, Box<BlendTreeNodeDef>, ParamId, BlendChannels, TranslationSpace),
+    /// Base and additive inputs, blend parameter, channels, falloff map, and an optional cull
+    /// threshold: the estimated contribution (blend weight times the largest matrix component
+    /// delta of the additive input, see `AnimationClip::max_pose_delta`) below which the
+    /// additive input is skipped. Without a threshold, the additive input is always applied.
+    AdditiveNode(Box<BlendTreeNodeDef>, Box<BlendTreeNodeDef>, ParamId, BlendChannels, Vec<JointWeightDef>, Option<f32>),
+    IKNode(Box<BlendTreeNodeDef>, Box<IKNodeDef>),
     ClipNode(ClipId),
+    ClipIndexNode(usize),
+    HashSelectNode(Vec<BlendTreeNodeDef>, ParamId),
+    /// Input, source joint, distributors, and the local axis of the source joint's roll, read
+    /// from the optional "axis" field in JSON, or `DEFAULT_TWIST_AXIS` without one
+    TwistNode(Box<BlendTreeNodeDef>, String, Vec<TwistDistributorDef>, Vector3<f32>),
+    MaskNode(Box<BlendTreeNodeDef>, Box<BlendTreeNodeDef>, Vec<String>, ParamId),
+    LookAtNode(Box<BlendTreeNodeDef>, Box<LookAtNodeDef>),
+    BlendSpace1DNode(Vec<(f32, Box<BlendTreeNodeDef>)>, ParamId),
+    SpeedNode(Box<BlendTreeNodeDef>, ParamId),
+}
+
+/// Definition of an IKNode, which solves the two-bone chain ending at the effector joint (e.g.
+/// shoulder, elbow and hand) to reach a model-space target
+#[derive(Debug, Clone, PartialEq)]
+pub struct IKNodeDef {
+    /// Name of the joint at the end of the chain
+    pub effector: String,
+
+    /// Parameter for the weight of the solved pose over the input pose
+    pub blend_param: ParamId,
+
+    /// Parameters for the x, y and z of the model-space target position
+    pub target: [ParamId; 3],
+
+    /// Parameters for the x, y and z of the model-space direction in which the middle joint
+    /// bends, or all zero to keep the input pose's bend
+    pub bend: [ParamId; 3],
+
+    /// Bone length below which the chain is treated as degenerate, and left unchanged.
+    /// Overrides `DEFAULT_IK_BONE_LENGTH_EPSILON`, e.g. for skeletons in centimetres.
+    pub bone_length_epsilon: f32,
+
+    /// Time in seconds over which the applied blend weight follows the blend parameter, or zero
+    /// to apply it immediately
+    pub blend_ramp_time: f32,
+
+    /// Whether to keep the effector's model-space orientation from the input pose
+    pub preserve_effector_orientation: bool,
+
+    /// Time constant in seconds with which the applied target follows the target parameters, or
+    /// zero to use them immediately
+    pub target_smoothing_time: f32,
+
+    /// Bend direction in the root joint's local space, for when neither the bend parameters nor
+    /// the input pose give one. Overrides `DEFAULT_IK_BEND_AXIS` for rigs whose joints bend
+    /// about a different axis.
+    pub bend_axis: Vector3<f32>,
+}
+
+impl IKNodeDef {
+
+    /// Create an IKNode definition with the given effector and parameters, and defaults for the
+    /// rest, as when they're omitted from JSON
+    pub fn new(effector: &str, blend_param: &str, target: [&str; 3], bend: [&str; 3]) -> IKNodeDef {
+        IKNodeDef {
+            effector: effector.to_string(),
+            blend_param: blend_param.to_string(),
+            target: [target[0].to_string(), target[1].to_string(), target[2].to_string()],
+            bend: [bend[0].to_string(), bend[1].to_string(), bend[2].to_string()],
+            bone_length_epsilon: DEFAULT_IK_BONE_LENGTH_EPSILON,
+            blend_ramp_time: 0.0,
+            preserve_effector_orientation: false,
+            target_smoothing_time: 0.0,
+            bend_axis: DEFAULT_IK_BEND_AXIS,
+        }
+    }
+}
+
+/// Definition of a LookAtNode, which rotates a single joint (e.g. the head) to point at a
+/// model-space target
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookAtNodeDef {
+    /// Name of the joint to rotate
+    pub joint: String,
+
+    /// Parameter for the weight of the aimed pose over the input pose
+    pub blend_param: ParamId,
+
+    /// Parameters for the x, y and z of the model-space target position
+    pub target: [ParamId; 3],
+
+    /// Local axis of the joint to point at the target, overriding `DEFAULT_LOOK_AT_AXIS`, e.g.
+    /// +Z for a head whose face looks along it
+    pub axis: Vector3<f32>,
+}
+
+impl LookAtNodeDef {
+
+    /// Create a LookAtNode definition with the given joint and parameters, and the default
+    /// axis, as when it's omitted from JSON
+    pub fn new(joint: &str, blend_param: &str, target: [&str; 3]) -> LookAtNodeDef {
+        LookAtNodeDef {
+            joint: joint.to_string(),
+            blend_param: blend_param.to_string(),
+            target: [target[0].to_string(), target[1].to_string(), target[2].to_string()],
+            axis: DEFAULT_LOOK_AT_AXIS,
+        }
+    }
+}
+
+/// A joint's weight (in [0, 1]) in an AdditiveNode's falloff map, multiplied into the node's
+/// blend weight for that joint, e.g. to feather a recoil from the shoulder down to the hand
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct JointWeightDef {
+    pub joint: String,
+    pub weight: f32,
+}
+
+/// A joint receiving a fraction of a TwistNode's source joint roll, e.g. a forearm twist bone
+#[derive(Debug, Clone, RustcDecodable)]
+pub struct TwistDistributorDef {
+    pub joint: String,
+    pub fraction: f32,
+}
+
+impl BlendTreeNodeDef {
+
+    /// Return the names of all controller parameters referenced by this node and its inputs
+    pub fn referenced_params(&self) -> Vec<&str> {
+        match *self {
+            BlendTreeNodeDef::LerpNode(ref input_1, ref input_2, ref param, _, _) |
+            BlendTreeNodeDef::AdditiveNode(ref input_1, ref input_2, ref param, _, _, _) |
+            BlendTreeNodeDef::MaskNode(ref input_1, ref input_2, _, ref param) => {
+                let mut params = vec![&param[..]];
+                params.extend(input_1.referenced_params());
+                params.extend(input_2.referenced_params());
+                params
+            }
+            BlendTreeNodeDef::IKNode(ref input, ref ik) => {
+                let mut params = vec![&ik.blend_param[..]];
+                params.extend(ik.target.iter().chain(ik.bend.iter()).map(|param| &param[..]));
+                params.extend(input.referenced_params());
+                params
+            }
+            BlendTreeNodeDef::LookAtNode(ref input, ref look_at) => {
+                let mut params: Vec<&str> = look_at.target.iter().map(|param| &param[..]).collect();
+                params.push(&look_at.blend_param[..]);
+                params.extend(input.referenced_params());
+                params
+            }
+            BlendTreeNodeDef::TwistNode(ref input, _, _, _) => input.referenced_params(),
+            BlendTreeNodeDef::SpeedNode(ref input, ref param) => {
+                let mut params = vec![&param[..]];
+                params.extend(input.referenced_params());
+                params
+            }
+            BlendTreeNodeDef::ClipNode(_) | BlendTreeNodeDef::ClipIndexNode(_) => Vec::new(),
+            BlendTreeNodeDef::HashSelectNode(ref inputs, ref param) => {
+                let mut params = vec![&param[..]];
+                for input in inputs.iter() {
+                    params.extend(input.referenced_params());
+                }
+                params
+            }
+            BlendTreeNodeDef::BlendSpace1DNode(ref inputs, ref param) => {
+                let mut params = vec![&param[..]];
+                for (_, input) in inputs.iter() {
+                    params.extend(input.referenced_params());
+                }
+                params
+            }
+        }
+    }
+
+    /// Return the names of all clips referenced by this node and its inputs. Clips referenced
+    /// by index into a clip table are not included.
+    pub fn referenced_clips(&self) -> Vec<&str> {
+        match *self {
+            BlendTreeNodeDef::LerpNode(ref input_1, ref input_2, ..) |
+            BlendTreeNodeDef::AdditiveNode(ref input_1, ref input_2, ..) |
+            BlendTreeNodeDef::MaskNode(ref input_1, ref input_2, ..) => {
+                let mut clips = input_1.referenced_clips();
+                clips.extend(input_2.referenced_clips());
+                clips
+            }
+            BlendTreeNodeDef::IKNode(ref input, ..) |
+            BlendTreeNodeDef::TwistNode(ref input, ..) |
+            BlendTreeNodeDef::LookAtNode(ref input, ..) |
+            BlendTreeNodeDef::SpeedNode(ref input, _) => input.referenced_clips(),
+            BlendTreeNodeDef::ClipNode(ref clip_id) => vec![&clip_id[..]],
+            BlendTreeNodeDef::ClipIndexNode(_) => Vec::new(),
+            BlendTreeNodeDef::HashSelectNode(ref inputs, _) => {
+                inputs.iter().flat_map(|input| input.referenced_clips()).collect()
+            }
+            BlendTreeNodeDef::BlendSpace1DNode(ref inputs, _) => {
+                inputs.iter().flat_map(|(_, input)| input.referenced_clips()).collect()
+            }
+        }
+    }
+
+    /// Return whether this node or any of its inputs references a clip by index into a clip
+    /// table, i.e. is a `ClipIndexNode`
+    pub fn uses_clip_table(&self) -> bool {
+        match *self {
+            BlendTreeNodeDef::LerpNode(ref input_1, ref input_2, ..) |
+            BlendTreeNodeDef::AdditiveNode(ref input_1, ref input_2, ..) |
+            BlendTreeNodeDef::MaskNode(ref input_1, ref input_2, ..) => {
+                input_1.uses_clip_table() || input_2.uses_clip_table()
+            }
+            BlendTreeNodeDef::IKNode(ref input, ..) |
+            BlendTreeNodeDef::TwistNode(ref input, ..) |
+            BlendTreeNodeDef::LookAtNode(ref input, ..) |
+            BlendTreeNodeDef::SpeedNode(ref input, _) => input.uses_clip_table(),
+            BlendTreeNodeDef::ClipNode(_) => false,
+            BlendTreeNodeDef::ClipIndexNode(_) => true,
+            BlendTreeNodeDef::HashSelectNode(ref inputs, _) => {
+                inputs.iter().any(|input| input.uses_clip_table())
+            }
+            BlendTreeNodeDef::BlendSpace1DNode(ref inputs, _) => {
+                inputs.iter().any(|(_, input)| input.uses_clip_table())
+            }
+        }
+    }
+
+    /// Check that no path from this node to a leaf is more than `max_depth` nodes long. Only
+    /// recurses as deep as `max_depth`, so is safe on arbitrarily deep definitions.
+    pub fn check_depth(&self, max_depth: usize) -> Result<(), String> {
+        if max_depth == 0 {
+            return Err("Blend tree exceeds maximum depth".to_string());
+        }
+        match *self {
+            BlendTreeNodeDef::LerpNode(ref input_1, ref input_2, _, _, _) |
+            BlendTreeNodeDef::AdditiveNode(ref input_1, ref input_2, _, _, _, _) |
+            BlendTreeNodeDef::MaskNode(ref input_1, ref input_2, _, _) => {
+                input_1.check_depth(max_depth - 1)?;
+                input_2.check_depth(max_depth - 1)
+            }
+            BlendTreeNodeDef::IKNode(ref input, ..) |
+            BlendTreeNodeDef::TwistNode(ref input, ..) |
+            BlendTreeNodeDef::LookAtNode(ref input, ..) |
+            BlendTreeNodeDef::SpeedNode(ref input, _) => input.check_depth(max_depth - 1),
+            BlendTreeNodeDef::ClipNode(_) | BlendTreeNodeDef::ClipIndexNode(_) => Ok(()),
+            BlendTreeNodeDef::HashSelectNode(ref inputs, _) => {
+                inputs.iter().try_for_each(|input| input.check_depth(max_depth - 1))
+            }
+            BlendTreeNodeDef::BlendSpace1DNode(ref inputs, _) => {
+                inputs.iter().try_for_each(|(_, input)| input.check_depth(max_depth - 1))
+            }
+        }
+    }
+
+    /// Rename every reference to the controller parameter `old` in this node and its inputs
+    /// as `new`
+    pub fn rename_param(&mut self, old: &str, new: &str) {
+        let rename = |param: &mut ParamId| {
+            if param == old {
+                *param = new.to_string();
+            }
+        };
+        match *self {
+            BlendTreeNodeDef::LerpNode(ref mut input_1, ref mut input_2, ref mut param, _, _) |
+            BlendTreeNodeDef::AdditiveNode(ref mut input_1, ref mut input_2, ref mut param, _, _, _) |
+            BlendTreeNodeDef::MaskNode(ref mut input_1, ref mut input_2, _, ref mut param) => {
+                rename(param);
+                input_1.rename_param(old, new);
+                input_2.rename_param(old, new);
+            }
+            BlendTreeNodeDef::IKNode(ref mut input, ref mut ik) => {
+                let ik = &mut **ik;
+                rename(&mut ik.blend_param);
+                for param in ik.target.iter_mut().chain(ik.bend.iter_mut()) {
+                    rename(param);
+                }
+                input.rename_param(old, new);
+            }
+            BlendTreeNodeDef::LookAtNode(ref mut input, ref mut look_at) => {
+                let look_at = &mut **look_at;
+                for param in look_at.target.iter_mut() {
+                    rename(param);
+                }
+                rename(&mut look_at.blend_param);
+                input.rename_param(old, new);
+            }
+            BlendTreeNodeDef::TwistNode(ref mut input, _, _, _) => input.rename_param(old, new),
+            BlendTreeNodeDef::SpeedNode(ref mut input, ref mut param) => {
+                rename(param);
+                input.rename_param(old, new);
+            }
+            BlendTreeNodeDef::ClipNode(_) | BlendTreeNodeDef::ClipIndexNode(_) => (),
+            BlendTreeNodeDef::HashSelectNode(ref mut inputs, ref mut param) => {
+                rename(param);
+                for input in inputs.iter_mut() {
+                    input.rename_param(old, new);
+                }
+            }
+            BlendTreeNodeDef::BlendSpace1DNode(ref mut inputs, ref mut param) => {
+                rename(param);
+                for (_, input) in inputs.iter_mut() {
+                    input.rename_param(old, new);
+                }
+            }
+        }
+    }
 }
 
 impl Decodable for BlendTreeNodeDef {
@@ -44,7 +367,15 @@ impl Decodable for BlendTreeNodeDef {
 
                     let blend_param_name = decoder.read_struct_field("param", 0, |decoder| { Ok(decoder.read_str()?) })?;
 
-                    Ok(BlendTreeNodeDef::LerpNode(Box::new(input_1), Box::new(input_2), blend_param_name))
+                    let channels: Option<BlendChannels> = decoder.read_struct_field("channels", 0, Decodable::decode)?;
+
+                    let translation_space: Option<TranslationSpace> = decoder.read_struct_field("translation_space", 0, Decodable::decode)?;
+
+                    Ok(BlendTreeNodeDef::LerpNode(Box::new(input_1),
+                                                  Box::new(input_2),
+                                                  blend_param_name,
+                                                  channels.unwrap_or(BlendChannels::All),
+                                                  translation_space.unwrap_or(TranslationSpace::Parent)))
 
                 },
                 "AdditiveNode" => {
@@ -60,47 +391,223 @@ impl Decodable for BlendTreeNodeDef {
 
                     let blend_param_name = decoder.read_struct_field("param", 0, |decoder| { Ok(decoder.read_str()?) })?;
 
-                    Ok(BlendTreeNodeDef::AdditiveNode(Box::new(input_1), Box::new(input_2), blend_param_name))
+                    let channels: Option<BlendChannels> = decoder.read_struct_field("channels", 0, Decodable::decode)?;
+
+                    let falloff: Option<Vec<JointWeightDef>> = decoder.read_struct_field("falloff", 0, Decodable::decode)?;
+
+                    let cull_threshold: Option<f32> = decoder.read_struct_field("cull_threshold", 0, Decodable::decode)?;
+
+                    Ok(BlendTreeNodeDef::AdditiveNode(Box::new(input_1), Box::new(input_2), blend_param_name, channels.unwrap_or(BlendChannels::All), falloff.unwrap_or_default(), cull_threshold))
 
                 },
                 "IKNode" => {
 
                     let input = decoder.read_struct_field("input", 0, Decodable::decode)?;
 
-                    let effector_name = decoder.read_struct_field("effector", 0, |decoder| { Ok(decoder.read_str()?) })?;
+                    let effector_name = decoder.read_struct_field("effector", 0, |decoder| decoder.read_str())?;
+
+                    let blend_param_name = decoder.read_struct_field("blend_param", 0, |decoder| decoder.read_str())?;
+
+                    let target_x_name = decoder.read_struct_field("target_x_param", 0, |decoder| decoder.read_str())?;
+                    let target_y_name = decoder.read_struct_field("target_y_param", 0, |decoder| decoder.read_str())?;
+                    let target_z_name = decoder.read_struct_field("target_z_param", 0, |decoder| decoder.read_str())?;
+
+                    let bend_x_name = decoder.read_struct_field("bend_x_param", 0, |decoder| decoder.read_str())?;
+                    let bend_y_name = decoder.read_struct_field("bend_y_param", 0, |decoder| decoder.read_str())?;
+                    let bend_z_name = decoder.read_struct_field("bend_z_param", 0, |decoder| decoder.read_str())?;
+
+                    let mut ik = IKNodeDef::new(&effector_name, &blend_param_name,
+                                                [&target_x_name, &target_y_name, &target_z_name],
+                                                [&bend_x_name, &bend_y_name, &bend_z_name]);
 
-                    let blend_param_name = decoder.read_struct_field("blend_param", 0, |decoder| { Ok(decoder.read_str()?) })?;
+                    if let Some(bone_length_epsilon) = decoder.read_struct_field("bone_length_epsilon", 0, Decodable::decode)? {
+                        ik.bone_length_epsilon = bone_length_epsilon;
+                    }
 
-                    let target_x_name = decoder.read_struct_field("target_x_param", 0, |decoder| { Ok(decoder.read_str()?) })?;
-                    let target_y_name = decoder.read_struct_field("target_y_param", 0, |decoder| { Ok(decoder.read_str()?) })?;
-                    let target_z_name = decoder.read_struct_field("target_z_param", 0, |decoder| { Ok(decoder.read_str()?) })?;
+                    if let Some(blend_ramp_time) = decoder.read_struct_field("blend_ramp_time", 0, Decodable::decode)? {
+                        ik.blend_ramp_time = blend_ramp_time;
+                    }
 
-                    let bend_x_name = decoder.read_struct_field("bend_x_param", 0, |decoder| { Ok(decoder.read_str()?) })?;
-                    let bend_y_name = decoder.read_struct_field("bend_y_param", 0, |decoder| { Ok(decoder.read_str()?) })?;
-                    let bend_z_name = decoder.read_struct_field("bend_z_param", 0, |decoder| { Ok(decoder.read_str()?) })?;
+                    if let Some(preserve_effector_orientation) = decoder.read_struct_field("preserve_effector_orientation", 0, Decodable::decode)? {
+                        ik.preserve_effector_orientation = preserve_effector_orientation;
+                    }
 
+                    if let Some(target_smoothing_time) = decoder.read_struct_field("target_smoothing_time", 0, Decodable::decode)? {
+                        ik.target_smoothing_time = target_smoothing_time;
+                    }
 
-                    Ok(BlendTreeNodeDef::IKNode(Box::new(input),
-                                                effector_name,
-                                                blend_param_name,
-                                                target_x_name,
-                                                target_y_name,
-                                                target_z_name,
-                                                bend_x_name,
-                                                bend_y_name,
-                                                bend_z_name))
+                    if let Some(bend_axis) = decoder.read_struct_field("bend_axis", 0, Decodable::decode)? {
+                        ik.bend_axis = bend_axis;
+                    }
+
+                    Ok(BlendTreeNodeDef::IKNode(Box::new(input), Box::new(ik)))
 
                 },
                 "ClipNode" => {
+                    // Clips may be referenced by index into a clip table, instead of by name
+                    let clip_index: Option<usize> = decoder.read_struct_field("clip_index", 0, Decodable::decode)?;
+                    if let Some(clip_index) = clip_index {
+                        return Ok(BlendTreeNodeDef::ClipIndexNode(clip_index));
+                    }
                     let clip_source = decoder.read_struct_field("clip_source", 0, |decoder| { Ok(decoder.read_str()?) })?;
                     Ok(BlendTreeNodeDef::ClipNode(clip_source))
                 }
-                _ => panic!("Unexpected blend node type")
+                "HashSelectNode" => {
+
+                    let inputs = decoder.read_struct_field("inputs", 0, |decoder| {
+                        decoder.read_seq(|decoder, len| {
+                            let mut inputs = Vec::new();
+                            for i in 0 .. len {
+                                inputs.push(decoder.read_seq_elt(i, Decodable::decode)?);
+                            }
+                            Ok(inputs)
+                        })
+                    })?;
+
+                    let select_param_name = decoder.read_struct_field("param", 0, |decoder| decoder.read_str())?;
+
+                    Ok(BlendTreeNodeDef::HashSelectNode(inputs, select_param_name))
+                }
+                "TwistNode" => {
+
+                    let input = decoder.read_struct_field("input", 0, Decodable::decode)?;
+
+                    let source_name = decoder.read_struct_field("source", 0, |decoder| decoder.read_str())?;
+
+                    let distributors = decoder.read_struct_field("distributors", 0, Decodable::decode)?;
+
+                    let axis: Option<Vector3<f32>> = decoder.read_struct_field("axis", 0, Decodable::decode)?;
+
+                    Ok(BlendTreeNodeDef::TwistNode(Box::new(input), source_name, distributors, axis.unwrap_or(DEFAULT_TWIST_AXIS)))
+                }
+                "LookAtNode" => {
+
+                    let input = decoder.read_struct_field("input", 0, Decodable::decode)?;
+
+                    let joint_name = decoder.read_struct_field("joint", 0, |decoder| decoder.read_str())?;
+
+                    let target_x_name = decoder.read_struct_field("target_x_param", 0, |decoder| decoder.read_str())?;
+                    let target_y_name = decoder.read_struct_field("target_y_param", 0, |decoder| decoder.read_str())?;
+                    let target_z_name = decoder.read_struct_field("target_z_param", 0, |decoder| decoder.read_str())?;
+
+                    let blend_param_name = decoder.read_struct_field("blend_param", 0, |decoder| decoder.read_str())?;
+
+                    let mut look_at = LookAtNodeDef::new(&joint_name, &blend_param_name, [&target_x_name, &target_y_name, &target_z_name]);
+
+                    if let Some(axis) = decoder.read_struct_field("axis", 0, Decodable::decode)? {
+                        look_at.axis = axis;
+                    }
+
+                    Ok(BlendTreeNodeDef::LookAtNode(Box::new(input), Box::new(look_at)))
+                }
+                "MaskNode" => {
+
+                    let (input_1, input_2) = decoder.read_struct_field("inputs", 0, |decoder| {
+                        decoder.read_seq(|decoder, _len| {
+                            Ok((
+                                decoder.read_seq_elt(0, Decodable::decode)?,
+                                decoder.read_seq_elt(1, Decodable::decode)?
+                            ))
+                        })
+                    })?;
+
+                    let joints = decoder.read_struct_field("joints", 0, Decodable::decode)?;
+
+                    let blend_param_name = decoder.read_struct_field("param", 0, |decoder| decoder.read_str())?;
+
+                    Ok(BlendTreeNodeDef::MaskNode(Box::new(input_1), Box::new(input_2), joints, blend_param_name))
+                }
+                "BlendSpace1DNode" => {
+
+                    let inputs = decoder.read_struct_field("inputs", 0, |decoder| {
+                        decoder.read_seq(|decoder, len| {
+                            let mut inputs = Vec::new();
+                            for i in 0 .. len {
+                                inputs.push(decoder.read_seq_elt(i, |decoder| {
+                                    decoder.read_struct("input", 0, |decoder| {
+                                        let position = decoder.read_struct_field("position", 0, Decodable::decode)?;
+                                        let input = decoder.read_struct_field("input", 0, Decodable::decode)?;
+                                        Ok((position, Box::new(input)))
+                                    })
+                                })?);
+                            }
+                            Ok(inputs)
+                        })
+                    })?;
+
+                    let blend_param_name = decoder.read_struct_field("param", 0, |decoder| decoder.read_str())?;
+
+                    Ok(BlendTreeNodeDef::BlendSpace1DNode(inputs, blend_param_name))
+                }
+                "SpeedNode" => {
+
+                    let input = decoder.read_struct_field("input", 0, Decodable::decode)?;
+
+                    let speed_param_name = decoder.read_struct_field("param", 0, |decoder| decoder.read_str())?;
+
+                    Ok(BlendTreeNodeDef::SpeedNode(Box::new(input), speed_param_name))
+                }
+                _ => Err(decoder.error(&format!("unknown blend node type: {}", node_type))),
             }
         })
     }
 }
 
+/// The transform channels that a blend node applies from its blended result, where
+/// any other channels are kept from the node's first (base) input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendChannels {
+    /// Blend the full transform
+    All,
+    /// Blend only rotation, keeping translation from the base input
+    Rotation,
+    /// Blend only translation, keeping rotation from the base input
+    Translation,
+}
+
+impl BlendChannels {
+    /// Composite a blended pose onto the base pose, according to the blended channels
+    fn apply<T: Transform>(self, base_pose: T, blended_pose: T) -> T {
+        match self {
+            BlendChannels::All => blended_pose,
+            BlendChannels::Rotation => {
+                let mut pose = base_pose;
+                pose.set_rotation(blended_pose.get_rotation());
+                pose
+            }
+            BlendChannels::Translation => {
+                let mut pose = base_pose;
+                pose.set_translation(blended_pose.get_translation());
+                pose
+            }
+        }
+    }
+}
+
+impl Decodable for BlendChannels {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<BlendChannels, D::Error> {
+        match &decoder.read_str()?[..] {
+            "all" => Ok(BlendChannels::All),
+            "rotation" => Ok(BlendChannels::Rotation),
+            "translation" => Ok(BlendChannels::Translation),
+            channels => Err(decoder.error(&format!("Unknown blend channels: {}", channels))),
+        }
+    }
+}
+
+/// Counts of the work done evaluating a blend tree, for profiling
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EvalStats {
+    /// Number of AnimNodes whose output pose was evaluated, including clip nodes
+    pub nodes_evaluated: usize,
+
+    /// Number of AnimationClips sampled
+    pub clips_sampled: usize,
+
+    /// Wall-clock time taken by the evaluation, if measured
+    pub duration: Duration,
+}
+
 /// A tree of AnimNodes
 pub struct AnimBlendTree<T: Transform> {
     root_node: AnimNodeHandle,
@@ -108,7 +615,17 @@ pub struct AnimBlendTree<T: Transform> {
     additive_nodes: Vec<AdditiveAnimNode>,
     ik_nodes: Vec<IKNode>,
     clip_nodes: Vec<ClipAnimNode<T>>,
+    hash_select_nodes: Vec<HashSelectAnimNode>,
+    twist_nodes: Vec<TwistAnimNode>,
+    mask_nodes: Vec<MaskAnimNode>,
+    look_at_nodes: Vec<LookAtAnimNode>,
+    blend_space_1d_nodes: Vec<BlendSpace1DAnimNode>,
+    speed_nodes: Vec<SpeedAnimNode>,
     skeleton: Rc<Skeleton>,
+
+    /// Whether to record `eval_stats` on each `get_output_pose`
+    profiling: bool,
+    eval_stats: Cell<EvalStats>,
 }
 
 impl<T: Transform> AnimBlendTree<T> {
@@ -120,11 +637,58 @@ impl<T: Transform> AnimBlendTree<T> {
     ///
     /// * `def` - The root BlendTreeNodeDef
     /// * `animations` - A mapping from ClipIds to shared AnimationClip instances
+    ///
+    /// # Panics
+    ///
+    /// Panics if the definition is deeper than `DEFAULT_MAX_BLEND_TREE_DEPTH`, or refers to a
+    /// missing animation clip or an unknown joint. Use `from_def_with_max_depth` to get an error
+    /// instead.
     pub fn from_def(
         def: BlendTreeNodeDef,
         animations: &HashMap<ClipId, Rc<AnimationClip<T>>>,
         skeleton: Rc<Skeleton>,
     ) -> AnimBlendTree<T> {
+        AnimBlendTree::from_def_with_clip_table(def, animations, &[], skeleton)
+    }
+
+    /// Initialize a new AnimBlendTree as with `from_def`, additionally resolving any
+    /// `ClipIndexNode`s by index into `clip_table`, e.g. for baked asset pipelines
+    ///
+    /// # Arguments
+    ///
+    /// * `def` - The root BlendTreeNodeDef
+    /// * `animations` - A mapping from ClipIds to shared AnimationClip instances
+    /// * `clip_table` - Shared AnimationClip instances, indexed by `ClipIndexNode`s
+    ///
+    /// # Panics
+    ///
+    /// Panics if the definition is deeper than `DEFAULT_MAX_BLEND_TREE_DEPTH`, or refers to a
+    /// missing animation clip or an unknown joint. Use `from_def_with_max_depth` to get an error
+    /// instead.
+    pub fn from_def_with_clip_table(
+        def: BlendTreeNodeDef,
+        animations: &HashMap<ClipId, Rc<AnimationClip<T>>>,
+        clip_table: &[Rc<AnimationClip<T>>],
+        skeleton: Rc<Skeleton>,
+    ) -> AnimBlendTree<T> {
+        match AnimBlendTree::from_def_with_max_depth(def, animations, clip_table, skeleton, DEFAULT_MAX_BLEND_TREE_DEPTH) {
+            Ok(tree) => tree,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Initialize a new AnimBlendTree as with `from_def_with_clip_table`, returning an error
+    /// rather than building a definition deeper than `max_depth` nodes, or one that refers to a
+    /// missing animation clip or an unknown joint
+    pub fn from_def_with_max_depth(
+        def: BlendTreeNodeDef,
+        animations: &HashMap<ClipId, Rc<AnimationClip<T>>>,
+        clip_table: &[Rc<AnimationClip<T>>],
+        skeleton: Rc<Skeleton>,
+        max_depth: usize,
+    ) -> Result<AnimBlendTree<T>, String> {
+
+        def.check_depth(max_depth)?;
 
         let mut tree = AnimBlendTree {
             root_node: AnimNodeHandle::None,
@@ -132,11 +696,19 @@ impl<T: Transform> AnimBlendTree<T> {
             additive_nodes: Vec::new(),
             ik_nodes: Vec::new(),
             clip_nodes: Vec::new(),
-            skeleton: skeleton.clone()
+            hash_select_nodes: Vec::new(),
+            twist_nodes: Vec::new(),
+            mask_nodes: Vec::new(),
+            look_at_nodes: Vec::new(),
+            blend_space_1d_nodes: Vec::new(),
+            speed_nodes: Vec::new(),
+            skeleton: skeleton.clone(),
+            profiling: false,
+            eval_stats: Cell::new(EvalStats::default()),
         };
 
-        tree.root_node = tree.add_node(def, animations, &skeleton);
-        tree
+        tree.root_node = tree.add_node(def, animations, clip_table, &skeleton)?;
+        Ok(tree)
     }
 
     /// Get the output skeletal pose from the blend tree for the given time and parameters
@@ -148,142 +720,667 @@ impl<T: Transform> AnimBlendTree<T> {
     /// * `output_poses` - The output array slice of joint transforms that will be populated
     ///                    according to the defined output for this BlendTreeNode
     pub fn get_output_pose(&self, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+        self.eval_stats.set(EvalStats::default());
         if let Some(ref node) = self.get_node(self.root_node.clone()) {
             node.get_output_pose(self, time, params, output_poses);
         }
     }
 
-    /// For each LerpNode with two animation clips, synchronize their playback rates according to the blend parameter
+    /// Enable or disable counting the work done by `get_output_pose`, which is off by default
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiling = enabled;
+        self.eval_stats.set(EvalStats::default());
+    }
+
+    /// Return the counts of nodes evaluated and clips sampled by the most recent call to
+    /// `get_output_pose`, while profiling (see `set_profiling`); otherwise all zero. The
+    /// duration is left for callers to measure.
+    pub fn last_eval_stats(&self) -> EvalStats {
+        self.eval_stats.get()
+    }
+
+    /// Count the evaluation of a node, and whether it sampled a clip, if profiling
+    fn record_evaluation(&self, sampled_clip: bool) {
+        if !self.profiling {
+            return;
+        }
+        let mut stats = self.eval_stats.get();
+        stats.nodes_evaluated += 1;
+        if sampled_clip {
+            stats.clips_sampled += 1;
+        }
+        self.eval_stats.set(stats);
+    }
+
+    /// Bake the output of the blend tree, for fixed parameter values, into a flat AnimationClip
     ///
     /// # Arguments
     ///
-    /// * `global_time` - The current global clock time from the controller
-    /// * `params` - A mapping from ParamIds to their current parameter values
-    pub fn synchronize(&mut self, global_time: f32, params: &HashMap<String, f32>) {
-        for lerp_node in self.lerp_nodes.iter() {
-            if let (AnimNodeHandle::ClipAnimNodeHandle(clip_1), AnimNodeHandle::ClipAnimNodeHandle(clip_2)) = (lerp_node.input_1.clone(), lerp_node.input_2.clone()) {
-                let blend_parameter = params[&lerp_node.blend_param[..]];
+    /// * `params` - A mapping from ParamIds to the parameter values to bake with
+    /// * `sample_count` - The number of evenly spaced samples in the baked clip
+    /// * `duration` - The duration of the baked clip in seconds, sampled from time 0
+    pub fn bake(&self, params: &HashMap<String, f32>, sample_count: usize, duration: f32) -> AnimationClip<T> {
 
-                let target_length = {
-                    let clip_1 = &self.clip_nodes[clip_1].clip;
-                    let clip_2 = &self.clip_nodes[clip_2].clip;
+        let samples_per_second = sample_count as f32 / duration;
 
-                    let length_1 = clip_1.get_duration();
-                    let length_2 = clip_2.get_duration();
+        let samples = (0 .. sample_count).map(|sample_index| {
+            let mut local_poses = vec![T::identity(); self.skeleton.joints.len()];
+            self.get_output_pose(sample_index as f32 / samples_per_second, params, &mut local_poses[..]);
+            AnimationSample { local_poses }
+        }).collect();
 
-                    (1.0 - blend_parameter) * length_1 + blend_parameter * length_2
-                };
+        AnimationClip::new(samples, samples_per_second)
+    }
 
-                {
-                    let clip_1 = &mut self.clip_nodes[clip_1].clip;
-                    let length = clip_1.get_duration();
-                    clip_1.set_playback_rate(global_time, length / target_length);
-                }
+    /// Return the local sampling time of each clip node in the tree for the given global time,
+    /// along with the id of the clip it plays (`#` and the clip table index, for clips referenced
+    /// by index). Useful for debugging clip synchronization.
+    pub fn clip_local_times(&self, global_time: f32) -> Vec<(ClipId, f32)> {
+        self.clip_nodes.iter().map(|clip_node| {
+            (clip_node.clip_id.clone(), clip_node.clip.get_local_time(global_time))
+        }).collect()
+    }
 
-                {
-                    let clip_2 = &mut self.clip_nodes[clip_2].clip;
-                    let length = clip_2.get_duration();
-                    clip_2.set_playback_rate(global_time, length / target_length);
-                }
-            }
-        }
+    /// Return the names of the events crossed by each clip in the tree between the given global
+    /// times, in clip node order
+    pub fn get_clip_events_between(&self, previous_global_time: f32, global_time: f32) -> Vec<&str> {
+        self.clip_nodes.iter().flat_map(|clip_node| {
+            let clip = &clip_node.clip;
+            clip.get_events_between(clip.get_local_time(previous_global_time), clip.get_local_time(global_time))
+        }).collect()
     }
 
-    fn add_node(
-        &mut self,
-        def: BlendTreeNodeDef,
-        animations: &HashMap<ClipId, Rc<AnimationClip<T>>>,
-        skeleton: &Skeleton
-    ) -> AnimNodeHandle {
-        match def {
-            BlendTreeNodeDef::LerpNode(input_1, input_2, param_id) => {
-                let input_1_handle = self.add_node(*input_1, animations, skeleton);
-                let input_2_handle = self.add_node(*input_2, animations, skeleton);
-                self.lerp_nodes.push(LerpAnimNode {
-                    input_1: input_1_handle,
-                    input_2: input_2_handle,
-                    blend_param: param_id.clone()
-                });
-                AnimNodeHandle::LerpAnimNodeHandle(self.lerp_nodes.len() - 1)
+    /// Return the root motion of the tree between the given global times: that of its clips,
+    /// blended as their poses are. Additive inputs contribute no root motion.
+    pub fn get_root_motion(&self, previous_global_time: f32, global_time: f32, params: &HashMap<String, f32>) -> Vector3<f32> {
+        self.get_root_transform_delta(previous_global_time, global_time, params).0
+    }
+
+    /// Return the root motion of the tree between the given global times as with
+    /// `get_root_motion`, along with the rotation of the root joint (see
+    /// `AnimationClip::root_rotation_between`), blended as their poses are
+    pub fn get_root_transform_delta(&self, previous_global_time: f32, global_time: f32, params: &HashMap<String, f32>) -> (Vector3<f32>, Quaternion<f32>) {
+        self.get_node_root_motion(self.root_node.clone(), previous_global_time, global_time, params)
+    }
+
+    fn get_node_root_motion(&self, node: AnimNodeHandle, previous_global_time: f32, global_time: f32, params: &HashMap<String, f32>) -> (Vector3<f32>, Quaternion<f32>) {
+        match node {
+            AnimNodeHandle::ClipAnimNodeHandle(i) => {
+                let clip = &self.clip_nodes[i].clip;
+                let (previous_time, time) = (clip.get_local_time(previous_global_time), clip.get_local_time(global_time));
+                (clip.clip.root_motion_between(previous_time, time), clip.clip.root_rotation_between(previous_time, time))
             }
-            BlendTreeNodeDef::AdditiveNode(input_1, input_2, param_id) => {
-                let input_1_handle = self.add_node(*input_1, animations, skeleton);
-                let input_2_handle = self.add_node(*input_2, animations, skeleton);
-                self.additive_nodes.push(AdditiveAnimNode {
-                    base_input: input_1_handle,
-                    additive_input: input_2_handle,
-                    blend_param: param_id.clone()
-                });
-                AnimNodeHandle::AdditiveAnimNodeHandle(self.additive_nodes.len() - 1)
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let lerp_node = &self.lerp_nodes[i];
+                let (motion_1, rotation_1) = self.get_node_root_motion(lerp_node.input_1.clone(), previous_global_time, global_time, params);
+                let (motion_2, rotation_2) = self.get_node_root_motion(lerp_node.input_2.clone(), previous_global_time, global_time, params);
+                let blend_parameter = params[&lerp_node.blend_param[..]];
+                let motion = match lerp_node.channels {
+                    BlendChannels::Rotation => motion_1,
+                    _ => interpolation::lerp(&motion_1, &motion_2, &blend_parameter),
+                };
+                let rotation = match lerp_node.channels {
+                    BlendChannels::Translation => rotation_1,
+                    _ => lerp_quaternion(&rotation_1, &rotation_2, &blend_parameter),
+                };
+                (motion, rotation)
             }
-            BlendTreeNodeDef::IKNode(input, effector_name, blend_param, target_x_param, target_y_param, target_z_param, bend_x_param, bend_y_param, bend_z_param) => {
-                let input_handle = self.add_node(*input, animations, skeleton);
-                self.ik_nodes.push(IKNode {
-                    input: input_handle,
-                    blend_param: blend_param.clone(),
-                    target_x_param: target_x_param.clone(),
-                    target_y_param: target_y_param.clone(),
-                    target_z_param: target_z_param.clone(),
-                    bend_x_param: bend_x_param.clone(),
-                    bend_y_param: bend_y_param.clone(),
-                    bend_z_param: bend_z_param.clone(),
-                    effector_bone_index: skeleton.get_joint_index(&effector_name).unwrap(),
-
-                });
-                AnimNodeHandle::IKAnimNodeHandle(self.ik_nodes.len() - 1)
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => self.get_node_root_motion(self.additive_nodes[i].base_input.clone(), previous_global_time, global_time, params),
+            AnimNodeHandle::IKAnimNodeHandle(i) => self.get_node_root_motion(self.ik_nodes[i].input.clone(), previous_global_time, global_time, params),
+            AnimNodeHandle::TwistAnimNodeHandle(i) => self.get_node_root_motion(self.twist_nodes[i].input.clone(), previous_global_time, global_time, params),
+            AnimNodeHandle::LookAtAnimNodeHandle(i) => self.get_node_root_motion(self.look_at_nodes[i].input.clone(), previous_global_time, global_time, params),
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => self.get_node_root_motion(self.speed_nodes[i].input.clone(), previous_global_time, global_time, params),
+            AnimNodeHandle::MaskAnimNodeHandle(i) => self.get_node_root_motion(self.mask_nodes[i].base_input.clone(), previous_global_time, global_time, params),
+            AnimNodeHandle::HashSelectAnimNodeHandle(i) => {
+                match self.hash_select_nodes[i].selected_input(params) {
+                    Some(input) => self.get_node_root_motion(input.clone(), previous_global_time, global_time, params),
+                    None => ([0.0; 3], quaternion_id()),
+                }
             }
-            BlendTreeNodeDef::ClipNode(clip_id) => {
-                let clip = animations.get(&clip_id[..]).expect(&format!("Missing animation clip: {}", clip_id)[..]);
-                self.clip_nodes.push(ClipAnimNode {
-                    clip: ClipInstance::new(clip.clone())
-                });
-                AnimNodeHandle::ClipAnimNodeHandle(self.clip_nodes.len() - 1)
+            AnimNodeHandle::BlendSpace1DAnimNodeHandle(i) => {
+                match self.blend_space_1d_nodes[i].active_inputs(params) {
+                    Some((input_1, input_2, blend_parameter)) => {
+                        let (motion_1, rotation_1) = self.get_node_root_motion(input_1.clone(), previous_global_time, global_time, params);
+                        let (motion_2, rotation_2) = self.get_node_root_motion(input_2.clone(), previous_global_time, global_time, params);
+                        (interpolation::lerp(&motion_1, &motion_2, &blend_parameter), lerp_quaternion(&rotation_1, &rotation_2, &blend_parameter))
+                    }
+                    None => ([0.0; 3], quaternion_id()),
+                }
             }
+            AnimNodeHandle::None => ([0.0; 3], quaternion_id()),
         }
     }
 
-    fn get_node(&self, handle: AnimNodeHandle) -> Option<&dyn AnimNode<T>> {
-        match handle {
-            AnimNodeHandle::LerpAnimNodeHandle(i) => Some(&self.lerp_nodes[i]),
-            AnimNodeHandle::AdditiveAnimNodeHandle(i) => Some(&self.additive_nodes[i]),
-            AnimNodeHandle::ClipAnimNodeHandle(i) => Some(&self.clip_nodes[i]),
-            AnimNodeHandle::IKAnimNodeHandle(i) => Some(&self.ik_nodes[i]),
-            AnimNodeHandle::None => None,
+    /// Return the effective weight of the given parameter as used by the tree's blending nodes:
+    /// the ramped blend weight of an IKNode with a blend ramp, otherwise the parameter's value.
+    /// None if no node blends by the parameter.
+    pub fn get_blend_weight(&self, param: &str, params: &HashMap<String, f32>) -> Option<f32> {
+
+        if let Some(ik_node) = self.ik_nodes.iter().find(|ik_node| ik_node.blend_param == param) {
+            return Some(ik_node.blend_weight);
         }
-    }
-}
 
-pub trait AnimNode<T: Transform> {
-    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]);
-}
+        let is_blend_param = self.lerp_nodes.iter().any(|node| node.blend_param == param)
+            || self.additive_nodes.iter().any(|node| node.blend_param == param)
+            || self.mask_nodes.iter().any(|node| node.blend_param == param)
+            || self.look_at_nodes.iter().any(|node| node.blend_param == param);
 
-#[derive(Clone)]
-pub enum AnimNodeHandle {
-    None,
-    LerpAnimNodeHandle(usize),
-    AdditiveAnimNodeHandle(usize),
-    ClipAnimNodeHandle(usize),
-    IKAnimNodeHandle(usize),
-}
+        if is_blend_param {
+            params.get(param).cloned()
+        } else {
+            None
+        }
+    }
 
-/// An AnimNode where pose output is linear blend between the output of the two input AnimNodes,
-/// with blend factor according the blend_param value
-pub struct LerpAnimNode {
-    input_1: AnimNodeHandle,
-    input_2: AnimNodeHandle,
-    blend_param: ParamId
-}
+    /// Return the most recent solve of each IKNode in the tree that has solved, for debug drawing
+    pub fn ik_debug_solves(&self) -> Vec<IKDebugSolve> {
+        self.ik_nodes.iter().filter_map(|ik_node| ik_node.last_solve.get()).collect()
+    }
+
+    /// Synchronize the playback rates of the clips beneath each LerpNode and BlendSpace1DNode
+    /// according to the blend parameters, so that blended clips stay phase-matched. Clips beneath
+    /// nested nodes are synchronized to the outermost such node above them.
+    ///
+    /// # Arguments
+    ///
+    /// * `global_time` - The current global clock time from the controller
+    /// * `params` - A mapping from ParamIds to their current parameter values
+    pub fn synchronize(&mut self, global_time: f32, params: &HashMap<String, f32>) {
+        let root_node = self.root_node.clone();
+        self.synchronize_node(root_node, global_time, params);
+    }
+
+    /// Advance the state of any stateful nodes (IK nodes ramping their blend weight, and speed
+    /// nodes scaling the playback of their clips) to the given time. Should be called once per
+    /// frame, before `get_output_pose`.
+    ///
+    /// # Arguments
+    ///
+    /// * `global_time` - The current global clock time from the controller
+    /// * `params` - A mapping from ParamIds to their current parameter values
+    pub fn update(&mut self, global_time: f32, params: &HashMap<String, f32>) {
+        for ik_node in self.ik_nodes.iter_mut() {
+            ik_node.update(global_time, params);
+        }
+        if !self.speed_nodes.is_empty() {
+            let root_node = self.root_node.clone();
+            self.update_node_speed(root_node, 1.0, global_time, params);
+        }
+    }
+
+    /// Set the node speed of the clips beneath the given node to `speed`, multiplied by the
+    /// speed parameter of each SpeedNode above them
+    fn update_node_speed(&mut self, node: AnimNodeHandle, speed: f32, global_time: f32, params: &HashMap<String, f32>) {
+        match node {
+            AnimNodeHandle::ClipAnimNodeHandle(i) => self.clip_nodes[i].clip.set_node_speed(global_time, speed),
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => {
+                let input = self.speed_nodes[i].input.clone();
+                let speed = speed * params[&self.speed_nodes[i].speed_param[..]];
+                self.update_node_speed(input, speed, global_time, params);
+            }
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let input_1 = self.lerp_nodes[i].input_1.clone();
+                let input_2 = self.lerp_nodes[i].input_2.clone();
+                self.update_node_speed(input_1, speed, global_time, params);
+                self.update_node_speed(input_2, speed, global_time, params);
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => {
+                let base_input = self.additive_nodes[i].base_input.clone();
+                let additive_input = self.additive_nodes[i].additive_input.clone();
+                self.update_node_speed(base_input, speed, global_time, params);
+                self.update_node_speed(additive_input, speed, global_time, params);
+            }
+            AnimNodeHandle::MaskAnimNodeHandle(i) => {
+                let base_input = self.mask_nodes[i].base_input.clone();
+                let masked_input = self.mask_nodes[i].masked_input.clone();
+                self.update_node_speed(base_input, speed, global_time, params);
+                self.update_node_speed(masked_input, speed, global_time, params);
+            }
+            AnimNodeHandle::IKAnimNodeHandle(i) => {
+                let input = self.ik_nodes[i].input.clone();
+                self.update_node_speed(input, speed, global_time, params);
+            }
+            AnimNodeHandle::TwistAnimNodeHandle(i) => {
+                let input = self.twist_nodes[i].input.clone();
+                self.update_node_speed(input, speed, global_time, params);
+            }
+            AnimNodeHandle::LookAtAnimNodeHandle(i) => {
+                let input = self.look_at_nodes[i].input.clone();
+                self.update_node_speed(input, speed, global_time, params);
+            }
+            AnimNodeHandle::HashSelectAnimNodeHandle(i) => {
+                for input in self.hash_select_nodes[i].inputs.clone() {
+                    self.update_node_speed(input, speed, global_time, params);
+                }
+            }
+            AnimNodeHandle::BlendSpace1DAnimNodeHandle(i) => {
+                for (_, input) in self.blend_space_1d_nodes[i].inputs.clone() {
+                    self.update_node_speed(input, speed, global_time, params);
+                }
+            }
+            AnimNodeHandle::None => {}
+        }
+    }
+
+    /// Find the outermost LerpNodes and BlendSpace1DNodes at or beneath the given node, and
+    /// synchronize the clips beneath them
+    fn synchronize_node(&mut self, node: AnimNodeHandle, global_time: f32, params: &HashMap<String, f32>) {
+        match node {
+            AnimNodeHandle::LerpAnimNodeHandle(_) | AnimNodeHandle::BlendSpace1DAnimNodeHandle(_) => {
+                let target_length = self.get_node_duration(node.clone(), params);
+                self.set_node_duration(node, target_length, global_time, params);
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => {
+                let base_input = self.additive_nodes[i].base_input.clone();
+                let additive_input = self.additive_nodes[i].additive_input.clone();
+                self.synchronize_node(base_input, global_time, params);
+                self.synchronize_node(additive_input, global_time, params);
+            }
+            AnimNodeHandle::MaskAnimNodeHandle(i) => {
+                let base_input = self.mask_nodes[i].base_input.clone();
+                let masked_input = self.mask_nodes[i].masked_input.clone();
+                self.synchronize_node(base_input, global_time, params);
+                self.synchronize_node(masked_input, global_time, params);
+            }
+            AnimNodeHandle::IKAnimNodeHandle(i) => {
+                let input = self.ik_nodes[i].input.clone();
+                self.synchronize_node(input, global_time, params);
+            }
+            AnimNodeHandle::TwistAnimNodeHandle(i) => {
+                let input = self.twist_nodes[i].input.clone();
+                self.synchronize_node(input, global_time, params);
+            }
+            AnimNodeHandle::LookAtAnimNodeHandle(i) => {
+                let input = self.look_at_nodes[i].input.clone();
+                self.synchronize_node(input, global_time, params);
+            }
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => {
+                let input = self.speed_nodes[i].input.clone();
+                self.synchronize_node(input, global_time, params);
+            }
+            AnimNodeHandle::HashSelectAnimNodeHandle(i) => {
+                for input in self.hash_select_nodes[i].inputs.clone() {
+                    self.synchronize_node(input, global_time, params);
+                }
+            }
+            AnimNodeHandle::ClipAnimNodeHandle(_) | AnimNodeHandle::None => {}
+        }
+    }
+
+    /// Representative duration of the subtree at the given node, at normal playback rate.
+    /// For a LerpNode, this is the durations of its inputs blended by the blend parameter, and
+    /// for a BlendSpace1DNode, the durations of its two active inputs.
+    fn get_node_duration(&self, node: AnimNodeHandle, params: &HashMap<String, f32>) -> f32 {
+        match node {
+            AnimNodeHandle::ClipAnimNodeHandle(i) => self.clip_nodes[i].clip.get_duration(),
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let lerp_node = &self.lerp_nodes[i];
+                let blend_parameter = params[&lerp_node.blend_param[..]];
+                let length_1 = self.get_node_duration(lerp_node.input_1.clone(), params);
+                let length_2 = self.get_node_duration(lerp_node.input_2.clone(), params);
+                (1.0 - blend_parameter) * length_1 + blend_parameter * length_2
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => self.get_node_duration(self.additive_nodes[i].base_input.clone(), params),
+            AnimNodeHandle::IKAnimNodeHandle(i) => self.get_node_duration(self.ik_nodes[i].input.clone(), params),
+            AnimNodeHandle::TwistAnimNodeHandle(i) => self.get_node_duration(self.twist_nodes[i].input.clone(), params),
+            AnimNodeHandle::LookAtAnimNodeHandle(i) => self.get_node_duration(self.look_at_nodes[i].input.clone(), params),
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => self.get_node_duration(self.speed_nodes[i].input.clone(), params),
+            AnimNodeHandle::MaskAnimNodeHandle(i) => self.get_node_duration(self.mask_nodes[i].base_input.clone(), params),
+            AnimNodeHandle::HashSelectAnimNodeHandle(i) => {
+                let hash_select_node = &self.hash_select_nodes[i];
+                match hash_select_node.selected_input(params) {
+                    Some(input) => self.get_node_duration(input.clone(), params),
+                    None => 0.0,
+                }
+            }
+            AnimNodeHandle::BlendSpace1DAnimNodeHandle(i) => {
+                match self.blend_space_1d_nodes[i].active_inputs(params) {
+                    Some((input_1, input_2, blend_parameter)) => {
+                        let length_1 = self.get_node_duration(input_1.clone(), params);
+                        let length_2 = self.get_node_duration(input_2.clone(), params);
+                        (1.0 - blend_parameter) * length_1 + blend_parameter * length_2
+                    }
+                    None => 0.0,
+                }
+            }
+            AnimNodeHandle::None => 0.0,
+        }
+    }
+
+    /// Set the playback rates of the clips beneath the given node, so that they each play
+    /// over the given duration. Additive inputs are synchronized independently.
+    fn set_node_duration(&mut self, node: AnimNodeHandle, target_length: f32, global_time: f32, params: &HashMap<String, f32>) {
+        match node {
+            AnimNodeHandle::ClipAnimNodeHandle(i) => {
+                let clip = &mut self.clip_nodes[i].clip;
+                let length = clip.get_duration();
+                clip.set_playback_rate(global_time, length / target_length);
+            }
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let input_1 = self.lerp_nodes[i].input_1.clone();
+                let input_2 = self.lerp_nodes[i].input_2.clone();
+                self.set_node_duration(input_1, target_length, global_time, params);
+                self.set_node_duration(input_2, target_length, global_time, params);
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => {
+                let base_input = self.additive_nodes[i].base_input.clone();
+                let additive_input = self.additive_nodes[i].additive_input.clone();
+                self.set_node_duration(base_input, target_length, global_time, params);
+                self.synchronize_node(additive_input, global_time, params);
+            }
+            AnimNodeHandle::MaskAnimNodeHandle(i) => {
+                let base_input = self.mask_nodes[i].base_input.clone();
+                let masked_input = self.mask_nodes[i].masked_input.clone();
+                self.set_node_duration(base_input, target_length, global_time, params);
+                self.synchronize_node(masked_input, global_time, params);
+            }
+            AnimNodeHandle::IKAnimNodeHandle(i) => {
+                let input = self.ik_nodes[i].input.clone();
+                self.set_node_duration(input, target_length, global_time, params);
+            }
+            AnimNodeHandle::TwistAnimNodeHandle(i) => {
+                let input = self.twist_nodes[i].input.clone();
+                self.set_node_duration(input, target_length, global_time, params);
+            }
+            AnimNodeHandle::LookAtAnimNodeHandle(i) => {
+                let input = self.look_at_nodes[i].input.clone();
+                self.set_node_duration(input, target_length, global_time, params);
+            }
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => {
+                let input = self.speed_nodes[i].input.clone();
+                self.set_node_duration(input, target_length, global_time, params);
+            }
+            AnimNodeHandle::HashSelectAnimNodeHandle(i) => {
+                for input in self.hash_select_nodes[i].inputs.clone() {
+                    self.set_node_duration(input, target_length, global_time, params);
+                }
+            }
+            // Inactive inputs are synchronized too, so they are in phase when blended in
+            AnimNodeHandle::BlendSpace1DAnimNodeHandle(i) => {
+                for (_, input) in self.blend_space_1d_nodes[i].inputs.clone() {
+                    self.set_node_duration(input, target_length, global_time, params);
+                }
+            }
+            AnimNodeHandle::None => {}
+        }
+    }
+
+    fn add_node(
+        &mut self,
+        def: BlendTreeNodeDef,
+        animations: &HashMap<ClipId, Rc<AnimationClip<T>>>,
+        clip_table: &[Rc<AnimationClip<T>>],
+        skeleton: &Skeleton
+    ) -> Result<AnimNodeHandle, String> {
+        let joint_index = |kind: &str, name: &str| {
+            skeleton.get_joint_index(name).ok_or_else(|| format!("Unknown {} joint: {}", kind, name))
+        };
+        let handle = match def {
+            BlendTreeNodeDef::LerpNode(input_1, input_2, param_id, channels, translation_space) => {
+                let input_1_handle = self.add_node(*input_1, animations, clip_table, skeleton)?;
+                let input_2_handle = self.add_node(*input_2, animations, clip_table, skeleton)?;
+                self.lerp_nodes.push(LerpAnimNode {
+                    input_1: input_1_handle,
+                    input_2: input_2_handle,
+                    blend_param: param_id.clone(),
+                    channels,
+                    translation_space,
+                });
+                AnimNodeHandle::LerpAnimNodeHandle(self.lerp_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::AdditiveNode(input_1, input_2, param_id, channels, falloff, cull_threshold) => {
+                let input_1_handle = self.add_node(*input_1, animations, clip_table, skeleton)?;
+                let input_2_handle = self.add_node(*input_2, animations, clip_table, skeleton)?;
+                let max_delta = self.get_node_max_delta(input_2_handle.clone());
+                let mut joint_weights = Vec::new();
+                if !falloff.is_empty() {
+                    joint_weights = vec![1.0; skeleton.joints.len()];
+                    for joint_weight in falloff.iter() {
+                        let joint = joint_index("falloff", &joint_weight.joint)?;
+                        joint_weights[joint as usize] = joint_weight.weight.clamp(0.0, 1.0);
+                    }
+                }
+                self.additive_nodes.push(AdditiveAnimNode {
+                    base_input: input_1_handle,
+                    additive_input: input_2_handle,
+                    blend_param: param_id.clone(),
+                    channels,
+                    max_delta,
+                    cull_threshold,
+                    joint_weights,
+                });
+                AnimNodeHandle::AdditiveAnimNodeHandle(self.additive_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::IKNode(input, ik) => {
+                let IKNodeDef {
+                    effector,
+                    blend_param,
+                    target: [target_x_param, target_y_param, target_z_param],
+                    bend: [bend_x_param, bend_y_param, bend_z_param],
+                    bone_length_epsilon,
+                    blend_ramp_time,
+                    preserve_effector_orientation,
+                    target_smoothing_time,
+                    bend_axis,
+                } = *ik;
+                let input_handle = self.add_node(*input, animations, clip_table, skeleton)?;
+                let effector_bone_index = joint_index("IK effector", &effector)?;
+                let effector_joint = &skeleton.joints[effector_bone_index as usize];
+                if effector_joint.is_root() || skeleton.joints[effector_joint.parent_index as usize].is_root() {
+                    return Err(format!("IK effector joint {} has fewer than two ancestors to form a two-bone chain", effector));
+                }
+                self.ik_nodes.push(IKNode {
+                    input: input_handle,
+                    blend_param,
+                    target_x_param,
+                    target_y_param,
+                    target_z_param,
+                    bend_x_param,
+                    bend_y_param,
+                    bend_z_param,
+                    effector_bone_index,
+                    bone_length_epsilon,
+                    blend_ramp_time,
+                    preserve_effector_orientation,
+                    target_smoothing_time,
+                    bend_axis: vec3_normalized(bend_axis),
+                    blend_weight: 0.0,
+                    smoothed_target: None,
+                    last_update_time: None,
+                    last_solve: Cell::new(None),
+                });
+                AnimNodeHandle::IKAnimNodeHandle(self.ik_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::ClipNode(clip_id) => {
+                let clip = animations.get(&clip_id[..]).ok_or_else(|| format!("Missing animation clip: {}", clip_id))?;
+                self.clip_nodes.push(ClipAnimNode {
+                    clip_id: clip_id.clone(),
+                    clip: ClipInstance::new(clip.clone())
+                });
+                AnimNodeHandle::ClipAnimNodeHandle(self.clip_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::ClipIndexNode(clip_index) => {
+                let clip = clip_table.get(clip_index).ok_or_else(|| format!("Missing animation clip at index: {}", clip_index))?;
+                self.clip_nodes.push(ClipAnimNode {
+                    clip_id: format!("#{}", clip_index),
+                    clip: ClipInstance::new(clip.clone())
+                });
+                AnimNodeHandle::ClipAnimNodeHandle(self.clip_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::HashSelectNode(inputs, param_id) => {
+                let inputs = inputs.into_iter().map(|input| self.add_node(input, animations, clip_table, skeleton)).collect::<Result<_, _>>()?;
+                self.hash_select_nodes.push(HashSelectAnimNode {
+                    inputs,
+                    select_param: param_id.clone(),
+                });
+                AnimNodeHandle::HashSelectAnimNodeHandle(self.hash_select_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::TwistNode(input, source_name, distributors, axis) => {
+                let input_handle = self.add_node(*input, animations, clip_table, skeleton)?;
+                let source_joint = joint_index("twist", &source_name)?;
+                let distributors = distributors.iter().map(|d| Ok((joint_index("twist", &d.joint)?, d.fraction))).collect::<Result<_, String>>()?;
+                self.twist_nodes.push(TwistAnimNode {
+                    input: input_handle,
+                    source_joint,
+                    distributors,
+                    axis: vec3_normalized(axis),
+                });
+                AnimNodeHandle::TwistAnimNodeHandle(self.twist_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::MaskNode(input_1, input_2, joint_names, param_id) => {
+                let input_1_handle = self.add_node(*input_1, animations, clip_table, skeleton)?;
+                let input_2_handle = self.add_node(*input_2, animations, clip_table, skeleton)?;
+                for name in joint_names.iter() {
+                    joint_index("mask", name)?;
+                }
+                // Joints are in topological order, so parents are resolved before their children
+                let mut in_mask = vec![false; skeleton.joints.len()];
+                for (i, joint) in skeleton.joints.iter().enumerate() {
+                    in_mask[i] = joint_names.contains(&joint.name) || (!joint.is_root() && in_mask[joint.parent_index as usize]);
+                }
+                self.mask_nodes.push(MaskAnimNode {
+                    base_input: input_1_handle,
+                    masked_input: input_2_handle,
+                    masked_joints: (0 .. in_mask.len()).filter(|&i| in_mask[i]).map(|i| i as JointIndex).collect(),
+                    blend_param: param_id.clone(),
+                });
+                AnimNodeHandle::MaskAnimNodeHandle(self.mask_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::LookAtNode(input, look_at) => {
+                let LookAtNodeDef {
+                    joint,
+                    blend_param,
+                    target: [target_x_param, target_y_param, target_z_param],
+                    axis,
+                } = *look_at;
+                let input_handle = self.add_node(*input, animations, clip_table, skeleton)?;
+                let joint = joint_index("look-at", &joint)?;
+                self.look_at_nodes.push(LookAtAnimNode {
+                    input: input_handle,
+                    joint,
+                    target_x_param,
+                    target_y_param,
+                    target_z_param,
+                    blend_param,
+                    axis: vec3_normalized(axis),
+                });
+                AnimNodeHandle::LookAtAnimNodeHandle(self.look_at_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::BlendSpace1DNode(inputs, param_id) => {
+                // e.g. a null position in JSON, which decodes as NaN
+                if let Some(&(position, _)) = inputs.iter().find(|&&(position, _)| !position.is_finite()) {
+                    return Err(format!("BlendSpace1DNode input position {} is not finite", position));
+                }
+                let mut inputs: Vec<(f32, AnimNodeHandle)> = inputs.into_iter().map(|(position, input)| {
+                    Ok((position, self.add_node(*input, animations, clip_table, skeleton)?))
+                }).collect::<Result<_, String>>()?;
+                inputs.sort_by(|a, b| a.0.total_cmp(&b.0));
+                self.blend_space_1d_nodes.push(BlendSpace1DAnimNode {
+                    inputs,
+                    blend_param: param_id,
+                });
+                AnimNodeHandle::BlendSpace1DAnimNodeHandle(self.blend_space_1d_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::SpeedNode(input, param_id) => {
+                let input_handle = self.add_node(*input, animations, clip_table, skeleton)?;
+                self.speed_nodes.push(SpeedAnimNode {
+                    input: input_handle,
+                    speed_param: param_id,
+                });
+                AnimNodeHandle::SpeedAnimNodeHandle(self.speed_nodes.len() - 1)
+            }
+        };
+        Ok(handle)
+    }
+
+    /// Upper bound on how far the output of the given node can stray from the identity pose,
+    /// as the largest difference of any matrix component. Unbounded for IK nodes.
+    fn get_node_max_delta(&self, node: AnimNodeHandle) -> f32 {
+        match node {
+            AnimNodeHandle::ClipAnimNodeHandle(i) => self.clip_nodes[i].clip.clip.max_pose_delta(),
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let lerp_node = &self.lerp_nodes[i];
+                self.get_node_max_delta(lerp_node.input_1.clone()).max(self.get_node_max_delta(lerp_node.input_2.clone()))
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => {
+                let additive_node = &self.additive_nodes[i];
+                self.get_node_max_delta(additive_node.base_input.clone()) + additive_node.max_delta
+            }
+            AnimNodeHandle::IKAnimNodeHandle(_) | AnimNodeHandle::LookAtAnimNodeHandle(_) => f32::INFINITY,
+            // Distributed twist is no larger than the source joint's rotation
+            AnimNodeHandle::TwistAnimNodeHandle(i) => self.get_node_max_delta(self.twist_nodes[i].input.clone()),
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => self.get_node_max_delta(self.speed_nodes[i].input.clone()),
+            AnimNodeHandle::MaskAnimNodeHandle(i) => {
+                let mask_node = &self.mask_nodes[i];
+                self.get_node_max_delta(mask_node.base_input.clone()).max(self.get_node_max_delta(mask_node.masked_input.clone()))
+            }
+            AnimNodeHandle::HashSelectAnimNodeHandle(i) => {
+                self.hash_select_nodes[i].inputs.iter().fold(0.0, |max_delta, input| max_delta.max(self.get_node_max_delta(input.clone())))
+            }
+            AnimNodeHandle::BlendSpace1DAnimNodeHandle(i) => {
+                self.blend_space_1d_nodes[i].inputs.iter().fold(0.0, |max_delta, (_, input)| max_delta.max(self.get_node_max_delta(input.clone())))
+            }
+            AnimNodeHandle::None => 0.0,
+        }
+    }
+
+    fn get_node(&self, handle: AnimNodeHandle) -> Option<&dyn AnimNode<T>> {
+        match handle {
+            AnimNodeHandle::LerpAnimNodeHandle(i) => Some(&self.lerp_nodes[i]),
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => Some(&self.additive_nodes[i]),
+            AnimNodeHandle::ClipAnimNodeHandle(i) => Some(&self.clip_nodes[i]),
+            AnimNodeHandle::IKAnimNodeHandle(i) => Some(&self.ik_nodes[i]),
+            AnimNodeHandle::HashSelectAnimNodeHandle(i) => Some(&self.hash_select_nodes[i]),
+            AnimNodeHandle::TwistAnimNodeHandle(i) => Some(&self.twist_nodes[i]),
+            AnimNodeHandle::MaskAnimNodeHandle(i) => Some(&self.mask_nodes[i]),
+            AnimNodeHandle::LookAtAnimNodeHandle(i) => Some(&self.look_at_nodes[i]),
+            AnimNodeHandle::BlendSpace1DAnimNodeHandle(i) => Some(&self.blend_space_1d_nodes[i]),
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => Some(&self.speed_nodes[i]),
+            AnimNodeHandle::None => None,
+        }
+    }
+}
+
+pub trait AnimNode<T: Transform> {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]);
+}
+
+#[derive(Clone)]
+pub enum AnimNodeHandle {
+    None,
+    LerpAnimNodeHandle(usize),
+    AdditiveAnimNodeHandle(usize),
+    ClipAnimNodeHandle(usize),
+    IKAnimNodeHandle(usize),
+    HashSelectAnimNodeHandle(usize),
+    TwistAnimNodeHandle(usize),
+    MaskAnimNodeHandle(usize),
+    LookAtAnimNodeHandle(usize),
+    BlendSpace1DAnimNodeHandle(usize),
+    SpeedAnimNodeHandle(usize),
+}
+
+/// An AnimNode where pose output is linear blend between the output of the two input AnimNodes,
+/// with blend factor according the blend_param value
+pub struct LerpAnimNode {
+    input_1: AnimNodeHandle,
+    input_2: AnimNodeHandle,
+    blend_param: ParamId,
+    channels: BlendChannels,
+    translation_space: TranslationSpace,
+}
 
 impl<T: Transform> AnimNode<T> for LerpAnimNode {
     fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
 
-        let mut input_poses = [ T::identity(); 64 ];
-        let sample_count = output_poses.len();
+        tree.record_evaluation(false);
+
+        let mut input_poses = PoseBuffer::<T>::new(output_poses.len());
 
         let blend_parameter = params[&self.blend_param[..]];
 
         if let Some(ref node) = tree.get_node(self.input_1.clone()) {
-            node.get_output_pose(tree, time, params, &mut input_poses[0 .. sample_count]);
+            node.get_output_pose(tree, time, params, &mut input_poses);
         }
 
         if let Some(ref node) = tree.get_node(self.input_2.clone()) {
@@ -293,29 +1390,134 @@ impl<T: Transform> AnimNode<T> for LerpAnimNode {
         for i in 0 .. output_poses.len() {
             let pose_1 = input_poses[i];
             let pose_2 = &mut output_poses[i];
-            (*pose_2) = pose_1.lerp(pose_2.clone(), blend_parameter);
+            (*pose_2) = self.channels.apply(pose_1, pose_1.lerp_in_space(*pose_2, blend_parameter, self.translation_space));
+        }
+    }
+}
+
+/// An AnimNode with inputs placed at positions along a parameter axis, e.g. walk, jog and run
+/// by speed, where pose output is a linear blend between the two inputs bracketing the
+/// blend_param value. Values beyond the first or last position give that input's pose.
+pub struct BlendSpace1DAnimNode {
+    /// Inputs with their positions, in ascending order of position
+    inputs: Vec<(f32, AnimNodeHandle)>,
+    blend_param: ParamId,
+}
+
+impl BlendSpace1DAnimNode {
+
+    /// The two inputs bracketing the current value of the blend parameter, with the blend factor
+    /// between them, if there are any inputs
+    fn active_inputs(&self, params: &HashMap<String, f32>) -> Option<(&AnimNodeHandle, &AnimNodeHandle, f32)> {
+        let value = params[&self.blend_param[..]];
+        let (first, last) = (self.inputs.first()?, self.inputs.last()?);
+        // A NaN parameter falls back to the first input
+        if value.is_nan() || value <= first.0 {
+            return Some((&first.1, &first.1, 0.0));
+        }
+        if value >= last.0 {
+            return Some((&last.1, &last.1, 0.0));
+        }
+        // Positions are ascending, and value lies strictly between the first and last
+        let i = match self.inputs.iter().position(|&(position, _)| position > value) {
+            Some(i) => i,
+            None => return Some((&last.1, &last.1, 0.0)),
+        };
+        let (position_1, ref input_1) = self.inputs[i - 1];
+        let (position_2, ref input_2) = self.inputs[i];
+        Some((input_1, input_2, (value - position_1) / (position_2 - position_1)))
+    }
+}
+
+impl<T: Transform> AnimNode<T> for BlendSpace1DAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+
+        tree.record_evaluation(false);
+
+        let (input_1, input_2, blend_parameter) = match self.active_inputs(params) {
+            Some(active_inputs) => active_inputs,
+            None => return,
+        };
+
+        if blend_parameter == 0.0 {
+            if let Some(node) = tree.get_node(input_1.clone()) {
+                node.get_output_pose(tree, time, params, output_poses);
+            }
+            return;
+        }
+
+        let mut input_poses = PoseBuffer::<T>::new(output_poses.len());
+
+        if let Some(node) = tree.get_node(input_1.clone()) {
+            node.get_output_pose(tree, time, params, &mut input_poses);
+        }
+
+        if let Some(node) = tree.get_node(input_2.clone()) {
+            node.get_output_pose(tree, time, params, output_poses);
+        }
+
+        for i in 0 .. output_poses.len() {
+            output_poses[i] = input_poses[i].lerp(output_poses[i], blend_parameter);
+        }
+    }
+}
+
+/// An AnimNode whose pose output is that of its input, with the playback of every clip beneath
+/// it scaled by the speed_param value, e.g. for slow motion. A speed of zero holds the clips at
+/// their current time, and a negative speed plays them in reverse. The speed is applied by
+/// `AnimBlendTree::update`, keeping each clip's current time as the speed changes.
+pub struct SpeedAnimNode {
+    input: AnimNodeHandle,
+    speed_param: ParamId,
+}
+
+impl<T: Transform> AnimNode<T> for SpeedAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+        tree.record_evaluation(false);
+        if let Some(node) = tree.get_node(self.input.clone()) {
+            node.get_output_pose(tree, time, params, output_poses);
         }
     }
 }
 
 /// An AnimNode where pose output is additive blend with output of additive_input
-/// added to base_input,  with blend factor according to value of blend_param
+/// added to base_input,  with blend factor according to value of blend_param, scaled per joint
+/// by the node's falloff map, if any
 pub struct AdditiveAnimNode {
     base_input: AnimNodeHandle,
     additive_input: AnimNodeHandle,
-    blend_param: ParamId
+    blend_param: ParamId,
+    channels: BlendChannels,
+    max_delta: f32,
+
+    /// Estimated contribution below which the additive input is skipped, if any
+    cull_threshold: Option<f32>,
+
+    /// Weight of each joint from the falloff map, or empty to weight all joints equally.
+    /// Joints missing from the map have a weight of one.
+    joint_weights: Vec<f32>,
 }
 
 impl<T: Transform> AnimNode<T> for AdditiveAnimNode {
     fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
 
-        let mut input_poses = [ T::identity(); 64 ];
-        let sample_count = output_poses.len();
+        tree.record_evaluation(false);
 
         let blend_parameter = params[&self.blend_param[..]];
 
+        // Pass through the base input when the additive contribution would be negligible
+        let culled = self.cull_threshold.map_or(false, |threshold| blend_parameter.abs() * self.max_delta < threshold);
+        if culled {
+            if let Some(node) = tree.get_node(self.base_input.clone()) {
+                node.get_output_pose(tree, time, params, output_poses);
+            }
+            return;
+        }
+
+        let mut input_poses = PoseBuffer::<T>::new(output_poses.len());
+
         if let Some(ref node) = tree.get_node(self.base_input.clone()) {
-            node.get_output_pose(tree, time, params, &mut input_poses[0 .. sample_count]);
+            node.get_output_pose(tree, time, params, &mut input_poses);
         }
 
         if let Some(ref node) = tree.get_node(self.additive_input.clone()) {
@@ -325,86 +1527,376 @@ impl<T: Transform> AnimNode<T> for AdditiveAnimNode {
         for i in 0 .. output_poses.len() {
             let pose_1 = input_poses[i];
             let pose_2 = &mut output_poses[i];
-            let additive_pose = T::identity().lerp(pose_2.clone(), blend_parameter);
-            (*pose_2) = pose_1.concat(additive_pose);
+            let weight = blend_parameter * self.joint_weights.get(i).cloned().unwrap_or(1.0);
+            let additive_pose = T::identity().lerp(*pose_2, weight);
+            (*pose_2) = self.channels.apply(pose_1, pose_1.concat(additive_pose));
         }
     }
 }
 
-/// An AnimNode where pose output is from an animation ClipInstance
-pub struct ClipAnimNode<T: Transform> {
-    clip: ClipInstance<T>
-}
-
-impl<T: Transform> AnimNode<T> for ClipAnimNode<T> {
-    fn get_output_pose(&self, _tree: &AnimBlendTree<T>, time: f32, _params: &HashMap<String, f32>, output_poses: &mut [T]) {
-        self.clip.get_pose_at_time(time, output_poses);
-    }
-}
-
-pub struct IKNode {
-    input: AnimNodeHandle,
+/// An AnimNode where pose output is the masked input blended over the base input, with blend
+/// factor according to the blend_param value, for only the masked joints, e.g. waving with the
+/// upper body while walking. The mask includes all descendants of the joints named in its
+/// definition; the rest of the skeleton keeps the base input's pose.
+pub struct MaskAnimNode {
+    base_input: AnimNodeHandle,
+    masked_input: AnimNodeHandle,
+    masked_joints: Vec<JointIndex>,
     blend_param: ParamId,
-    target_x_param: ParamId,
-    target_y_param: ParamId,
-    target_z_param: ParamId,
-    bend_x_param: ParamId,
-    bend_y_param: ParamId,
-    bend_z_param: ParamId,
-    effector_bone_index: JointIndex,
 }
 
-impl<T: Transform> AnimNode<T> for IKNode {
+impl<T: Transform> AnimNode<T> for MaskAnimNode {
     fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
 
-        // Get input pose
-        if let Some(ref node) = tree.get_node(self.input.clone()) {
+        tree.record_evaluation(false);
+
+        if let Some(node) = tree.get_node(self.base_input.clone()) {
             node.get_output_pose(tree, time, params, output_poses);
         }
 
-        // Target position should be in model-space
-        let effector_target_position = [params[&self.target_x_param[..]],
-                                        params[&self.target_y_param[..]],
-                                        params[&self.target_z_param[..]]];
-
+        let mut masked_poses = PoseBuffer::<T>::new(output_poses.len());
 
-        let effector_bone_index = self.effector_bone_index;
-        let middle_bone_index = tree.skeleton.joints[effector_bone_index as usize].parent_index;
-        let root_bone_index = tree.skeleton.joints[middle_bone_index as usize].parent_index;
-        let root_bone_parent_index = tree.skeleton.joints[root_bone_index as usize].parent_index;
+        if let Some(node) = tree.get_node(self.masked_input.clone()) {
+            node.get_output_pose(tree, time, params, &mut masked_poses);
+        }
 
-        // Get bone positions in model-space by calculating global poses
-        let mut global_poses = [ Matrix4::<f32>::identity(); 64 ];
-        tree.skeleton.calculate_global_poses(output_poses, &mut global_poses);
+        let blend_parameter = params[&self.blend_param[..]];
 
-        let root_bone_position = global_poses[root_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
-        let middle_bone_position = global_poses[middle_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
-        let effector_bone_position = global_poses[effector_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
+        for &joint in self.masked_joints.iter() {
+            let pose = &mut output_poses[joint as usize];
+            *pose = pose.lerp(masked_poses[joint as usize], blend_parameter);
+        }
+    }
+}
 
-        let length_1 = vec3_len(vec3_sub(root_bone_position, middle_bone_position));
-        let length_2 = vec3_len(vec3_sub(middle_bone_position, effector_bone_position));
+/// An AnimNode where pose output is from one of its inputs, chosen by hashing the integer value
+/// of select_param (e.g. an instance id), so each instance stably picks the same variant
+pub struct HashSelectAnimNode {
+    inputs: Vec<AnimNodeHandle>,
+    select_param: ParamId,
+}
 
-        // get effector target position on a 2D bend plane,
-        // with coordinates relative to root bone position
+impl HashSelectAnimNode {
+
+    /// The input selected by the current value of the select parameter, if there are any inputs
+    fn selected_input(&self, params: &HashMap<String, f32>) -> Option<&AnimNodeHandle> {
+        if self.inputs.is_empty() {
+            return None;
+        }
+        let id = params[&self.select_param[..]] as i64 as u64;
+        self.inputs.get((hash_id(id) % self.inputs.len() as u64) as usize)
+    }
+}
+
+impl<T: Transform> AnimNode<T> for HashSelectAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+        tree.record_evaluation(false);
+        if let Some(node) = self.selected_input(params).and_then(|input| tree.get_node(input.clone())) {
+            node.get_output_pose(tree, time, params, output_poses);
+        }
+    }
+}
+
+/// Mix the bits of an id (the SplitMix64 finalizer), so that consecutive ids spread evenly
+/// across variants. Stable across platforms and runs.
+fn hash_id(id: u64) -> u64 {
+    let mut x = id.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// An AnimNode which distributes a fraction of the source joint's roll (its twist about `axis`)
+/// to each distributor joint, on top of the input pose, e.g. spreading a wrist's twist along
+/// forearm twist bones to reduce candy-wrapping
+pub struct TwistAnimNode {
+    input: AnimNodeHandle,
+    source_joint: JointIndex,
+    distributors: Vec<(JointIndex, f32)>,
+    axis: Vector3<f32>,
+}
+
+impl<T: Transform> AnimNode<T> for TwistAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+
+        tree.record_evaluation(false);
+
+        if let Some(node) = tree.get_node(self.input.clone()) {
+            node.get_output_pose(tree, time, params, output_poses);
+        }
+
+        let roll = quaternion_twist_angle(output_poses[self.source_joint as usize].get_rotation(), self.axis);
+
+        for &(joint, fraction) in self.distributors.iter() {
+            let pose = &mut output_poses[joint as usize];
+            let twist = quaternion::axis_angle(self.axis, roll * fraction);
+            pose.set_rotation(quaternion_mul(pose.get_rotation(), twist));
+        }
+    }
+}
+
+/// An AnimNode that rotates a single joint so that its local `axis` points at a model-space
+/// target, e.g. for head or eye tracking, blended with the input pose by the blend parameter
+pub struct LookAtAnimNode {
+    input: AnimNodeHandle,
+    joint: JointIndex,
+    target_x_param: ParamId,
+    target_y_param: ParamId,
+    target_z_param: ParamId,
+    blend_param: ParamId,
+    axis: Vector3<f32>,
+}
+
+impl<T: Transform> AnimNode<T> for LookAtAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+
+        tree.record_evaluation(false);
+
+        if let Some(node) = tree.get_node(self.input.clone()) {
+            node.get_output_pose(tree, time, params, output_poses);
+        }
+
+        let target = [params[&self.target_x_param[..]],
+                      params[&self.target_y_param[..]],
+                      params[&self.target_z_param[..]]];
+
+        let mut global_poses = PoseBuffer::<Matrix4<f32>>::new(output_poses.len());
+        tree.skeleton.calculate_global_poses(output_poses, &mut global_poses);
+
+        let joint_index = self.joint as usize;
+        let joint = &tree.skeleton.joints[joint_index];
+        let joint_position = global_poses[joint_index].transform_vector([0.0, 0.0, 0.0]);
+
+        // Leave the input pose unchanged when the target is on the joint
+        let target_direction = vec3_sub(target, joint_position);
+        if vec3_len(target_direction) < DEFAULT_IK_BONE_LENGTH_EPSILON {
+            return;
+        }
+
+        let original_direction = vec3_sub(global_poses[joint_index].transform_vector(self.axis), joint_position);
+        let rotation_change = quaternion::rotation_from_to(target_direction, original_direction);
+        let original_rotation = global_poses[joint_index].get_rotation();
+        global_poses[joint_index].set_rotation(quaternion::mul(original_rotation, rotation_change));
+
+        let local_pose = if joint.is_root() {
+            global_poses[joint_index]
+        } else {
+            row_mat4_mul(mat4_inv(global_poses[joint.parent_index as usize]), global_poses[joint_index])
+        };
+        let look_at_pose = joint.without_orientation_offset(T::from_matrix(local_pose));
+
+        let output_pose = &mut output_poses[joint_index];
+        *output_pose = output_pose.lerp(look_at_pose, params[&self.blend_param[..]]);
+    }
+}
+
+/// An AnimNode where pose output is from an animation ClipInstance
+pub struct ClipAnimNode<T: Transform> {
+    clip_id: ClipId,
+    clip: ClipInstance<T>
+}
+
+impl<T: Transform> AnimNode<T> for ClipAnimNode<T> {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, _params: &HashMap<String, f32>, output_poses: &mut [T]) {
+        tree.record_evaluation(true);
+        self.clip.get_pose_at_time(time, output_poses);
+    }
+}
+
+pub struct IKNode {
+    input: AnimNodeHandle,
+    blend_param: ParamId,
+    target_x_param: ParamId,
+    target_y_param: ParamId,
+    target_z_param: ParamId,
+    bend_x_param: ParamId,
+    bend_y_param: ParamId,
+    bend_z_param: ParamId,
+    effector_bone_index: JointIndex,
+    bone_length_epsilon: f32,
+
+    /// Time in seconds over which the applied blend weight follows changes to the blend
+    /// parameter, or zero to apply the blend parameter immediately
+    blend_ramp_time: f32,
+
+    /// Whether to keep the effector's model-space orientation from the input pose, rather than
+    /// letting it follow the middle bone
+    preserve_effector_orientation: bool,
+
+    /// Time constant in seconds with which the applied target follows changes to the target
+    /// parameters, or zero to use the target parameters immediately
+    target_smoothing_time: f32,
+
+    /// Bend direction in the root joint's local space, for when no other bend direction is given
+    bend_axis: Vector3<f32>,
+
+    /// The applied blend weight, when ramping
+    blend_weight: f32,
+
+    /// The applied target position, when smoothing
+    smoothed_target: Option<Vector3<f32>>,
+
+    /// Time of the last call to `AnimBlendTree::update`, if any
+    last_update_time: Option<f32>,
+
+    /// The most recent solve, kept for debug drawing
+    last_solve: Cell<Option<IKDebugSolve>>,
+}
+
+/// The model-space result of an IKNode solve, before blending with the input pose
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IKDebugSolve {
+    /// Effector target position
+    pub target: Vector3<f32>,
+
+    /// Normal of the plane the chain bends in
+    pub bend_normal: Vector3<f32>,
+
+    /// Solved positions of the root, middle and effector joints of the chain
+    pub joint_positions: [Vector3<f32>; 3],
+}
+
+impl IKDebugSolve {
+
+    /// Size of the cross marking the target position
+    const TARGET_SIZE: f32 = 0.1;
+
+    /// Line segments `(start, end, color)` showing the solve: a cross at the target, the solved
+    /// bones, and the bend plane normal at the middle joint
+    pub fn debug_lines(&self) -> Vec<(Vector3<f32>, Vector3<f32>, [f32; 4])> {
+
+        let target_color = [1.0, 1.0, 0.2, 1.0];
+        let bone_color = [0.2, 1.0, 1.0, 1.0];
+        let normal_color = [1.0, 0.2, 1.0, 1.0];
+
+        let mut lines = Vec::new();
+
+        for axis in 0 .. 3 {
+            let mut offset = [0.0; 3];
+            offset[axis] = IKDebugSolve::TARGET_SIZE;
+            lines.push((vec3_sub(self.target, offset), vec3_add(self.target, offset), target_color));
+        }
+
+        let [root, middle, effector] = self.joint_positions;
+        lines.push((root, middle, bone_color));
+        lines.push((middle, effector, bone_color));
+        lines.push((middle, vec3_add(middle, vec3_scale(self.bend_normal, vec3_len(vec3_sub(middle, root)) * 0.5)), normal_color));
+
+        lines
+    }
+
+    /// Draw the solve with the given debug renderer
+    pub fn draw<R: gfx::Resources, F: gfx::Factory<R>>(&self, debug_renderer: &mut gfx_debug_draw::DebugRenderer<R, F>) {
+        for (start, end, color) in self.debug_lines() {
+            debug_renderer.draw_line(start, end, color);
+        }
+    }
+}
+
+impl IKNode {
+
+    /// The effector target position given by the target parameters, in model-space
+    fn target_param(&self, params: &HashMap<String, f32>) -> Vector3<f32> {
+        [params[&self.target_x_param[..]],
+         params[&self.target_y_param[..]],
+         params[&self.target_z_param[..]]]
+    }
+
+    /// Move the applied blend weight towards the blend parameter, at a rate that would take it
+    /// from 0 to 1 over the ramp time, and ease the applied target towards the target parameters
+    fn update(&mut self, global_time: f32, params: &HashMap<String, f32>) {
+        let blend_parameter = params[&self.blend_param[..]];
+        self.blend_weight = match self.last_update_time {
+            Some(last_update_time) if self.blend_ramp_time > 0.0 => {
+                let max_change = (global_time - last_update_time).max(0.0) / self.blend_ramp_time;
+                self.blend_weight + (blend_parameter - self.blend_weight).max(-max_change).min(max_change)
+            }
+            _ => blend_parameter,
+        };
+
+        let target = self.target_param(params);
+        self.smoothed_target = match (self.last_update_time, self.smoothed_target) {
+            (Some(last_update_time), Some(smoothed_target)) if self.target_smoothing_time > 0.0 => {
+                let elapsed = (global_time - last_update_time).max(0.0);
+                let t = 1.0 - (-elapsed / self.target_smoothing_time).exp();
+                Some(interpolation::lerp(&smoothed_target, &target, &t))
+            }
+            _ => Some(target),
+        };
+
+        self.last_update_time = Some(global_time);
+    }
+}
+
+impl<T: Transform> AnimNode<T> for IKNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+
+        tree.record_evaluation(false);
+
+        // Get input pose
+        if let Some(ref node) = tree.get_node(self.input.clone()) {
+            node.get_output_pose(tree, time, params, output_poses);
+        }
+
+        // Target position should be in model-space
+        let effector_target_position = match self.smoothed_target {
+            Some(smoothed_target) if self.target_smoothing_time > 0.0 => smoothed_target,
+            _ => self.target_param(params),
+        };
+
+
+        let effector_bone_index = self.effector_bone_index;
+        let middle_bone_index = tree.skeleton.joints[effector_bone_index as usize].parent_index;
+        let root_bone_index = tree.skeleton.joints[middle_bone_index as usize].parent_index;
+        let root_bone_parent_index = tree.skeleton.joints[root_bone_index as usize].parent_index;
+
+        // Get bone positions in model-space by calculating global poses
+        let mut global_poses = PoseBuffer::<Matrix4<f32>>::new(output_poses.len());
+        tree.skeleton.calculate_global_poses(output_poses, &mut global_poses);
+
+        let root_bone_position = global_poses[root_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
+        let middle_bone_position = global_poses[middle_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
+        let effector_bone_position = global_poses[effector_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
+
+        let length_1 = vec3_len(vec3_sub(root_bone_position, middle_bone_position));
+        let length_2 = vec3_len(vec3_sub(middle_bone_position, effector_bone_position));
+
+        // Leave the input pose unchanged for degenerate chains, or a target on the root joint,
+        // rather than normalizing zero-length directions into NaNs
+        let target_distance = vec3_len(vec3_sub(effector_target_position, root_bone_position));
+        if length_1 < self.bone_length_epsilon || length_2 < self.bone_length_epsilon || target_distance < self.bone_length_epsilon {
+            return;
+        }
+
+        // get effector target position on a 2D bend plane,
+        // with coordinates relative to root bone position
 
         // x axis of bend plane
         let root_to_effector = vec3_normalized(vec3_sub(effector_target_position, root_bone_position));
 
         // z axis of bend plane
         let plane_normal = {
-            let bend_direction = [params[&self.bend_x_param[..]],
-                                  params[&self.bend_y_param[..]],
-                                  params[&self.bend_z_param[..]]];
+            let mut bend_direction = [params[&self.bend_x_param[..]],
+                                      params[&self.bend_y_param[..]],
+                                      params[&self.bend_z_param[..]]];
             if vec3_len(bend_direction) == 0.0 {
-                // Choose a somewhat arbitary bend normal:
-                vec3_normalized(vec3_cross(vec3_sub(middle_bone_position, root_bone_position),
-                                           root_to_effector))
-            } else {
-                // Use desired bend direction:
-                let desired_bend_direction = vec3_normalized(bend_direction);
-                vec3_normalized(vec3_cross(desired_bend_direction,
-                                           root_to_effector))
+                // Keep the input pose's bend: the middle joint's offset from the line between
+                // the root and effector joints. Unlike the middle joint's direction alone, this
+                // doesn't swing around as the target crosses it near full extension.
+                let root_to_middle = vec3_sub(middle_bone_position, root_bone_position);
+                let chain_direction = vec3_normalized(vec3_sub(effector_bone_position, root_bone_position));
+                bend_direction = vec3_sub(root_to_middle, vec3_scale(chain_direction, vec3_dot(root_to_middle, chain_direction)));
+            }
+            if vec3_len(bend_direction) < self.bone_length_epsilon {
+                // The input pose is straight, so bend along the root joint's bend axis
+                bend_direction = quaternion::rotate_vector(global_poses[root_bone_index as usize].get_rotation(), self.bend_axis);
+            }
+            let plane_normal = vec3_cross(vec3_normalized(bend_direction), root_to_effector);
+            if vec3_len(plane_normal) < self.bone_length_epsilon {
+                // The target lies along the bend direction, leaving no plane to bend in
+                return;
             }
+            vec3_normalized(plane_normal)
         };
 
         // y axis of bend plane
@@ -424,10 +1916,8 @@ impl<T: Transform> AnimNode<T> for IKNode {
         if let Some(elbow_target) = solve_ik_2d(length_1, length_2, [plane_target[0], plane_target[1]]) {
 
             // Copy input poses into IK target poses
-            let mut target_poses = [ T::identity(); 64 ];
-            for i in 0 .. 64 {
-                target_poses[i] = output_poses[i];
-            }
+            let mut target_poses = PoseBuffer::<T>::new(output_poses.len());
+            target_poses.copy_from_slice(output_poses);
 
             let middle_bone_plane = [elbow_target[0], elbow_target[1], 0.0];
 
@@ -435,6 +1925,15 @@ impl<T: Transform> AnimNode<T> for IKNode {
                                                                  middle_bone_plane),
                                               root_bone_position);
 
+            let effector_bone_target = vec3_add(middle_bone_target,
+                                                vec3_scale(vec3_normalized(vec3_sub(effector_target_position, middle_bone_target)), length_2));
+
+            self.last_solve.set(Some(IKDebugSolve {
+                target: effector_target_position,
+                bend_normal: plane_normal,
+                joint_positions: [root_bone_position, middle_bone_target, effector_bone_target],
+            }));
+
             // calculate root bone pose
             {
                 let original_direction = vec3_normalized(vec3_sub(middle_bone_position, root_bone_position));
@@ -445,8 +1944,14 @@ impl<T: Transform> AnimNode<T> for IKNode {
 
                 global_poses[root_bone_index as usize].set_rotation(new_rotation);
 
-                let local_pose = row_mat4_mul(mat4_inv(global_poses[root_bone_parent_index as usize]), global_poses[root_bone_index as usize]);
-                target_poses[root_bone_index as usize] = T::from_matrix(local_pose);
+                // A root joint's parent global pose is the identity
+                let parent_global_pose = if tree.skeleton.joints[root_bone_index as usize].is_root() {
+                    mat4_id()
+                } else {
+                    global_poses[root_bone_parent_index as usize]
+                };
+                let local_pose = row_mat4_mul(mat4_inv(parent_global_pose), global_poses[root_bone_index as usize]);
+                target_poses[root_bone_index as usize] = tree.skeleton.joints[root_bone_index as usize].without_orientation_offset(T::from_matrix(local_pose));
             }
 
             // calculate middle bone pose
@@ -461,16 +1966,1167 @@ impl<T: Transform> AnimNode<T> for IKNode {
                 global_poses[middle_bone_index as usize].set_translation(middle_bone_target);
 
                 let local_pose = row_mat4_mul(mat4_inv(global_poses[root_bone_index as usize]), global_poses[middle_bone_index as usize]);
-                target_poses[middle_bone_index as usize] = T::from_matrix(local_pose);
+                target_poses[middle_bone_index as usize] = tree.skeleton.joints[middle_bone_index as usize].without_orientation_offset(T::from_matrix(local_pose));
+            }
+
+            // calculate effector pose, keeping its model-space orientation from the input pose
+            if self.preserve_effector_orientation {
+                let mut local_pose = row_mat4_mul(mat4_inv(global_poses[middle_bone_index as usize]), global_poses[effector_bone_index as usize]);
+                local_pose.set_translation(output_poses[effector_bone_index as usize].get_translation());
+                target_poses[effector_bone_index as usize] = tree.skeleton.joints[effector_bone_index as usize].without_orientation_offset(T::from_matrix(local_pose));
             }
 
             // Blend between input and IK target poses
 
-            let blend_parameter = params[&self.blend_param[..]];
-            for i in 0 .. output_poses.len() {
-                let ik_pose = target_poses[i];
-                let output_pose = &mut output_poses[i];
-                (*output_pose) = output_pose.lerp(ik_pose.clone(), blend_parameter);
+            let blend_parameter = if self.blend_ramp_time > 0.0 {
+                self.blend_weight
+            } else {
+                params[&self.blend_param[..]]
+            };
+
+            // Only the chain joints have IK target poses, so leave the rest of the pose untouched
+            let chain_joints = [root_bone_index, middle_bone_index, effector_bone_index];
+            let changed_joints = if self.preserve_effector_orientation { &chain_joints[..] } else { &chain_joints[.. 2] };
+
+            for &joint in changed_joints.iter() {
+                let output_pose = &mut output_poses[joint as usize];
+                (*output_pose) = output_pose.lerp(target_poses[joint as usize], blend_parameter);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use animation::{AnimationClip, AnimationSample};
+    use math::*;
+    use skeleton::{Joint, JointIndex, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::{Transform, QVTransform, TranslationSpace};
+
+    use super::{AnimBlendTree, BlendTreeNodeDef, BlendChannels, IKNodeDef, JointWeightDef, LookAtNodeDef, TwistDistributorDef, DEFAULT_IK_BEND_AXIS, DEFAULT_IK_BONE_LENGTH_EPSILON, DEFAULT_LOOK_AT_AXIS, DEFAULT_MAX_BLEND_TREE_DEPTH, DEFAULT_TWIST_AXIS, EvalStats};
+
+    static EPSILON: f32 = 0.00001;
+
+    /// A chain of joints, each the child of the previous
+    fn chain_skeleton(joint_count: usize) -> Rc<Skeleton> {
+        Rc::new(Skeleton::from_joints((0 .. joint_count).map(|i| {
+            let parent_index = if i == 0 { ROOT_JOINT_PARENT_INDEX } else { (i - 1) as JointIndex };
+            Joint::new(&format!("joint_{}", i), parent_index, mat4_id())
+        }).collect()))
+    }
+
+    /// A clip where every joint is translated along x by `f(sample_index)`
+    fn translation_clip<F: Fn(usize) -> f32>(joint_count: usize, sample_count: usize, duration: f32, f: F) -> Rc<AnimationClip<QVTransform>> {
+        Rc::new(AnimationClip::new((0 .. sample_count).map(|sample_index| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([f(sample_index), 0.0, 0.0]);
+            AnimationSample { local_poses: vec![pose; joint_count] }
+        }).collect(), sample_count as f32 / duration))
+    }
+
+    fn clip_node(clip_id: &str) -> Box<BlendTreeNodeDef> {
+        Box::new(BlendTreeNodeDef::ClipNode(clip_id.to_string()))
+    }
+
+    /// An IKNode definition with effector joint_3, target parameters x, y and z, and bend
+    /// parameters bend_x, bend_y and bend_z
+    fn ik_node_def() -> IKNodeDef {
+        IKNodeDef::new("joint_3", "blend", ["x", "y", "z"], ["bend_x", "bend_y", "bend_z"])
+    }
+
+    /// Local poses for a chain of joints, each translated from its parent by the given offset
+    fn chain_poses(translations: &[Vector3<f32>]) -> Vec<QVTransform> {
+        translations.iter().map(|&translation| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation(translation);
+            pose
+        }).collect()
+    }
+
+    /// Local poses for a straight chain of four joints up the y axis, from joint_1 at (0, 1, 0)
+    /// with unit bones
+    fn straight_chain_poses() -> Vec<QVTransform> {
+        chain_poses(&[[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0]])
+    }
+
+    /// A blend tree applying the given IKNode definition to a single-sample clip of
+    /// `local_poses`, on a chain skeleton with a joint for each pose
+    fn ik_tree(ik: IKNodeDef, local_poses: Vec<QVTransform>) -> AnimBlendTree<QVTransform> {
+        let skeleton = chain_skeleton(local_poses.len());
+        let mut animations = HashMap::new();
+        animations.insert("pose".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample { local_poses }], 1.0)));
+        AnimBlendTree::from_def(BlendTreeNodeDef::IKNode(clip_node("pose"), Box::new(ik)), &animations, skeleton)
+    }
+
+    /// Parameters for `ik_node_def`, with the given blend weight, target and bend direction
+    fn ik_params(blend: f32, target: Vector3<f32>, bend: Vector3<f32>) -> HashMap<String, f32> {
+        let names = ["blend", "x", "y", "z", "bend_x", "bend_y", "bend_z"];
+        let values = [blend, target[0], target[1], target[2], bend[0], bend[1], bend[2]];
+        names.iter().zip(values.iter()).map(|(name, &value)| (name.to_string(), value)).collect()
+    }
+
+    #[test]
+    fn test_bake_lerp_node() {
+
+        let skeleton = chain_skeleton(2);
+
+        let mut animations = HashMap::new();
+        animations.insert("a".to_string(), translation_clip(2, 4, 1.0, |i| i as f32));
+        animations.insert("b".to_string(), translation_clip(2, 4, 1.0, |i| 10.0 + 2.0 * i as f32));
+
+        let def = BlendTreeNodeDef::LerpNode(clip_node("a"), clip_node("b"), "blend".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        let mut params = HashMap::new();
+        params.insert("blend".to_string(), 0.5);
+
+        let baked = tree.bake(&params, 8, 1.0);
+        assert_eq!(baked.samples.len(), 8);
+
+        for &time in [0.0, 0.25, 0.375, 0.5, 0.875].iter() {
+            let mut live_poses = [QVTransform::identity(); 2];
+            let mut baked_poses = [QVTransform::identity(); 2];
+            tree.get_output_pose(time, &params, &mut live_poses);
+            baked.get_pose_at_time(time, &mut baked_poses);
+            for i in 0 .. 2 {
+                let live = live_poses[i].get_translation();
+                let baked = baked_poses[i].get_translation();
+                assert!(vec3_len(vec3_sub(live, baked)) < EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_stats() {
+
+        let skeleton = chain_skeleton(2);
+
+        let mut animations = HashMap::new();
+        animations.insert("a".to_string(), translation_clip(2, 4, 1.0, |i| i as f32));
+        animations.insert("b".to_string(), translation_clip(2, 4, 1.0, |i| 2.0 * i as f32));
+
+        let def = BlendTreeNodeDef::LerpNode(clip_node("a"), clip_node("b"), "blend".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let mut tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        let mut params = HashMap::new();
+        params.insert("blend".to_string(), 0.5);
+
+        // Nothing is recorded until profiling is enabled
+        let mut poses = [QVTransform::identity(); 2];
+        tree.get_output_pose(0.5, &params, &mut poses);
+        assert_eq!(tree.last_eval_stats(), EvalStats::default());
+
+        tree.set_profiling(true);
+        for _ in 0 .. 2 {
+            tree.get_output_pose(0.5, &params, &mut poses);
+            let stats = tree.last_eval_stats();
+            assert_eq!(stats.nodes_evaluated, 3);
+            assert_eq!(stats.clips_sampled, 2);
+        }
+    }
+
+    #[test]
+    fn test_clip_local_times_synchronized() {
+
+        let skeleton = chain_skeleton(1);
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), translation_clip(1, 4, 1.0, |i| i as f32));
+        animations.insert("run".to_string(), translation_clip(1, 4, 0.5, |i| i as f32));
+
+        let def = BlendTreeNodeDef::LerpNode(clip_node("walk"), clip_node("run"), "blend".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let mut tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        let mut params = HashMap::new();
+        params.insert("blend".to_string(), 0.25);
+        tree.synchronize(0.0, &params);
+
+        let local_times = tree.clip_local_times(0.3);
+        assert_eq!(local_times.len(), 2);
+        assert_eq!(local_times[0].0, "walk");
+        assert_eq!(local_times[1].0, "run");
+
+        // Both clips should be at the same phase
+        let walk_phase = local_times[0].1 / 1.0;
+        let run_phase = local_times[1].1 / 0.5;
+        assert!((walk_phase - run_phase).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_synchronize_nested_lerp_nodes() {
+
+        let skeleton = chain_skeleton(1);
+
+        let mut animations = HashMap::new();
+        animations.insert("walk_slow".to_string(), translation_clip(1, 4, 1.2, |i| i as f32));
+        animations.insert("walk_fast".to_string(), translation_clip(1, 4, 0.8, |i| i as f32));
+        animations.insert("run_slow".to_string(), translation_clip(1, 4, 0.6, |i| i as f32));
+        animations.insert("run_fast".to_string(), translation_clip(1, 4, 0.4, |i| i as f32));
+
+        let walk = BlendTreeNodeDef::LerpNode(clip_node("walk_slow"), clip_node("walk_fast"), "speed".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let run = BlendTreeNodeDef::LerpNode(clip_node("run_slow"), clip_node("run_fast"), "speed".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let def = BlendTreeNodeDef::LerpNode(Box::new(walk), Box::new(run), "gait".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let mut tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        let mut params = HashMap::new();
+        params.insert("speed".to_string(), 0.5);
+        params.insert("gait".to_string(), 0.25);
+        tree.synchronize(0.0, &params);
+
+        // All four clips should be at the same phase
+        let durations = [1.2, 0.8, 0.6, 0.4];
+        let phases: Vec<f32> = tree.clip_local_times(0.3).iter().zip(durations.iter()).map(|(&(_, local_time), duration)| {
+            local_time / duration
+        }).collect();
+
+        for phase in phases.iter() {
+            assert!((phase - phases[0]).abs() < EPSILON);
+        }
+
+        // Blended cycle duration is 0.75 * 1.0 + 0.25 * 0.5
+        assert!((phases[0] - 0.3 / 0.875).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_rotation_only_additive() {
+
+        let skeleton = chain_skeleton(2);
+
+        let mut additive_pose = QVTransform::identity();
+        additive_pose.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], 1.0));
+        additive_pose.set_translation([0.0, 5.0, 0.0]);
+
+        let mut animations = HashMap::new();
+        animations.insert("base".to_string(), translation_clip(2, 4, 1.0, |i| 1.0 + i as f32));
+        animations.insert("additive".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample { local_poses: vec![additive_pose; 2] }], 1.0)));
+
+        let def = BlendTreeNodeDef::AdditiveNode(clip_node("base"), clip_node("additive"), "blend".to_string(), BlendChannels::Rotation, Vec::new(), None);
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        let mut params = HashMap::new();
+        params.insert("blend".to_string(), 1.0);
+
+        let mut base_poses = [QVTransform::identity(); 2];
+        let mut output_poses = [QVTransform::identity(); 2];
+        animations["base"].get_pose_at_time(0.5, &mut base_poses);
+        tree.get_output_pose(0.5, &params, &mut output_poses);
+
+        for i in 0 .. 2 {
+            let base_translation = base_poses[i].get_translation();
+            let output_translation = output_poses[i].get_translation();
+            assert!(vec3_len(vec3_sub(base_translation, output_translation)) < EPSILON);
+
+            let rotated = quaternion::rotate_vector(output_poses[i].get_rotation(), [1.0, 0.0, 0.0]);
+            let expected = quaternion::rotate_vector(additive_pose.get_rotation(), [1.0, 0.0, 0.0]);
+            assert!(vec3_len(vec3_sub(rotated, expected)) < 0.0001);
+        }
+    }
+
+    #[test]
+    fn test_additive_node_falloff() {
+
+        // joint_1 to joint_3 are the shoulder, elbow and hand
+        let skeleton = chain_skeleton(4);
+
+        let mut animations = HashMap::new();
+        animations.insert("base".to_string(), translation_clip(4, 1, 1.0, |_| 0.0));
+        animations.insert("recoil".to_string(), translation_clip(4, 1, 1.0, |_| 2.0));
+
+        let falloff = [("joint_0", 0.0), ("joint_1", 1.0), ("joint_2", 0.5), ("joint_3", 0.25)].iter().map(|&(joint, weight)| {
+            JointWeightDef { joint: joint.to_string(), weight }
+        }).collect();
+        let def = BlendTreeNodeDef::AdditiveNode(clip_node("base"), clip_node("recoil"), "blend".to_string(), BlendChannels::All, falloff, None);
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        for &blend in [1.0, 0.5].iter() {
+            let mut params = HashMap::new();
+            params.insert("blend".to_string(), blend);
+
+            let mut output_poses = [QVTransform::identity(); 4];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+
+            for (output, &weight) in output_poses.iter().zip([0.0, 1.0, 0.5, 0.25].iter()) {
+                assert!((output.get_translation()[0] - 2.0 * blend * weight).abs() < EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_additive_node_falloff() {
+
+        use rustc_serialize::json;
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "AdditiveNode",
+            "inputs": [
+                { "type": "ClipNode", "clip_source": "aim" },
+                { "type": "ClipNode", "clip_source": "recoil" }
+            ],
+            "param": "recoil",
+            "falloff": [{ "joint": "shoulder", "weight": 1.0 }, { "joint": "hand", "weight": 0.2 }],
+            "cull_threshold": 0.02
+        }"#).unwrap();
+        match def {
+            BlendTreeNodeDef::AdditiveNode(_, _, _, _, falloff, cull_threshold) => {
+                assert_eq!(cull_threshold, Some(0.02));
+                assert_eq!(falloff, vec![
+                    JointWeightDef { joint: "shoulder".to_string(), weight: 1.0 },
+                    JointWeightDef { joint: "hand".to_string(), weight: 0.2 },
+                ]);
+            }
+            _ => panic!("Expected an AdditiveNode"),
+        }
+    }
+
+    #[test]
+    fn test_additive_node_culled_at_low_weight() {
+
+        let skeleton = chain_skeleton(2);
+
+        let mut animations = HashMap::new();
+        animations.insert("base".to_string(), translation_clip(2, 4, 1.0, |i| i as f32 * 0.1));
+        animations.insert("additive".to_string(), translation_clip(2, 4, 1.0, |_| 1.0));
+
+        // The additive input is a lerp on a parameter that isn't set, so would panic if evaluated
+        let additive = BlendTreeNodeDef::LerpNode(clip_node("additive"), clip_node("additive"), "unset".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let def = BlendTreeNodeDef::AdditiveNode(clip_node("base"), Box::new(additive), "blend".to_string(), BlendChannels::All, Vec::new(), Some(0.01));
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        let mut params = HashMap::new();
+        params.insert("blend".to_string(), 0.001);
+
+        let mut base_poses = [QVTransform::identity(); 2];
+        let mut output_poses = [QVTransform::identity(); 2];
+        animations["base"].get_pose_at_time(0.6, &mut base_poses);
+        tree.get_output_pose(0.6, &params, &mut output_poses);
+
+        for (output, base) in output_poses.iter().zip(base_poses.iter()) {
+            assert_eq!(output.get_translation(), base.get_translation());
+            assert_eq!(output.get_rotation(), base.get_rotation());
+        }
+    }
+
+    #[test]
+    fn test_ik_node_degenerate_chain() {
+
+        // The effector joint coincides with the middle joint
+        let local_poses = chain_poses(&[[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]]);
+        let tree = ik_tree(ik_node_def(), local_poses.clone());
+        let params = ik_params(1.0, [1.0, 1.0, 0.0], [0.0, 0.0, 0.0]);
+
+        let mut output_poses = [QVTransform::identity(); 4];
+        tree.get_output_pose(0.0, &params, &mut output_poses);
+
+        for (output, input) in output_poses.iter().zip(local_poses.iter()) {
+            assert!(!output.get_translation().iter().any(|c| c.is_nan()));
+            assert_eq!(output.get_translation(), input.get_translation());
+        }
+    }
+
+    #[test]
+    fn test_ik_node_chain_depth() {
+
+        let mut animations = HashMap::new();
+        animations.insert("pose".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample {
+            local_poses: chain_poses(&[[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0]]),
+        }], 1.0)));
+        let ik_def = |effector: &str| {
+            let ik = IKNodeDef { effector: effector.to_string(), ..ik_node_def() };
+            BlendTreeNodeDef::IKNode(clip_node("pose"), Box::new(ik))
+        };
+
+        // The effector needs a middle and a root joint above it
+        for &effector in ["joint_0", "joint_1"].iter() {
+            assert!(AnimBlendTree::from_def_with_max_depth(ik_def(effector), &animations, &[], chain_skeleton(3), DEFAULT_MAX_BLEND_TREE_DEPTH).is_err());
+        }
+
+        // A chain whose root is the skeleton's root solves relative to the origin
+        let tree = AnimBlendTree::from_def_with_max_depth(ik_def("joint_2"), &animations, &[], chain_skeleton(3), DEFAULT_MAX_BLEND_TREE_DEPTH).unwrap();
+        let params = ik_params(1.0, [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]);
+        let mut output_poses = [QVTransform::identity(); 3];
+        tree.get_output_pose(0.0, &params, &mut output_poses);
+
+        let mut global_poses = [mat4_id(); 3];
+        tree.skeleton.calculate_global_poses(&output_poses, &mut global_poses);
+        let effector_position = global_poses[2].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(effector_position, [1.0, 1.0, 0.0])) < 0.001, "{:?}", effector_position);
+    }
+
+    #[test]
+    fn test_ik_node_bend_fallback() {
+
+        let skeleton = chain_skeleton(4);
+
+        // Model-space position of the middle joint after solving for the target, from an input
+        // pose with the middle joint bent along z by `bend`, and no bend direction parameters
+        let solve = |bend: f32, target: Vector3<f32>, bend_axis: Vector3<f32>| {
+            let local_poses = chain_poses(&[[0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 1.0, bend], [0.0, 1.0, -bend]]);
+            let tree = ik_tree(IKNodeDef { bend_axis, ..ik_node_def() }, local_poses);
+            let params = ik_params(1.0, target, [0.0, 0.0, 0.0]);
+
+            let mut output_poses = [QVTransform::identity(); 4];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+            let mut global_poses = [mat4_id(); 4];
+            skeleton.calculate_global_poses(&output_poses, &mut global_poses);
+            global_poses[2].transform_vector([0.0, 0.0, 0.0])
+        };
+
+        // Nearly straight input, with targets either side of the middle joint's direction. The
+        // middle joint keeps bending towards +z, away from the line to the target.
+        for &offset in [-0.01, 0.01].iter() {
+            let target = [0.0, 1.98, 0.099 + offset];
+            let middle = solve(0.05, target, DEFAULT_IK_BEND_AXIS);
+            let target_direction = vec3_normalized(target);
+            let bend = vec3_sub(middle, vec3_scale(target_direction, vec3_dot(middle, target_direction)));
+            assert!(bend[0].abs() < EPSILON && bend[2] > 0.0, "Middle joint flipped for offset {}: {:?}", offset, middle);
+        }
+
+        // Straight input, where the middle joint bends along the bend axis
+        let middle = solve(0.0, [0.0, 1.9, 0.0], DEFAULT_IK_BEND_AXIS);
+        assert!(middle[0].abs() < EPSILON && middle[2] > 0.1, "{:?}", middle);
+        let middle = solve(0.0, [0.0, 1.9, 0.0], [-1.0, 0.0, 0.0]);
+        assert!(middle[0] < -0.1 && middle[2].abs() < EPSILON, "{:?}", middle);
+    }
+
+    #[test]
+    fn test_ik_node_only_changes_chain() {
+
+        // joint_0 is the chain root's parent, and joint_4 the effector's child
+        let local_poses: Vec<QVTransform> = (0 .. 5).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_rotation(quaternion::axis_angle([1.0, 0.0, 0.0], 0.1 + 0.37 * i as f32));
+            pose.set_translation([0.0, if i == 0 { 0.0 } else { 1.0 }, 0.0]);
+            pose
+        }).collect();
+
+        let tree = ik_tree(ik_node_def(), local_poses.clone());
+        let mut params = ik_params(0.0, [1.0, 2.0, 0.0], [1.0, 0.0, 0.0]);
+
+        for &blend in [0.3, 1.0].iter() {
+            params.insert("blend".to_string(), blend);
+
+            let mut output_poses = [QVTransform::identity(); 5];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+
+            for &joint in [0, 3, 4].iter() {
+                assert_eq!(output_poses[joint].get_translation(), local_poses[joint].get_translation());
+                assert_eq!(output_poses[joint].get_rotation(), local_poses[joint].get_rotation());
+            }
+            for &joint in [1, 2].iter() {
+                assert!(output_poses[joint].get_rotation() != local_poses[joint].get_rotation());
+            }
+        }
+    }
+
+    #[test]
+    fn test_ik_node_preserve_effector_orientation() {
+
+        let skeleton = chain_skeleton(4);
+        let params = ik_params(1.0, [1.0, 2.0, 0.0], [-1.0, 0.0, 0.0]);
+
+        // Model-space position and x axis of the effector after the IK solve
+        let solve = |preserve_effector_orientation: bool| {
+            let tree = ik_tree(IKNodeDef { preserve_effector_orientation, ..ik_node_def() }, straight_chain_poses());
+
+            let mut output_poses = [QVTransform::identity(); 4];
+            let mut global_poses = [mat4_id(); 4];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+            skeleton.calculate_global_poses(&output_poses, &mut global_poses);
+
+            let position = global_poses[3].transform_vector([0.0, 0.0, 0.0]);
+            (position, vec3_sub(global_poses[3].transform_vector([1.0, 0.0, 0.0]), position))
+        };
+
+        // Bending away from +x, so the middle bone runs along +x from (0, 2, 0) to the target
+        let (position, x_axis) = solve(false);
+        let (preserved_position, preserved_x_axis) = solve(true);
+
+        // Both reach the target
+        assert!(vec3_len(vec3_sub(position, [1.0, 2.0, 0.0])) < 0.05);
+        assert!(vec3_len(vec3_sub(preserved_position, [1.0, 2.0, 0.0])) < 0.05);
+
+        // Without the option, the effector turns with the middle bone. With it, the effector
+        // keeps its input (identity) orientation.
+        assert!(vec3_len(vec3_sub(x_axis, [1.0, 0.0, 0.0])) > 0.5);
+        assert!(vec3_len(vec3_sub(preserved_x_axis, [1.0, 0.0, 0.0])) < 0.05);
+    }
+
+    #[test]
+    fn test_ik_node_blend_ramp() {
+
+        let skeleton = chain_skeleton(4);
+        let mut tree = ik_tree(IKNodeDef { blend_ramp_time: 0.5, ..ik_node_def() }, straight_chain_poses());
+        let mut params = ik_params(0.0, [1.0, 2.0, 0.0], [0.0, 0.0, 0.0]);
+
+        let effector_distance = |tree: &AnimBlendTree<QVTransform>, params: &HashMap<String, f32>| {
+            let mut local_poses = [QVTransform::identity(); 4];
+            let mut global_poses = [mat4_id(); 4];
+            tree.get_output_pose(0.0, params, &mut local_poses);
+            skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+            vec3_len(vec3_sub(global_poses[3].transform_vector([0.0, 0.0, 0.0]), [1.0, 2.0, 0.0]))
+        };
+
+        tree.update(0.0, &params);
+        let mut last_distance = effector_distance(&tree, &params);
+
+        // Turning IK on ramps the blend weight in over half a second, rather than snapping
+        params.insert("blend".to_string(), 1.0);
+        for &(time, expected_weight) in [(0.1, 0.2), (0.2, 0.4), (0.35, 0.7), (0.5, 1.0), (0.6, 1.0)].iter() {
+            tree.update(time, &params);
+            assert!((tree.ik_nodes[0].blend_weight - expected_weight).abs() < EPSILON);
+
+            let distance = effector_distance(&tree, &params);
+            assert!(distance <= last_distance);
+            last_distance = distance;
+        }
+        assert!(last_distance < 0.05);
+
+        // And ramps back out
+        params.insert("blend".to_string(), 0.0);
+        tree.update(0.7, &params);
+        assert!((tree.ik_nodes[0].blend_weight - 0.8).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_ik_node_target_smoothing() {
+
+        let skeleton = chain_skeleton(4);
+        let mut tree = ik_tree(IKNodeDef { target_smoothing_time: 0.2, ..ik_node_def() }, straight_chain_poses());
+        let mut params = ik_params(1.0, [1.0, 2.0, 0.0], [1.0, 0.0, 0.0]);
+
+        let effector_position = |tree: &AnimBlendTree<QVTransform>, params: &HashMap<String, f32>| {
+            let mut local_poses = [QVTransform::identity(); 4];
+            let mut global_poses = [mat4_id(); 4];
+            tree.get_output_pose(0.0, params, &mut local_poses);
+            skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+            global_poses[3].transform_vector([0.0, 0.0, 0.0])
+        };
+
+        // The first update takes the target immediately
+        tree.update(0.0, &params);
+        assert!(vec3_len(vec3_sub(effector_position(&tree, &params), [1.0, 2.0, 0.0])) < EPSILON);
+
+        // Teleporting the target eases the effector towards it, rather than snapping
+        params.insert("x".to_string(), -1.0);
+        let mut last_distance = 2.0;
+        for frame in 1 .. 30 {
+            tree.update(frame as f32 / 30.0, &params);
+            let smoothed_target = tree.ik_nodes[0].smoothed_target.unwrap();
+            let distance = vec3_len(vec3_sub(smoothed_target, [-1.0, 2.0, 0.0]));
+            assert!(distance < last_distance);
+            if frame == 1 {
+                assert!(distance > 1.5);
+                assert!(vec3_len(vec3_sub(effector_position(&tree, &params), [1.0, 2.0, 0.0])) < 0.5);
+            }
+            last_distance = distance;
+        }
+
+        // After several time constants the effector has arrived
+        assert!(last_distance < 0.02);
+        assert!(vec3_len(vec3_sub(effector_position(&tree, &params), [-1.0, 2.0, 0.0])) < 0.05);
+    }
+
+    #[test]
+    fn test_ik_debug_solve() {
+
+        let tree = ik_tree(ik_node_def(), straight_chain_poses());
+        assert!(tree.ik_debug_solves().is_empty());
+
+        let params = ik_params(1.0, [1.0, 2.0, 0.0], [1.0, 0.0, 0.0]);
+
+        let mut output_poses = [QVTransform::identity(); 4];
+        tree.get_output_pose(0.0, &params, &mut output_poses);
+
+        let solves = tree.ik_debug_solves();
+        assert_eq!(solves.len(), 1);
+
+        let close = |a: Vector3<f32>, b: Vector3<f32>| vec3_len(vec3_sub(a, b)) < 0.001;
+
+        // Chain from (0, 1, 0) with unit bones, bending towards +x, reaches (1, 2, 0) via (1, 1, 0)
+        let [root, middle, effector] = solves[0].joint_positions;
+        assert!(close(root, [0.0, 1.0, 0.0]));
+        assert!(close(middle, [1.0, 1.0, 0.0]));
+        assert!(close(effector, [1.0, 2.0, 0.0]));
+        assert!(close(solves[0].target, [1.0, 2.0, 0.0]));
+        assert!((vec3_dot(solves[0].bend_normal, [0.0, 0.0, 1.0]).abs() - 1.0).abs() < 0.001);
+
+        // A cross at the target, two bones, and the bend normal
+        let lines = solves[0].debug_lines();
+        assert_eq!(lines.len(), 6);
+        for &(start, end, _) in lines[0 .. 3].iter() {
+            assert!(close(vec3_scale(vec3_add(start, end), 0.5), [1.0, 2.0, 0.0]));
+        }
+        assert!(close(lines[3].0, root) && close(lines[3].1, middle));
+        assert!(close(lines[4].0, middle) && close(lines[4].1, effector));
+        assert!(close(lines[5].0, middle));
+    }
+
+    #[test]
+    fn test_clip_index_node() {
+
+        let walk = translation_clip(1, 4, 1.0, |i| i as f32);
+        let run = translation_clip(1, 4, 0.5, |i| 2.0 * i as f32);
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), walk.clone());
+        animations.insert("run".to_string(), run.clone());
+        let named_def = BlendTreeNodeDef::LerpNode(clip_node("walk"), clip_node("run"), "blend".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let named_tree = AnimBlendTree::from_def(named_def, &animations, chain_skeleton(1));
+
+        let clip_table = [run, walk];
+        let indexed_def = BlendTreeNodeDef::LerpNode(Box::new(BlendTreeNodeDef::ClipIndexNode(1)), Box::new(BlendTreeNodeDef::ClipIndexNode(0)),
+                                                     "blend".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        let indexed_tree = AnimBlendTree::from_def_with_clip_table(indexed_def, &HashMap::new(), &clip_table, chain_skeleton(1));
+
+        let mut params = HashMap::new();
+        params.insert("blend".to_string(), 0.3);
+
+        for i in 0 .. 10 {
+            let time = i as f32 * 0.1;
+            let mut named_poses = [QVTransform::identity()];
+            let mut indexed_poses = [QVTransform::identity()];
+            named_tree.get_output_pose(time, &params, &mut named_poses);
+            indexed_tree.get_output_pose(time, &params, &mut indexed_poses);
+            assert_eq!(named_poses[0].get_translation(), indexed_poses[0].get_translation());
+        }
+
+        assert_eq!(indexed_tree.clip_local_times(0.0)[0].0, "#1");
+    }
+
+    #[test]
+    fn test_decode_clip_index_node() {
+
+        use rustc_serialize::json;
+
+        let def: BlendTreeNodeDef = json::decode(r#"{ "type": "ClipNode", "clip_index": 2 }"#).unwrap();
+        match def {
+            BlendTreeNodeDef::ClipIndexNode(clip_index) => assert_eq!(clip_index, 2),
+            _ => panic!("Expected a ClipIndexNode"),
+        }
+
+        let def: BlendTreeNodeDef = json::decode(r#"{ "type": "ClipNode", "clip_source": "walk" }"#).unwrap();
+        match def {
+            BlendTreeNodeDef::ClipNode(clip_id) => assert_eq!(clip_id, "walk"),
+            _ => panic!("Expected a ClipNode"),
+        }
+    }
+
+    #[test]
+    fn test_hash_select_node() {
+
+        let variant_count = 3;
+        let mut animations = HashMap::new();
+        let mut inputs = Vec::new();
+        for variant in 0 .. variant_count {
+            let clip_id = format!("idle_{}", variant);
+            animations.insert(clip_id.clone(), translation_clip(1, 1, 1.0, |_| variant as f32));
+            inputs.push(BlendTreeNodeDef::ClipNode(clip_id));
+        }
+
+        let def = BlendTreeNodeDef::HashSelectNode(inputs, "instance_id".to_string());
+        assert_eq!(def.referenced_params(), vec!["instance_id"]);
+
+        let tree = AnimBlendTree::from_def(def, &animations, chain_skeleton(1));
+
+        let selected_variant = |instance_id: u32| {
+            let mut params = HashMap::new();
+            params.insert("instance_id".to_string(), instance_id as f32);
+            let mut output_poses = [QVTransform::identity()];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+            output_poses[0].get_translation()[0] as usize
+        };
+
+        let mut counts = vec![0; variant_count];
+        for instance_id in 0 .. 300 {
+            let variant = selected_variant(instance_id);
+            assert_eq!(selected_variant(instance_id), variant);
+            counts[variant] += 1;
+        }
+
+        // Consecutive ids spread across all variants
+        for &count in counts.iter() {
+            assert!(count > 70, "Uneven variant selection: {:?}", counts);
+        }
+    }
+
+    #[test]
+    fn test_blend_space_1d_node() {
+
+        // Single-sample clips translated along x, with different durations
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), translation_clip(1, 1, 1.0, |_| 0.0));
+        animations.insert("jog".to_string(), translation_clip(1, 1, 2.0, |_| 10.0));
+        animations.insert("run".to_string(), translation_clip(1, 1, 4.0, |_| 100.0));
+
+        // Positions needn't be given in order
+        let def = BlendTreeNodeDef::BlendSpace1DNode(vec![
+            (3.0, clip_node("run")),
+            (0.0, clip_node("walk")),
+            (1.0, clip_node("jog")),
+        ], "speed".to_string());
+        assert_eq!(def.referenced_params(), vec!["speed"]);
+        assert_eq!(def.referenced_clips(), vec!["run", "walk", "jog"]);
+
+        let mut tree = AnimBlendTree::from_def(def, &animations, chain_skeleton(1));
+
+        let output_x = |tree: &AnimBlendTree<QVTransform>, speed: f32| {
+            let mut params = HashMap::new();
+            params.insert("speed".to_string(), speed);
+            let mut output_poses = [QVTransform::identity()];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+            output_poses[0].get_translation()[0]
+        };
+
+        for &(speed, expected_x) in [(-1.0, 0.0), (0.0, 0.0), (0.5, 5.0), (1.0, 10.0), (2.0, 55.0), (3.0, 100.0), (5.0, 100.0)].iter() {
+            let x = output_x(&tree, speed);
+            assert!((x - expected_x).abs() < EPSILON, "speed {}: expected x {}, got {}", speed, expected_x, x);
+        }
+        assert_eq!(output_x(&tree, f32::NAN), 0.0);
+
+        // The active pair is synchronized to their blended duration, here halfway between
+        // walk and jog: 1.5 seconds
+        let mut params = HashMap::new();
+        params.insert("speed".to_string(), 0.5);
+        tree.synchronize(0.0, &params);
+        let local_times = tree.clip_local_times(0.75);
+        let phase = |clip_id: &str, duration: f32| {
+            local_times.iter().find(|&(id, _)| id == clip_id).unwrap().1 / duration
+        };
+        assert!((phase("walk", 1.0) - 0.5).abs() < EPSILON);
+        assert!((phase("jog", 2.0) - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_decode_ik_node() {
+
+        use rustc_serialize::json;
+
+        let decode_ik = |extra_fields: &str| {
+            let def: BlendTreeNodeDef = json::decode(&format!(r#"{{
+                "type": "IKNode",
+                "input": {{ "type": "ClipNode", "clip_source": "idle" }},
+                "effector": "hand_r",
+                "blend_param": "reach",
+                "target_x_param": "tx", "target_y_param": "ty", "target_z_param": "tz",
+                "bend_x_param": "bx", "bend_y_param": "by", "bend_z_param": "bz"{}
+            }}"#, extra_fields)).unwrap();
+            match def {
+                BlendTreeNodeDef::IKNode(input, ik) => {
+                    assert_eq!(input.referenced_clips(), vec!["idle"]);
+                    *ik
+                }
+                _ => panic!("Expected an IKNode"),
+            }
+        };
+
+        let ik = decode_ik("");
+        assert_eq!(ik, IKNodeDef::new("hand_r", "reach", ["tx", "ty", "tz"], ["bx", "by", "bz"]));
+        assert_eq!(ik.bone_length_epsilon, DEFAULT_IK_BONE_LENGTH_EPSILON);
+        assert_eq!(ik.bend_axis, DEFAULT_IK_BEND_AXIS);
+
+        let ik = decode_ik(r#",
+            "bone_length_epsilon": 0.01,
+            "blend_ramp_time": 0.3,
+            "preserve_effector_orientation": true,
+            "target_smoothing_time": 0.1,
+            "bend_axis": [1.0, 0.0, 0.0]"#);
+        assert_eq!(ik, IKNodeDef {
+            bone_length_epsilon: 0.01,
+            blend_ramp_time: 0.3,
+            preserve_effector_orientation: true,
+            target_smoothing_time: 0.1,
+            bend_axis: [1.0, 0.0, 0.0],
+            ..IKNodeDef::new("hand_r", "reach", ["tx", "ty", "tz"], ["bx", "by", "bz"])
+        });
+    }
+
+    #[test]
+    fn test_decode_blend_space_1d_node() {
+
+        use rustc_serialize::json;
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "BlendSpace1DNode",
+            "param": "speed",
+            "inputs": [
+                { "position": 0.0, "input": { "type": "ClipNode", "clip_source": "walk" } },
+                { "position": 2.5, "input": { "type": "ClipNode", "clip_source": "run" } }
+            ]
+        }"#).unwrap();
+        match def {
+            BlendTreeNodeDef::BlendSpace1DNode(ref inputs, ref param) => {
+                assert_eq!(param, "speed");
+                assert_eq!(inputs.iter().map(|(position, _)| *position).collect::<Vec<_>>(), vec![0.0, 2.5]);
+            }
+            _ => panic!("Expected a BlendSpace1DNode"),
+        }
+        assert_eq!(def.referenced_clips(), vec!["walk", "run"]);
+    }
+
+    #[test]
+    fn test_blend_space_1d_node_non_finite_position() {
+
+        use rustc_serialize::json;
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), translation_clip(1, 4, 1.0, |i| i as f32));
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "BlendSpace1DNode",
+            "param": "speed",
+            "inputs": [
+                { "position": 0.0, "input": { "type": "ClipNode", "clip_source": "walk" } },
+                { "position": 1.0, "input": { "type": "ClipNode", "clip_source": "walk" } },
+                { "position": null, "input": { "type": "ClipNode", "clip_source": "walk" } }
+            ]
+        }"#).unwrap();
+
+        assert!(AnimBlendTree::from_def_with_max_depth(def, &animations, &[], chain_skeleton(1), DEFAULT_MAX_BLEND_TREE_DEPTH).is_err());
+    }
+
+    #[test]
+    fn test_speed_node() {
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), translation_clip(1, 4, 1.0, |i| i as f32));
+
+        let def = BlendTreeNodeDef::SpeedNode(clip_node("walk"), "speed".to_string());
+        assert_eq!(def.referenced_params(), vec!["speed"]);
+        let mut tree = AnimBlendTree::from_def(def, &animations, chain_skeleton(1));
+
+        let set_speed = |tree: &mut AnimBlendTree<QVTransform>, time: f32, speed: f32| {
+            let mut params = HashMap::new();
+            params.insert("speed".to_string(), speed);
+            tree.update(time, &params);
+            params
+        };
+        let output_x = |tree: &AnimBlendTree<QVTransform>, time: f32, params: &HashMap<String, f32>| {
+            let mut output_poses = [QVTransform::identity()];
+            tree.get_output_pose(time, params, &mut output_poses);
+            output_poses[0].get_translation()[0]
+        };
+
+        // At 2x speed, the clip is sampled at double the time
+        let params = set_speed(&mut tree, 0.0, 2.0);
+        assert!((output_x(&tree, 0.25, &params) - 2.0).abs() < EPSILON);
+        assert!((tree.clip_local_times(0.25)[0].1 - 0.5).abs() < EPSILON);
+
+        // Zero speed holds the clip where it is
+        let params = set_speed(&mut tree, 0.25, 0.0);
+        assert!((output_x(&tree, 1.0, &params) - 2.0).abs() < EPSILON);
+
+        // Negative speed plays in reverse, wrapping back past the start of the looping clip
+        let params = set_speed(&mut tree, 1.0, -1.0);
+        assert!((output_x(&tree, 1.25, &params) - 1.0).abs() < EPSILON);
+        assert!((output_x(&tree, 1.75, &params) - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_speed_node_keeps_user_speed() {
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), translation_clip(1, 4, 1.0, |i| i as f32));
+
+        let def = BlendTreeNodeDef::SpeedNode(clip_node("walk"), "speed".to_string());
+        let mut tree = AnimBlendTree::from_def(def, &animations, chain_skeleton(1));
+        tree.clip_nodes[0].clip.set_user_speed(0.0, 1.5);
+
+        let mut params = HashMap::new();
+        params.insert("speed".to_string(), 2.0);
+        tree.update(0.0, &params);
+        tree.update(0.1, &params);
+
+        // The SpeedNode's factor multiplies the user speed instead of replacing it
+        assert!((tree.clip_nodes[0].clip.user_speed - 1.5).abs() < EPSILON);
+        assert!((tree.clip_local_times(0.2)[0].1 - 0.6).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_max_depth() {
+
+        let mut animations = HashMap::new();
+        animations.insert("a".to_string(), translation_clip(1, 1, 1.0, |_| 1.0));
+
+        // Each twist node nests its input one level deeper
+        let nested = |depth: usize| (1 .. depth).fold(BlendTreeNodeDef::ClipNode("a".to_string()), |input, _| {
+            BlendTreeNodeDef::TwistNode(Box::new(input), "joint_0".to_string(), Vec::new(), DEFAULT_TWIST_AXIS)
+        });
+
+        assert!(AnimBlendTree::from_def_with_max_depth(nested(8), &animations, &[], chain_skeleton(1), 8).is_ok());
+        assert!(AnimBlendTree::from_def_with_max_depth(nested(9), &animations, &[], chain_skeleton(1), 8).is_err());
+
+        let deep = nested(5_000);
+        assert!(deep.check_depth(DEFAULT_MAX_BLEND_TREE_DEPTH).is_err());
+        assert!(AnimBlendTree::from_def_with_max_depth(deep, &animations, &[], chain_skeleton(1), DEFAULT_MAX_BLEND_TREE_DEPTH).is_err());
+    }
+
+    #[test]
+    fn test_from_def_errors() {
+
+        let mut animations = HashMap::new();
+        animations.insert("a".to_string(), translation_clip(1, 1, 1.0, |_| 1.0));
+
+        let build = |def: BlendTreeNodeDef| {
+            AnimBlendTree::<QVTransform>::from_def_with_max_depth(def, &animations, &[], chain_skeleton(1), DEFAULT_MAX_BLEND_TREE_DEPTH).err()
+        };
+
+        assert_eq!(build(BlendTreeNodeDef::ClipNode("b".to_string())), Some("Missing animation clip: b".to_string()));
+        assert_eq!(build(BlendTreeNodeDef::ClipIndexNode(0)), Some("Missing animation clip at index: 0".to_string()));
+        assert_eq!(build(BlendTreeNodeDef::TwistNode(clip_node("a"), "wrist".to_string(), Vec::new(), DEFAULT_TWIST_AXIS)),
+                   Some("Unknown twist joint: wrist".to_string()));
+        assert_eq!(build(BlendTreeNodeDef::MaskNode(clip_node("a"), clip_node("a"), vec!["arm".to_string()], "blend".to_string())),
+                   Some("Unknown mask joint: arm".to_string()));
+        assert_eq!(build(BlendTreeNodeDef::IKNode(clip_node("a"), Box::new(ik_node_def()))),
+                   Some("Unknown IK effector joint: joint_3".to_string()));
+    }
+
+    #[test]
+    fn test_mask_node() {
+
+        use rustc_serialize::json;
+
+        // The mask includes the arm beneath the spine, but not the leg
+        let skeleton = Rc::new(Skeleton::from_joints(vec![
+            Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("spine", 0, mat4_id()),
+            Joint::new("arm", 1, mat4_id()),
+            Joint::new("leg", 0, mat4_id()),
+        ]));
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), translation_clip(4, 1, 1.0, |_| 1.0));
+        animations.insert("wave".to_string(), translation_clip(4, 1, 1.0, |_| 5.0));
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "MaskNode",
+            "param": "wave",
+            "joints": ["spine"],
+            "inputs": [{ "type": "ClipNode", "clip_source": "walk" }, { "type": "ClipNode", "clip_source": "wave" }]
+        }"#).unwrap();
+        assert_eq!(def.referenced_params(), vec!["wave"]);
+
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        for &(weight, masked_x) in [(1.0, 5.0), (0.5, 3.0), (0.0, 1.0)].iter() {
+            let mut params = HashMap::new();
+            params.insert("wave".to_string(), weight);
+
+            let mut output_poses = [QVTransform::identity(); 4];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+
+            let x: Vec<f32> = output_poses.iter().map(|pose| pose.get_translation()[0]).collect();
+            assert_eq!(x, vec![1.0, masked_x, masked_x, 1.0]);
+        }
+    }
+
+    #[test]
+    fn test_twist_node() {
+
+        let skeleton = chain_skeleton(4);
+
+        // The wrist (joint_3) rolls 1 radian about its bone axis
+        let mut wrist_pose = QVTransform::identity();
+        wrist_pose.set_rotation(quaternion::axis_angle(DEFAULT_TWIST_AXIS, 1.0));
+        let mut animations = HashMap::new();
+        animations.insert("roll".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample {
+            local_poses: vec![QVTransform::identity(), QVTransform::identity(), QVTransform::identity(), wrist_pose],
+        }], 1.0)));
+
+        let distributors = vec![
+            TwistDistributorDef { joint: "joint_1".to_string(), fraction: 0.25 },
+            TwistDistributorDef { joint: "joint_2".to_string(), fraction: 0.5 },
+        ];
+        let def = BlendTreeNodeDef::TwistNode(clip_node("roll"), "joint_3".to_string(), distributors, DEFAULT_TWIST_AXIS);
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+        let mut output_poses = [QVTransform::identity(); 4];
+        tree.get_output_pose(0.0, &HashMap::new(), &mut output_poses);
+
+        let roll = |joint: usize| quaternion_twist_angle(output_poses[joint].get_rotation(), DEFAULT_TWIST_AXIS);
+        assert!(roll(0).abs() < EPSILON);
+        assert!((roll(1) - 0.25).abs() < EPSILON);
+        assert!((roll(2) - 0.5).abs() < EPSILON);
+        assert!((roll(3) - 1.0).abs() < EPSILON);
+
+        use rustc_serialize::json;
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "TwistNode",
+            "input": { "type": "ClipNode", "clip_source": "roll" },
+            "source": "wrist",
+            "distributors": [{ "joint": "forearm_twist", "fraction": 0.5 }]
+        }"#).unwrap();
+        match def {
+            BlendTreeNodeDef::TwistNode(_, source, distributors, axis) => {
+                assert_eq!(source, "wrist");
+                assert_eq!(distributors[0].joint, "forearm_twist");
+                assert_eq!(axis, DEFAULT_TWIST_AXIS);
+            }
+            _ => panic!("Expected a TwistNode"),
+        }
+    }
+
+    #[test]
+    fn test_look_at_node() {
+
+        let skeleton = chain_skeleton(2);
+
+        // A neck at (1, 0, 0) turned 90 degrees about z, with the head 1 along its y axis
+        let mut neck_pose = QVTransform::identity();
+        neck_pose.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], ::std::f32::consts::PI / 2.0));
+        neck_pose.set_translation([1.0, 0.0, 0.0]);
+        let mut head_pose = QVTransform::identity();
+        head_pose.set_translation([0.0, 1.0, 0.0]);
+
+        let mut animations = HashMap::new();
+        animations.insert("pose".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample {
+            local_poses: vec![neck_pose, head_pose],
+        }], 1.0)));
+
+        let def = BlendTreeNodeDef::LookAtNode(clip_node("pose"), Box::new(LookAtNodeDef::new("joint_1", "blend", ["x", "y", "z"])));
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton.clone());
+
+        let mut params = HashMap::new();
+        for &(name, value) in [("blend", 1.0), ("x", 0.0), ("y", 3.0), ("z", 4.0)].iter() {
+            params.insert(name.to_string(), value);
+        }
+
+        let aim = |params: &HashMap<String, f32>| {
+            let mut local_poses = [QVTransform::identity(); 2];
+            let mut global_poses = [mat4_id(); 2];
+            tree.get_output_pose(0.0, params, &mut local_poses);
+            skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+            let position = global_poses[1].transform_vector([0.0, 0.0, 0.0]);
+            (position, vec3_sub(global_poses[1].transform_vector(DEFAULT_LOOK_AT_AXIS), position))
+        };
+
+        // The head at (0, 0, 0) points its +Y axis at the target, without moving
+        let (position, forward) = aim(&params);
+        assert!(vec3_len(position) < EPSILON);
+        assert!(vec3_len(vec3_sub(forward, [0.0, 0.6, 0.8])) < EPSILON);
+
+        // With no blend weight, the head keeps pointing along the neck's y axis, i.e. -x
+        params.insert("blend".to_string(), 0.0);
+        let (_, forward) = aim(&params);
+        assert!(vec3_len(vec3_sub(forward, [-1.0, 0.0, 0.0])) < EPSILON);
+
+        use rustc_serialize::json;
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "LookAtNode",
+            "input": { "type": "ClipNode", "clip_source": "pose" },
+            "joint": "head",
+            "target_x_param": "x",
+            "target_y_param": "y",
+            "target_z_param": "z",
+            "blend_param": "blend",
+            "axis": [0.0, 0.0, 1.0]
+        }"#).unwrap();
+        assert_eq!(def.referenced_params(), vec!["x", "y", "z", "blend"]);
+        match def {
+            BlendTreeNodeDef::LookAtNode(_, ref look_at) => {
+                let mut expected = LookAtNodeDef::new("head", "blend", ["x", "y", "z"]);
+                expected.axis = [0.0, 0.0, 1.0];
+                assert_eq!(**look_at, expected);
+            }
+            _ => panic!("Expected a LookAtNode"),
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown_node_type() {
+
+        use rustc_serialize::json;
+
+        let result: Result<BlendTreeNodeDef, _> = json::decode(r#"{ "type": "ClipNdoe", "clip_source": "walk" }"#);
+        assert!(result.is_err());
+
+        let result: Result<BlendTreeNodeDef, _> = json::decode(r#"{
+            "type": "LerpNode",
+            "param": "blend",
+            "inputs": [{ "type": "ClipNode", "clip_source": "a" }, { "type": "BlendNode", "clip_source": "b" }]
+        }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_decode_blend_channels() {
+
+        use rustc_serialize::json;
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "AdditiveNode",
+            "param": "blend",
+            "channels": "rotation",
+            "inputs": [{ "type": "ClipNode", "clip_source": "a" }, { "type": "ClipNode", "clip_source": "b" }]
+        }"#).unwrap();
+
+        match def {
+            BlendTreeNodeDef::AdditiveNode(_, _, _, channels, _, cull_threshold) => {
+                assert_eq!(channels, BlendChannels::Rotation);
+                // No culling unless a threshold is given
+                assert_eq!(cull_threshold, None);
+            }
+            _ => panic!("Expected AdditiveNode"),
+        }
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "LerpNode",
+            "param": "blend",
+            "inputs": [{ "type": "ClipNode", "clip_source": "a" }, { "type": "ClipNode", "clip_source": "b" }]
+        }"#).unwrap();
+
+        match def {
+            BlendTreeNodeDef::LerpNode(_, _, _, channels, translation_space) => {
+                assert_eq!(channels, BlendChannels::All);
+                assert_eq!(translation_space, TranslationSpace::Parent);
+            }
+            _ => panic!("Expected LerpNode"),
+        }
+
+        let def: BlendTreeNodeDef = json::decode(r#"{
+            "type": "LerpNode",
+            "param": "blend",
+            "translation_space": "joint",
+            "inputs": [{ "type": "ClipNode", "clip_source": "a" }, { "type": "ClipNode", "clip_source": "b" }]
+        }"#).unwrap();
+
+        match def {
+            BlendTreeNodeDef::LerpNode(_, _, _, _, translation_space) => assert_eq!(translation_space, TranslationSpace::Joint),
+            _ => panic!("Expected LerpNode"),
+        }
+    }
+
+    #[test]
+    fn test_lerp_node_beyond_pose_buffer_capacity() {
+
+        use pose::POSE_BUFFER_CAPACITY;
+
+        for &joint_count in [POSE_BUFFER_CAPACITY, POSE_BUFFER_CAPACITY + 1].iter() {
+
+            let skeleton = Rc::new(Skeleton::from_joints((0 .. joint_count).map(|i| {
+                Joint::new(&format!("joint_{}", i), ROOT_JOINT_PARENT_INDEX, mat4_id())
+            }).collect()));
+
+            let mut animations = HashMap::new();
+            animations.insert("a".to_string(), translation_clip(joint_count, 2, 1.0, |_| 0.0));
+            animations.insert("b".to_string(), translation_clip(joint_count, 2, 1.0, |_| 2.0));
+
+            let def = BlendTreeNodeDef::LerpNode(clip_node("a"), clip_node("b"), "blend".to_string(), BlendChannels::All, TranslationSpace::Parent);
+            let tree = AnimBlendTree::from_def(def, &animations, skeleton);
+
+            let mut params = HashMap::new();
+            params.insert("blend".to_string(), 0.5);
+
+            let mut output_poses = vec![QVTransform::identity(); joint_count];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+
+            for pose in output_poses.iter() {
+                assert!((pose.get_translation()[0] - 1.0).abs() < EPSILON);
             }
         }
     }
@@ -0,0 +1,188 @@
+//! Convenience loading of a complete animated character: its skeleton, animation controller
+//! and skinned renderer, from a single asset definition file.
+
+use std::path::Path;
+use std::rc::Rc;
+
+use collada::document::ColladaDocument;
+use gfx;
+use gfx_texture::TextureContext;
+
+use controller::AnimationController;
+use manager::{AssetError, AssetManager};
+use skeleton::Skeleton;
+use skinned_renderer::{SkinnedRenderer, SkinnedRendererError, HasShaderSources};
+use transform::{Transform, FromTransform};
+
+/// The skinned mesh of a character, read from the `skinned_mesh` field of an asset definition
+/// file. Paths are relative to the definition file's folder.
+#[derive(Debug, Clone, RustcDecodable)]
+pub struct SkinnedMeshDef {
+    /// COLLADA file with the mesh and its skeleton
+    pub source: String,
+
+    /// Texture for each object in the mesh
+    pub textures: Vec<String>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct CharacterDefs {
+    skinned_mesh: SkinnedMeshDef,
+}
+
+/// A skeleton, animation controller and skinned renderer, bundled into a ready-to-drive
+/// character
+pub struct Character<R: gfx::Resources, T: Transform> {
+    pub skeleton: Rc<Skeleton>,
+    pub controller: AnimationController<T>,
+    pub renderer: SkinnedRenderer<R, T>,
+    global_poses: Vec<T>,
+}
+
+impl<'a, R: gfx::Resources, T: Transform + FromTransform<T> + HasShaderSources<'a>> Character<R, T> {
+
+    /// Advance the character's animation by the given time delta
+    pub fn update(&mut self, delta_time: f64) {
+        self.controller.update(delta_time);
+    }
+
+    /// Render the character in its current pose
+    pub fn render<C: gfx::CommandBuffer<R>, Rf: gfx::format::RenderFormat> (
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        out_color: &gfx::handle::RenderTargetView<R, Rf>,
+        out_depth: &gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        view: [[f32; 4]; 4],
+        projection: [[f32; 4]; 4],
+    )
+        where T: gfx::traits::Pod
+    {
+        self.controller.get_output_pose(0.0, &mut self.global_poses[..]);
+        self.renderer.render(encoder, out_color, out_depth, view, projection, &self.global_poses[..]);
+    }
+}
+
+/// Load a character from an asset definition file, which in addition to the usual asset
+/// definitions (see `AssetManager::load_assets`) has a `skinned_mesh` (see `SkinnedMeshDef`),
+/// driving it with the named controller
+pub fn load_character<'a, F, R, C, T>(
+    tcx: &mut TextureContext<F, R, C>,
+    asset_json: &str,
+    controller_name: &str,
+) -> Result<Character<R, T>, AssetError>
+    where F: gfx::Factory<R>,
+          R: gfx::Resources,
+          C: gfx::CommandBuffer<R>,
+          T: Transform + FromTransform<T> + HasShaderSources<'a>
+{
+    let character_defs: CharacterDefs = AssetManager::<T>::load_def_from_path(asset_json)?;
+    let parent_path = Path::new(asset_json).parent().ok_or_else(|| AssetError::InvalidPath(asset_json.to_string()))?;
+
+    let mut asset_manager = AssetManager::<T>::new();
+    asset_manager.load_assets(asset_json)?;
+
+    let (mesh_document, skeleton) = load_mesh(parent_path, &character_defs.skinned_mesh.source)?;
+
+    let skeleton = Rc::new(skeleton);
+    let controller = build_controller(&asset_manager, controller_name, skeleton.clone())?;
+
+    let texture_paths: Vec<String> = character_defs.skinned_mesh.textures.iter().map(|texture| {
+        parent_path.join(texture).to_string_lossy().into_owned()
+    }).collect();
+    let renderer = SkinnedRenderer::from_collada(tcx, mesh_document, texture_paths.iter().map(|p| &p[..]).collect())
+        .map_err(|e| match e {
+            SkinnedRendererError::Program(e) => AssetError::Program(e),
+            SkinnedRendererError::Texture(path, e) => AssetError::Texture(path, e),
+            SkinnedRendererError::NoTextures | SkinnedRendererError::PaletteTooLarge(..) => AssetError::Mesh(character_defs.skinned_mesh.source.clone(), e.to_string()),
+        })?;
+
+    Ok(Character {
+        global_poses: vec![T::identity(); skeleton.joints.len()],
+        skeleton,
+        controller,
+        renderer,
+    })
+}
+
+/// Load the COLLADA document for a skinned mesh, relative to the given folder, along with its
+/// skeleton
+fn load_mesh(parent_path: &Path, source: &str) -> Result<(ColladaDocument, Skeleton), AssetError> {
+    let mesh_error = |e: &str| AssetError::Mesh(source.to_string(), e.to_string());
+    let mesh_document = ColladaDocument::from_path(&parent_path.join(source)).map_err(mesh_error)?;
+    let skeleton = match mesh_document.get_skeletons() {
+        Some(ref skeletons) if !skeletons.is_empty() => Skeleton::from_collada(&skeletons[0]),
+        _ => return Err(mesh_error("No skeleton found")),
+    };
+    skeleton.validate().map_err(|names| mesh_error(&format!("Duplicate joint names {:?}", names)))?;
+    Ok((mesh_document, skeleton))
+}
+
+/// Build the named controller from the loaded assets, for the given skeleton
+fn build_controller<T: Transform>(
+    asset_manager: &AssetManager<T>,
+    controller_name: &str,
+    skeleton: Rc<Skeleton>,
+) -> Result<AnimationController<T>, AssetError> {
+    let controller_def = asset_manager.controller_defs.get(controller_name)
+        .ok_or_else(|| AssetError::MissingController(controller_name.to_string()))?;
+    AnimationController::try_new(controller_def.clone(), skeleton, &asset_manager.animation_clips)
+        .map_err(|e| AssetError::InvalidController(controller_name.to_string(), e))
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::path::Path;
+    use std::rc::Rc;
+
+    use animation::{AnimationClip, AnimationSample};
+    use blend_tree::BlendTreeNodeDef;
+    use controller::{AnimationControllerDef, AnimationStateDef};
+    use manager::{AssetError, AssetManager};
+    use math::*;
+    use skeleton::{Joint, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::{Transform, QVTransform};
+
+    use super::{build_controller, load_mesh};
+
+    #[test]
+    fn test_build_controller() {
+
+        let mut asset_manager = AssetManager::<QVTransform>::new();
+        asset_manager.animation_clips.insert("idle".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample {
+            local_poses: vec![QVTransform::identity()],
+        }], 1.0)));
+        asset_manager.controller_defs.insert("human".to_string(), AnimationControllerDef {
+            name: "human".to_string(),
+            parameters: Vec::new(),
+            computed_parameters: None,
+            output_parameters: None,
+            states: vec![AnimationStateDef {
+                name: "idle".to_string(),
+                blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+                transitions: Vec::new(),
+                reference: None,
+            }],
+            initial_state: "idle".to_string(),
+        });
+
+        let skeleton = Rc::new(Skeleton::from_joints(vec![Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id())]));
+
+        let controller = build_controller(&asset_manager, "human", skeleton.clone()).unwrap();
+        assert_eq!(controller.get_state(controller.current_state()).name, "idle");
+
+        match build_controller(&asset_manager, "robot", skeleton) {
+            Err(AssetError::MissingController(name)) => assert_eq!(name, "robot"),
+            _ => panic!("Expected MissingController"),
+        }
+    }
+
+    #[test]
+    fn test_load_missing_mesh() {
+        match load_mesh(Path::new("/no/such/folder"), "character.dae") {
+            Err(AssetError::Mesh(source, _)) => assert_eq!(source, "character.dae"),
+            Err(e) => panic!("Expected Mesh, got {:?}", e),
+            Ok(_) => panic!("Expected Mesh, got a mesh"),
+        }
+    }
+}
@@ -1,24 +1,135 @@
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::time::Instant;
 
 use rustc_serialize::{Decodable, Decoder};
+use interpolation;
 
 use animation::AnimationClip;
 use transform::{Transform, FromTransform};
-use blend_tree::{AnimBlendTree, BlendTreeNodeDef, ClipId};
-use skeleton::Skeleton;
+use blend_tree::{AnimBlendTree, BlendTreeNodeDef, ClipId, EvalStats, DEFAULT_MAX_BLEND_TREE_DEPTH};
+use expression::Expression;
+use skeleton::{JointIndex, Skeleton};
+use math::{Quaternion, Vector3, lerp_quaternion, mirror_point, quaternion_id, quaternion_to_euler, euler_to_quaternion};
+use pose::PoseBuffer;
+
+/// Number of clock ticks per second, for fixed-step integer clock updates
+pub const TICKS_PER_SECOND: u64 = 1_000_000;
+
+/// Handle to an AnimationState within an AnimationController, resolved from the state's name
+/// when the controller is constructed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StateHandle(usize);
+
+/// Handle to an AnimationClip referenced by an AnimationController's blend trees, resolved from
+/// the clip's id when the controller is constructed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClipHandle(usize);
+
+/// A notification from an AnimationController, queued until drained with
+/// `AnimationController::drain_events`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimEvent {
+    /// A named event crossed by a clip playing in the current state, or in the target state of
+    /// an active transition
+    Clip(String),
+
+    /// A state began playing, as the target of a transition
+    StateEntered(StateHandle),
+
+    /// A state stopped playing, at the end of a transition away from it
+    StateExited(StateHandle),
+
+    /// A transition from the first state to the second completed
+    TransitionCompleted(StateHandle, StateHandle),
+}
+
+/// Limits on the local rotation of a joint, as Euler angles in radians (see
+/// `math::quaternion_to_euler`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointLimit {
+    /// Minimum rotation about each of the x, y and z axes
+    pub min: Vector3<f32>,
+
+    /// Maximum rotation about each of the x, y and z axes
+    pub max: Vector3<f32>,
+}
 
-const MAX_JOINTS: usize = 64;
+impl JointLimit {
+
+    /// Clamp the rotation of the given local pose to the limits
+    pub fn clamp<T: Transform>(&self, pose: &mut T) {
+        let euler = quaternion_to_euler(pose.get_rotation());
+        let mut clamped = euler;
+        for i in 0 .. 3 {
+            clamped[i] = euler[i].max(self.min[i]).min(self.max[i]);
+        }
+        if clamped != euler {
+            pose.set_rotation(euler_to_quaternion(clamped));
+        }
+    }
+}
 
 /// A state that an AnimationController can be in, consisting
 /// of a blend tree and a collection of transitions to other states
 pub struct AnimationState<T: Transform> {
 
+    /// The identifying name for the state
+    pub name: String,
+
     /// The blend tree used to determine the final blended pose for this state
     pub blend_tree: AnimBlendTree<T>,
 
-    /// Transitions from this state to other AnimationStates
-    pub transitions: Vec<AnimationTransition>,
+    /// Transitions from this state to other AnimationStates, each with the handle
+    /// of its target state
+    pub transitions: Vec<(StateHandle, AnimationTransition)>,
+
+    /// Optional blend tree for a reference pose, which the output of `blend_tree` is
+    /// differenced against on each frame (for additive states)
+    pub reference_tree: Option<AnimBlendTree<T>>,
+}
+
+impl<T: Transform> AnimationState<T> {
+
+    /// Synchronize and update the state's blend trees for the given time, and sample the
+    /// state's pose. For states with a reference tree, this is the difference from the
+    /// reference pose at the same time.
+    fn get_output_pose(&mut self, time: f32, params: &HashMap<String, f32>, local_poses: &mut [T]) {
+
+        self.blend_tree.synchronize(time, params);
+        self.blend_tree.update(time, params);
+        self.blend_tree.get_output_pose(time, params, local_poses);
+
+        if let Some(ref mut reference_tree) = self.reference_tree {
+            let mut reference_poses = PoseBuffer::<T>::new(local_poses.len());
+            reference_tree.synchronize(time, params);
+            reference_tree.update(time, params);
+            reference_tree.get_output_pose(time, params, &mut reference_poses[..]);
+
+            for (pose, reference_pose) in local_poses.iter_mut().zip(reference_poses.iter()) {
+                *pose = reference_pose.inverse().concat(*pose);
+            }
+        }
+    }
+
+    /// Enable or disable profiling of the state's trees
+    fn set_profiling(&mut self, enabled: bool) {
+        self.blend_tree.set_profiling(enabled);
+        if let Some(ref mut reference_tree) = self.reference_tree {
+            reference_tree.set_profiling(enabled);
+        }
+    }
+
+    /// Counts of the work done by the most recent `get_output_pose`, across the state's trees
+    fn last_eval_stats(&self) -> EvalStats {
+        let mut stats = self.blend_tree.last_eval_stats();
+        if let Some(ref reference_tree) = self.reference_tree {
+            let reference_stats = reference_tree.last_eval_stats();
+            stats.nodes_evaluated += reference_stats.nodes_evaluated;
+            stats.clips_sampled += reference_stats.clips_sampled;
+        }
+        stats
+    }
 }
 
 /// Representation of a state transition to a target state, with a condition and a duration
@@ -60,6 +171,8 @@ impl TransitionCondition {
             Operator::GreaterThanEqual => parameters[&self.parameter[..]] >= self.value,
             Operator::Equal => parameters[&self.parameter[..]] == self.value,
             Operator::NotEqual => parameters[&self.parameter[..]] != self.value,
+            Operator::IsTrue => parameters[&self.parameter[..]] != 0.0,
+            Operator::IsFalse => parameters[&self.parameter[..]] == 0.0,
         }
     }
 }
@@ -72,6 +185,11 @@ pub enum Operator {
     GreaterThanEqual,
     Equal,
     NotEqual,
+
+    /// For bool and trigger parameters (see `AnimationController::set_param_bool` and
+    /// `AnimationController::set_trigger`), ignoring the condition's value
+    IsTrue,
+    IsFalse,
 }
 
 impl Decodable for Operator {
@@ -83,7 +201,9 @@ impl Decodable for Operator {
             ">=" => Ok(Operator::GreaterThanEqual),
             "=" => Ok(Operator::Equal),
             "!=" => Ok(Operator::NotEqual),
-            _ => Ok(Operator::Equal), // FIXME -- figure out how to throw a D::Error...
+            "is_true" => Ok(Operator::IsTrue),
+            "is_false" => Ok(Operator::IsFalse),
+            operator => Err(decoder.error(&format!("unknown operator: {}", operator))),
         }
     }
 }
@@ -100,6 +220,14 @@ pub struct AnimationControllerDef {
     /// including state transition conditions and blend tree parameters
     pub parameters: Vec<String>,
 
+    /// Optional parameters computed from other parameters, evaluated in order on each update
+    pub computed_parameters: Option<Vec<ComputedParamDef>>,
+
+    /// Optional blend parameters to expose as outputs, whose values as read by
+    /// `get_param_value` are the effective blend weights used on the latest frame (e.g. after an
+    /// IK node's blend ramp), for driving effects
+    pub output_parameters: Option<Vec<String>>,
+
     /// List of animation state definitions
     pub states: Vec<AnimationStateDef>,
 
@@ -107,6 +235,127 @@ pub struct AnimationControllerDef {
     pub initial_state: String,
 }
 
+/// Definition of a controller parameter whose value is computed from an expression over
+/// other parameters, e.g. `{ "name": "blend", "expression": "speed / max_speed" }`
+#[derive(Clone, Debug, RustcDecodable)]
+pub struct ComputedParamDef {
+
+    /// The name of the computed parameter
+    pub name: String,
+
+    /// The expression giving the parameter's value
+    pub expression: Expression,
+}
+
+impl AnimationControllerDef {
+
+    /// Check that every parameter referenced by the transition conditions and blend trees of
+    /// the controller's states, and by computed parameter expressions, is declared in
+    /// `parameters` or `computed_parameters`. Returns the names of any undeclared parameters,
+    /// in order of first reference.
+    pub fn validate_params(&self) -> Result<(), Vec<String>> {
+
+        let computed_parameters: &[ComputedParamDef] = match self.computed_parameters {
+            Some(ref computed_parameters) => computed_parameters,
+            None => &[],
+        };
+
+        let is_declared = |param: &str| {
+            self.parameters.iter().any(|p| p == param) || computed_parameters.iter().any(|c| c.name == param)
+        };
+
+        let mut unknown_params: Vec<String> = Vec::new();
+
+        let expression_params = computed_parameters.iter().flat_map(|c| c.expression.referenced_params());
+        let output_params = self.output_parameters.iter().flat_map(|outputs| outputs.iter().map(|p| &p[..]));
+
+        let state_params = self.states.iter().flat_map(|state| {
+            let condition_params = state.transitions.iter().map(|t| &t.condition.parameter[..]);
+            let reference_params = state.reference.iter().flat_map(|reference| reference.referenced_params());
+            condition_params.chain(state.blend_tree.referenced_params()).chain(reference_params)
+        });
+
+        for param in expression_params.chain(output_params).chain(state_params) {
+            if !is_declared(param) && !unknown_params.iter().any(|p| p == param) {
+                unknown_params.push(param.to_string());
+            }
+        }
+
+        if unknown_params.is_empty() {
+            Ok(())
+        } else {
+            Err(unknown_params)
+        }
+    }
+
+    /// Check that the blend trees (and reference blend trees) of the controller's states can be
+    /// built by `AnimationController::new`: that none is deeper than
+    /// `DEFAULT_MAX_BLEND_TREE_DEPTH`, and none has `clip_index` nodes, as there is no clip table
+    /// to resolve them against. Returns a description of the first problem found.
+    pub fn validate_blend_trees(&self) -> Result<(), String> {
+        for state in self.states.iter() {
+            let trees = Some(&state.blend_tree).into_iter().chain(state.reference.iter());
+            for tree in trees {
+                tree.check_depth(DEFAULT_MAX_BLEND_TREE_DEPTH).map_err(|e| format!("State '{}': {}", state.name, e))?;
+                if tree.uses_clip_table() {
+                    return Err(format!("State '{}' references a clip by index, which controllers don't support", state.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the names of all clips referenced by the blend trees (and reference blend trees)
+    /// of the controller's states, without duplicates, in order of first reference
+    pub fn referenced_clips(&self) -> Vec<&str> {
+        let mut clips: Vec<&str> = Vec::new();
+        for state in self.states.iter() {
+            let reference_clips = state.reference.iter().flat_map(|reference| reference.referenced_clips());
+            for clip in state.blend_tree.referenced_clips().into_iter().chain(reference_clips) {
+                if !clips.contains(&clip) {
+                    clips.push(clip);
+                }
+            }
+        }
+        clips
+    }
+
+    /// Rename the parameter `old` as `new`, in the declared (or computed) parameters and in every
+    /// reference to it from computed parameter expressions, transition conditions and blend
+    /// trees, e.g. to share a definition across characters with different naming conventions
+    pub fn rename_param(&mut self, old: &str, new: &str) {
+
+        for param in self.parameters.iter_mut().filter(|param| *param == old) {
+            *param = new.to_string();
+        }
+
+        if let Some(ref mut computed_parameters) = self.computed_parameters {
+            for computed_parameter in computed_parameters.iter_mut() {
+                if computed_parameter.name == old {
+                    computed_parameter.name = new.to_string();
+                }
+                computed_parameter.expression.rename_param(old, new);
+            }
+        }
+
+        if let Some(ref mut output_parameters) = self.output_parameters {
+            for param in output_parameters.iter_mut().filter(|param| *param == old) {
+                *param = new.to_string();
+            }
+        }
+
+        for state in self.states.iter_mut() {
+            for transition in state.transitions.iter_mut().filter(|t| t.condition.parameter == old) {
+                transition.condition.parameter = new.to_string();
+            }
+            state.blend_tree.rename_param(old, new);
+            if let Some(ref mut reference) = state.reference {
+                reference.rename_param(old, new);
+            }
+        }
+    }
+}
+
 /// Definition struct for an AnimationState, which can be deserialized from JSON
 /// and converted to an AnimationState instance at runtime
 #[derive(Clone, Debug)]
@@ -120,6 +369,11 @@ pub struct AnimationStateDef {
 
     /// The transitions to other states that can occur from this state
     pub transitions: Vec<AnimationTransition>,
+
+    /// Optional blend tree definition for a reference pose (typically a single ClipNode),
+    /// for additive states whose reference is itself animated. The state's output is the
+    /// difference from the live reference pose, rather than a pre-computed difference clip.
+    pub reference: Option<BlendTreeNodeDef>,
 }
 
 impl Decodable for AnimationStateDef {
@@ -142,10 +396,13 @@ impl Decodable for AnimationStateDef {
                 })
             })?;
 
+            let reference = decoder.read_struct_field("reference", 0, Decodable::decode)?;
+
             Ok(AnimationStateDef {
                 name: name,
                 blend_tree: blend_tree,
                 transitions: transitions,
+                reference,
             })
         })
     }
@@ -160,23 +417,72 @@ pub struct AnimationController<T: Transform> {
     /// Parameters that will be referenced by blend tree nodes and animation states
     parameters: HashMap<String, f32>,
 
+    /// Parameters computed from the other parameters on each update, in evaluation order
+    computed_parameters: Vec<ComputedParamDef>,
+
+    /// Names of blend parameters exposed as outputs
+    output_parameters: Vec<String>,
+
+    /// Effective blend weights of the output parameters on the latest frame
+    output_values: HashMap<String, f32>,
+
     /// Shared reference to the skeleton this controller is using
     skeleton: Rc<Skeleton>,
 
     /// Tracks seconds since controller started running
     local_clock: f64,
 
-    /// Playback speed multiplier.
+    /// Playback speed multiplier. Never negative; see `set_playback_speed`.
     playback_speed: f64,
 
-    /// Mapping of all animation state names to their instances
-    states: HashMap<String, AnimationState<T>>,
+    /// All animation states, indexed by StateHandle
+    states: Vec<AnimationState<T>>,
+
+    /// Mapping of all animation state names to their handles
+    state_handles: HashMap<String, StateHandle>,
+
+    /// All animation clips referenced by the states' blend trees, indexed by ClipHandle
+    clips: Vec<Rc<AnimationClip<T>>>,
+
+    /// Mapping of all referenced animation clip ids to their handles
+    clip_handles: HashMap<ClipId, ClipHandle>,
+
+    /// The current active AnimationState
+    current_state: StateHandle,
 
-    /// The name of the current active AnimationState
-    current_state: String,
+    /// The current active AnimationTransition, its start time and target state, if any
+    transition: Option<(f64, StateHandle, AnimationTransition)>,
 
-    /// The current active AnimationTransition and its start time, if any
-    transition: Option<(f64, AnimationTransition)>,
+    /// The (previous, current) sampling times used by the most recent `get_output_pose`
+    frame_window: (f32, f32),
+
+    /// Events queued since the last call to `drain_events`, in the order they occurred
+    events: Vec<AnimEvent>,
+
+    /// Parameters set by `set_trigger` and not yet consumed by a transition
+    triggers: Vec<String>,
+
+    /// Rotation limits for joints, applied to the blended local poses
+    joint_limits: Vec<(JointIndex, JointLimit)>,
+
+    /// Joints held at their local bind pose, along with that pose
+    frozen_joints: Vec<(JointIndex, T)>,
+
+    /// Root motion over the most recent `frame_window`
+    root_motion: Vector3<f32>,
+
+    /// Whether to zero the root joint's translation in the output pose, for applying root
+    /// motion to the entity instead
+    extract_root_translation: bool,
+
+    /// Whether to reset the root joint's rotation to identity in the output pose
+    extract_root_rotation: bool,
+
+    /// Whether to record `eval_stats` on each `get_output_pose`
+    profiling: bool,
+
+    /// Work done by the most recent `get_output_pose`, if profiling
+    eval_stats: EvalStats,
 }
 
 
@@ -185,7 +491,22 @@ impl<T: Transform> AnimationController<T> {
 
     /// Create an AnimationController instance from its definition, the desired skeleton, and a
     /// collection of currently loaded animation clips.
+    ///
+    /// Panics if the definition refers to a state, animation clip or joint that doesn't exist, or
+    /// fails `AnimationControllerDef::validate_blend_trees`. See `try_new` for a fallible version.
     pub fn new(controller_def: AnimationControllerDef, skeleton: Rc<Skeleton>, animations: &HashMap<ClipId, Rc<AnimationClip<T>>>) -> AnimationController<T> {
+        match AnimationController::try_new(controller_def, skeleton, animations) {
+            Ok(controller) => controller,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Create an AnimationController instance as with `new`, returning an error describing the
+    /// first problem found: a failure of `AnimationControllerDef::validate_blend_trees`, or a
+    /// state, animation clip or joint the definition refers to that doesn't exist
+    pub fn try_new(controller_def: AnimationControllerDef, skeleton: Rc<Skeleton>, animations: &HashMap<ClipId, Rc<AnimationClip<T>>>) -> Result<AnimationController<T>, String> {
+
+        controller_def.validate_blend_trees()?;
 
         let mut parameters = HashMap::new();
 
@@ -193,54 +514,211 @@ impl<T: Transform> AnimationController<T> {
             parameters.insert(parameter.clone(), 0.0);
         };
 
-        let mut states = HashMap::new();
+        let computed_parameters = controller_def.computed_parameters.clone().unwrap_or_default();
+        for computed_parameter in computed_parameters.iter() {
+            let value = computed_parameter.expression.evaluate(&parameters);
+            parameters.insert(computed_parameter.name.clone(), value);
+        }
+
+        let state_handles: HashMap<String, StateHandle> = controller_def.states.iter().enumerate().map(|(i, state_def)| {
+            (state_def.name.clone(), StateHandle(i))
+        }).collect();
+
+        let resolve_state = |name: &str| -> Result<StateHandle, String> {
+            state_handles.get(name).cloned().ok_or_else(|| {
+                format!("Unknown animation state in controller '{}': {}", controller_def.name, name)
+            })
+        };
+
+        let mut clips = Vec::new();
+        let mut clip_handles: HashMap<ClipId, ClipHandle> = HashMap::new();
         for state_def in controller_def.states.iter() {
+            let trees = Some(&state_def.blend_tree).into_iter().chain(state_def.reference.iter());
+            for clip_id in trees.flat_map(|tree| tree.referenced_clips()) {
+                if clip_handles.contains_key(clip_id) {
+                    continue;
+                }
+                let clip = animations.get(clip_id).ok_or_else(|| {
+                    format!("Unknown animation clip in controller '{}': {}", controller_def.name, clip_id)
+                })?;
+                clip_handles.insert(clip_id.to_string(), ClipHandle(clips.len()));
+                clips.push(clip.clone());
+            }
+        }
 
-            let mut blend_tree = AnimBlendTree::from_def(state_def.blend_tree.clone(), animations, skeleton.clone());
-            blend_tree.synchronize(0.0, &parameters);
+        let mut states = Vec::new();
+        for state_def in controller_def.states.iter() {
 
-            states.insert(state_def.name.clone(), AnimationState {
-                blend_tree: blend_tree,
-                transitions: state_def.transitions.clone()
+            let build_tree = |def: BlendTreeNodeDef| -> Result<AnimBlendTree<T>, String> {
+                let mut tree = AnimBlendTree::from_def_with_max_depth(def, animations, &[], skeleton.clone(), DEFAULT_MAX_BLEND_TREE_DEPTH)
+                    .map_err(|e| format!("State '{}': {}", state_def.name, e))?;
+                tree.synchronize(0.0, &parameters);
+                Ok(tree)
+            };
+
+            let blend_tree = build_tree(state_def.blend_tree.clone())?;
+            let reference_tree = match state_def.reference.clone() {
+                Some(reference) => Some(build_tree(reference)?),
+                None => None,
+            };
+
+            let transitions = state_def.transitions.iter().map(|transition| {
+                Ok((resolve_state(&transition.target_state[..])?, transition.clone()))
+            }).collect::<Result<_, String>>()?;
+
+            states.push(AnimationState {
+                name: state_def.name.clone(),
+                blend_tree,
+                transitions,
+                reference_tree,
             });
 
         }
 
-        AnimationController {
+        let current_state = resolve_state(&controller_def.initial_state[..])?;
+
+        Ok(AnimationController {
             parameters: parameters,
+            computed_parameters,
+            output_parameters: controller_def.output_parameters.clone().unwrap_or_default(),
+            output_values: HashMap::new(),
             skeleton: skeleton.clone(),
             local_clock: 0.0,
             playback_speed: 1.0,
-            states: states,
-            current_state: controller_def.initial_state,
+            states,
+            state_handles,
+            clips,
+            clip_handles,
+            current_state,
             transition: None,
-        }
+            frame_window: (0.0, 0.0),
+            events: Vec::new(),
+            triggers: Vec::new(),
+            joint_limits: Vec::new(),
+            frozen_joints: Vec::new(),
+            root_motion: [0.0; 3],
+            extract_root_translation: false,
+            extract_root_rotation: false,
+            profiling: false,
+            eval_stats: EvalStats::default(),
+        })
+    }
+
+    /// Return the handle of the named state, if any
+    pub fn get_state_handle(&self, name: &str) -> Option<StateHandle> {
+        self.state_handles.get(name).cloned()
+    }
+
+    /// Return the state for the given handle
+    pub fn get_state(&self, handle: StateHandle) -> &AnimationState<T> {
+        &self.states[handle.0]
+    }
+
+    /// Return the handle of the animation clip with the given id, if any of the controller's
+    /// blend trees reference it
+    pub fn get_clip_handle(&self, clip_id: &str) -> Option<ClipHandle> {
+        self.clip_handles.get(clip_id).cloned()
+    }
+
+    /// Return the animation clip for the given handle
+    pub fn get_clip(&self, handle: ClipHandle) -> &Rc<AnimationClip<T>> {
+        &self.clips[handle.0]
+    }
+
+    /// Return the handle of the current active state
+    pub fn current_state(&self) -> StateHandle {
+        self.current_state
+    }
+
+    /// Return the name of the current active state
+    pub fn current_state_name(&self) -> &str {
+        &self.states[self.current_state.0].name[..]
+    }
+
+    /// Return whether the controller is transitioning from the current state to another
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Return the name of the state being transitioned to, if any
+    pub fn target_state_name(&self) -> Option<&str> {
+        self.transition.as_ref().map(|&(_, target_state, _)| &self.states[target_state.0].name[..])
+    }
+
+    /// Return the progress of the active transition, if any, as the 0 to 1 blend factor with
+    /// the target state at the controller's current clock time (as `get_output_pose` would
+    /// compute it, with no external time delta). Transitions are only started and completed
+    /// by `get_output_pose`, so this holds at 1 once a transition's duration has elapsed.
+    pub fn transition_progress(&self) -> Option<f32> {
+        self.transition.as_ref().map(|&(start_time, _, ref transition)| {
+            transition_blend_factor(start_time, transition.duration, self.local_clock)
+        })
+    }
+
+    /// The time in seconds left in the active transition, if any, at the controller's current
+    /// clock time, as with `transition_progress`. Measured on the controller's clock, so at a
+    /// playback speed other than one, it passes faster or slower than real time. Holds at 0 once
+    /// the transition's duration has elapsed, until `get_output_pose` completes it.
+    pub fn transition_remaining(&self) -> Option<f32> {
+        self.transition.as_ref().map(|&(start_time, _, ref transition)| {
+            ((start_time + transition.duration as f64 - self.local_clock) as f32).max(0.0)
+        })
     }
 
     /// Update the controller's local clock with the given time delta
     pub fn update(&mut self, delta_time: f64) {
         self.local_clock += delta_time * self.playback_speed;
+        self.update_computed_params();
+    }
+
+    /// Update the controller's local clock by a whole number of ticks (see `TICKS_PER_SECOND`).
+    ///
+    /// The clock is kept on exact tick boundaries, so replaying the same sequence of ticks
+    /// always yields the same sampling times, and the same output poses, on any machine.
+    pub fn update_ticks(&mut self, delta_ticks: u64) {
+        let clock_ticks = seconds_to_ticks(self.local_clock)
+            + (delta_ticks as f64 * self.playback_speed).round() as i64;
+        self.local_clock = clock_ticks as f64 / TICKS_PER_SECOND as f64;
+        self.update_computed_params();
+    }
+
+    /// Evaluate the computed parameters from the current parameter values
+    fn update_computed_params(&mut self) {
+        for computed_parameter in self.computed_parameters.iter() {
+            let value = computed_parameter.expression.evaluate(&self.parameters);
+            self.parameters.insert(computed_parameter.name.clone(), value);
+        }
     }
 
     /// Checks if controller should transition to a different state, or if currently
     /// in a transition, checks if the transition is complete
     fn update_state(&mut self, ext_dt: f64) {
         match self.transition.clone() {
-            Some((ref start_time, ref transition)) => {
+            Some((ref start_time, target_state, ref transition)) => {
                 // If transition is finished, switch state to new transition
                 if self.local_clock + ext_dt >= start_time + transition.duration as f64{
-                    self.current_state = transition.target_state.clone();
+                    self.events.push(AnimEvent::StateExited(self.current_state));
+                    self.events.push(AnimEvent::TransitionCompleted(self.current_state, target_state));
+                    self.current_state = target_state;
                     self.transition = None;
                 }
             },
             None => {
 
                 // Check for any transitions with passing conditions
-                let current_state = &self.states[&self.current_state[..]];
-                for transition in current_state.transitions.iter() {
+                let current_state = &self.states[self.current_state.0];
+                for &(target_state, ref transition) in current_state.transitions.iter() {
 
                     if transition.condition.is_true(&self.parameters) {
-                        self.transition = Some((self.local_clock + ext_dt, transition.clone()));
+                        self.transition = Some((self.local_clock + ext_dt, target_state, transition.clone()));
+                        self.events.push(AnimEvent::StateEntered(target_state));
+
+                        // A trigger is consumed by the transition it fires
+                        let parameter = &transition.condition.parameter;
+                        if self.triggers.contains(parameter) {
+                            self.triggers.retain(|trigger| trigger != parameter);
+                            self.parameters.insert(parameter.clone(), 0.0);
+                        }
                         break;
                     }
                 }
@@ -248,19 +726,109 @@ impl<T: Transform> AnimationController<T> {
         }
     }
 
-    /// Set the playback speed for the controller
+    /// Limit the local rotation of the given joint, as a safety net against blends producing
+    /// impossible poses. Replaces any existing limit for the joint. Returns an error, leaving
+    /// the limits unchanged, if the joint isn't in the controller's skeleton.
+    pub fn set_joint_limit(&mut self, joint: JointIndex, limit: JointLimit) -> Result<(), String> {
+        self.check_joint_index(joint)?;
+        self.joint_limits.retain(|&(j, _)| j != joint);
+        self.joint_limits.push((joint, limit));
+        Ok(())
+    }
+
+    /// Return an error if the given joint index is out of range for the controller's skeleton
+    fn check_joint_index(&self, joint: JointIndex) -> Result<(), String> {
+        if (joint as usize) < self.skeleton.joints.len() {
+            Ok(())
+        } else {
+            Err(format!("Joint index {} is out of range for a skeleton of {} joints", joint, self.skeleton.joints.len()))
+        }
+    }
+
+    /// Remove all joint rotation limits
+    pub fn clear_joint_limits(&mut self) {
+        self.joint_limits.clear();
+    }
+
+    /// Hold the given joints at their local bind pose, regardless of the animation, e.g. for a
+    /// limp limb. Replaces any previously frozen joints; pass an empty slice to unfreeze all.
+    /// Returns an error, leaving the frozen joints unchanged, if any joint isn't in the
+    /// controller's skeleton.
+    pub fn set_frozen_joints(&mut self, joints: &[JointIndex]) -> Result<(), String> {
+        for &joint in joints.iter() {
+            self.check_joint_index(joint)?;
+        }
+        let bind_poses: Vec<T> = self.skeleton.bind_pose_local();
+        self.frozen_joints = joints.iter().map(|&joint| (joint, bind_poses[joint as usize])).collect();
+        Ok(())
+    }
+
+    /// Set the playback speed for the controller. Negative speeds (and NaN) are clamped to zero,
+    /// pausing the controller, since a clock running backward would leave transitions waiting on
+    /// start times in its future and re-fire clip events.
     pub fn set_playback_speed(&mut self, speed: f64) {
-        self.playback_speed = speed;
+        self.playback_speed = speed.max(0.0);
     }
 
-    /// Set the value for the given controller parameter
+    /// The playback speed multiplier, as clamped by `set_playback_speed`
+    pub fn get_playback_speed(&self) -> f64 {
+        self.playback_speed
+    }
+
+    /// Set the value for the given controller parameter, and re-evaluate the computed
+    /// parameters that may depend on it
     pub fn set_param_value(&mut self, name: &str, value: f32) {
         self.parameters.insert(name.to_string(), value); // :(
+        self.update_computed_params();
     }
 
-    /// Return the value for the given controller parameter
+    /// Return the value for the given controller parameter. For output parameters, this is
+    /// the effective blend weight used on the latest frame, once there has been one.
     pub fn get_param_value(&self, name: &str) -> f32 {
-        self.parameters[name]
+        match self.output_values.get(name) {
+            Some(&value) => value,
+            None => self.parameters[name],
+        }
+    }
+
+    /// Set a bool parameter, stored as 1.0 for true and 0.0 for false, for use with the
+    /// `IsTrue` and `IsFalse` transition operators
+    pub fn set_param_bool(&mut self, name: &str, value: bool) {
+        self.set_param_value(name, if value { 1.0 } else { 0.0 });
+    }
+
+    /// Return the value of the given bool parameter, true for any nonzero value
+    pub fn get_param_bool(&self, name: &str) -> bool {
+        self.get_param_value(name) != 0.0
+    }
+
+    /// Set a trigger parameter to true until it's consumed by firing a transition whose
+    /// condition is on the parameter, after which it resets to false, e.g. for one-shot actions
+    pub fn set_trigger(&mut self, name: &str) {
+        self.set_param_bool(name, true);
+        if !self.triggers.iter().any(|trigger| trigger == name) {
+            self.triggers.push(name.to_string());
+        }
+    }
+
+    /// Set the target position parameters of two IK nodes that should stay symmetric (e.g. both
+    /// hands on a two-handed grip), where the second target is the given target mirrored across
+    /// the plane passing through `plane_point` with normal `plane_normal`.
+    ///
+    /// Targets are in model-space, as for the IK node target parameters.
+    pub fn set_mirrored_ik_target(
+        &mut self,
+        target_params: [&str; 3],
+        mirrored_target_params: [&str; 3],
+        target: Vector3<f32>,
+        plane_point: Vector3<f32>,
+        plane_normal: Vector3<f32>,
+    ) {
+        let mirrored_target = mirror_point(target, plane_point, plane_normal);
+        for i in 0 .. 3 {
+            self.set_param_value(target_params[i], target[i]);
+            self.set_param_value(mirrored_target_params[i], mirrored_target[i]);
+        }
     }
 
     /// Return a read-only reference to the controller parameter map
@@ -270,32 +838,51 @@ impl<T: Transform> AnimationController<T> {
 
     /// Calculate global skeletal joint poses for the given time since last update
     pub fn get_output_pose<TOutput: Transform + FromTransform<T>>(&mut self, ext_dt: f64, output_poses: &mut [TOutput]) {
+        let mut local_poses = PoseBuffer::<T>::new(self.skeleton.joints.len());
+        self.get_local_pose(ext_dt, &mut local_poses);
+        self.skeleton.calculate_global_poses(&local_poses[..], output_poses);
+    }
+
+    /// Calculate local (parent-relative) skeletal joint poses for the given time since last
+    /// update, i.e. the output of the current state's blend tree (and any active transition),
+    /// before joint poses are accumulated into global poses
+    pub fn get_local_pose(&mut self, ext_dt: f64, local_poses: &mut [T]) {
+
+        let start_time = if self.profiling { Some(Instant::now()) } else { None };
 
         self.update_state(ext_dt);
 
         let elapsed_time = self.local_clock + ext_dt * self.playback_speed;
+        self.frame_window = (self.frame_window.1, elapsed_time as f32);
+        let (previous_time, time) = self.frame_window;
 
-        let mut local_poses = [ T::identity(); MAX_JOINTS ];
+        let joint_count = self.skeleton.joints.len();
+        let mut target_blend = None;
 
         {
-            let current_state = self.states.get_mut(&self.current_state[..]).unwrap();
-            current_state.blend_tree.synchronize(elapsed_time as f32, &self.parameters);
-            current_state.blend_tree.get_output_pose(elapsed_time as f32, &self.parameters, &mut local_poses[..]);
+            let current_state = &mut self.states[self.current_state.0];
+            current_state.get_output_pose(elapsed_time as f32, &self.parameters, local_poses);
+
+            let clip_events = current_state.blend_tree.get_clip_events_between(previous_time, time);
+            self.events.extend(clip_events.into_iter().map(|name| AnimEvent::Clip(name.to_string())));
         }
 
-        if let Some((transition_start_time, ref transition)) = self.transition {
+        if let Some((transition_start_time, target_handle, ref transition)) = self.transition {
 
             // Blend with the target state ...
 
-            let mut target_poses = [ T::identity(); MAX_JOINTS ];
+            let mut target_poses = PoseBuffer::<T>::new(joint_count);
 
-            let target_state = self.states.get_mut(&transition.target_state[..]).unwrap();
-            target_state.blend_tree.synchronize(elapsed_time as f32, &self.parameters);
-            target_state.blend_tree.get_output_pose(elapsed_time as f32, &self.parameters, &mut target_poses[..]);
+            let target_state = &mut self.states[target_handle.0];
+            target_state.get_output_pose(elapsed_time as f32, &self.parameters, &mut target_poses[..]);
 
-            let blend_parameter = ((self.local_clock + ext_dt - transition_start_time) / transition.duration as f64) as f32;
+            let clip_events = target_state.blend_tree.get_clip_events_between(previous_time, time);
+            self.events.extend(clip_events.into_iter().map(|name| AnimEvent::Clip(name.to_string())));
 
-            for i in 0 .. output_poses.len() {
+            let blend_parameter = transition_blend_factor(transition_start_time, transition.duration, self.local_clock + ext_dt);
+            target_blend = Some((target_handle, blend_parameter));
+
+            for i in 0 .. local_poses.len() {
                 let pose_1 = &mut local_poses[i];
                 let pose_2 = target_poses[i];
                 *pose_1 = pose_1.lerp(pose_2, blend_parameter);
@@ -303,26 +890,1139 @@ impl<T: Transform> AnimationController<T> {
 
         }
 
-        self.calculate_global_poses(&local_poses[..], output_poses);
-    }
+        self.root_motion = self.blended_root_motion(previous_time, time, ext_dt).0;
 
-    /// Calculate global poses from the controller's skeleton and the given local poses
-    fn calculate_global_poses<TOutput: Transform + FromTransform<T>>(
-        &self,
-        local_poses: &[T],
-        global_poses: &mut [TOutput],
-    ) {
+        if let Some(root_pose) = local_poses.first_mut() {
+            if self.extract_root_translation {
+                root_pose.set_translation([0.0; 3]);
+            }
+            if self.extract_root_rotation {
+                root_pose.set_rotation(quaternion_id());
+            }
+        }
 
-        for (joint_index, joint) in self.skeleton.joints.iter().enumerate() {
+        for &(joint, ref limit) in self.joint_limits.iter() {
+            limit.clamp(&mut local_poses[joint as usize]);
+        }
 
-            let parent_pose = if !joint.is_root() {
-                global_poses[joint.parent_index as usize]
-            } else {
-                TOutput::identity()
+        for &(joint, bind_pose) in self.frozen_joints.iter() {
+            local_poses[joint as usize] = bind_pose;
+        }
+
+        self.update_output_values(target_blend);
+
+        if let Some(start_time) = start_time {
+            let mut stats = self.states[self.current_state.0].last_eval_stats();
+            if let Some((_, target_state, _)) = self.transition {
+                let target_stats = self.states[target_state.0].last_eval_stats();
+                stats.nodes_evaluated += target_stats.nodes_evaluated;
+                stats.clips_sampled += target_stats.clips_sampled;
+            }
+            stats.duration = start_time.elapsed();
+            self.eval_stats = stats;
+        }
+    }
+
+    /// Record the effective blend weights of the output parameters, blending between the
+    /// current and target states' weights during a transition
+    fn update_output_values(&mut self, target_blend: Option<(StateHandle, f32)>) {
+        for name in self.output_parameters.iter() {
+            let current_weight = self.states[self.current_state.0].blend_tree.get_blend_weight(name, &self.parameters);
+            let target_weight = target_blend.and_then(|(target_state, blend_parameter)| {
+                self.states[target_state.0].blend_tree.get_blend_weight(name, &self.parameters).map(|weight| (weight, blend_parameter))
+            });
+            let weight = match (current_weight, target_weight) {
+                (Some(current_weight), Some((target_weight, blend_parameter))) => current_weight + (target_weight - current_weight) * blend_parameter,
+                (Some(weight), None) | (None, Some((weight, _))) => weight,
+                (None, None) => self.parameters[name],
             };
+            self.output_values.insert(name.clone(), weight);
+        }
+    }
 
-            let local_pose = local_poses[joint_index];
-            global_poses[joint_index] = parent_pose.concat(TOutput::from_transform(local_pose));
+    /// Enable or disable profiling of `get_output_pose`, which is off by default to avoid the
+    /// cost of timing each call
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiling = enabled;
+        for state in self.states.iter_mut() {
+            state.set_profiling(enabled);
+        }
+        if !enabled {
+            self.eval_stats = EvalStats::default();
         }
     }
+
+    /// Return the nodes evaluated, clips sampled and time taken by the most recent call to
+    /// `get_output_pose`, including any transition target and reference poses. Only recorded
+    /// while profiling (see `set_profiling`); otherwise all zero.
+    pub fn last_eval_stats(&self) -> EvalStats {
+        self.eval_stats
+    }
+
+    /// Take all events queued since the last call, in the order they occurred. Should be called
+    /// once per frame, after `get_output_pose`.
+    pub fn drain_events(&mut self) -> Vec<AnimEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Return the root motion (the translation of the root joint) over the most recent call to
+    /// `get_output_pose`, i.e. over `last_frame_window`, blended across any active transition
+    pub fn root_motion_delta(&self) -> Vector3<f32> {
+        self.root_motion
+    }
+
+    /// Return the translation and rotation of the root joint from the most recent sample (i.e.
+    /// the end of `last_frame_window`) to the given time since last update, as would be
+    /// sampled by `get_output_pose(ext_dt, ..)`, blended across any active transition. Both
+    /// are in model space (the space of the root joint's local pose): the rotation `q` takes
+    /// the root's previous rotation to its current rotation `q * previous`. Loops crossed
+    /// contribute their net motion, rather than a jump back to the start of the clip.
+    pub fn get_root_motion_delta(&self, ext_dt: f64) -> (Vector3<f32>, Quaternion<f32>) {
+        let time = (self.local_clock + ext_dt * self.playback_speed) as f32;
+        self.blended_root_motion(self.frame_window.1, time, ext_dt)
+    }
+
+    /// Zero the root joint's translation and/or reset its rotation in the output pose, e.g. when
+    /// applying root motion (see `get_root_motion_delta`) to the game entity instead
+    pub fn set_root_motion_extraction(&mut self, translation: bool, rotation: bool) {
+        self.extract_root_translation = translation;
+        self.extract_root_rotation = rotation;
+    }
+
+    /// Root translation and rotation of the current state between the given times, blended
+    /// with those of the target state of any active transition as the poses are, so that
+    /// travel doesn't hitch over the transition
+    fn blended_root_motion(&self, previous_time: f32, time: f32, ext_dt: f64) -> (Vector3<f32>, Quaternion<f32>) {
+        let current_tree = &self.states[self.current_state.0].blend_tree;
+        let (motion, rotation) = current_tree.get_root_transform_delta(previous_time, time, &self.parameters);
+        match self.transition {
+            Some((transition_start_time, target_handle, ref transition)) => {
+                let blend_parameter = transition_blend_factor(transition_start_time, transition.duration, self.local_clock + ext_dt);
+                let target_tree = &self.states[target_handle.0].blend_tree;
+                let (target_motion, target_rotation) = target_tree.get_root_transform_delta(previous_time, time, &self.parameters);
+                (interpolation::lerp(&motion, &target_motion, &blend_parameter),
+                 lerp_quaternion(&rotation, &target_rotation, &blend_parameter))
+            }
+            None => (motion, rotation),
+        }
+    }
+
+    /// Return the (previous, current) sampling times used by the most recent call to
+    /// `get_output_pose`, so that consumers of root motion or events can query a consistent
+    /// time window. Windows of consecutive frames are contiguous.
+    pub fn last_frame_window(&self) -> (f32, f32) {
+        self.frame_window
+    }
+
+    /// Calculate global skeletal joint poses for the given number of ticks since last update,
+    /// for use with `update_ticks`
+    pub fn get_output_pose_ticks<TOutput: Transform + FromTransform<T>>(&mut self, ext_ticks: u64, output_poses: &mut [TOutput]) {
+        self.get_output_pose(ext_ticks as f64 / TICKS_PER_SECOND as f64, output_poses);
+    }
+}
+
+/// Round a clock time in seconds to the nearest whole tick
+fn seconds_to_ticks(seconds: f64) -> i64 {
+    (seconds * TICKS_PER_SECOND as f64).round() as i64
+}
+
+/// The 0 to 1 blend factor with the target state of a transition started at `start_time`, at
+/// the given clock time. A transition with no duration is fully blended from its start.
+fn transition_blend_factor(start_time: f64, duration: f32, time: f64) -> f32 {
+    if duration <= 0.0 {
+        1.0
+    } else {
+        (((time - start_time) / duration as f64) as f32).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use animation::{AnimationClip, AnimationSample};
+    use blend_tree::{BlendTreeNodeDef, BlendChannels, EvalStats, IKNodeDef, DEFAULT_MAX_BLEND_TREE_DEPTH};
+    use math::*;
+    use skeleton::{Joint, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::{Transform, QVTransform, TranslationSpace};
+
+    use expression::Expression;
+
+    use super::{AnimationController, AnimationControllerDef, AnimationStateDef, AnimationTransition,
+                AnimEvent, ComputedParamDef, JointLimit, TransitionCondition, Operator, TICKS_PER_SECOND};
+
+    static EPSILON: f32 = 0.000001;
+
+    fn test_skeleton() -> Rc<Skeleton> {
+        Rc::new(Skeleton::from_joints(vec![Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id())]))
+    }
+
+    fn test_controller(parameters: &[&str]) -> AnimationController<QVTransform> {
+        AnimationController::new(test_controller_def(parameters), test_skeleton(), &test_animations())
+    }
+
+    fn test_animations() -> HashMap<String, Rc<AnimationClip<QVTransform>>> {
+
+        let clip = AnimationClip::new((0 .. 3).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([i as f32, 0.0, 0.0]);
+            AnimationSample { local_poses: vec![pose] }
+        }).collect(), 3.0);
+
+        let mut animations = HashMap::new();
+        animations.insert("idle".to_string(), Rc::new(clip));
+        animations
+    }
+
+    fn test_controller_def(parameters: &[&str]) -> AnimationControllerDef {
+        AnimationControllerDef {
+            name: "test".to_string(),
+            parameters: parameters.iter().map(|p| p.to_string()).collect(),
+            computed_parameters: None,
+            output_parameters: None,
+            states: vec![AnimationStateDef {
+                name: "idle".to_string(),
+                blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+                transitions: Vec::new(),
+                reference: None,
+            }],
+            initial_state: "idle".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_state_handles() {
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states.push(AnimationStateDef {
+            name: "walk".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+            transitions: Vec::new(),
+            reference: None,
+        });
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "walk".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.1,
+        });
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &test_animations());
+
+        let idle = controller.get_state_handle("idle").unwrap();
+        let walk = controller.get_state_handle("walk").unwrap();
+        assert!(controller.get_state_handle("run").is_none());
+        assert_eq!(controller.get_state(walk).name, "walk");
+        assert_eq!(controller.get_state(idle).transitions[0].0, walk);
+        assert_eq!(controller.current_state(), idle);
+
+        let mut output = [mat4_id()];
+        controller.set_param_value("speed", 1.0);
+        controller.get_output_pose(0.0, &mut output);
+        controller.update(0.2);
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.current_state(), walk);
+    }
+
+    #[test]
+    fn test_drain_events() {
+
+        let mut walk_clip = AnimationClip::new((0 .. 4).map(|_| AnimationSample { local_poses: vec![QVTransform::identity()] }).collect(), 4.0);
+        walk_clip.events = vec![(0.1, "footstep".to_string()), (0.6, "footstep".to_string())];
+
+        let mut animations = test_animations();
+        animations.insert("walk".to_string(), Rc::new(walk_clip));
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states.push(AnimationStateDef {
+            name: "walk".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("walk".to_string()),
+            transitions: Vec::new(),
+            reference: None,
+        });
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "walk".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.3,
+        });
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &animations);
+        let idle = controller.get_state_handle("idle").unwrap();
+        let walk = controller.get_state_handle("walk").unwrap();
+
+        let mut output = [mat4_id()];
+        controller.get_output_pose(0.0, &mut output);
+        assert!(controller.drain_events().is_empty());
+
+        // The transition starts, and the walk clip crosses its first footstep, in the same frame
+        controller.set_param_value("speed", 1.0);
+        controller.update(0.15);
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.drain_events(), vec![AnimEvent::StateEntered(walk), AnimEvent::Clip("footstep".to_string())]);
+        assert!(controller.drain_events().is_empty());
+
+        // The transition completes, and the second footstep follows
+        controller.update(0.5);
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.drain_events(), vec![
+            AnimEvent::StateExited(idle),
+            AnimEvent::TransitionCompleted(idle, walk),
+            AnimEvent::Clip("footstep".to_string()),
+        ]);
+
+        // Looping around to the first footstep again
+        controller.update(0.5);
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.drain_events(), vec![AnimEvent::Clip("footstep".to_string())]);
+    }
+
+    #[test]
+    fn test_root_motion_across_transition() {
+
+        // Walk at 1 unit per second, run at 3
+        let moving_clip = |speed: f32| Rc::new(AnimationClip::new((0 .. 21).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([0.0, 0.0, speed * i as f32 / 10.0]);
+            AnimationSample { local_poses: vec![pose] }
+        }).collect(), 10.0));
+
+        let mut animations = HashMap::new();
+        animations.insert("idle".to_string(), moving_clip(1.0));
+        animations.insert("run".to_string(), moving_clip(3.0));
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states.push(AnimationStateDef {
+            name: "run".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("run".to_string()),
+            transitions: Vec::new(),
+            reference: None,
+        });
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "run".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.4,
+        });
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &animations);
+        let mut output = [mat4_id()];
+
+        controller.update(0.2);
+        controller.get_output_pose(0.0, &mut output);
+        controller.set_param_value("speed", 1.0);
+
+        // Each 0.1s frame, the delta moves from walking to running speed with the blend
+        let mut deltas = Vec::new();
+        for _ in 0 .. 6 {
+            controller.update(0.1);
+            controller.get_output_pose(0.0, &mut output);
+            deltas.push(controller.root_motion_delta()[2]);
+        }
+
+        let expected = [0.1, 0.15, 0.2, 0.25, 0.3, 0.3];
+        for (delta, expected) in deltas.iter().zip(expected.iter()) {
+            assert!((delta - expected).abs() < 0.001, "{:?}", deltas);
+        }
+    }
+
+    #[test]
+    fn test_root_motion_delta() {
+
+        // Walking forward along z at 1 unit per second, and turning about y at 1 radian per second
+        let mut animations = HashMap::new();
+        animations.insert("idle".to_string(), Rc::new(AnimationClip::new((0 .. 21).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([0.0, 0.0, i as f32 / 10.0]);
+            pose.set_rotation(quaternion::axis_angle([0.0, 1.0, 0.0], i as f32 / 10.0));
+            AnimationSample { local_poses: vec![pose] }
+        }).collect(), 10.0)));
+
+        let mut controller = AnimationController::new(test_controller_def(&[]), test_skeleton(), &animations);
+        let mut local_poses = [QVTransform::identity()];
+
+        let expect_delta = |controller: &AnimationController<QVTransform>, ext_dt: f64, distance: f32| {
+            let (translation, rotation) = controller.get_root_motion_delta(ext_dt);
+            assert!(vec3_len(vec3_sub(translation, [0.0, 0.0, distance])) < 0.001, "{:?}", translation);
+            let expected_rotation = quaternion::axis_angle([0.0, 1.0, 0.0], distance);
+            assert!(quaternion_angle_between(rotation, expected_rotation) < 0.001, "{:?}", rotation);
+        };
+
+        controller.update(0.2);
+        controller.get_local_pose(0.0, &mut local_poses);
+        expect_delta(&controller, 0.1, 0.1);
+
+        // Crossing the loop boundary at 2.1s continues forwards, rather than jumping back
+        controller.update(1.8);
+        controller.get_local_pose(0.0, &mut local_poses);
+        expect_delta(&controller, 0.2, 0.1);
+        controller.get_local_pose(0.2, &mut local_poses);
+        expect_delta(&controller, 0.2, 0.0);
+
+        // With extraction, the root stays at the origin in the emitted pose
+        assert!(local_poses[0].get_translation()[2] > 0.0);
+        controller.set_root_motion_extraction(true, true);
+        controller.get_local_pose(0.3, &mut local_poses);
+        assert_eq!(local_poses[0].get_translation(), [0.0, 0.0, 0.0]);
+        assert_eq!(local_poses[0].get_rotation(), quaternion_id());
+        assert!(vec3_len(vec3_sub(controller.root_motion_delta(), [0.0, 0.0, 0.1])) < 0.001);
+    }
+
+    #[test]
+    fn test_output_parameters() {
+
+        let skeleton = Rc::new(Skeleton::from_joints((0 .. 4).map(|i| {
+            let parent_index = if i == 0 { ROOT_JOINT_PARENT_INDEX } else { i - 1 };
+            Joint::new(&format!("joint_{}", i), parent_index, mat4_id())
+        }).collect()));
+
+        let local_poses = [0.0, 1.0, 1.0, 1.0].iter().map(|&y| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([0.0, y, 0.0]);
+            pose
+        }).collect();
+        let mut animations = HashMap::new();
+        animations.insert("idle".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample { local_poses }], 1.0)));
+
+        let params = ["blend", "x", "y", "z", "bend_x", "bend_y", "bend_z"];
+        let mut def = test_controller_def(&params);
+        def.output_parameters = Some(vec!["blend".to_string()]);
+        let mut ik = IKNodeDef::new("joint_3", "blend", ["x", "y", "z"], ["bend_x", "bend_y", "bend_z"]);
+        ik.blend_ramp_time = 0.5;
+        def.states[0].blend_tree = BlendTreeNodeDef::IKNode(Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())), Box::new(ik));
+        assert_eq!(def.validate_params(), Ok(()));
+
+        let mut controller = AnimationController::new(def, skeleton, &animations);
+        let mut output = [mat4_id(); 4];
+
+        controller.get_output_pose(0.0, &mut output);
+        controller.set_param_value("blend", 1.0);
+
+        // The output follows the IK node's blend ramp, rather than the value that was set
+        for &expected_weight in [0.2, 0.4, 0.6].iter() {
+            controller.update(0.1);
+            controller.get_output_pose(0.0, &mut output);
+            assert!((controller.get_param_value("blend") - expected_weight).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn test_trigger_fires_one_transition() {
+
+        let on_wave = |target_state: &str| AnimationTransition {
+            target_state: target_state.to_string(),
+            condition: TransitionCondition {
+                parameter: "wave".to_string(),
+                operator: Operator::IsTrue,
+                value: 0.0,
+            },
+            duration: 0.1,
+        };
+
+        // Waving and idling each transition to the other on the same trigger
+        let mut def = test_controller_def(&["wave", "crouch"]);
+        def.states[0].transitions.push(on_wave("wave"));
+        def.states.push(AnimationStateDef {
+            name: "wave".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+            transitions: vec![on_wave("idle")],
+            reference: None,
+        });
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &test_animations());
+        let mut output = [mat4_id()];
+
+        controller.set_trigger("wave");
+        assert!(controller.get_param_bool("wave"));
+
+        for _ in 0 .. 10 {
+            controller.update(0.1);
+            controller.get_output_pose(0.0, &mut output);
+        }
+
+        assert_eq!(controller.current_state_name(), "wave");
+        assert!(!controller.get_param_bool("wave"));
+        let entered = controller.drain_events().into_iter().filter(|event| matches!(*event, AnimEvent::StateEntered(_))).count();
+        assert_eq!(entered, 1);
+
+        // Bools aren't consumed
+        controller.set_param_bool("crouch", true);
+        assert!(controller.get_param_bool("crouch"));
+        assert_eq!(controller.get_param_value("crouch"), 1.0);
+        controller.set_param_bool("crouch", false);
+        assert!(!controller.get_param_bool("crouch"));
+    }
+
+    #[test]
+    fn test_transition_progress() {
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states.push(AnimationStateDef {
+            name: "walk".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+            transitions: Vec::new(),
+            reference: None,
+        });
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "walk".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 1.0,
+        });
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &test_animations());
+        let mut output = [mat4_id()];
+
+        assert_eq!(controller.current_state_name(), "idle");
+        assert!(!controller.is_transitioning());
+        assert_eq!(controller.target_state_name(), None);
+        assert_eq!(controller.transition_progress(), None);
+
+        controller.set_param_value("speed", 1.0);
+        controller.get_output_pose(0.0, &mut output);
+        assert!(controller.is_transitioning());
+        assert_eq!(controller.target_state_name(), Some("walk"));
+
+        // Progress follows the clock between calls to get_output_pose
+        controller.update(0.25);
+        assert!((controller.transition_progress().unwrap() - 0.25).abs() < EPSILON);
+        assert!((controller.transition_progress().unwrap() - 0.25).abs() < EPSILON);
+        controller.update(0.25);
+        assert!((controller.transition_progress().unwrap() - 0.5).abs() < EPSILON);
+        assert_eq!(controller.current_state_name(), "idle");
+
+        controller.update(1.0);
+        assert_eq!(controller.transition_progress(), Some(1.0));
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.current_state_name(), "walk");
+        assert!(!controller.is_transitioning());
+    }
+
+    #[test]
+    fn test_zero_duration_transition() {
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states.push(AnimationStateDef {
+            name: "walk".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+            transitions: Vec::new(),
+            reference: None,
+        });
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "walk".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.0,
+        });
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &test_animations());
+        let mut output = [mat4_id()];
+
+        // At its start instant, the transition is complete rather than NaN
+        controller.set_param_value("speed", 1.0);
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.transition_progress(), Some(1.0));
+        assert!(output[0].iter().all(|row| row.iter().all(|x| x.is_finite())));
+
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.current_state_name(), "walk");
+    }
+
+    #[test]
+    fn test_transition_remaining() {
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states.push(AnimationStateDef {
+            name: "walk".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+            transitions: Vec::new(),
+            reference: None,
+        });
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "walk".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.5,
+        });
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &test_animations());
+        let mut output = [mat4_id()];
+        assert_eq!(controller.transition_remaining(), None);
+
+        controller.set_param_value("speed", 1.0);
+        controller.get_output_pose(0.0, &mut output);
+        assert!((controller.transition_remaining().unwrap() - 0.5).abs() < EPSILON);
+
+        // Decreases with the clock, and holds at zero once the duration has elapsed
+        for &expected in [0.4, 0.3, 0.2, 0.1, 0.0, 0.0].iter() {
+            controller.update(0.1);
+            assert!((controller.transition_remaining().unwrap() - expected).abs() < EPSILON);
+        }
+
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.transition_remaining(), None);
+    }
+
+    #[test]
+    fn test_decode_operator() {
+
+        use rustc_serialize::json;
+
+        let condition: TransitionCondition = json::decode(r#"{ "parameter": "speed", "operator": ">=", "value": 0.5 }"#).unwrap();
+        match condition.operator {
+            Operator::GreaterThanEqual => (),
+            operator => panic!("Expected GreaterThanEqual, got {:?}", operator),
+        }
+
+        let condition: TransitionCondition = json::decode(r#"{ "parameter": "jump", "operator": "is_true", "value": 0 }"#).unwrap();
+        match condition.operator {
+            Operator::IsTrue => (),
+            operator => panic!("Expected IsTrue, got {:?}", operator),
+        }
+
+        let result: Result<TransitionCondition, _> = json::decode(r#"{ "parameter": "speed", "operator": "=>", "value": 0.5 }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_last_eval_stats() {
+
+        let mut animations = test_animations();
+        let walk = animations["idle"].clone();
+        animations.insert("walk".to_string(), walk);
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states[0].blend_tree = BlendTreeNodeDef::LerpNode(
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            Box::new(BlendTreeNodeDef::ClipNode("walk".to_string())),
+            "speed".to_string(),
+            BlendChannels::All,
+            TranslationSpace::Parent,
+        );
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &animations);
+        let mut output = [mat4_id()];
+
+        controller.get_output_pose(0.0, &mut output);
+        assert_eq!(controller.last_eval_stats(), EvalStats::default());
+
+        controller.set_profiling(true);
+        controller.update(0.1);
+        controller.get_output_pose(0.0, &mut output);
+        let stats = controller.last_eval_stats();
+        assert_eq!(stats.nodes_evaluated, 3);
+        assert_eq!(stats.clips_sampled, 2);
+    }
+
+    #[test]
+    fn test_joint_limits() {
+
+        // A knee bent 120 degrees about x
+        let mut pose = QVTransform::identity();
+        pose.set_rotation(quaternion::axis_angle([1.0, 0.0, 0.0], 120.0f32.to_radians()));
+
+        let mut animations = HashMap::new();
+        animations.insert("idle".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample { local_poses: vec![pose] }], 1.0)));
+
+        let mut controller = AnimationController::new(test_controller_def(&[]), test_skeleton(), &animations);
+
+        let limit = JointLimit { min: [0.0, -0.1, -0.1], max: [90.0f32.to_radians(), 0.1, 0.1] };
+        controller.set_joint_limit(0, limit).unwrap();
+
+        let mut local_poses = [QVTransform::identity()];
+        controller.get_local_pose(0.0, &mut local_poses);
+
+        // Clamped to 90 degrees about x
+        let expected = quaternion::axis_angle([1.0, 0.0, 0.0], 90.0f32.to_radians());
+        let bent = quaternion::rotate_vector(local_poses[0].get_rotation(), [0.0, 1.0, 0.0]);
+        assert!(vec3_len(vec3_sub(bent, quaternion::rotate_vector(expected, [0.0, 1.0, 0.0]))) < 0.001);
+
+        // Within the limits, the pose is untouched
+        controller.clear_joint_limits();
+        controller.get_local_pose(0.0, &mut local_poses);
+        let unlimited = local_poses[0].get_rotation();
+        controller.set_joint_limit(0, JointLimit { min: [-3.0; 3], max: [3.0; 3] }).unwrap();
+        controller.get_local_pose(0.0, &mut local_poses);
+        assert_eq!(local_poses[0].get_rotation(), unlimited);
+
+        // A joint outside the skeleton is rejected up front, rather than panicking later
+        assert!(controller.set_joint_limit(1, limit).is_err());
+        controller.get_local_pose(0.0, &mut local_poses);
+        assert_eq!(local_poses[0].get_rotation(), unlimited);
+    }
+
+    #[test]
+    fn test_frozen_joints() {
+
+        let mut arm_bind_pose = mat4_id();
+        arm_bind_pose.set_translation([-1.0, 0.0, 0.0]);
+        let skeleton = Rc::new(Skeleton::from_joints(vec![
+            Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("arm", 0, arm_bind_pose),
+        ]));
+
+        let clip = AnimationClip::new((0 .. 3).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([i as f32, 2.0, 0.0]);
+            AnimationSample { local_poses: vec![pose, pose] }
+        }).collect(), 3.0);
+
+        let mut animations = HashMap::new();
+        animations.insert("idle".to_string(), Rc::new(clip));
+
+        let mut controller = AnimationController::new(test_controller_def(&[]), skeleton, &animations);
+        controller.set_frozen_joints(&[1]).unwrap();
+
+        let mut local_poses = [QVTransform::identity(); 2];
+        for &time in [0.0, 0.5].iter() {
+            controller.update(time - controller.local_clock);
+            controller.get_local_pose(0.0, &mut local_poses);
+            assert_eq!(local_poses[1].get_translation(), [1.0, 0.0, 0.0]);
+            assert!((local_poses[0].get_translation()[0] - time as f32 * 3.0).abs() < 0.001);
+        }
+
+        // A joint outside the skeleton is rejected, keeping the frozen joints as they were
+        assert!(controller.set_frozen_joints(&[0, 2]).is_err());
+        controller.get_local_pose(0.0, &mut local_poses);
+        assert_eq!(local_poses[1].get_translation(), [1.0, 0.0, 0.0]);
+
+        controller.set_frozen_joints(&[]).unwrap();
+        controller.get_local_pose(0.0, &mut local_poses);
+        assert_eq!(local_poses[1].get_translation(), local_poses[0].get_translation());
+    }
+
+    #[test]
+    fn test_live_reference() {
+
+        // An aim pose offset from a moving base, referenced against the moving base
+        let clip = |y: f32| AnimationClip::new((0 .. 3).map(|i| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([i as f32, y, 0.0]);
+            AnimationSample { local_poses: vec![pose] }
+        }).collect(), 3.0);
+
+        let mut animations = HashMap::new();
+        animations.insert("idle".to_string(), Rc::new(clip(0.0)));
+        animations.insert("aim".to_string(), Rc::new(clip(1.0)));
+
+        let mut def = test_controller_def(&[]);
+        def.states[0].blend_tree = BlendTreeNodeDef::ClipNode("aim".to_string());
+        def.states[0].reference = Some(BlendTreeNodeDef::ClipNode("idle".to_string()));
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &animations);
+
+        // The difference stays constant as the reference moves
+        let mut local_poses = [QVTransform::identity()];
+        for &time in [0.0, 0.25, 0.5].iter() {
+            controller.update(time - controller.local_clock);
+            controller.get_local_pose(0.0, &mut local_poses);
+            let translation = local_poses[0].get_translation();
+            assert!(vec3_len(vec3_sub(translation, [0.0, 1.0, 0.0])) < 0.001, "{:?} at {}", translation, time);
+        }
+    }
+
+    #[test]
+    fn test_validate_params() {
+
+        let mut def = test_controller_def(&["speed"]);
+        assert_eq!(def.validate_params(), Ok(()));
+
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "idle".to_string(),
+            condition: TransitionCondition {
+                parameter: "sped".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.1,
+        });
+        def.states[0].blend_tree = BlendTreeNodeDef::LerpNode(
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            "speed".to_string(), BlendChannels::All, TranslationSpace::Parent);
+        def.states.push(AnimationStateDef {
+            name: "lean".to_string(),
+            blend_tree: BlendTreeNodeDef::AdditiveNode(
+                Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+                Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+                "lean".to_string(), BlendChannels::All, Vec::new(), None),
+            transitions: Vec::new(),
+            reference: None,
+        });
+
+        assert_eq!(def.validate_params(), Err(vec!["sped".to_string(), "lean".to_string()]));
+    }
+
+    #[test]
+    fn test_validate_blend_trees() {
+
+        let mut def = test_controller_def(&["speed"]);
+        assert_eq!(def.validate_blend_trees(), Ok(()));
+
+        def.states[0].reference = Some(BlendTreeNodeDef::LerpNode(
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            Box::new(BlendTreeNodeDef::ClipIndexNode(0)),
+            "speed".to_string(), BlendChannels::All, TranslationSpace::Parent));
+        assert_eq!(def.validate_blend_trees(),
+                   Err("State 'idle' references a clip by index, which controllers don't support".to_string()));
+
+        let mut deep_tree = BlendTreeNodeDef::ClipNode("idle".to_string());
+        for _ in 0 .. DEFAULT_MAX_BLEND_TREE_DEPTH {
+            deep_tree = BlendTreeNodeDef::SpeedNode(Box::new(deep_tree), "speed".to_string());
+        }
+        def.states[0].reference = None;
+        def.states[0].blend_tree = deep_tree;
+        assert_eq!(def.validate_blend_trees(), Err("State 'idle': Blend tree exceeds maximum depth".to_string()));
+    }
+
+    #[test]
+    fn test_rename_param() {
+
+        let lerp_tree = || BlendTreeNodeDef::LerpNode(
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            "speed".to_string(), BlendChannels::All, TranslationSpace::Parent);
+
+        let mut def = test_controller_def(&["speed", "max_speed"]);
+        def.computed_parameters = Some(vec![
+            ComputedParamDef { name: "blend".to_string(), expression: Expression::parse("speed / max_speed").unwrap() },
+        ]);
+        def.states[0].blend_tree = lerp_tree();
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "walk".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.5,
+        });
+        def.states.push(AnimationStateDef {
+            name: "walk".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode("idle".to_string()),
+            transitions: Vec::new(),
+            reference: Some(lerp_tree()),
+        });
+
+        let mut renamed_def = def.clone();
+        renamed_def.rename_param("speed", "velocity");
+
+        assert_eq!(renamed_def.parameters, vec!["velocity".to_string(), "max_speed".to_string()]);
+        assert_eq!(renamed_def.validate_params(), Ok(()));
+
+        let computed_parameters = renamed_def.computed_parameters.clone().unwrap();
+        let mut referenced_params: Vec<&str> = computed_parameters.iter().flat_map(|c| c.expression.referenced_params()).collect();
+        for state in renamed_def.states.iter() {
+            referenced_params.extend(state.transitions.iter().map(|t| &t.condition.parameter[..]));
+            referenced_params.extend(state.blend_tree.referenced_params());
+            referenced_params.extend(state.reference.iter().flat_map(|reference| reference.referenced_params()));
+        }
+        assert!(referenced_params.contains(&"velocity"));
+        assert!(!referenced_params.contains(&"speed"));
+
+        let mut controller = AnimationController::new(def, test_skeleton(), &test_animations());
+        let mut renamed_controller = AnimationController::new(renamed_def, test_skeleton(), &test_animations());
+
+        controller.set_param_value("speed", 1.0);
+        controller.set_param_value("max_speed", 2.0);
+        renamed_controller.set_param_value("velocity", 1.0);
+        renamed_controller.set_param_value("max_speed", 2.0);
+
+        for _ in 0 .. 4 {
+            controller.update(0.2);
+            renamed_controller.update(0.2);
+
+            let mut output = [mat4_id()];
+            let mut renamed_output = [mat4_id()];
+            controller.get_output_pose(0.0, &mut output);
+            renamed_controller.get_output_pose(0.0, &mut renamed_output);
+
+            assert_eq!(controller.current_state(), renamed_controller.current_state());
+            assert_eq!(controller.get_param_value("blend"), renamed_controller.get_param_value("blend"));
+            assert_eq!(output, renamed_output);
+        }
+    }
+
+    #[test]
+    fn test_computed_params() {
+
+        let mut def = test_controller_def(&["speed", "max_speed"]);
+        def.computed_parameters = Some(vec![
+            ComputedParamDef { name: "blend".to_string(), expression: Expression::parse("speed / max_speed * 0.5").unwrap() },
+            ComputedParamDef { name: "lean".to_string(), expression: Expression::parse("blend - 0.25").unwrap() },
+        ]);
+        assert_eq!(def.validate_params(), Ok(()));
+
+        let mut controller = AnimationController::new(def.clone(), test_skeleton(), &test_animations());
+
+        controller.set_param_value("speed", 3.0);
+        controller.set_param_value("max_speed", 2.0);
+        controller.update(0.1);
+
+        assert!((controller.get_param_value("blend") - 0.75).abs() < EPSILON);
+        assert!((controller.get_param_value("lean") - 0.5).abs() < EPSILON);
+
+        // Dependents are re-evaluated as soon as an input is set, without waiting for an update
+        controller.set_param_value("speed", 1.0);
+        assert!((controller.get_param_value("blend") - 0.25).abs() < EPSILON);
+        assert!((controller.get_param_value("lean") - 0.0).abs() < EPSILON);
+
+        def.computed_parameters = Some(vec![
+            ComputedParamDef { name: "blend".to_string(), expression: Expression::parse("sped * 0.5").unwrap() },
+        ]);
+        assert_eq!(def.validate_params(), Err(vec!["sped".to_string()]));
+    }
+
+    #[test]
+    fn test_computed_params_default_zero() {
+
+        let mut def = test_controller_def(&["speed", "max_speed"]);
+        def.computed_parameters = Some(vec![
+            ComputedParamDef { name: "blend".to_string(), expression: Expression::parse("speed / max_speed").unwrap() },
+        ]);
+        def.states[0].blend_tree = BlendTreeNodeDef::LerpNode(
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            "blend".to_string(), BlendChannels::All, TranslationSpace::Parent);
+
+        // Every declared parameter starts at 0, so the division is by zero
+        let mut controller = AnimationController::new(def, test_skeleton(), &test_animations());
+        assert_eq!(controller.get_param_value("blend"), 0.0);
+
+        let mut output = [mat4_id(); 1];
+        controller.get_output_pose(0.0, &mut output);
+        assert!(!output[0].iter().flat_map(|row| row.iter()).any(|c| c.is_nan()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown animation state in controller 'test': missing")]
+    fn test_unknown_initial_state() {
+        let mut def = test_controller_def(&[]);
+        def.initial_state = "missing".to_string();
+        AnimationController::new(def, test_skeleton(), &test_animations());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown animation state in controller 'test': missing")]
+    fn test_unknown_transition_target() {
+        let mut def = test_controller_def(&["speed"]);
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "missing".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.1,
+        });
+        AnimationController::new(def, test_skeleton(), &test_animations());
+    }
+
+    #[test]
+    fn test_try_new_unknown_state() {
+
+        let mut def = test_controller_def(&[]);
+        def.initial_state = "missing".to_string();
+        let result = AnimationController::<QVTransform>::try_new(def, test_skeleton(), &test_animations());
+        assert_eq!(result.err(), Some("Unknown animation state in controller 'test': missing".to_string()));
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states[0].transitions.push(AnimationTransition {
+            target_state: "mising".to_string(),
+            condition: TransitionCondition {
+                parameter: "speed".to_string(),
+                operator: Operator::GreaterThan,
+                value: 0.5,
+            },
+            duration: 0.1,
+        });
+        let result = AnimationController::<QVTransform>::try_new(def, test_skeleton(), &test_animations());
+        assert_eq!(result.err(), Some("Unknown animation state in controller 'test': mising".to_string()));
+    }
+
+    #[test]
+    fn test_try_new_over_deep_blend_tree() {
+
+        let mut deep_tree = BlendTreeNodeDef::ClipNode("idle".to_string());
+        for _ in 0 .. DEFAULT_MAX_BLEND_TREE_DEPTH {
+            deep_tree = BlendTreeNodeDef::SpeedNode(Box::new(deep_tree), "speed".to_string());
+        }
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states[0].reference = Some(deep_tree);
+        let result = AnimationController::<QVTransform>::try_new(def, test_skeleton(), &test_animations());
+        assert_eq!(result.err(), Some("State 'idle': Blend tree exceeds maximum depth".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "State 'idle': Blend tree exceeds maximum depth")]
+    fn test_new_over_deep_blend_tree() {
+
+        let mut deep_tree = BlendTreeNodeDef::ClipNode("idle".to_string());
+        for _ in 0 .. DEFAULT_MAX_BLEND_TREE_DEPTH {
+            deep_tree = BlendTreeNodeDef::SpeedNode(Box::new(deep_tree), "speed".to_string());
+        }
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states[0].blend_tree = deep_tree;
+        AnimationController::<QVTransform>::new(def, test_skeleton(), &test_animations());
+    }
+
+    #[test]
+    fn test_try_new_invalid_blend_tree() {
+
+        let mut def = test_controller_def(&[]);
+        def.states[0].blend_tree = BlendTreeNodeDef::ClipIndexNode(0);
+        let result = AnimationController::<QVTransform>::try_new(def, test_skeleton(), &test_animations());
+        assert_eq!(result.err(), Some("State 'idle' references a clip by index, which controllers don't support".to_string()));
+
+        let mut def = test_controller_def(&[]);
+        def.states[0].blend_tree = BlendTreeNodeDef::IKNode(
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            Box::new(IKNodeDef::new("hand", "blend", ["x", "y", "z"], ["bend_x", "bend_y", "bend_z"])));
+        let result = AnimationController::<QVTransform>::try_new(def, test_skeleton(), &test_animations());
+        assert_eq!(result.err(), Some("State 'idle': Unknown IK effector joint: hand".to_string()));
+    }
+
+    #[test]
+    fn test_try_new_unknown_clip() {
+        let mut def = test_controller_def(&[]);
+        def.states[0].reference = Some(BlendTreeNodeDef::ClipNode("missing".to_string()));
+        let result = AnimationController::<QVTransform>::try_new(def, test_skeleton(), &test_animations());
+        assert_eq!(result.err(), Some("Unknown animation clip in controller 'test': missing".to_string()));
+    }
+
+    #[test]
+    fn test_clip_handles() {
+
+        let mut def = test_controller_def(&["speed"]);
+        def.states[0].blend_tree = BlendTreeNodeDef::LerpNode(
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            Box::new(BlendTreeNodeDef::ClipNode("idle".to_string())),
+            "speed".to_string(), BlendChannels::All, TranslationSpace::Parent);
+
+        let animations = test_animations();
+        let controller = AnimationController::try_new(def, test_skeleton(), &animations).unwrap();
+
+        let idle = controller.get_clip_handle("idle").unwrap();
+        assert!(Rc::ptr_eq(controller.get_clip(idle), &animations["idle"]));
+        assert_eq!(controller.get_clip_handle("walk"), None);
+    }
+
+    #[test]
+    fn test_set_mirrored_ik_target() {
+
+        let params = ["lx", "ly", "lz", "rx", "ry", "rz"];
+        let mut controller = test_controller(&params);
+
+        controller.set_mirrored_ik_target(["lx", "ly", "lz"], ["rx", "ry", "rz"],
+                                          [0.3, 1.2, 0.5], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+
+        let left = [controller.get_param_value("lx"), controller.get_param_value("ly"), controller.get_param_value("lz")];
+        let right = [controller.get_param_value("rx"), controller.get_param_value("ry"), controller.get_param_value("rz")];
+
+        // Targets should be equidistant from the mirror plane, on opposite sides
+        assert!((left[0] + right[0]).abs() < EPSILON);
+        assert!((left[1] - right[1]).abs() < EPSILON);
+        assert!((left[2] - right[2]).abs() < EPSILON);
+        assert!((left[0] - 0.3).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_update_ticks_is_exact() {
+
+        let mut controller = test_controller(&[]);
+        for _ in 0 .. 10 {
+            controller.update_ticks(TICKS_PER_SECOND / 10);
+        }
+        assert_eq!(controller.local_clock, 1.0);
+    }
+
+    #[test]
+    fn test_update_ticks_is_deterministic() {
+
+        let ticks = [16_667, 16_666, 33_334, 8_333, 16_667, 1, 250_000];
+
+        let run = || {
+            let mut controller = test_controller(&[]);
+            let mut poses = Vec::new();
+            for &delta in ticks.iter() {
+                controller.update_ticks(delta);
+                let mut output = [mat4_id()];
+                controller.get_output_pose_ticks(delta / 2, &mut output);
+                poses.push(output[0]);
+            }
+            poses
+        };
+
+        let run_1 = run();
+        let run_2 = run();
+
+        for (pose_1, pose_2) in run_1.iter().zip(run_2.iter()) {
+            for row in 0 .. 4 {
+                for col in 0 .. 4 {
+                    assert_eq!(pose_1[row][col].to_bits(), pose_2[row][col].to_bits());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_last_frame_window() {
+
+        let mut controller = test_controller(&[]);
+        let mut output = [mat4_id()];
+
+        controller.update(0.1);
+        controller.get_output_pose(0.0, &mut output);
+        let (start_1, end_1) = controller.last_frame_window();
+
+        controller.update(0.1);
+        controller.get_output_pose(0.05, &mut output);
+        let (start_2, end_2) = controller.last_frame_window();
+
+        assert!((start_1 - 0.0).abs() < EPSILON);
+        assert!((end_1 - 0.1).abs() < EPSILON);
+        assert_eq!(end_1, start_2);
+        assert!((end_2 - 0.25).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_negative_playback_speed_is_clamped() {
+
+        let mut controller = test_controller(&[]);
+        controller.update(0.5);
+
+        controller.set_playback_speed(-2.0);
+        assert_eq!(controller.get_playback_speed(), 0.0);
+        controller.update(0.25);
+        assert!((controller.local_clock - 0.5).abs() < EPSILON as f64);
+
+        controller.set_playback_speed(f64::NAN);
+        assert_eq!(controller.get_playback_speed(), 0.0);
+
+        controller.set_playback_speed(2.0);
+        controller.update(0.25);
+        assert!((controller.local_clock - 1.0).abs() < EPSILON as f64);
+    }
 }
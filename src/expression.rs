@@ -0,0 +1,217 @@
+//! Minimal arithmetic expressions over controller parameters, for computed parameters
+//! such as `speed / max_speed`.
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use rustc_serialize::{Decodable, Decoder};
+
+/// A parsed arithmetic expression of numbers, parameter references, `+ - * /`, unary minus,
+/// and parentheses, with the usual precedence
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Constant(f32),
+    Param(String),
+    Negate(Box<Expression>),
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Box<Expression>, Box<Expression>),
+    Divide(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+
+    /// Parse an expression from a string, e.g. `"speed * 0.5 + 1"`
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let mut chars = source.chars().peekable();
+        let expression = parse_sum(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(expression),
+            Some(c) => Err(format!("Unexpected '{}' in expression: {}", c, source)),
+        }
+    }
+
+    /// Evaluate the expression with the given parameter values. Parameters without a value
+    /// evaluate to 0.0, as does division by zero, so that e.g. `speed / max_speed` is 0.0 rather
+    /// than NaN before `max_speed` is set.
+    pub fn evaluate(&self, parameters: &HashMap<String, f32>) -> f32 {
+        match *self {
+            Expression::Constant(value) => value,
+            Expression::Param(ref name) => parameters.get(name).cloned().unwrap_or(0.0),
+            Expression::Negate(ref a) => -a.evaluate(parameters),
+            Expression::Add(ref a, ref b) => a.evaluate(parameters) + b.evaluate(parameters),
+            Expression::Subtract(ref a, ref b) => a.evaluate(parameters) - b.evaluate(parameters),
+            Expression::Multiply(ref a, ref b) => a.evaluate(parameters) * b.evaluate(parameters),
+            Expression::Divide(ref a, ref b) => {
+                let divisor = b.evaluate(parameters);
+                if divisor == 0.0 { 0.0 } else { a.evaluate(parameters) / divisor }
+            }
+        }
+    }
+
+    /// Names of the parameters referenced by the expression, in order of appearance
+    pub fn referenced_params(&self) -> Vec<&str> {
+        match *self {
+            Expression::Constant(_) => Vec::new(),
+            Expression::Param(ref name) => vec![&name[..]],
+            Expression::Negate(ref a) => a.referenced_params(),
+            Expression::Add(ref a, ref b)
+            | Expression::Subtract(ref a, ref b)
+            | Expression::Multiply(ref a, ref b)
+            | Expression::Divide(ref a, ref b) => {
+                let mut params = a.referenced_params();
+                params.extend(b.referenced_params());
+                params
+            }
+        }
+    }
+
+    /// Rename every reference to the parameter `old` as `new`
+    pub fn rename_param(&mut self, old: &str, new: &str) {
+        match *self {
+            Expression::Constant(_) => (),
+            Expression::Param(ref mut name) => {
+                if name == old {
+                    *name = new.to_string();
+                }
+            }
+            Expression::Negate(ref mut a) => a.rename_param(old, new),
+            Expression::Add(ref mut a, ref mut b)
+            | Expression::Subtract(ref mut a, ref mut b)
+            | Expression::Multiply(ref mut a, ref mut b)
+            | Expression::Divide(ref mut a, ref mut b) => {
+                a.rename_param(old, new);
+                b.rename_param(old, new);
+            }
+        }
+    }
+}
+
+impl Decodable for Expression {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Expression, D::Error> {
+        let source = decoder.read_str()?;
+        Expression::parse(&source[..]).map_err(|e| decoder.error(&e))
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while let Some(&c) = chars.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+}
+
+/// sum := product (('+' | '-') product)*
+fn parse_sum(chars: &mut Peekable<Chars>) -> Result<Expression, String> {
+    let mut expression = parse_product(chars)?;
+    loop {
+        skip_whitespace(chars);
+        match chars.peek().cloned() {
+            Some('+') => {
+                chars.next();
+                expression = Expression::Add(Box::new(expression), Box::new(parse_product(chars)?));
+            }
+            Some('-') => {
+                chars.next();
+                expression = Expression::Subtract(Box::new(expression), Box::new(parse_product(chars)?));
+            }
+            _ => return Ok(expression),
+        }
+    }
+}
+
+/// product := factor (('*' | '/') factor)*
+fn parse_product(chars: &mut Peekable<Chars>) -> Result<Expression, String> {
+    let mut expression = parse_factor(chars)?;
+    loop {
+        skip_whitespace(chars);
+        match chars.peek().cloned() {
+            Some('*') => {
+                chars.next();
+                expression = Expression::Multiply(Box::new(expression), Box::new(parse_factor(chars)?));
+            }
+            Some('/') => {
+                chars.next();
+                expression = Expression::Divide(Box::new(expression), Box::new(parse_factor(chars)?));
+            }
+            _ => return Ok(expression),
+        }
+    }
+}
+
+/// factor := number | name | '-' factor | '(' sum ')'
+fn parse_factor(chars: &mut Peekable<Chars>) -> Result<Expression, String> {
+    skip_whitespace(chars);
+    match chars.peek().cloned() {
+        Some('-') => {
+            chars.next();
+            Ok(Expression::Negate(Box::new(parse_factor(chars)?)))
+        }
+        Some('(') => {
+            chars.next();
+            let expression = parse_sum(chars)?;
+            skip_whitespace(chars);
+            match chars.next() {
+                Some(')') => Ok(expression),
+                _ => Err("Expected ')' in expression".to_string()),
+            }
+        }
+        Some(c) if c.is_ascii_digit() || c == '.' => {
+            let number = take_while(chars, |c| c.is_ascii_digit() || c == '.');
+            number.parse().map(Expression::Constant).map_err(|_| format!("Invalid number in expression: {}", number))
+        }
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            Ok(Expression::Param(take_while(chars, |c| c.is_alphanumeric() || c == '_')))
+        }
+        Some(c) => Err(format!("Unexpected '{}' in expression", c)),
+        None => Err("Unexpected end of expression".to_string()),
+    }
+}
+
+fn take_while<F: Fn(char) -> bool>(chars: &mut Peekable<Chars>, predicate: F) -> String {
+    let mut token = String::new();
+    while let Some(&c) = chars.peek() {
+        if !predicate(c) {
+            break;
+        }
+        token.push(c);
+        chars.next();
+    }
+    token
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+
+    use super::Expression;
+
+    #[test]
+    fn test_evaluate() {
+
+        let mut parameters = HashMap::new();
+        parameters.insert("speed".to_string(), 3.0);
+        parameters.insert("max_speed".to_string(), 4.0);
+
+        let expression = Expression::parse("speed / max_speed * 2").unwrap();
+        assert_eq!(expression.evaluate(&parameters), 1.5);
+        assert_eq!(expression.referenced_params(), vec!["speed", "max_speed"]);
+
+        // Precedence, unary minus and parentheses
+        assert_eq!(Expression::parse("1 + speed * 2").unwrap().evaluate(&parameters), 7.0);
+        assert_eq!(Expression::parse("-(1 + speed) * 2").unwrap().evaluate(&parameters), -8.0);
+
+        // Division by zero, or by an unset parameter
+        assert_eq!(Expression::parse("speed / 0").unwrap().evaluate(&parameters), 0.0);
+        assert_eq!(Expression::parse("speed / unset").unwrap().evaluate(&HashMap::new()), 0.0);
+
+        assert!(Expression::parse("speed *").is_err());
+        assert!(Expression::parse("(speed").is_err());
+        assert!(Expression::parse("speed max_speed").is_err());
+    }
+}
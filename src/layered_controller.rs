@@ -0,0 +1,212 @@
+use std::rc::Rc;
+
+use controller::AnimationController;
+use pose::PoseBuffer;
+use skeleton::Skeleton;
+use transform::{Transform, FromTransform};
+
+/// How a layer's pose is composited onto the result of the layers beneath it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerBlendMode {
+    /// Blend from the underlying pose towards the layer's pose by the layer weight
+    Override,
+    /// Apply the layer's pose as an additive (difference) pose, scaled by the layer weight
+    Additive,
+}
+
+/// A named AnimationController within a LayeredController
+pub struct AnimationLayer<T: Transform> {
+
+    /// Identifying name for the layer
+    pub name: String,
+
+    /// The controller providing the layer's local poses
+    pub controller: AnimationController<T>,
+
+    /// Layers are composited in ascending order of priority. Layers with equal
+    /// priority are composited in the order they were added.
+    pub priority: i32,
+
+    /// How the layer is composited onto the layers beneath it
+    pub blend_mode: LayerBlendMode,
+
+    /// Blend weight for the layer, from 0.0 to 1.0
+    pub weight: f32,
+}
+
+/// A stack of AnimationControllers sharing a skeleton, where the local poses of each layer
+/// are composited strictly in priority order, before global poses are calculated.
+///
+/// Compositing order matters, as additive rotations don't commute.
+pub struct LayeredController<T: Transform> {
+
+    /// Shared reference to the skeleton all layers are using
+    skeleton: Rc<Skeleton>,
+
+    /// The layers, sorted in compositing order
+    layers: Vec<AnimationLayer<T>>,
+}
+
+impl<T: Transform> LayeredController<T> {
+
+    /// Create a LayeredController with no layers for the given skeleton
+    pub fn new(skeleton: Rc<Skeleton>) -> LayeredController<T> {
+        LayeredController {
+            skeleton,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Add a layer, positioned after any existing layers of the same or lower priority
+    pub fn add_layer(&mut self, name: &str, controller: AnimationController<T>, priority: i32, blend_mode: LayerBlendMode, weight: f32) {
+        self.insert_layer(AnimationLayer {
+            name: name.to_string(),
+            controller,
+            priority,
+            blend_mode,
+            weight,
+        });
+    }
+
+    /// Change the priority of the named layer, moving it after any other layers of the
+    /// same or lower priority
+    pub fn set_layer_priority(&mut self, name: &str, priority: i32) {
+        if let Some(index) = self.layers.iter().position(|l| l.name == name) {
+            let mut layer = self.layers.remove(index);
+            layer.priority = priority;
+            self.insert_layer(layer);
+        }
+    }
+
+    /// Set the blend weight of the named layer
+    pub fn set_layer_weight(&mut self, name: &str, weight: f32) {
+        if let Some(layer) = self.layers.iter_mut().find(|l| l.name == name) {
+            layer.weight = weight;
+        }
+    }
+
+    /// Return the named layer, if any
+    pub fn get_layer_mut(&mut self, name: &str) -> Option<&mut AnimationLayer<T>> {
+        self.layers.iter_mut().find(|l| l.name == name)
+    }
+
+    /// Return the layers in compositing order
+    pub fn layers(&self) -> &[AnimationLayer<T>] {
+        &self.layers
+    }
+
+    /// Update the local clocks of all layers with the given time delta
+    pub fn update(&mut self, delta_time: f64) {
+        for layer in self.layers.iter_mut() {
+            layer.controller.update(delta_time);
+        }
+    }
+
+    /// Calculate global skeletal joint poses for the given time since last update, by
+    /// compositing the local poses of each layer in priority order
+    pub fn get_output_pose<TOutput: Transform + FromTransform<T>>(&mut self, ext_dt: f64, output_poses: &mut [TOutput]) {
+
+        let joint_count = self.skeleton.joints.len();
+        let mut composite_poses = PoseBuffer::<T>::new(joint_count);
+        let mut layer_poses = PoseBuffer::<T>::new(joint_count);
+
+        for layer in self.layers.iter_mut() {
+
+            layer.controller.get_local_pose(ext_dt, &mut layer_poses);
+
+            for (composite_pose, layer_pose) in composite_poses.iter_mut().zip(layer_poses.iter()) {
+                *composite_pose = match layer.blend_mode {
+                    LayerBlendMode::Override => composite_pose.lerp(*layer_pose, layer.weight),
+                    LayerBlendMode::Additive => composite_pose.concat(T::identity().lerp(*layer_pose, layer.weight)),
+                };
+            }
+        }
+
+        self.skeleton.calculate_global_poses(&composite_poses, output_poses);
+    }
+
+    fn insert_layer(&mut self, layer: AnimationLayer<T>) {
+        let index = self.layers.iter().position(|l| l.priority > layer.priority).unwrap_or(self.layers.len());
+        self.layers.insert(index, layer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use animation::{AnimationClip, AnimationSample};
+    use blend_tree::BlendTreeNodeDef;
+    use controller::{AnimationController, AnimationControllerDef, AnimationStateDef};
+    use math::*;
+    use skeleton::{Joint, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::Transform;
+
+    use super::{LayeredController, LayerBlendMode};
+
+    static EPSILON: f32 = 0.0001;
+
+    fn test_skeleton() -> Rc<Skeleton> {
+        Rc::new(Skeleton::from_joints(vec![Joint::new("root", ROOT_JOINT_PARENT_INDEX, mat4_id())]))
+    }
+
+    /// A controller holding a single pose rotated about the given axis
+    fn rotation_controller(skeleton: Rc<Skeleton>, axis: Vector3<f32>) -> AnimationController<DualQuaternion<f32>> {
+
+        let pose = dual_quaternion::from_rotation_and_translation(
+            quaternion::axis_angle(axis, ::std::f32::consts::PI / 2.0), [0.0, 0.0, 0.0]);
+
+        let mut animations = HashMap::new();
+        animations.insert("pose".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample { local_poses: vec![pose] }], 1.0)));
+
+        let def = AnimationControllerDef {
+            name: "layer".to_string(),
+            parameters: Vec::new(),
+            computed_parameters: None,
+            output_parameters: None,
+            states: vec![AnimationStateDef {
+                name: "pose".to_string(),
+                blend_tree: BlendTreeNodeDef::ClipNode("pose".to_string()),
+                transitions: Vec::new(),
+                reference: None,
+            }],
+            initial_state: "pose".to_string(),
+        };
+
+        AnimationController::new(def, skeleton, &animations)
+    }
+
+    fn output_direction(layered: &mut LayeredController<DualQuaternion<f32>>) -> Vector3<f32> {
+        let mut output = [mat4_id()];
+        layered.get_output_pose(0.0, &mut output);
+        output[0].transform_vector([1.0, 1.0, 0.0])
+    }
+
+    #[test]
+    fn test_additive_layer_order() {
+
+        let skeleton = test_skeleton();
+        let mut layered = LayeredController::new(skeleton.clone());
+
+        // Added in reverse priority order
+        layered.add_layer("z", rotation_controller(skeleton.clone(), [0.0, 0.0, 1.0]), 1, LayerBlendMode::Additive, 1.0);
+        layered.add_layer("x", rotation_controller(skeleton.clone(), [1.0, 0.0, 0.0]), 0, LayerBlendMode::Additive, 1.0);
+
+        let names: Vec<&str> = layered.layers().iter().map(|l| &l.name[..]).collect();
+        assert_eq!(names, vec!["x", "z"]);
+
+        // Rx * Rz applied to (1, 1, 0)
+        let x_then_z = output_direction(&mut layered);
+        assert!(vec3_len(vec3_sub(x_then_z, [-1.0, 0.0, 1.0])) < EPSILON);
+
+        // Stable across evaluations
+        assert!(vec3_len(vec3_sub(x_then_z, output_direction(&mut layered))) < EPSILON);
+
+        // Rz * Rx applied to (1, 1, 0)
+        layered.set_layer_priority("x", 2);
+        let z_then_x = output_direction(&mut layered);
+        assert!(vec3_len(vec3_sub(z_then_x, [0.0, 1.0, 1.0])) < EPSILON);
+    }
+}
@@ -5,6 +5,7 @@ extern crate collada;
 extern crate gfx;
 extern crate gfx_debug_draw;
 extern crate gfx_texture;
+extern crate image;
 pub extern crate quaternion;
 pub extern crate dual_quaternion;
 extern crate vecmath;
@@ -15,18 +16,24 @@ extern crate float;
 pub mod animation;
 pub mod skinned_renderer;
 pub mod blend_tree;
+pub mod character;
 pub mod controller;
+pub mod expression;
+pub mod layered_controller;
 pub mod manager;
 pub mod skeleton;
 pub mod math;
+pub mod pose;
 mod transform;
 
 pub use animation::{
     AnimationClip,
     AnimationSample,
+    LoopMode,
+    MirrorConfig,
 };
 
-pub use transform::{Transform, QVTransform, FromTransform};
+pub use transform::{Transform, QVTransform, FromTransform, TranslationSpace};
 
 pub use skeleton::{
     Skeleton,
@@ -35,8 +42,13 @@ pub use skeleton::{
 pub use manager::{
     AssetManager,
     AssetDefs,
+    AssetError,
 };
 
-pub use controller::AnimationController;
+pub use character::{Character, load_character};
 
-pub use skinned_renderer::{SkinnedRenderer, HasShaderSources};
+pub use controller::{AnimationController, AnimEvent, ClipHandle, JointLimit, StateHandle};
+
+pub use layered_controller::{LayeredController, LayerBlendMode};
+
+pub use skinned_renderer::{SkinnedRenderer, SkinnedRendererError, HasShaderSources};
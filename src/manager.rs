@@ -5,9 +5,11 @@ use std::rc::Rc;
 use std::path::Path;
 use std::{fmt, error};
 
+use gfx;
 use rustc_serialize::{Decodable, json};
 
 use animation::{AnimationClip, AnimationClipDef, DifferenceClipDef};
+use skeleton::Skeleton;
 use transform::Transform;
 use controller::AnimationControllerDef;
 
@@ -24,23 +26,68 @@ pub struct AssetDefs {
 ///
 pub struct AssetManager<T: Transform> {
     pub animation_clips: HashMap<String, Rc<AnimationClip<T>>>,
-    pub controller_defs: HashMap<String, AnimationControllerDef>
+    pub controller_defs: HashMap<String, AnimationControllerDef>,
+
+    /// Definitions of the loaded difference clips, in load order, for rebuilding them when
+    /// their source or reference clips change
+    difference_clip_defs: Vec<DifferenceClipDef>,
 }
 
-/// Created when attempting to load assets from a path that does not have a parent folder (see
-/// [`Path::parent` documentation][0])
-///
-/// [0]: https://doc.rust-lang.org/std/path/struct.Path.html#method.parent
+/// An error loading assets, identifying the file or definition at fault
 #[derive(Debug)]
-pub struct InvalidAssetPathError;
+pub enum AssetError {
+    /// The asset path does not have a parent folder (see [`Path::parent` documentation][0])
+    ///
+    /// [0]: https://doc.rust-lang.org/std/path/struct.Path.html#method.parent
+    InvalidPath(String),
+
+    /// A definition file could not be opened or read
+    FileNotFound(String),
+
+    /// A definition file is not valid JSON, or doesn't match the expected definitions
+    Decode(String, json::DecoderError),
+
+    /// A difference clip references a clip that hasn't been loaded, as
+    /// (difference clip name, missing clip name)
+    MissingClip(String, String),
+
+    /// A clip's COLLADA source could not be loaded, as (clip name, problem)
+    Collada(String, String),
+
+    /// A character's skinned mesh could not be loaded, as (mesh source, problem)
+    Mesh(String, String),
 
-impl fmt::Display for InvalidAssetPathError {
+    /// A controller definition can't be built into a controller, as (controller name, problem)
+    InvalidController(String, String),
+
+    /// No controller definition with the given name was loaded
+    MissingController(String),
+
+    /// The shader program for a renderer failed to link
+    Program(gfx::shade::ProgramError),
+
+    /// A renderer's texture could not be loaded, as (texture path, problem)
+    Texture(String, String),
+}
+
+impl fmt::Display for AssetError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(fmt, "Asset path must have a parent folder")
+        match *self {
+            AssetError::InvalidPath(ref path) => write!(fmt, "Asset path must have a parent folder: {}", path),
+            AssetError::FileNotFound(ref path) => write!(fmt, "Failed to read definition file: {}", path),
+            AssetError::Decode(ref path, ref e) => write!(fmt, "Failed to decode definition file {}: {}", path, e),
+            AssetError::MissingClip(ref name, ref clip) => write!(fmt, "Difference clip '{}' references unknown clip '{}'", name, clip),
+            AssetError::Collada(ref name, ref e) => write!(fmt, "Failed to load clip '{}': {}", name, e),
+            AssetError::Mesh(ref source, ref e) => write!(fmt, "Failed to load skinned mesh '{}': {}", source, e),
+            AssetError::InvalidController(ref name, ref e) => write!(fmt, "Invalid animation controller '{}': {}", name, e),
+            AssetError::MissingController(ref name) => write!(fmt, "Unknown animation controller '{}'", name),
+            AssetError::Program(ref e) => write!(fmt, "Failed to link shader program: {:?}", e),
+            AssetError::Texture(ref path, ref e) => write!(fmt, "Failed to load texture {}: {}", path, e),
+        }
     }
 }
 
-impl error::Error for InvalidAssetPathError {}
+impl error::Error for AssetError {}
 
 impl<T: Transform> AssetManager<T> {
 
@@ -48,36 +95,44 @@ impl<T: Transform> AssetManager<T> {
         AssetManager {
             animation_clips: HashMap::new(),
             controller_defs: HashMap::new(),
+            difference_clip_defs: Vec::new(),
         }
     }
 
-    pub fn load_assets(&mut self, path: &str) -> Result<(), InvalidAssetPathError> {
+    /// Load the clips and controller definitions from the asset definition file at the given
+    /// path. Stops at the first error, keeping any assets loaded before it. Controller
+    /// definitions are checked with `AnimationControllerDef::validate_blend_trees`, so that
+    /// they can be built into controllers.
+    pub fn load_assets(&mut self, path: &str) -> Result<(), AssetError> {
 
-        let asset_defs: AssetDefs = AssetManager::<T>::load_def_from_path(path).unwrap();
-        let parent_path = Path::new(path).parent().ok_or(InvalidAssetPathError)?;
+        let asset_defs: AssetDefs = AssetManager::<T>::load_def_from_path(path)?;
+        let parent_path = Path::new(path).parent().ok_or_else(|| AssetError::InvalidPath(path.to_string()))?;
 
         if let Some(animation_clips) = asset_defs.animation_clips {
             for clip_def in animation_clips.iter() {
-                let clip = AnimationClip::from_def(clip_def, parent_path.to_owned());
+                let clip = AnimationClip::from_def(clip_def, parent_path.to_owned())
+                    .map_err(|e| AssetError::Collada(clip_def.name.clone(), e))?;
                 self.animation_clips.insert(clip_def.name.clone(), Rc::new(clip));
             }
         }
 
-        if let Some(difference_clips) = asset_defs.difference_clips {
-            for difference_clip_def in difference_clips.iter() {
-
-                let clip = {
-                    let ref source_clip = self.animation_clips[&difference_clip_def.source_clip[..]];
-                    let ref reference_clip = self.animation_clips[&difference_clip_def.reference_clip[..]];
-                    AnimationClip::as_difference_clip(source_clip, reference_clip)
-                };
+        // Refresh difference clips from earlier loads whose clips were just reloaded
+        self.rebuild_difference_clips()?;
 
+        if let Some(difference_clips) = asset_defs.difference_clips {
+            for difference_clip_def in difference_clips.into_iter() {
+                // Only keep definitions that build, so a bad one can't break later loads
+                let clip = self.build_difference_clip(&difference_clip_def)?;
                 self.animation_clips.insert(difference_clip_def.name.clone(), Rc::new(clip));
+                self.difference_clip_defs.retain(|def| def.name != difference_clip_def.name);
+                self.difference_clip_defs.push(difference_clip_def);
             }
         }
 
         if let Some(animation_controllers) = asset_defs.animation_controllers {
             for controller_def in animation_controllers.iter() {
+                controller_def.validate_blend_trees()
+                    .map_err(|e| AssetError::InvalidController(controller_def.name.clone(), e))?;
                 self.controller_defs.insert(controller_def.name.clone(), controller_def.clone());
             }
         }
@@ -85,23 +140,369 @@ impl<T: Transform> AssetManager<T> {
         Ok(())
     }
 
-    pub fn load_def_from_path<D>(path: &str) -> Result<D, &'static str>
+    /// Recompute all difference clips from their current source and reference clips, e.g. after
+    /// replacing a reference clip in `animation_clips`
+    pub fn rebuild_difference_clips(&mut self) -> Result<(), AssetError> {
+        for difference_clip_def in self.difference_clip_defs.iter() {
+            let clip = self.build_difference_clip(difference_clip_def)?;
+            self.animation_clips.insert(difference_clip_def.name.clone(), Rc::new(clip));
+        }
+        Ok(())
+    }
+
+    /// Compute a difference clip from its currently loaded source and reference clips
+    fn build_difference_clip(&self, difference_clip_def: &DifferenceClipDef) -> Result<AnimationClip<T>, AssetError> {
+        let get_clip = |name: &str| self.animation_clips.get(name).ok_or_else(|| {
+            AssetError::MissingClip(difference_clip_def.name.clone(), name.to_string())
+        });
+        let source_clip = get_clip(&difference_clip_def.source_clip[..])?;
+        let reference_clip = get_clip(&difference_clip_def.reference_clip[..])?;
+        Ok(AnimationClip::as_difference_clip(source_clip, reference_clip))
+    }
+
+    /// Check that every clip referenced by the loaded controller definitions has been loaded,
+    /// e.g. after `load_assets`, rather than finding out when a controller is created. Returns
+    /// a description of every missing clip, across all controllers, sorted by controller name.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+
+        let mut controller_names: Vec<&String> = self.controller_defs.keys().collect();
+        controller_names.sort();
+
+        let mut missing_clips = Vec::new();
+        for name in controller_names {
+            for clip in self.controller_defs[name].referenced_clips() {
+                if !self.animation_clips.contains_key(clip) {
+                    missing_clips.push(format!("Controller '{}' references unknown clip '{}'", name, clip));
+                }
+            }
+        }
+
+        if missing_clips.is_empty() {
+            Ok(())
+        } else {
+            Err(missing_clips)
+        }
+    }
+
+    /// Add a single-sample clip of the skeleton's bind pose under the given name, which
+    /// difference clips can name as their `reference_clip`. Should be added before loading
+    /// difference clips that reference it, or followed by `rebuild_difference_clips`.
+    pub fn add_bind_pose_clip(&mut self, name: &str, skeleton: &Skeleton) {
+        self.animation_clips.insert(name.to_string(), Rc::new(AnimationClip::from_bind_pose(skeleton)));
+    }
+
+    /// Return the names of all loaded clips with the given tag, in sorted order
+    pub fn clips_with_tag(&self, tag: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = self.animation_clips.iter()
+            .filter(|&(_, clip)| clip.tags.iter().any(|t| t == tag))
+            .map(|(name, _)| &name[..])
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn load_def_from_path<D>(path: &str) -> Result<D, AssetError>
         where D: Decodable
     {
         let file_result = File::open(path);
 
         let mut file = match file_result {
             Ok(file) => file,
-            Err(_) => return Err("Failed to open definition file at path.")
+            Err(_) => return Err(AssetError::FileNotFound(path.to_string()))
         };
 
         let mut json_string = String::new();
         match file.read_to_string(&mut json_string) {
             Ok(_) => {},
-            Err(_) => return Err("Failed to read definition file.")
+            Err(_) => return Err(AssetError::FileNotFound(path.to_string()))
+        };
+
+        json::decode(&json_string[..]).map_err(|e| AssetError::Decode(path.to_string(), e))
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    use rustc_serialize::json;
+
+    use animation::{AnimationClip, AnimationClipDef, AnimationSample, ClipEventDef, LoopMode};
+    use math::{DualQuaternion, mat4_id};
+    use skeleton::{Joint, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::{Transform, QVTransform};
+
+    use super::{AssetError, AssetManager};
+
+    fn tagged_clip(tags: &[&str]) -> Rc<AnimationClip<DualQuaternion<f32>>> {
+        let mut clip = AnimationClip::new(Vec::new(), 30.0);
+        clip.tags = tags.iter().map(|t| t.to_string()).collect();
+        Rc::new(clip)
+    }
+
+    #[test]
+    fn test_decode_clip_tags() {
+        let tagged: AnimationClipDef = json::decode(r#"{
+            "name": "walk-forward",
+            "source": "walk.dae",
+            "tags": ["locomotion", "loop"]
+        }"#).unwrap();
+        assert_eq!(tagged.tags, Some(vec!["locomotion".to_string(), "loop".to_string()]));
+
+        let untagged: AnimationClipDef = json::decode(r#"{
+            "name": "idle",
+            "source": "idle.dae"
+        }"#).unwrap();
+        assert_eq!(untagged.tags, None);
+    }
+
+    #[test]
+    fn test_decode_clip_events() {
+        let clip_def: AnimationClipDef = json::decode(r#"{
+            "name": "walk-forward",
+            "source": "walk.dae",
+            "events": [
+                { "time": 0.25, "name": "footstep_left" },
+                { "time": 0.75, "name": "footstep_right" }
+            ]
+        }"#).unwrap();
+        assert_eq!(clip_def.events, Some(vec![
+            ClipEventDef { time: 0.25, name: "footstep_left".to_string() },
+            ClipEventDef { time: 0.75, name: "footstep_right".to_string() },
+        ]));
+    }
+
+    #[test]
+    fn test_decode_clip_loop_mode() {
+        let decode = |loop_fields: &str| -> AnimationClipDef {
+            json::decode(&format!(r#"{{ "name": "attack", "source": "attack.dae"{} }}"#, loop_fields)).unwrap()
+        };
+
+        assert_eq!(decode(r#", "loop_mode": "ping_pong""#).loop_mode, Some(LoopMode::PingPong));
+        assert_eq!(decode(r#", "loop_mode": "clamp""#).loop_mode, Some(LoopMode::Clamp));
+        assert_eq!(decode(r#", "looping": false"#).looping, Some(false));
+        assert_eq!(decode("").loop_mode, None);
+
+        let unknown: Result<AnimationClipDef, _> = json::decode(r#"{ "name": "attack", "source": "attack.dae", "loop_mode": "bounce" }"#);
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn test_clips_with_tag() {
+        let mut manager = AssetManager::new();
+        manager.animation_clips.insert("walk".to_string(), tagged_clip(&["locomotion", "loop"]));
+        manager.animation_clips.insert("run".to_string(), tagged_clip(&["locomotion"]));
+        manager.animation_clips.insert("wave".to_string(), tagged_clip(&["gesture"]));
+
+        assert_eq!(manager.clips_with_tag("locomotion"), vec!["run", "walk"]);
+        assert_eq!(manager.clips_with_tag("gesture"), vec!["wave"]);
+        assert!(manager.clips_with_tag("combat").is_empty());
+    }
+
+    /// An asset definition file in a temporary folder, which is removed when dropped
+    struct TempAssetDefs {
+        folder: PathBuf,
+        path: String,
+    }
+
+    impl TempAssetDefs {
+        fn path(&self) -> &str {
+            &self.path
+        }
+    }
+
+    impl Drop for TempAssetDefs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.folder);
+        }
+    }
+
+    /// Write an asset definition file to a fresh temporary folder
+    fn write_asset_defs(name: &str, contents: &str) -> TempAssetDefs {
+        use std::io::Write;
+
+        let mut folder = ::std::env::temp_dir();
+        folder.push(format!("skeletal_animation_{}_{}", name, ::std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("assets.json");
+        fs::File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        TempAssetDefs { path: path.to_str().unwrap().to_string(), folder }
+    }
+
+    #[test]
+    fn test_load_assets_errors() {
+        let mut manager = AssetManager::<DualQuaternion<f32>>::new();
+
+        match manager.load_assets("/no/such/folder/assets.json") {
+            Err(AssetError::FileNotFound(path)) => assert_eq!(path, "/no/such/folder/assets.json"),
+            result => panic!("Expected FileNotFound, got {:?}", result),
+        }
+
+        let defs = write_asset_defs("malformed", r#"{ "animation_clips": [ "#);
+        match manager.load_assets(defs.path()) {
+            Err(AssetError::Decode(..)) => {}
+            result => panic!("Expected Decode, got {:?}", result),
+        }
+
+        let defs = write_asset_defs("missing_clip", r#"{
+            "difference_clips": [{ "name": "aim-additive", "source_clip": "aim", "reference_clip": "idle" }]
+        }"#);
+        match manager.load_assets(defs.path()) {
+            Err(AssetError::MissingClip(name, clip)) => {
+                assert_eq!(name, "aim-additive");
+                assert_eq!(clip, "aim");
+            }
+            result => panic!("Expected MissingClip, got {:?}", result),
+        }
+
+        // The bad difference clip isn't kept, so later loads still succeed
+        let defs = write_asset_defs("after_missing_clip", r#"{ "animation_controllers": [] }"#);
+        manager.load_assets(defs.path()).unwrap();
+
+        let defs = write_asset_defs("missing_collada", r#"{
+            "animation_clips": [{ "name": "walk", "source": "walk.dae" }]
+        }"#);
+        match manager.load_assets(defs.path()) {
+            Err(AssetError::Collada(name, _)) => assert_eq!(name, "walk"),
+            result => panic!("Expected Collada, got {:?}", result),
+        }
+
+        let defs = write_asset_defs("clip_index", r#"{
+            "animation_controllers": [{
+                "name": "human",
+                "parameters": [],
+                "initial_state": "walk",
+                "states": [{
+                    "name": "walk",
+                    "transitions": [],
+                    "blend_tree": { "type": "ClipNode", "clip_index": 0 }
+                }]
+            }]
+        }"#);
+        match manager.load_assets(defs.path()) {
+            Err(AssetError::InvalidController(name, _)) => assert_eq!(name, "human"),
+            result => panic!("Expected InvalidController, got {:?}", result),
+        }
+        assert!(!manager.controller_defs.contains_key("human"));
+    }
+
+    #[test]
+    fn test_rebuild_difference_clips() {
+
+        let translation_clip = |x: f32| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([x, 0.0, 0.0]);
+            Rc::new(AnimationClip::new(vec![AnimationSample { local_poses: vec![pose] }], 1.0))
         };
 
-        Ok(json::decode(&json_string[..]).unwrap())
+        let mut manager = AssetManager::<QVTransform>::new();
+        manager.animation_clips.insert("aim".to_string(), translation_clip(3.0));
+        manager.animation_clips.insert("idle".to_string(), translation_clip(1.0));
+
+        let defs = write_asset_defs("rebuild", r#"{
+            "difference_clips": [{ "name": "aim-additive", "source_clip": "aim", "reference_clip": "idle" }]
+        }"#);
+        manager.load_assets(defs.path()).unwrap();
+
+        let difference_x = |manager: &AssetManager<QVTransform>| {
+            manager.animation_clips["aim-additive"].get_joint_pose_at_time(0, 0.0).get_translation()[0]
+        };
+        assert_eq!(difference_x(&manager), 2.0);
+
+        // Swap the reference clip
+        manager.animation_clips.insert("idle".to_string(), translation_clip(2.5));
+        manager.rebuild_difference_clips().unwrap();
+        assert_eq!(difference_x(&manager), 0.5);
+    }
+
+    #[test]
+    fn test_validate() {
+
+        let empty_clip = || Rc::new(AnimationClip::<QVTransform>::new(Vec::new(), 30.0));
+
+        let mut manager = AssetManager::new();
+        manager.animation_clips.insert("walk".to_string(), empty_clip());
+
+        let defs = write_asset_defs("validate", r#"{
+            "animation_controllers": [{
+                "name": "human",
+                "parameters": ["speed"],
+                "initial_state": "walk",
+                "states": [{
+                    "name": "walk",
+                    "transitions": [],
+                    "blend_tree": {
+                        "type": "LerpNode",
+                        "param": "speed",
+                        "inputs": [
+                            { "type": "ClipNode", "clip_source": "walk" },
+                            { "type": "ClipNode", "clip_source": "run" }
+                        ]
+                    }
+                }]
+            }, {
+                "name": "bird",
+                "parameters": [],
+                "initial_state": "fly",
+                "states": [{
+                    "name": "fly",
+                    "transitions": [],
+                    "blend_tree": { "type": "ClipNode", "clip_source": "flap" }
+                }]
+            }]
+        }"#);
+        manager.load_assets(defs.path()).unwrap();
+
+        assert_eq!(manager.validate(), Err(vec![
+            "Controller 'bird' references unknown clip 'flap'".to_string(),
+            "Controller 'human' references unknown clip 'run'".to_string(),
+        ]));
+
+        manager.animation_clips.insert("run".to_string(), empty_clip());
+        manager.animation_clips.insert("flap".to_string(), empty_clip());
+        assert_eq!(manager.validate(), Ok(()));
     }
 
+    #[test]
+    fn test_bind_pose_clip() {
+
+        let mut skeleton = Skeleton::from_joints(vec![
+            Joint::new("hip", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("knee", 0, mat4_id()),
+        ]);
+        for i in 0 .. 2 {
+            let mut inverse_bind_pose = mat4_id();
+            inverse_bind_pose.set_translation([0.0, -(i as f32), 0.0]);
+            skeleton.set_inverse_bind_pose(i, inverse_bind_pose);
+        }
+
+        let mut manager = AssetManager::<QVTransform>::new();
+        manager.add_bind_pose_clip("bind", &skeleton);
+
+        let bind_pose_local: Vec<QVTransform> = skeleton.bind_pose_local();
+        let bind_clip = &manager.animation_clips["bind"];
+        assert_eq!(bind_clip.samples.len(), 1);
+        for (sampled, expected) in bind_clip.samples[0].local_poses.iter().zip(bind_pose_local.iter()) {
+            assert_eq!(sampled.get_translation(), expected.get_translation());
+            assert_eq!(sampled.get_rotation(), expected.get_rotation());
+        }
+
+        // A kick of the knee, relative to the bind pose
+        let mut kick = bind_pose_local.clone();
+        kick[1].set_translation([0.0, 1.0, 0.5]);
+        manager.animation_clips.insert("kick".to_string(), Rc::new(AnimationClip::new(vec![AnimationSample { local_poses: kick }], 1.0)));
+
+        let defs = write_asset_defs("bind_pose", r#"{
+            "difference_clips": [{ "name": "kick-additive", "source_clip": "kick", "reference_clip": "bind" }]
+        }"#);
+        manager.load_assets(defs.path()).unwrap();
+
+        let additive = &manager.animation_clips["kick-additive"];
+        assert_eq!(additive.get_joint_pose_at_time(0, 0.0).get_translation(), [0.0, 0.0, 0.0]);
+        assert_eq!(additive.get_joint_pose_at_time(1, 0.0).get_translation(), [0.0, 0.0, 0.5]);
+    }
 }
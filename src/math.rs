@@ -1,5 +1,3 @@
-use std::mem;
-
 pub use vecmath::*;
 
 pub use quaternion::id as quaternion_id;
@@ -127,18 +125,102 @@ pub fn quaternion_to_matrix(q: Quaternion<f32>) -> Matrix4<f32> {
 
 }
 
+/// Inverse square root. Computed exactly rather than with the `0x5f3759df` approximation,
+/// whose error accumulated visibly along long bone chains, and which is no faster on modern
+/// hardware (see `benches/inv_sqrt.rs`).
 pub fn inv_sqrt(x: f32) -> f32 {
+    1.0 / x.sqrt()
+}
+
+/// Weighted average of any number of rotations, independent of the order they're given in.
+///
+/// Finds the dominant eigenvector of the weighted sum of the quaternions' outer products (by
+/// power iteration, starting from their hemisphere-aligned weighted sum), so `q` and `-q` are
+/// treated as the same rotation. The result has a non-negative scalar part.
+pub fn weighted_average_quaternion(qs: &[Quaternion<f32>], weights: &[f32]) -> Quaternion<f32> {
+
+    assert_eq!(qs.len(), weights.len(), "Expected one weight per quaternion");
+
+    let as_vec4 = |q: &Quaternion<f32>| [q.0, q.1[0], q.1[1], q.1[2]];
+
+    // Accumulate the weighted outer products, and an initial estimate from the weighted sum
+    let mut m = [[0.0f32; 4]; 4];
+    let mut estimate = [0.0f32; 4];
+    for (q, &weight) in qs.iter().zip(weights.iter()) {
+        let q = as_vec4(q);
+        for i in 0 .. 4 {
+            for j in 0 .. 4 {
+                m[i][j] += weight * q[i] * q[j];
+            }
+        }
+        let sign = if vec4_dot(q, estimate) < 0.0 { -1.0 } else { 1.0 };
+        estimate = vec4_add(estimate, vec4_scale(q, sign * weight));
+    }
+
+    let mut average = if vec4_len(estimate) > 0.0 { vec4_normalized(estimate) } else { [1.0, 0.0, 0.0, 0.0] };
+
+    for _ in 0 .. 32 {
+        let next = col_mat4_transform(m, average);
+        if vec4_len(next) == 0.0 {
+            break;
+        }
+        average = vec4_normalized(next);
+    }
 
-    let x2: f32 = x * 0.5;
-    let mut y: f32 = x;
+    if average[0] < 0.0 {
+        average = vec4_neg(average);
+    }
+
+    (average[0], [average[1], average[2], average[3]])
+}
+
+/// Angle (in radians) of the rotation between two unit quaternions, in the range 0 to pi
+pub fn quaternion_angle_between(q1: Quaternion<f32>, q2: Quaternion<f32>) -> f32 {
+    let dot = q1.0 * q2.0 + vec3_dot(q1.1, q2.1);
+    2.0 * dot.abs().min(1.0).acos()
+}
 
-    let mut i: i32 = unsafe { mem::transmute(y) };
-    i = 0x5f3759df - (i >> 1);
-    y = unsafe { mem::transmute(i) };
+/// Angle (in radians) of the twist of a rotation about the given unit axis, from its
+/// swing-twist decomposition, in the range -pi to pi
+pub fn quaternion_twist_angle(q: Quaternion<f32>, axis: Vector3<f32>) -> f32 {
+    use std::f32::consts::PI;
+    let angle = 2.0 * vec3_dot(q.1, axis).atan2(q.0);
+    if angle > PI {
+        angle - 2.0 * PI
+    } else if angle < -PI {
+        angle + 2.0 * PI
+    } else {
+        angle
+    }
+}
+
+/// Euler angles (in radians) of a rotation, as `[x, y, z]` where the rotation is about x, then
+/// about y, then about z
+pub fn quaternion_to_euler(q: Quaternion<f32>) -> Vector3<f32> {
+    let (w, [x, y, z]) = q;
+    [
+        (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y)),
+        (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin(),
+        (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z)),
+    ]
+}
 
-    y = y * (1.5 - (x2 * y * y));
-    y
+/// Rotation for the given Euler angles (in radians), as returned by `quaternion_to_euler`
+pub fn euler_to_quaternion(euler: Vector3<f32>) -> Quaternion<f32> {
+    let (sx, cx) = (euler[0] * 0.5).sin_cos();
+    let (sy, cy) = (euler[1] * 0.5).sin_cos();
+    let (sz, cz) = (euler[2] * 0.5).sin_cos();
+    (cx * cy * cz + sx * sy * sz,
+     [sx * cy * cz - cx * sy * sz,
+      cx * sy * cz + sx * cy * sz,
+      cx * cy * sz - sx * sy * cz])
+}
 
+/// Reflect a point across the plane passing through `plane_point` with the given normal
+pub fn mirror_point(point: Vector3<f32>, plane_point: Vector3<f32>, plane_normal: Vector3<f32>) -> Vector3<f32> {
+    let normal = vec3_normalized(plane_normal);
+    let distance = vec3_dot(vec3_sub(point, plane_point), normal);
+    vec3_sub(point, vec3_scale(normal, 2.0 * distance))
 }
 
 pub fn solve_ik_2d(length_1: f32, length_2: f32, target: [f32; 2]) -> Option<[f32; 2]>
@@ -180,6 +262,14 @@ mod test {
 
     static EPSILON: f32 = 0.000001;
 
+    #[test]
+    fn test_inv_sqrt() {
+        for &x in [1.0e-30, 1.0e-12, 1.0e-6, 0.001, 0.5, 1.0, 2.0, 1000.0, 1.0e12].iter() {
+            let expected = 1.0 / f32::sqrt(x);
+            assert!((super::inv_sqrt(x) - expected).abs() < 1.0e-6 * expected.max(1.0), "{}", x);
+        }
+    }
+
     #[test]
     fn test_ik() {
 
@@ -204,4 +294,52 @@ mod test {
         assert!((m[1] + 1.0).abs() < EPSILON);
     }
 
+    #[test]
+    fn test_mirror_point() {
+
+        let m = super::mirror_point([1.0, 2.0, 3.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        assert!((m[0] + 1.0).abs() < EPSILON);
+        assert!((m[1] - 2.0).abs() < EPSILON);
+        assert!((m[2] - 3.0).abs() < EPSILON);
+
+        // Plane offset from the origin, with a non-unit normal
+        let m = super::mirror_point([0.0, 3.0, 0.0], [0.0, 1.0, 0.0], [0.0, 2.0, 0.0]);
+        assert!((m[0] - 0.0).abs() < EPSILON);
+        assert!((m[1] + 1.0).abs() < EPSILON);
+        assert!((m[2] - 0.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_weighted_average_quaternion() {
+
+        use std::f32::consts::PI;
+        use quaternion;
+
+        let about_z = |angle: f32| quaternion::axis_angle([0.0, 0.0, 1.0], angle);
+
+        // Equal weights of 0, 60 and 120 degrees about z average to 60 degrees, including when
+        // one rotation is given by its negated (opposite hemisphere) quaternion
+        let q = about_z(2.0 * PI / 3.0);
+        let qs = [about_z(0.0), about_z(PI / 3.0), (-q.0, [-q.1[0], -q.1[1], -q.1[2]])];
+        let average = super::weighted_average_quaternion(&qs, &[1.0, 1.0, 1.0]);
+        let expected = about_z(PI / 3.0);
+        assert!((average.0 - expected.0).abs() < 0.0001);
+        assert!(super::vec3_len(super::vec3_sub(average.1, expected.1)) < 0.0001);
+
+        // Order independent, for rotations about different axes with unequal weights
+        let qs = [
+            quaternion::axis_angle([1.0, 0.0, 0.0], 0.4),
+            quaternion::axis_angle([0.0, 1.0, 0.0], 0.9),
+            quaternion::axis_angle(super::vec3_normalized([1.0, 1.0, 1.0]), -0.7),
+        ];
+        let weights = [0.2, 0.5, 0.3];
+        let average = super::weighted_average_quaternion(&qs, &weights);
+
+        for &(a, b, c) in [(0, 2, 1), (1, 0, 2), (1, 2, 0), (2, 0, 1), (2, 1, 0)].iter() {
+            let permuted = super::weighted_average_quaternion(&[qs[a], qs[b], qs[c]], &[weights[a], weights[b], weights[c]]);
+            assert!((average.0 - permuted.0).abs() < 0.0001);
+            assert!(super::vec3_len(super::vec3_sub(average.1, permuted.1)) < 0.0001);
+        }
+    }
+
 }
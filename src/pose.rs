@@ -0,0 +1,259 @@
+//! Helpers operating on whole skeletal poses (arrays of joint transforms).
+
+use std::ops::{Deref, DerefMut};
+
+use math::*;
+use skeleton::{JointIndex, Skeleton};
+use transform::Transform;
+
+/// Capacity of the stack-allocated scratch pose buffers used while evaluating poses.
+/// Skeletons with more joints than this are evaluated using heap-allocated buffers instead.
+pub const POSE_BUFFER_CAPACITY: usize = 64;
+
+/// A scratch array of joint poses, kept on the stack unless it is larger than
+/// `POSE_BUFFER_CAPACITY`
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum PoseBuffer<T: Transform> {
+    Stack([T; POSE_BUFFER_CAPACITY], usize),
+    Heap(Vec<T>),
+}
+
+impl<T: Transform> PoseBuffer<T> {
+    /// Create a buffer of `len` identity poses
+    pub fn new(len: usize) -> PoseBuffer<T> {
+        if len <= POSE_BUFFER_CAPACITY {
+            PoseBuffer::Stack([T::identity(); POSE_BUFFER_CAPACITY], len)
+        } else {
+            PoseBuffer::Heap(vec![T::identity(); len])
+        }
+    }
+}
+
+impl<T: Transform> Deref for PoseBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match *self {
+            PoseBuffer::Stack(ref poses, len) => &poses[.. len],
+            PoseBuffer::Heap(ref poses) => &poses[..],
+        }
+    }
+}
+
+impl<T: Transform> DerefMut for PoseBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match *self {
+            PoseBuffer::Stack(ref mut poses, len) => &mut poses[.. len],
+            PoseBuffer::Heap(ref mut poses) => &mut poses[..],
+        }
+    }
+}
+
+/// Combine two local poses per-joint, e.g. for driving the lower and upper body of a
+/// split-body rig from separate controllers.
+///
+/// # Arguments
+///
+/// * `lower` - Local poses used for every joint not listed in `upper_joints`
+/// * `upper` - Local poses used for the joints listed in `upper_joints`
+/// * `upper_joints` - The joints to take from `upper`
+/// * `out` - The output array slice of local joint poses
+pub fn stitch_poses<T: Transform>(lower: &[T], upper: &[T], upper_joints: &[JointIndex], out: &mut [T]) {
+    out.copy_from_slice(&lower[.. out.len()]);
+    for &joint_index in upper_joints.iter() {
+        out[joint_index as usize] = upper[joint_index as usize];
+    }
+}
+
+/// Blend between two already-computed pose arrays, e.g. the global poses from a full-detail
+/// controller and a cheaper level-of-detail source, where `t` of 0.0 gives `a` and 1.0 gives `b`
+pub fn crossfade_poses<T: Transform>(a: &[T], b: &[T], t: f32, out: &mut [T]) {
+    for (i, pose) in out.iter_mut().enumerate() {
+        *pose = a[i].lerp(b[i], t);
+    }
+}
+
+/// Compute the additive difference between two poses per-joint, as `as_difference_clip` does
+/// for each sample of a clip, e.g. a lean from two poses captured during gameplay. Applying
+/// the result additively onto `reference` (as an AdditiveNode does) reproduces `source`.
+pub fn pose_difference_additive<T: Transform>(source: &[T], reference: &[T], out: &mut [T]) {
+    for (i, pose) in out.iter_mut().enumerate() {
+        *pose = reference[i].inverse().concat(source[i]);
+    }
+}
+
+/// Tracks the progress of a crossfade between two pose sources over a fixed duration,
+/// so that switches (e.g. across a level-of-detail boundary) blend rather than pop
+#[derive(Debug, Clone, Copy)]
+pub struct Crossfade {
+    /// Duration of the crossfade in seconds
+    pub duration: f32,
+
+    /// Seconds elapsed since the crossfade started
+    pub elapsed: f32,
+}
+
+impl Crossfade {
+
+    /// Start a crossfade of the given duration in seconds
+    pub fn new(duration: f32) -> Crossfade {
+        Crossfade {
+            duration,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Advance the crossfade by the given time delta
+    pub fn update(&mut self, delta_time: f32) {
+        self.elapsed += delta_time;
+    }
+
+    /// The current blend factor, from 0.0 (entirely the old source) to 1.0 (entirely the new)
+    pub fn blend_factor(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return 1.0;
+        }
+        (self.elapsed / self.duration).clamp(0.0, 1.0)
+    }
+
+    /// Returns true once the crossfade has fully reached the new source
+    pub fn is_finished(&self) -> bool {
+        self.blend_factor() >= 1.0
+    }
+
+    /// Blend from the poses of the old source to the new source at the current blend factor
+    pub fn apply<T: Transform>(&self, from: &[T], to: &[T], out: &mut [T]) {
+        crossfade_poses(from, to, self.blend_factor(), out);
+    }
+}
+
+/// Approximate the centre of mass of a posed skeleton as the mass-weighted average of the
+/// model-space joint positions. Returns the origin if the total mass is zero.
+///
+/// # Arguments
+///
+/// * `skeleton` - The skeleton the poses are for
+/// * `global_poses` - Model-space joint poses, e.g. from `AnimationController::get_output_pose`
+/// * `masses` - The mass associated with each joint
+pub fn centre_of_mass<T: Transform>(skeleton: &Skeleton, global_poses: &[T], masses: &[f32]) -> Vector3<f32> {
+
+    let mut weighted_sum = [0.0, 0.0, 0.0];
+    let mut total_mass = 0.0;
+
+    for joint_index in 0 .. skeleton.joints.len() {
+        let position = global_poses[joint_index].transform_vector([0.0, 0.0, 0.0]);
+        weighted_sum = vec3_add(weighted_sum, vec3_scale(position, masses[joint_index]));
+        total_mass += masses[joint_index];
+    }
+
+    if total_mass == 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+
+    vec3_scale(weighted_sum, 1.0 / total_mass)
+}
+
+#[cfg(test)]
+mod test {
+
+    use math::*;
+    use skeleton::{Joint, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::{Transform, QVTransform};
+
+    static EPSILON: f32 = 0.000001;
+
+    fn translation_x(x: f32) -> QVTransform {
+        let mut pose = QVTransform::identity();
+        pose.set_translation([x, 0.0, 0.0]);
+        pose
+    }
+
+    #[test]
+    fn test_pose_difference_additive() {
+
+        let pose = |angle: f32, x: f32| {
+            let mut pose = translation_x(x);
+            pose.set_rotation(quaternion::axis_angle([0.0, 1.0, 0.0], angle));
+            pose
+        };
+        let source = [pose(0.5, 1.0), pose(-0.3, 2.0), pose(1.2, 0.0)];
+        let reference = [pose(0.1, 0.5), pose(0.4, -1.0), pose(1.2, 0.0)];
+
+        let mut difference = [QVTransform::identity(); 3];
+        super::pose_difference_additive(&source, &reference, &mut difference);
+
+        // Identical joints give an identity difference
+        assert!(vec3_len(difference[2].get_translation()) < EPSILON);
+
+        for i in 0 .. 3 {
+            let applied = reference[i].concat(difference[i]);
+            assert!(vec3_len(vec3_sub(applied.get_translation(), source[i].get_translation())) < 0.0001);
+            assert!(quaternion_angle_between(applied.get_rotation(), source[i].get_rotation()) < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_stitch_poses() {
+
+        let lower: Vec<QVTransform> = (0 .. 4).map(|_| translation_x(1.0)).collect();
+        let upper: Vec<QVTransform> = (0 .. 4).map(|_| translation_x(2.0)).collect();
+        let mut out = [QVTransform::identity(); 4];
+
+        super::stitch_poses(&lower, &upper, &[1, 3], &mut out);
+
+        let xs: Vec<f32> = out.iter().map(|p| p.get_translation()[0]).collect();
+        assert_eq!(xs, vec![1.0, 2.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_centre_of_mass() {
+
+        let skeleton = Skeleton::from_joints((0 .. 2).map(|i| {
+            Joint::new(&format!("joint_{}", i), ROOT_JOINT_PARENT_INDEX, mat4_id())
+        }).collect());
+
+        let global_poses = [translation_x(-1.0), translation_x(1.0)];
+
+        let centre = super::centre_of_mass(&skeleton, &global_poses, &[1.0, 1.0]);
+        assert!(vec3_len(centre) < EPSILON);
+
+        let centre = super::centre_of_mass(&skeleton, &global_poses, &[1.0, 3.0]);
+        assert!(vec3_len(vec3_sub(centre, [0.5, 0.0, 0.0])) < EPSILON);
+    }
+
+    #[test]
+    fn test_crossfade_poses() {
+
+        let a = [translation_x(0.0), translation_x(2.0)];
+        let b = [translation_x(4.0), translation_x(2.0)];
+        let mut out = [QVTransform::identity(); 2];
+
+        for &(t, expected) in [(0.0, 0.0), (0.5, 2.0), (1.0, 4.0)].iter() {
+            super::crossfade_poses(&a, &b, t, &mut out);
+            assert!((out[0].get_translation()[0] - expected).abs() < EPSILON);
+            assert!((out[1].get_translation()[0] - 2.0).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_crossfade_timer() {
+
+        let a = [translation_x(0.0)];
+        let b = [translation_x(4.0)];
+        let mut out = [QVTransform::identity()];
+
+        let mut crossfade = super::Crossfade::new(0.2);
+        crossfade.apply(&a, &b, &mut out);
+        assert!((out[0].get_translation()[0] - 0.0).abs() < EPSILON);
+
+        crossfade.update(0.1);
+        crossfade.apply(&a, &b, &mut out);
+        assert!((out[0].get_translation()[0] - 2.0).abs() < EPSILON);
+        assert!(!crossfade.is_finished());
+
+        crossfade.update(0.15);
+        crossfade.apply(&a, &b, &mut out);
+        assert!((out[0].get_translation()[0] - 4.0).abs() < EPSILON);
+        assert!(crossfade.is_finished());
+    }
+}
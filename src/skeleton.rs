@@ -1,3 +1,6 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
 use gfx;
 use gfx_debug_draw;
 
@@ -5,15 +8,25 @@ use collada;
 use math::*;
 use transform::{Transform, FromTransform};
 
-pub type JointIndex = u8;
-pub const ROOT_JOINT_PARENT_INDEX: JointIndex  = 255u8;
+/// Index of a joint within its skeleton. Wider than the `u8` used by collada, so that
+/// dense rigs can have more than 255 joints.
+pub type JointIndex = u16;
+pub const ROOT_JOINT_PARENT_INDEX: JointIndex = JointIndex::MAX;
 
+/// A hierarchy of joints. Caches its joint name index behind interior mutability, so a
+/// Skeleton is not `Sync`; share one between threads by cloning it.
 #[derive(Debug, Clone)]
 pub struct Skeleton {
     ///
     /// All joints in the skeleton
     ///
     pub joints: Vec<Joint>,
+
+    /// Lazily built index of joint names, for `get_joint_index`
+    joint_indices: RefCell<HashMap<String, JointIndex>>,
+
+    /// Number of joints when `joint_indices` was last built, if it has been
+    indexed_joint_count: Cell<Option<usize>>,
 }
 
 impl Skeleton {
@@ -22,17 +35,30 @@ impl Skeleton {
     /// Build a skeleton fromm a Collada skeleton
     ///
     pub fn from_collada(skeleton: &collada::Skeleton) -> Skeleton {
+        Skeleton::from_joints(skeleton.joints.iter().map(|j| {
+            let parent_index = if j.is_root() { ROOT_JOINT_PARENT_INDEX } else { j.parent_index as JointIndex };
+            Joint::new(&j.name[..], parent_index, j.inverse_bind_pose)
+        }).collect())
+    }
+
+    ///
+    /// Build a skeleton directly from its joints, e.g. for procedural rigs. Parent joints
+    /// must precede their children.
+    ///
+    pub fn from_joints(joints: Vec<Joint>) -> Skeleton {
+        assert!(joints.len() <= ROOT_JOINT_PARENT_INDEX as usize, "Too many joints for JointIndex");
         Skeleton {
-            joints: skeleton.joints.iter().map(|j| {
-                Joint {
-                    name: j.name.clone(),
-                    parent_index: j.parent_index,
-                    inverse_bind_pose: j.inverse_bind_pose,
-                }
-            }).collect()
+            joints,
+            joint_indices: RefCell::new(HashMap::new()),
+            indexed_joint_count: Cell::new(None),
         }
     }
 
+    ///
+    /// Calculate global (model-space) poses from local (parent-relative) poses, composing each
+    /// joint's local pose with its parent's global pose, with roots taking their local pose.
+    /// Joints must be topologically ordered, with parents before their children.
+    ///
     pub fn calculate_global_poses<T: Transform, TOutput: Transform + FromTransform<T>>(
         &self,
         local_poses: &[T],
@@ -41,26 +67,119 @@ impl Skeleton {
 
         for (joint_index, joint) in self.joints.iter().enumerate() {
 
-            let parent_pose = if !joint.is_root() {
-                global_poses[joint.parent_index as usize]
+            let local_pose = TOutput::from_transform(joint.with_orientation_offset(local_poses[joint_index]));
+
+            // Roots have an identity parent, so skip the concat
+            global_poses[joint_index] = if !joint.is_root() {
+                global_poses[joint.parent_index as usize].concat(local_pose)
             } else {
-                TOutput::identity()
+                local_pose
             };
+        }
+    }
 
-            let local_pose = local_poses[joint_index];
-            global_poses[joint_index] = parent_pose.concat(TOutput::from_transform(local_pose));
+    ///
+    /// Calculate local (parent-relative) poses from global poses, the inverse of
+    /// `calculate_global_poses`, e.g. to feed poses from physics back into blending
+    ///
+    pub fn global_to_local<T: Transform>(&self, global_poses: &[T], local_poses: &mut [T]) {
+        for (joint_index, joint) in self.joints.iter().enumerate() {
+            let local_pose = if !joint.is_root() {
+                global_poses[joint.parent_index as usize].inverse().concat(global_poses[joint_index])
+            } else {
+                global_poses[joint_index]
+            };
+            local_poses[joint_index] = joint.without_orientation_offset(local_pose);
         }
     }
 
-    pub fn get_joint_index(&self, joint_name: &str) -> Option<JointIndex> {
+    ///
+    /// Global (model-space) poses of the joints in the skeleton's bind pose, i.e. the inverse of
+    /// each joint's inverse bind pose, e.g. for rendering the character at rest
+    ///
+    pub fn bind_pose_global<T: Transform>(&self) -> Vec<T> {
+        self.joints.iter().map(|joint| T::from_matrix(joint.inverse_bind_pose).inverse()).collect()
+    }
+
+    ///
+    /// Local (parent-relative) poses of the joints in the skeleton's bind pose
+    ///
+    pub fn bind_pose_local<T: Transform>(&self) -> Vec<T> {
+        let mut local_poses = vec![T::identity(); self.joints.len()];
+        self.global_to_local(&self.bind_pose_global::<T>(), &mut local_poses);
+        local_poses
+    }
+
+    ///
+    /// Check that the joint names are unique, as joints and animations are matched up by name.
+    /// Returns the names used by more than one joint, if any.
+    ///
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+
+        let mut duplicate_names: Vec<String> = Vec::new();
 
         for (index, joint) in self.joints.iter().enumerate() {
-            if joint.name == joint_name {
-                return Some(index as JointIndex);
+            let is_duplicate = self.joints[.. index].iter().any(|other| other.name == joint.name);
+            if is_duplicate && !duplicate_names.contains(&joint.name) {
+                duplicate_names.push(joint.name.clone());
+            }
+        }
+
+        if duplicate_names.is_empty() {
+            Ok(())
+        } else {
+            Err(duplicate_names)
+        }
+    }
+
+    ///
+    /// Index of the first joint with the given name, if any. Names are looked up in an index
+    /// built on first use. A name missing from the index falls back to a scan of `joints`, and
+    /// the index is rebuilt if the scan finds it (e.g. after a joint was renamed in place), or
+    /// when joints have since been added or removed.
+    ///
+    pub fn get_joint_index(&self, joint_name: &str) -> Option<JointIndex> {
+
+        if self.indexed_joint_count.get() == Some(self.joints.len()) {
+            if let Some(&index) = self.joint_indices.borrow().get(joint_name) {
+                if self.joints[index as usize].name == joint_name {
+                    return Some(index);
+                }
+            }
+            if !self.joints.iter().any(|joint| joint.name == joint_name) {
+                return None;
             }
         }
 
-        None
+        // Not yet built, or stale, so rebuild the index and look again
+        let mut joint_indices = self.joint_indices.borrow_mut();
+        joint_indices.clear();
+        for (index, joint) in self.joints.iter().enumerate() {
+            joint_indices.entry(joint.name.clone()).or_insert(index as JointIndex);
+        }
+        self.indexed_joint_count.set(Some(self.joints.len()));
+        joint_indices.get(joint_name).cloned()
+    }
+
+    /// Override the inverse bind pose of the given joint, e.g. when skinning a mesh that was
+    /// bound against a different bind pose. Panics if the joint index is out of range.
+    pub fn set_inverse_bind_pose(&mut self, joint: JointIndex, inverse_bind_pose: Matrix4<f32>) {
+        let joint_count = self.joints.len();
+        match self.joints.get_mut(joint as usize) {
+            Some(j) => j.inverse_bind_pose = inverse_bind_pose,
+            None => panic!("Joint index {} out of range for skeleton with {} joints", joint, joint_count),
+        }
+    }
+
+    /// Set a fixed orientation offset for the given joint (e.g. a bone roll correction), which
+    /// rotates the joint's frame, and so those of its children, after its animated local pose
+    /// is applied. Panics if the joint index is out of range.
+    pub fn set_orientation_offset(&mut self, joint: JointIndex, orientation_offset: Quaternion<f32>) {
+        let joint_count = self.joints.len();
+        match self.joints.get_mut(joint as usize) {
+            Some(j) => j.orientation_offset = Some(orientation_offset),
+            None => panic!("Joint index {} out of range for skeleton with {} joints", joint, joint_count),
+        }
     }
 
     pub fn draw<R: gfx::Resources, F: gfx::Factory<R>, T: Transform> (
@@ -150,10 +269,343 @@ pub struct Joint {
     /// Column-major.
     ///
     pub inverse_bind_pose: Matrix4<f32>,
+
+    ///
+    /// Optional fixed rotation applied to the joint's local pose during the local to global
+    /// pose accumulation, e.g. to correct bone roll between rigs with different bone axes
+    ///
+    pub orientation_offset: Option<Quaternion<f32>>,
 }
 
 impl Joint {
+
+    ///
+    /// Create a joint, where `parent_index` is ROOT_JOINT_PARENT_INDEX for a root joint
+    ///
+    pub fn new(name: &str, parent_index: JointIndex, inverse_bind_pose: Matrix4<f32>) -> Joint {
+        Joint {
+            name: name.to_string(),
+            parent_index,
+            inverse_bind_pose,
+            orientation_offset: None,
+        }
+    }
+
+    /// Apply the joint's orientation offset, if any, to a local pose
+    pub(crate) fn with_orientation_offset<T: Transform>(&self, mut local_pose: T) -> T {
+        if let Some(orientation_offset) = self.orientation_offset {
+            let rotation = quaternion::mul(local_pose.get_rotation(), orientation_offset);
+            local_pose.set_rotation(rotation);
+        }
+        local_pose
+    }
+
+    /// Remove the joint's orientation offset, if any, from a local pose
+    pub(crate) fn without_orientation_offset<T: Transform>(&self, mut local_pose: T) -> T {
+        if let Some(orientation_offset) = self.orientation_offset {
+            let rotation = quaternion::mul(local_pose.get_rotation(), quaternion_conj(orientation_offset));
+            local_pose.set_rotation(rotation);
+        }
+        local_pose
+    }
+
     pub fn is_root(&self) -> bool {
         self.parent_index == ROOT_JOINT_PARENT_INDEX
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use math::*;
+    use transform::{Transform, QVTransform};
+
+    use super::{Joint, JointIndex, Skeleton, ROOT_JOINT_PARENT_INDEX};
+
+    fn chain_skeleton() -> Skeleton {
+        Skeleton::from_joints(vec![
+            Joint::new("hip", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("knee", 0, mat4_id()),
+            Joint::new("ankle", 1, mat4_id()),
+        ])
+    }
+
+    fn skinning_palette(skeleton: &Skeleton, global_poses: &[Matrix4<f32>]) -> Vec<Matrix4<f32>> {
+        skeleton.joints.iter().enumerate().map(|(i, joint)| {
+            global_poses[i].concat(joint.inverse_bind_pose)
+        }).collect()
+    }
+
+    #[test]
+    fn test_set_inverse_bind_pose() {
+
+        let mut skeleton = chain_skeleton();
+
+        let mut local_pose = mat4_id();
+        local_pose.set_translation([0.0, 1.0, 0.0]);
+        let mut global_poses = [mat4_id(); 3];
+        skeleton.calculate_global_poses(&[local_pose; 3], &mut global_poses);
+
+        let before = skinning_palette(&skeleton, &global_poses);
+
+        let mut inverse_bind_pose = mat4_id();
+        inverse_bind_pose.set_translation([0.0, -2.0, 0.0]);
+        skeleton.set_inverse_bind_pose(1, inverse_bind_pose);
+
+        let after = skinning_palette(&skeleton, &global_poses);
+
+        assert_eq!(before[0], after[0]);
+        assert!(before[1] != after[1]);
+        assert_eq!(after[1].get_translation(), [0.0, 0.0, 0.0]);
+        assert_eq!(before[2], after[2]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_inverse_bind_pose_out_of_range() {
+        chain_skeleton().set_inverse_bind_pose(3, mat4_id());
+    }
+
+    #[test]
+    fn test_from_joints() {
+
+        let skeleton = chain_skeleton();
+        assert_eq!(skeleton.joints.len(), 3);
+        assert_eq!(skeleton.get_joint_index("knee"), Some(1));
+        assert!(skeleton.joints[0].is_root());
+
+        let mut local_poses = [DualQuaternion::identity(); 3];
+        local_poses[0].set_translation([0.0, 1.0, 0.0]);
+        local_poses[1].set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], ::std::f32::consts::PI / 2.0));
+        local_poses[1].set_translation([0.0, 1.0, 0.0]);
+        local_poses[2].set_translation([0.0, 1.0, 0.0]);
+
+        let mut global_poses = [DualQuaternion::identity(); 3];
+        skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+
+        let positions: Vec<Vector3<f32>> = global_poses.iter().map(|p| p.transform_vector([0.0, 0.0, 0.0])).collect();
+        let expected = [[0.0, 1.0, 0.0], [0.0, 2.0, 0.0], [-1.0, 2.0, 0.0]];
+        for (position, expected) in positions.iter().zip(expected.iter()) {
+            assert!(vec3_len(vec3_sub(*position, *expected)) < 0.0001);
+        }
+    }
+
+    #[test]
+    fn test_global_to_local_round_trip() {
+
+        let skeleton = chain_skeleton();
+
+        let local_poses: Vec<DualQuaternion<f32>> = (0 .. 3).map(|i| {
+            let axis = vec3_normalized([1.0, i as f32, 2.0]);
+            dual_quaternion::from_rotation_and_translation(quaternion::axis_angle(axis, 0.3 * (i + 1) as f32), [0.5, 1.0, i as f32])
+        }).collect();
+
+        let mut global_poses = [DualQuaternion::identity(); 3];
+        skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+
+        let mut round_trip_local = [DualQuaternion::identity(); 3];
+        skeleton.global_to_local(&global_poses, &mut round_trip_local);
+
+        let mut round_trip_global = [DualQuaternion::identity(); 3];
+        skeleton.calculate_global_poses(&round_trip_local, &mut round_trip_global);
+
+        for i in 0 .. 3 {
+            for &v in [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 1.0]].iter() {
+                assert!(vec3_len(vec3_sub(round_trip_local[i].transform_vector(v), local_poses[i].transform_vector(v))) < 0.0001);
+                assert!(vec3_len(vec3_sub(round_trip_global[i].transform_vector(v), global_poses[i].transform_vector(v))) < 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn test_orientation_offset() {
+
+        let mut skeleton = chain_skeleton();
+
+        let mut local_poses = [DualQuaternion::identity(); 3];
+        for pose in local_poses.iter_mut() {
+            pose.set_translation([0.0, 1.0, 0.0]);
+        }
+
+        let mut global_poses = [DualQuaternion::identity(); 3];
+        skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+
+        // 90 degree roll about the knee's y axis
+        skeleton.set_orientation_offset(1, quaternion::axis_angle([0.0, 1.0, 0.0], ::std::f32::consts::PI / 2.0));
+
+        let mut offset_global_poses = [DualQuaternion::identity(); 3];
+        skeleton.calculate_global_poses(&local_poses, &mut offset_global_poses);
+
+        let close = |a: Vector3<f32>, b: Vector3<f32>| vec3_len(vec3_sub(a, b)) < 0.0001;
+
+        // The hip is unaffected
+        assert!(close(offset_global_poses[0].transform_vector([1.0, 0.0, 0.0]), global_poses[0].transform_vector([1.0, 0.0, 0.0])));
+
+        // The knee stays in place, but its frame is rolled
+        assert!(close(offset_global_poses[1].transform_vector([0.0, 0.0, 0.0]), [0.0, 2.0, 0.0]));
+        assert!(close(offset_global_poses[1].transform_vector([1.0, 0.0, 0.0]), [0.0, 2.0, -1.0]));
+
+        // The ankle's frame is rolled along with its parent's
+        assert!(close(offset_global_poses[2].transform_vector([0.0, 0.0, 0.0]), [0.0, 3.0, 0.0]));
+        assert!(close(offset_global_poses[2].transform_vector([1.0, 0.0, 0.0]), [0.0, 3.0, -1.0]));
+        assert!(close(global_poses[2].transform_vector([1.0, 0.0, 0.0]), [1.0, 3.0, 0.0]));
+
+        // Round trip through local poses removes the offset again
+        let mut round_trip_local = [DualQuaternion::identity(); 3];
+        skeleton.global_to_local(&offset_global_poses, &mut round_trip_local);
+        for (round_trip, local) in round_trip_local.iter().zip(local_poses.iter()) {
+            assert!(close(round_trip.transform_vector([1.0, 0.0, 0.0]), local.transform_vector([1.0, 0.0, 0.0])));
+        }
+    }
+
+    #[test]
+    fn test_global_poses_of_roots() {
+
+        // Alternating roots and children
+        let skeleton = Skeleton::from_joints((0 .. 8).map(|i| {
+            let parent = if i % 2 == 0 { ROOT_JOINT_PARENT_INDEX } else { i - 1 };
+            Joint::new(&format!("joint_{}", i), parent, mat4_id())
+        }).collect());
+
+        let local_poses: Vec<Matrix4<f32>> = (0 .. 8).map(|i| {
+            let mut pose = quaternion_to_matrix(quaternion::axis_angle([0.0, 0.0, 1.0], 0.2 * i as f32));
+            pose.set_translation([i as f32, 1.0, 0.0]);
+            pose
+        }).collect();
+
+        let mut global_poses = [mat4_id(); 8];
+        skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+
+        for (i, joint) in skeleton.joints.iter().enumerate() {
+            let expected = if joint.is_root() {
+                mat4_id().concat(local_poses[i])
+            } else {
+                global_poses[joint.parent_index as usize].concat(local_poses[i])
+            };
+            assert_eq!(global_poses[i], expected);
+        }
+    }
+
+    #[test]
+    fn test_bind_pose_global() {
+
+        let mut skeleton = chain_skeleton();
+        for i in 0 .. 3 {
+            let mut inverse_bind_pose = mat4_rotate_z(0.5 * i as f32);
+            inverse_bind_pose.set_translation([0.0, -(i as f32), 1.0]);
+            skeleton.set_inverse_bind_pose(i, inverse_bind_pose);
+        }
+
+        let bind_poses: Vec<Matrix4<f32>> = skeleton.bind_pose_global();
+        assert_eq!(bind_poses.len(), 3);
+
+        // Skinning with the bind pose leaves vertices in place
+        for transform in skinning_palette(&skeleton, &bind_poses).iter() {
+            for (row, identity_row) in transform.iter().zip(mat4_id::<f32>().iter()) {
+                for (value, identity_value) in row.iter().zip(identity_row.iter()) {
+                    assert!((value - identity_value).abs() < 0.0001);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+
+        assert_eq!(chain_skeleton().validate(), Ok(()));
+
+        let skeleton = Skeleton::from_joints(vec![
+            Joint::new("hip", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("spine", 0, mat4_id()),
+            Joint::new("spine", 1, mat4_id()),
+            Joint::new("spine", 2, mat4_id()),
+        ]);
+        assert_eq!(skeleton.validate(), Err(vec!["spine".to_string()]));
+    }
+
+    #[test]
+    fn test_bind_pose_local() {
+
+        let mut skeleton = chain_skeleton();
+        for i in 0 .. 3 {
+            let mut inverse_bind_pose = mat4_id();
+            inverse_bind_pose.set_translation([0.0, -(i as f32), 0.0]);
+            skeleton.set_inverse_bind_pose(i, inverse_bind_pose);
+        }
+
+        let local_poses: Vec<Matrix4<f32>> = skeleton.bind_pose_local();
+        assert_eq!(local_poses[0].get_translation(), [0.0, 0.0, 0.0]);
+        assert_eq!(local_poses[1].get_translation(), [0.0, 1.0, 0.0]);
+        assert_eq!(local_poses[2].get_translation(), [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_get_joint_index() {
+
+        let mut skeleton = chain_skeleton();
+        assert_eq!(skeleton.get_joint_index("hip"), Some(0));
+        assert_eq!(skeleton.get_joint_index("ankle"), Some(2));
+        assert_eq!(skeleton.get_joint_index("toe"), None);
+
+        // A miss doesn't rebuild the index
+        skeleton.joint_indices.borrow_mut().insert("marker".to_string(), 0);
+        assert_eq!(skeleton.get_joint_index("heel"), None);
+        assert!(skeleton.joint_indices.borrow().contains_key("marker"));
+
+        // Renamed joints are picked up
+        skeleton.joints[2].name = "toe".to_string();
+        assert_eq!(skeleton.get_joint_index("toe"), Some(2));
+        assert_eq!(skeleton.get_joint_index("ankle"), None);
+
+        // Added joints are picked up
+        skeleton.joints.push(Joint::new("heel", 2, mat4_id()));
+        assert_eq!(skeleton.get_joint_index("heel"), Some(3));
+    }
+
+    #[test]
+    fn test_calculate_global_poses_two_joint_chain() {
+
+        let skeleton = Skeleton::from_joints(vec![
+            Joint::new("shoulder", ROOT_JOINT_PARENT_INDEX, mat4_id()),
+            Joint::new("elbow", 0, mat4_id()),
+        ]);
+
+        // Shoulder at (1, 0, 0) rotated 90 degrees about z, with the elbow 2 along its x axis
+        let mut shoulder = QVTransform::identity();
+        shoulder.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], ::std::f32::consts::PI / 2.0));
+        shoulder.set_translation([1.0, 0.0, 0.0]);
+        let mut elbow = QVTransform::identity();
+        elbow.set_translation([2.0, 0.0, 0.0]);
+
+        let mut global_poses = [mat4_id(); 2];
+        skeleton.calculate_global_poses(&[shoulder, elbow], &mut global_poses);
+
+        let elbow_position = global_poses[1].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(elbow_position, [1.0, 2.0, 0.0])) < 0.0001);
+        assert!(vec3_len(vec3_sub(global_poses[0].transform_vector([0.0, 0.0, 0.0]), [1.0, 0.0, 0.0])) < 0.0001);
+    }
+
+    #[test]
+    fn test_calculate_global_poses_long_chain() {
+
+        // More joints than fit in a u8, each offset 1 along x from its parent
+        let joint_count = 300;
+        let skeleton = Skeleton::from_joints((0 .. joint_count).map(|i| {
+            let parent_index = if i == 0 { ROOT_JOINT_PARENT_INDEX } else { (i - 1) as JointIndex };
+            Joint::new(&format!("joint_{}", i), parent_index, mat4_id())
+        }).collect());
+
+        assert_eq!(skeleton.joints[299].parent_index, 298);
+        assert_eq!(skeleton.get_joint_index("joint_299"), Some(299));
+
+        let mut local_pose = QVTransform::identity();
+        local_pose.set_translation([1.0, 0.0, 0.0]);
+        let local_poses = vec![local_pose; joint_count];
+        let mut global_poses = vec![mat4_id(); joint_count];
+        skeleton.calculate_global_poses(&local_poses, &mut global_poses);
+
+        for (i, global_pose) in global_poses.iter().enumerate() {
+            assert_eq!(global_pose.transform_vector([0.0, 0.0, 0.0]), [(i + 1) as f32, 0.0, 0.0]);
+        }
+    }
+}
@@ -1,11 +1,17 @@
 use std::default::Default;
+use std::mem;
+use std::ops::Range;
 use std::path::Path;
+use std::{fmt, error};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
 
 use collada;
 use gfx;
 use gfx::memory::Typed;
 use gfx::traits::*;
 use gfx_texture;
+use image::{self, RgbaImage};
 
 use gfx_texture::TextureContext;
 
@@ -13,23 +19,142 @@ use math::*;
 use skeleton::Skeleton;
 use transform::Transform;
 
-const MAX_JOINTS: usize = 64;
+/// Size of the skinning palette declared in the vertex shaders, replaced with the skeleton's
+/// joint count when the shaders are linked (see `with_constant`)
+const SOURCE_MAX_JOINTS: usize = 64;
+
+/// Number of instances declared in the instanced vertex shaders, replaced with the number that
+/// fit in the uniform block when the shaders are linked (see `instances_per_draw`)
+const SOURCE_MAX_INSTANCES: usize = 16;
+
+/// The smallest `GL_MAX_UNIFORM_BLOCK_SIZE` that GL guarantees, in bytes. A safe
+/// `max_uniform_block_size` for `enable_instancing` where the actual limit isn't queried.
+pub const GL_MIN_UNIFORM_BLOCK_SIZE: usize = 16384;
+
+/// Shader view of a render batch's texture
+pub type TextureView<R> = gfx::handle::ShaderResourceView<R, [f32; 4]>;
+
+/// An error creating a `SkinnedRenderer`
+#[derive(Debug)]
+pub enum SkinnedRendererError {
+    /// The shader program failed to link
+    Program(gfx::shade::ProgramError),
+
+    /// A texture could not be loaded, as (texture path, problem)
+    Texture(String, String),
+
+    /// No textures (or texture paths) were given for the skinned objects
+    NoTextures,
+
+    /// A skinning palette of the given size in bytes doesn't fit in a uniform block of the
+    /// given maximum size, so not even one instance can be drawn by `render_instances`
+    PaletteTooLarge(usize, usize),
+}
+
+impl fmt::Display for SkinnedRendererError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SkinnedRendererError::Program(ref e) => write!(fmt, "Failed to link shader program: {:?}", e),
+            SkinnedRendererError::Texture(ref path, ref e) => write!(fmt, "Failed to load texture {}: {}", path, e),
+            SkinnedRendererError::NoTextures => write!(fmt, "At least one texture path is required"),
+            SkinnedRendererError::PaletteTooLarge(palette_bytes, max_bytes) => write!(fmt, "Skinning palette of {} bytes exceeds the uniform block limit of {} bytes", palette_bytes, max_bytes),
+        }
+    }
+}
+
+impl error::Error for SkinnedRendererError {}
+
+/// Pipeline state, palette constant buffer, and the number of instances per draw call for
+/// instanced drawing
+type InstancedPipeline<R, T> = (gfx::PipelineState<R, pipe::Meta>, gfx::handle::Buffer<R, T>, usize);
 
 pub struct SkinnedRenderBatch<R: gfx::Resources, T: Transform> {
     skinning_transforms_buffer: gfx::handle::Buffer<R, T>,
     slice: gfx::Slice<R>,
     vertex_buffer: gfx::handle::Buffer<R, SkinnedVertex>,
-    texture: (gfx::handle::ShaderResourceView<R, [f32; 4]>, gfx::handle::Sampler<R>),
+    texture: (TextureView<R>, gfx::handle::Sampler<R>),
+
+    /// Index in the COLLADA file of the object drawn by the batch
+    object_index: usize,
 }
 
 pub struct SkinnedRenderer<R: gfx::Resources, T: Transform> {
     pso: gfx::PipelineState<R, pipe::Meta>,
-    skeleton: Skeleton, // TODO Should this be a ref? Should this just be the joints?
+
+    /// Created by `enable_instancing`, as the instanced shaders' palette can exceed the uniform
+    /// limits of some GL implementations, failing to link
+    instanced_pipeline: Option<InstancedPipeline<R, T>>,
+
+    inverse_bind_poses: Vec<T>,
     render_batches: Vec<SkinnedRenderBatch<R, T>>,
+
+    /// Number of transforms in each skinning palette constant buffer: one per joint
+    palette_size: usize,
+
+    /// Skinning transforms uploaded by the most recent `render`
+    last_skinning_palette: Vec<T>,
+
+    /// Skinning palettes of the instances in the current `render_instances` draw, reused
+    /// between draws
+    instance_palettes: Vec<T>,
+
+    /// Textures still loading for `from_collada_deferred`
+    deferred_textures: DeferredTextures<RgbaImage>,
+}
+
+/// Indices of the render batches waiting on a texture, and the texture once loaded
+type PendingTexture<D> = (Vec<usize>, Receiver<Result<D, String>>);
+
+/// Textures loading on background threads, each with the indices of the render batches
+/// waiting on it
+struct DeferredTextures<D> {
+    pending: Vec<PendingTexture<D>>,
+}
+
+impl<D: Send + 'static> DeferredTextures<D> {
+
+    fn new() -> DeferredTextures<D> {
+        DeferredTextures { pending: Vec::new() }
+    }
+
+    /// Start loading a texture for the given batches on a new thread
+    fn push<L: FnOnce() -> Result<D, String> + Send + 'static>(&mut self, batches: Vec<usize>, load: L) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The renderer may have been dropped in the meantime
+            let _ = sender.send(load());
+        });
+        self.pending.push((batches, receiver));
+    }
+
+    /// Take the textures that have finished loading (or failed to) since the last call,
+    /// without blocking on the rest
+    fn poll(&mut self) -> Vec<(Vec<usize>, Result<D, String>)> {
+        let mut finished = Vec::new();
+        self.pending.retain(|(batches, receiver)| {
+            match receiver.try_recv() {
+                Ok(result) => finished.push((batches.clone(), result)),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => finished.push((batches.clone(), Err("Texture loading thread panicked".to_string()))),
+            }
+            false
+        });
+        finished
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Read and decode a texture image, e.g. on a background thread
+fn load_texture_image(path: &str) -> Result<RgbaImage, String> {
+    image::open(path).map(|image| image.to_rgba8()).map_err(|e| format!("Failed to load texture {}: {}", path, e))
 }
 
 pub trait HasShaderSources<'a> {
     fn vertex_shader_source() -> &'a [u8];
+    fn instanced_vertex_shader_source() -> &'a [u8];
     fn fragment_shader_source() -> &'a [u8];
 }
 
@@ -37,6 +162,9 @@ impl<'a> HasShaderSources<'a> for Matrix4<f32> {
     fn vertex_shader_source() -> &'a [u8] {
         include_bytes!("lbs_skinning_150.glslv")
     }
+    fn instanced_vertex_shader_source() -> &'a [u8] {
+        include_bytes!("lbs_skinning_instanced_150.glslv")
+    }
     fn fragment_shader_source() -> &'a [u8] {
         include_bytes!("skinning_150.glslf")
     }
@@ -46,6 +174,9 @@ impl<'a> HasShaderSources<'a> for DualQuaternion<f32> {
     fn vertex_shader_source() -> &'a [u8] {
         include_bytes!("dlb_skinning_150.glslv")
     }
+    fn instanced_vertex_shader_source() -> &'a [u8] {
+        include_bytes!("dlb_skinning_instanced_150.glslv")
+    }
     fn fragment_shader_source() -> &'a [u8] {
         include_bytes!("skinning_150.glslf")
     }
@@ -53,39 +184,72 @@ impl<'a> HasShaderSources<'a> for DualQuaternion<f32> {
 
 impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer<R, T> {
 
+    /// Create a renderer for the skinned objects of a COLLADA document, where `texture_paths`
+    /// gives the texture of each object by its index in the file. Objects beyond the end of
+    /// `texture_paths` reuse its last texture, so there must be at least one, or
+    /// `SkinnedRendererError::NoTextures` is returned. The textures of the
+    /// skinned objects are loaded (each once) before returning; see `from_collada_deferred` to
+    /// load them in the background.
     pub fn from_collada<F: gfx::Factory<R>, C: gfx::CommandBuffer<R>>(
         tcx: &mut TextureContext<F, R, C>,
         collada_document: collada::document::ColladaDocument,
         texture_paths: Vec<&str>, // TODO - read from the COLLADA document (if available)
-    ) -> Result<SkinnedRenderer<R, T>, gfx::shade::ProgramError> {
-        use gfx::format::Formatted;
-
-        let program = {
-            let vs = T::vertex_shader_source();
-            let fs = T::fragment_shader_source();
-            match tcx.factory.link_program(vs, fs) {
-                Ok(program_handle) => program_handle,
-                Err(e) => return Err(e),
-            }
-        };
+    ) -> Result<SkinnedRenderer<R, T>, SkinnedRendererError> {
+
+        if texture_paths.is_empty() {
+            return Err(SkinnedRendererError::NoTextures);
+        }
 
-        // TODO: Pass in format as parameter.
-        let format = gfx::format::Srgba8::get_format();
-        let init = pipe::Init {
-            vertex: (),
-            u_model_view_proj: "u_model_view_proj",
-            u_model_view: "u_model_view",
-            u_skinning_transforms: "u_skinning_transforms",
-            u_texture: "u_texture",
-            out_color: ("out_color", format, gfx::state::ColorMask::all(), None),
-            out_depth: gfx::preset::depth::LESS_EQUAL_WRITE,
+        let object_indices: Vec<usize> = match collada_document.get_obj_set() {
+            Some(obj_set) => skinned_objects(&obj_set.objects).into_iter().map(|(i, _)| i).collect(),
+            None => Vec::new(),
         };
-        let pso = tcx.factory.create_pipeline_from_program(
-            &program,
-            gfx::Primitive::TriangleList,
-            gfx::state::Rasterizer::new_fill(),
-            init
-        ).unwrap();
+
+        let mut textures = Vec::new();
+        for (path, batches) in batches_by_texture(&texture_paths, &object_indices) {
+            let texture = gfx_texture::Texture::from_path(
+                tcx,
+                Path::new(&path),
+                gfx_texture::Flip::None,
+                &gfx_texture::TextureSettings::new()
+            ).map_err(|e| SkinnedRendererError::Texture(path.clone(), e.to_string()))?;
+            textures.push((batches, texture.view));
+        }
+
+        // Created with any one texture, then each batch is given its own
+        let initial_textures = textures.first().map(|(_, view)| view.clone()).into_iter().collect();
+        let mut renderer = SkinnedRenderer::from_collada_with_textures(tcx, collada_document, initial_textures)?;
+        for (batches, view) in textures {
+            for batch in batches {
+                renderer.render_batches[batch].texture.0 = view.clone();
+            }
+        }
+
+        Ok(renderer)
+    }
+
+    /// Create a renderer as with `from_collada`, but with the texture of each object already
+    /// loaded, e.g. shared with other renderers. As with `from_collada`, there must be at least
+    /// one texture if the document has any skinned objects.
+    pub fn from_collada_with_textures<F: gfx::Factory<R>, C: gfx::CommandBuffer<R>>(
+        tcx: &mut TextureContext<F, R, C>,
+        collada_document: collada::document::ColladaDocument,
+        textures: Vec<TextureView<R>>,
+    ) -> Result<SkinnedRenderer<R, T>, SkinnedRendererError> {
+
+        let obj_set = collada_document.get_obj_set().unwrap();
+        let objects = skinned_objects(&obj_set.objects);
+        if textures.is_empty() && !objects.is_empty() {
+            return Err(SkinnedRendererError::NoTextures);
+        }
+
+        let skeleton_set = collada_document.get_skeletons().unwrap();
+        let skeleton = Skeleton::from_collada(&skeleton_set[0]);
+        let (bind_scale, inverse_bind_poses) = unscaled_inverse_bind_poses(&skeleton);
+        let palette_size = palette_size(&skeleton);
+
+        let pso = create_pipeline(&mut tcx.factory, &with_constant(T::vertex_shader_source(), "MAX_JOINTS", SOURCE_MAX_JOINTS, palette_size), T::fragment_shader_source())
+            .map_err(SkinnedRendererError::Program)?;
 
         let sampler = tcx.factory.create_sampler(
             gfx::texture::SamplerInfo::new(
@@ -94,53 +258,111 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
             )
         );
 
-        let obj_set = collada_document.get_obj_set().unwrap();
-
-        let skeleton_set = collada_document.get_skeletons().unwrap();
-        let skeleton = Skeleton::from_collada(&skeleton_set[0]);
-
         let mut render_batches = Vec::new();
 
-        for (i, object) in obj_set.objects.iter().enumerate().take(6) {
+        for (i, object) in objects {
 
             let mut vertex_data: Vec<SkinnedVertex> = Vec::new();
             let mut index_data: Vec<u32> = Vec::new();
 
             get_vertex_index_data(&object, &mut vertex_data, &mut index_data);
 
+            if let Some(bind_shape_matrix) = get_bind_shape_matrix(&collada_document, &object.id) {
+                apply_bind_shape_matrix(bind_shape_matrix, &mut vertex_data);
+            }
+
+            for vertex in vertex_data.iter_mut() {
+                vertex.pos = vec3_scale(vertex.pos, bind_scale);
+            }
+
             let (vbuf, slice) = tcx.factory.create_vertex_buffer_with_slice
                 (&vertex_data, &index_data[..]);
 
             let skinning_transforms_buffer = tcx.factory.create_buffer::<T>(
-                MAX_JOINTS,
+                palette_size,
                 gfx::buffer::Role::Constant,
                 gfx::memory::Usage::Dynamic,
                 gfx::memory::Bind::empty()
             ).unwrap();
 
-            let texture = gfx_texture::Texture::from_path(
-                tcx,
-                &Path::new(&texture_paths[i]),
-                gfx_texture::Flip::None,
-                &gfx_texture::TextureSettings::new()
-            ).unwrap();
+            // Textures are matched to objects by their index in the file, including any props
+            let texture = object_texture(&textures, i);
 
             render_batches.push(SkinnedRenderBatch {
                 slice: slice,
                 vertex_buffer: vbuf,
                 skinning_transforms_buffer: skinning_transforms_buffer,
-                texture: (texture.view.clone(), sampler.clone()),
+                texture: (texture.clone(), sampler.clone()),
+                object_index: i,
             });
         }
 
 
         Ok(SkinnedRenderer {
             pso: pso,
+            instanced_pipeline: None,
             render_batches: render_batches,
-            skeleton: skeleton.clone(),
+            palette_size,
+            inverse_bind_poses,
+            last_skinning_palette: Vec::new(),
+            instance_palettes: Vec::new(),
+            deferred_textures: DeferredTextures::new(),
         })
     }
 
+    /// Create a renderer as with `from_collada`, without waiting for its textures: every object
+    /// is drawn with the `fallback` texture until its own texture, loaded on a background
+    /// thread, is swapped in by `poll_textures`. With no `texture_paths`, every object keeps the
+    /// fallback texture.
+    pub fn from_collada_deferred<F: gfx::Factory<R>, C: gfx::CommandBuffer<R>>(
+        tcx: &mut TextureContext<F, R, C>,
+        collada_document: collada::document::ColladaDocument,
+        texture_paths: Vec<&str>,
+        fallback: TextureView<R>,
+    ) -> Result<SkinnedRenderer<R, T>, SkinnedRendererError> {
+
+        let mut renderer = SkinnedRenderer::from_collada_with_textures(tcx, collada_document, vec![fallback])?;
+
+        if texture_paths.is_empty() {
+            return Ok(renderer);
+        }
+
+        let object_indices: Vec<usize> = renderer.render_batches.iter().map(|batch| batch.object_index).collect();
+        for (path, batches) in batches_by_texture(&texture_paths, &object_indices) {
+            renderer.deferred_textures.push(batches, move || load_texture_image(&path));
+        }
+
+        Ok(renderer)
+    }
+
+    /// Upload any textures for `from_collada_deferred` that have finished loading, and use
+    /// them in place of the fallback. Should be called once per frame until `textures_pending`
+    /// is false. Returns a description of each texture that failed to load, whose objects keep
+    /// the fallback texture.
+    pub fn poll_textures<F: gfx::Factory<R>, C: gfx::CommandBuffer<R>>(&mut self, tcx: &mut TextureContext<F, R, C>) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (batches, result) in self.deferred_textures.poll() {
+            let texture = result.and_then(|image| {
+                gfx_texture::Texture::from_image(tcx, &image, &gfx_texture::TextureSettings::new())
+                    .map_err(|e| format!("Failed to upload texture: {:?}", e))
+            });
+            match texture {
+                Ok(texture) => {
+                    for &batch in batches.iter() {
+                        self.render_batches[batch].texture.0 = texture.view.clone();
+                    }
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        errors
+    }
+
+    /// Whether any textures for `from_collada_deferred` are still loading
+    pub fn textures_pending(&self) -> bool {
+        !self.deferred_textures.is_empty()
+    }
+
     pub fn render<C: gfx::CommandBuffer<R>, Rf: gfx::format::RenderFormat> (
         &mut self,
         encoder: &mut gfx::Encoder<R, C>,
@@ -153,11 +375,11 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
         where T: gfx::traits::Pod
     {
 
-        let skinning_transforms = self.calculate_skinning_transforms(&joint_poses);
+        write_skinning_palette(&self.inverse_bind_poses, joint_poses, &mut self.last_skinning_palette);
 
         for material in self.render_batches.iter_mut() {
             // FIXME -- should all be able to share the same buffer
-            encoder.update_buffer(&material.skinning_transforms_buffer, &skinning_transforms[..], 0).unwrap();
+            encoder.update_buffer(&material.skinning_transforms_buffer, &self.last_skinning_palette[..], 0).unwrap();
 
             let data = pipe::Data {
                 vertex: material.vertex_buffer.clone(),
@@ -173,14 +395,211 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
         }
     }
 
+    /// Create the pipeline for `render_instances`, if not already created, drawing as many
+    /// instances per draw call as fit in a uniform block of `max_uniform_block_size` bytes: the
+    /// GL implementation's `GL_MAX_UNIFORM_BLOCK_SIZE`, or `GL_MIN_UNIFORM_BLOCK_SIZE` if it
+    /// isn't known. Returns an error if not even one instance's palette fits, or if the
+    /// instanced shaders fail to link, in which case `render` can still be used.
+    pub fn enable_instancing<F: gfx::Factory<R>>(&mut self, factory: &mut F, max_uniform_block_size: usize) -> Result<(), SkinnedRendererError> {
+        if self.instanced_pipeline.is_none() {
+            let instances_per_draw = instances_per_draw(self.palette_size, mem::size_of::<T>(), max_uniform_block_size);
+            if instances_per_draw == 0 {
+                return Err(SkinnedRendererError::PaletteTooLarge(self.palette_size * mem::size_of::<T>(), max_uniform_block_size));
+            }
+            let vertex_shader_source = with_constant(T::instanced_vertex_shader_source(), "MAX_JOINTS", SOURCE_MAX_JOINTS, self.palette_size);
+            let vertex_shader_source = with_constant(&vertex_shader_source, "MAX_INSTANCES", SOURCE_MAX_INSTANCES, instances_per_draw);
+            let instanced_pso = create_pipeline(factory, &vertex_shader_source, T::fragment_shader_source())
+                .map_err(SkinnedRendererError::Program)?;
+            let instance_transforms_buffer = factory.create_buffer::<T>(
+                self.palette_size * instances_per_draw,
+                gfx::buffer::Role::Constant,
+                gfx::memory::Usage::Dynamic,
+                gfx::memory::Bind::empty()
+            ).unwrap();
+            self.instanced_pipeline = Some((instanced_pso, instance_transforms_buffer, instances_per_draw));
+        }
+        Ok(())
+    }
+
+    /// The number of instances drawn per draw call by `render_instances`, once
+    /// `enable_instancing` has succeeded
+    pub fn instances_per_draw(&self) -> Option<usize> {
+        self.instanced_pipeline.as_ref().map(|&(_, _, instances_per_draw)| instances_per_draw)
+    }
+
+    /// Render several instances of the character, each with its own global joint poses, using
+    /// one instanced draw per render batch for every `instances_per_draw()` instances. Instances
+    /// are placed by their joint poses, so any per-instance model transform should be applied to
+    /// the root joint.
+    ///
+    /// Panics if `enable_instancing` hasn't been called.
+    pub fn render_instances<C: gfx::CommandBuffer<R>, Rf: gfx::format::RenderFormat> (
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        out_color: &gfx::handle::RenderTargetView<R, Rf>,
+        out_depth: &gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        view: [[f32; 4]; 4],
+        projection: [[f32; 4]; 4],
+        instance_joint_poses: &[&[T]]
+    )
+        where T: gfx::traits::Pod
+    {
+        let (ref instanced_pso, ref instance_transforms_buffer, instances_per_draw) = *self.instanced_pipeline.as_ref()
+            .expect("SkinnedRenderer::render_instances requires enable_instancing");
+
+        for draw in instanced_draws(instance_joint_poses.len(), instances_per_draw) {
+
+            let instances = &instance_joint_poses[draw];
+            write_instance_palettes(&self.inverse_bind_poses, instances, self.palette_size, &mut self.instance_palettes);
+            encoder.update_buffer(instance_transforms_buffer, &self.instance_palettes[..], 0).unwrap();
+
+            for material in self.render_batches.iter() {
+
+                let mut slice = material.slice.clone();
+                slice.instances = Some((instances.len() as u32, 0));
+
+                let data = pipe::Data {
+                    vertex: material.vertex_buffer.clone(),
+                    u_model_view_proj: projection,
+                    u_model_view: view,
+                    u_skinning_transforms: instance_transforms_buffer.raw().clone(),
+                    u_texture: material.texture.clone(),
+                    out_color: out_color.raw().clone(),
+                    out_depth: out_depth.clone(),
+                };
+
+                encoder.draw(&slice, instanced_pso, &data);
+            }
+        }
+    }
+
     ///
     /// TODO - don't allocate a new vector
     ///
+    /// The inverse bind poses are without their uniform scale (see `unscaled_inverse_bind_poses`),
+    /// which is baked into the vertex positions instead
     pub fn calculate_skinning_transforms(&self, global_poses: &[T]) -> Vec<T> {
-        self.skeleton.joints.iter().enumerate().map(|(i, joint)| {
-            // TODO avoid conversion...
-            global_poses[i].concat(T::from_matrix(joint.inverse_bind_pose))
-        }).collect()
+        let mut skinning_transforms = Vec::with_capacity(self.inverse_bind_poses.len());
+        write_skinning_palette(&self.inverse_bind_poses, global_poses, &mut skinning_transforms);
+        skinning_transforms
+    }
+
+    /// The skinning transforms uploaded by the most recent call to `render`, one per joint, e.g.
+    /// for drawing each joint's skinning matrix in a debug overlay. Empty before the first call.
+    pub fn last_skinning_palette(&self) -> &[T] {
+        &self.last_skinning_palette
+    }
+}
+
+/// Replace the contents of `palette` with the skinning transforms for the given global poses,
+/// reusing its allocation
+fn write_skinning_palette<T: Transform>(inverse_bind_poses: &[T], global_poses: &[T], palette: &mut Vec<T>) {
+    palette.clear();
+    palette.extend(inverse_bind_poses.iter().enumerate().map(|(i, &inverse_bind_pose)| {
+        global_poses[i].concat(inverse_bind_pose)
+    }));
+}
+
+/// Pack a skinning palette (e.g. from `calculate_skinning_transforms`) into RGBA float texels
+/// for a texture-fetch skinning shader, as an alternative to the constant buffer.
+///
+/// Each joint's transform is written as its 4x4 matrix, one row per texel, so joint `i`
+/// occupies texels `4 * i .. 4 * i + 4`, with the translation in the alpha channel of the
+/// first three.
+pub fn pack_skinning_palette<T: Transform>(skinning_transforms: &[T]) -> Vec<[f32; 4]> {
+    let mut texels = Vec::with_capacity(skinning_transforms.len() * 4);
+    for transform in skinning_transforms.iter() {
+        texels.extend_from_slice(&transform.to_matrix());
+    }
+    texels
+}
+
+/// Concatenate the skinning palettes of several instances for the instanced shaders, padding
+/// each to `palette_size` transforms so that instance `i`'s palette starts at `i * palette_size`.
+///
+/// Panics if any palette has more than `palette_size` transforms.
+pub fn pack_instance_palettes<T: Transform>(palettes: &[Vec<T>], palette_size: usize) -> Vec<T> {
+    let mut packed = Vec::with_capacity(palettes.len() * palette_size);
+    for palette in palettes.iter() {
+        assert!(palette.len() <= palette_size, "Skinning palette has more than {} joints", palette_size);
+        packed.extend_from_slice(palette);
+        packed.resize(packed.len() + palette_size - palette.len(), T::identity());
+    }
+    packed
+}
+
+/// Replace the contents of `packed` with the skinning palettes of the given instances, laid out
+/// as by `pack_instance_palettes`, reusing its allocation
+fn write_instance_palettes<T: Transform>(inverse_bind_poses: &[T], instance_global_poses: &[&[T]], palette_size: usize, packed: &mut Vec<T>) {
+    packed.clear();
+    for (instance, global_poses) in instance_global_poses.iter().enumerate() {
+        packed.extend(inverse_bind_poses.iter().enumerate().map(|(i, &inverse_bind_pose)| {
+            global_poses[i].concat(inverse_bind_pose)
+        }));
+        packed.resize((instance + 1) * palette_size, T::identity());
+    }
+}
+
+/// Number of instances whose palettes of `palette_size` transforms, each `transform_size`
+/// bytes, fit in a uniform block of `max_uniform_block_size` bytes
+fn instances_per_draw(palette_size: usize, transform_size: usize, max_uniform_block_size: usize) -> usize {
+    max_uniform_block_size / (palette_size * transform_size)
+}
+
+/// The range of instances drawn by each instanced draw call (per render batch), for
+/// `instance_count` instances drawn `instances_per_draw` at a time
+fn instanced_draws(instance_count: usize, instances_per_draw: usize) -> Vec<Range<usize>> {
+    (0 .. instance_count).step_by(instances_per_draw).map(|first| {
+        first .. (first + instances_per_draw).min(instance_count)
+    }).collect()
+}
+
+/// Number of transforms in the skinning palette for the skeleton: one per joint, and at least
+/// one, as GLSL arrays can't be empty
+fn palette_size(skeleton: &Skeleton) -> usize {
+    skeleton.joints.len().max(1)
+}
+
+/// The given shader source, with the integer constant `name` set to `value` in place of the
+/// `declared` value in the source, e.g. to size the palette for the skeleton's joint count
+fn with_constant(shader_source: &[u8], name: &str, declared: usize, value: usize) -> Vec<u8> {
+    let declaration = format!("const int {} = {};", name, declared);
+    String::from_utf8_lossy(shader_source)
+        .replace(&declaration[..], &format!("const int {} = {};", name, value))
+        .into_bytes()
+}
+
+fn create_pipeline<R: gfx::Resources, F: gfx::Factory<R>>(
+    factory: &mut F,
+    vertex_shader_source: &[u8],
+    fragment_shader_source: &[u8],
+) -> Result<gfx::PipelineState<R, pipe::Meta>, gfx::shade::ProgramError> {
+
+    let program = factory.link_program(vertex_shader_source, fragment_shader_source)?;
+
+    let pso = factory.create_pipeline_from_program(
+        &program,
+        gfx::Primitive::TriangleList,
+        gfx::state::Rasterizer::new_fill(),
+        pipe_init()
+    ).unwrap();
+
+    Ok(pso)
+}
+
+fn pipe_init() -> pipe::Init<'static> {
+    use gfx::format::Formatted;
+
+    // TODO: Pass in format as parameter.
+    let format = gfx::format::Srgba8::get_format();
+    pipe::Init {
+        vertex: (),
+        u_model_view_proj: "u_model_view_proj",
+        u_model_view: "u_model_view",
+        u_skinning_transforms: "u_skinning_transforms",
+        u_texture: "u_texture",
+        out_color: ("out_color", format, gfx::state::ColorMask::all(), None),
+        out_depth: gfx::preset::depth::LESS_EQUAL_WRITE,
     }
 }
 
@@ -227,6 +646,35 @@ impl Default for SkinnedVertex {
     }
 }
 
+/// The objects to render with skinning, along with their index in the file: those with joint
+/// weights for every vertex. Static props without weights are skipped.
+fn skinned_objects(objects: &[collada::Object]) -> Vec<(usize, &collada::Object)> {
+    objects.iter().enumerate().filter(|&(_, object)| {
+        !object.vertices.is_empty() && object.joint_weights.len() == object.vertices.len()
+    }).collect()
+}
+
+/// The texture (or texture path) for the object with the given index in the file, reusing the
+/// last texture for any objects beyond the end of the list
+fn object_texture<V>(textures: &[V], object_index: usize) -> &V {
+    textures.get(object_index).or_else(|| textures.last())
+        .expect("SkinnedRenderer requires at least one texture")
+}
+
+/// Group render batches, given the object index of each, by their texture path, so that each
+/// texture is loaded once
+fn batches_by_texture(texture_paths: &[&str], object_indices: &[usize]) -> Vec<(String, Vec<usize>)> {
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (batch, &object_index) in object_indices.iter().enumerate() {
+        let path = *object_texture(texture_paths, object_index);
+        match groups.iter_mut().find(|group| group.0 == path) {
+            Some(group) => group.1.push(batch),
+            None => groups.push((path.to_string(), vec![batch])),
+        }
+    }
+    groups
+}
+
 fn vtn_to_vertex(a: collada::VTNIndex, obj: &collada::Object) -> SkinnedVertex
 {
     let mut vertex: SkinnedVertex = Default::default();
@@ -260,7 +708,8 @@ fn vtn_to_vertex(a: collada::VTNIndex, obj: &collada::Object) -> SkinnedVertex
 
 fn get_vertex_index_data(obj: &collada::Object, vertex_data: &mut Vec<SkinnedVertex>, index_data: &mut Vec<u32>) {
     for geom in obj.geometry.iter() {
-        let mut i = vertex_data.len() as u32;
+        let first_vertex = vertex_data.len();
+        let mut i = first_vertex as u32;
         let mut uvs: u32 = 0;
         let mut normals: u32 = 0;
         {
@@ -307,5 +756,475 @@ fn get_vertex_index_data(obj: &collada::Object, vertex_data: &mut Vec<SkinnedVer
                 }
             }
         }
+
+        if normals == 0 {
+            calculate_flat_normals(&mut vertex_data[first_vertex ..]);
+        }
+    }
+}
+
+/// Calculate per-face normals for a list of unindexed triangle vertices, for meshes
+/// exported without normals
+fn calculate_flat_normals(vertex_data: &mut [SkinnedVertex]) {
+    for triangle in vertex_data.chunks_mut(3) {
+        if triangle.len() < 3 {
+            continue;
+        }
+
+        let edge_1 = vec3_sub(triangle[1].pos, triangle[0].pos);
+        let edge_2 = vec3_sub(triangle[2].pos, triangle[0].pos);
+        let normal = vec3_cross(edge_1, edge_2);
+
+        // Skip degenerate triangles rather than producing NaNs
+        if vec3_len(normal) == 0.0 {
+            continue;
+        }
+
+        let normal = vec3_normalized(normal);
+        for vertex in triangle.iter_mut() {
+            vertex.normal = normal;
+        }
+    }
+}
+
+/// Read the bind-shape matrix of the skin controller bound to the object with the given id,
+/// if present. This isn't exposed by the collada crate, so is read from the XML directly.
+fn get_bind_shape_matrix(collada_document: &collada::document::ColladaDocument, object_id: &str) -> Option<Matrix4<f32>> {
+
+    let root = &collada_document.root_element;
+    let ns = root.ns.as_ref().map(|ns| &ns[..]);
+
+    let library_controllers = root.get_child("library_controllers", ns)?;
+
+    let skin = library_controllers.get_children("controller", ns)
+        .filter_map(|controller| controller.get_child("skin", ns))
+        .find(|skin| skin.get_attribute("source", None).map(|s| s.trim_start_matches('#')) == Some(object_id))?;
+
+    let values: Vec<f32> = skin.get_child("bind_shape_matrix", ns)?
+        .content_str()
+        .split_whitespace()
+        .filter_map(|v| v.parse().ok())
+        .collect();
+
+    if values.len() != 16 {
+        return None;
+    }
+
+    let mut m = mat4_id();
+    for (i, value) in values.into_iter().enumerate() {
+        m[i / 4][i % 4] = value;
+    }
+    Some(m)
+}
+
+/// Split the uniform scale out of the skeleton's inverse bind poses, as not every transform type
+/// can represent scale (dual quaternions drop it). Returns the scale, taken from the first joint
+/// as the rig is assumed to be uniformly scaled throughout, and the inverse bind poses without
+/// it, which expect vertex positions already multiplied by the scale.
+fn unscaled_inverse_bind_poses<T: Transform>(skeleton: &Skeleton) -> (f32, Vec<T>) {
+
+    let scale = match skeleton.joints.first() {
+        Some(joint) => {
+            let m = joint.inverse_bind_pose;
+            vec3_len([m[0][0], m[1][0], m[2][0]])
+        }
+        None => 1.0,
+    };
+    let scale = if scale > 0.0 { scale } else { 1.0 };
+
+    let inverse_bind_poses = skeleton.joints.iter().map(|joint| {
+        let mut m = joint.inverse_bind_pose;
+        for row in m.iter_mut().take(3) {
+            for value in row.iter_mut().take(3) {
+                *value /= scale;
+            }
+        }
+        T::from_matrix(m)
+    }).collect();
+
+    (scale, inverse_bind_poses)
+}
+
+/// Bake the bind-shape matrix into the vertex positions and normals, so they are in the
+/// space the inverse bind poses expect
+fn apply_bind_shape_matrix(bind_shape_matrix: Matrix4<f32>, vertex_data: &mut [SkinnedVertex]) {
+
+    let normal_matrix = mat3_transposed(mat3_inv([
+        [bind_shape_matrix[0][0], bind_shape_matrix[0][1], bind_shape_matrix[0][2]],
+        [bind_shape_matrix[1][0], bind_shape_matrix[1][1], bind_shape_matrix[1][2]],
+        [bind_shape_matrix[2][0], bind_shape_matrix[2][1], bind_shape_matrix[2][2]],
+    ]));
+
+    for vertex in vertex_data.iter_mut() {
+        vertex.pos = bind_shape_matrix.transform_vector(vertex.pos);
+        if vertex.normal != [0.0; 3] {
+            vertex.normal = vec3_normalized(row_mat3_transform(normal_matrix, vertex.normal));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use collada::document::ColladaDocument;
+
+    use math::*;
+    use skeleton::{Joint, Skeleton, ROOT_JOINT_PARENT_INDEX};
+    use transform::{Transform, QVTransform};
+
+    use super::SkinnedVertex;
+
+    static EPSILON: f32 = 0.000001;
+
+    #[test]
+    fn test_get_bind_shape_matrix() {
+
+        let document = ColladaDocument::from_str(r##"<?xml version="1.0" encoding="utf-8"?>
+            <COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">
+              <library_controllers>
+                <controller id="armature-skin">
+                  <skin source="#body-mesh">
+                    <bind_shape_matrix>2 0 0 1 0 2 0 0 0 0 2 0 0 0 0 1</bind_shape_matrix>
+                  </skin>
+                </controller>
+              </library_controllers>
+            </COLLADA>"##).unwrap();
+
+        assert!(super::get_bind_shape_matrix(&document, "prop-mesh").is_none());
+
+        let m = super::get_bind_shape_matrix(&document, "body-mesh").unwrap();
+        let p = m.transform_vector([1.0, 1.0, 1.0]);
+        assert!(vec3_len(vec3_sub(p, [3.0, 2.0, 2.0])) < EPSILON);
+    }
+
+    #[test]
+    fn test_apply_bind_shape_matrix() {
+
+        // Scale by 2, rotate 90 degrees about z, then translate along x
+        let mut m = mat4_rotate_z(::std::f32::consts::PI / 2.0);
+        for row in m.iter_mut().take(3) {
+            for value in row.iter_mut().take(3) {
+                *value *= 2.0;
+            }
+        }
+        m[0][3] = 1.0;
+
+        let mut vertices = [SkinnedVertex {
+            pos: [1.0, 0.0, 0.0],
+            normal: [1.0, 0.0, 0.0],
+            ..Default::default()
+        }];
+
+        super::apply_bind_shape_matrix(m, &mut vertices);
+
+        let expected_pos = m.transform_vector([1.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(vertices[0].pos, expected_pos)) < EPSILON);
+        assert!((vec3_len(vertices[0].normal) - 1.0).abs() < EPSILON);
+
+        // Normal should be rotated with the mesh, without picking up the scale
+        let expected_normal = vec3_normalized(vec3_sub(expected_pos, m.transform_vector([0.0, 0.0, 0.0])));
+        assert!(vec3_len(vec3_sub(vertices[0].normal, expected_normal)) < EPSILON);
+    }
+
+    #[test]
+    fn test_unscaled_inverse_bind_poses() {
+
+        fn pose(angle: f32, translation: Vector3<f32>) -> Matrix4<f32> {
+            let mut m = mat4_rotate_z(angle);
+            m.set_translation(translation);
+            m
+        }
+
+        // Skinned vertex for a rig with the mesh at twice the skeleton's size, with the
+        // difference baked into its inverse bind poses
+        fn skinned_vertex<T: Transform>(local_poses: &[Matrix4<f32>], vertex: Vector3<f32>) -> Vector3<f32> {
+
+            let bind_pose_1 = pose(0.3, [0.0, 1.0, 0.0]);
+            let bind_pose_2 = row_mat4_mul(bind_pose_1, pose(0.2, [0.0, 2.0, 0.0]));
+            let mesh_scale = [[0.5, 0.0, 0.0, 0.0], [0.0, 0.5, 0.0, 0.0], [0.0, 0.0, 0.5, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+            let skeleton = Skeleton::from_joints(vec![
+                Joint::new("root", ROOT_JOINT_PARENT_INDEX, row_mat4_mul(mat4_inv(bind_pose_1), mesh_scale)),
+                Joint::new("end", 0, row_mat4_mul(mat4_inv(bind_pose_2), mesh_scale)),
+            ]);
+
+            let (scale, inverse_bind_poses) = super::unscaled_inverse_bind_poses::<T>(&skeleton);
+            assert!((scale - 0.5).abs() < EPSILON);
+
+            let global_pose = T::from_matrix(local_poses[0]).concat(T::from_matrix(local_poses[1]));
+            global_pose.concat(inverse_bind_poses[1]).transform_vector(vec3_scale(vertex, scale))
+        }
+
+        let local_poses = [pose(0.6, [1.0, 0.0, 0.0]), pose(-0.4, [0.0, 2.0, 0.0])];
+        let vertex = [1.0, 6.0, 0.5];
+
+        // At its bind pose, the rig skins the vertex to half its size
+        let bind_local_poses = [pose(0.3, [0.0, 1.0, 0.0]), pose(0.2, [0.0, 2.0, 0.0])];
+        let bind_vertex = skinned_vertex::<Matrix4<f32>>(&bind_local_poses, vertex);
+        assert!(vec3_len(vec3_sub(bind_vertex, vec3_scale(vertex, 0.5))) < 0.0001);
+
+        let expected = skinned_vertex::<Matrix4<f32>>(&local_poses, vertex);
+        let qv = skinned_vertex::<QVTransform>(&local_poses, vertex);
+        let dq = skinned_vertex::<DualQuaternion<f32>>(&local_poses, vertex);
+        assert!(vec3_len(vec3_sub(qv, expected)) < 0.0001, "{:?} {:?}", qv, expected);
+        assert!(vec3_len(vec3_sub(dq, expected)) < 0.0001, "{:?} {:?}", dq, expected);
+    }
+
+    #[test]
+    fn test_calculate_flat_normals() {
+
+        let mut vertices = [
+            SkinnedVertex { pos: [0.0, 0.0, 1.0], ..Default::default() },
+            SkinnedVertex { pos: [1.0, 0.0, 1.0], ..Default::default() },
+            SkinnedVertex { pos: [0.0, 1.0, 1.0], ..Default::default() },
+        ];
+
+        super::calculate_flat_normals(&mut vertices);
+
+        for vertex in vertices.iter() {
+            assert!(vec3_len(vec3_sub(vertex.normal, [0.0, 0.0, 1.0])) < EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_write_skinning_palette() {
+
+        let inverse_bind_poses = [mat4_id(), mat4_rotate_z(0.5), mat4_rotate_z(-1.0)];
+        let global_poses = |x: f32| {
+            let mut poses = [mat4_rotate_z(1.0); 3];
+            for (i, pose) in poses.iter_mut().enumerate() {
+                pose.set_translation([x, i as f32, 0.0]);
+            }
+            poses
+        };
+
+        // A cached palette, rewritten for a later frame, matches a freshly computed one
+        let mut cached = vec![mat4_id(); 5];
+        super::write_skinning_palette(&inverse_bind_poses, &global_poses(1.0), &mut cached);
+        super::write_skinning_palette(&inverse_bind_poses, &global_poses(2.0), &mut cached);
+
+        let fresh: Vec<Matrix4<f32>> = global_poses(2.0).iter().zip(inverse_bind_poses.iter()).map(|(&global_pose, &inverse_bind_pose)| {
+            global_pose.concat(inverse_bind_pose)
+        }).collect();
+        assert_eq!(cached, fresh);
+    }
+
+    #[test]
+    fn test_pack_skinning_palette() {
+
+        let mut m = mat4_rotate_z(::std::f32::consts::PI / 2.0);
+        m.set_translation([1.0, 2.0, 3.0]);
+
+        let texels = super::pack_skinning_palette(&[mat4_id(), m, mat4_id()]);
+        assert_eq!(texels.len(), 12);
+
+        assert_eq!(texels[0], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(texels[3], [0.0, 0.0, 0.0, 1.0]);
+        for row in 0 .. 4 {
+            assert_eq!(texels[4 + row], m[row]);
+        }
+        assert_eq!([texels[4][3], texels[5][3], texels[6][3]], [1.0, 2.0, 3.0]);
+        assert_eq!(texels[8], [1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_pack_instance_palettes() {
+
+        let mut m = mat4_id();
+        m.set_translation([1.0, 2.0, 3.0]);
+
+        let packed = super::pack_instance_palettes(&[vec![m, mat4_id()], vec![mat4_id(), m]], 64);
+        assert_eq!(packed.len(), 2 * 64);
+        assert_eq!(packed[0], m);
+        assert_eq!(packed[63], mat4_id());
+        assert_eq!(packed[64 + 1], m);
+
+        // Palettes are sized for the skeleton, so aren't capped at the shaders' declared size
+        let large = vec![m; 300];
+        let packed = super::pack_instance_palettes(&[large.clone(), large], 300);
+        assert_eq!(packed.len(), 600);
+        assert_eq!(packed[599], m);
+    }
+
+    #[test]
+    fn test_instances_per_draw() {
+        use std::mem::size_of;
+
+        // 64 joints of Matrix4 transforms take 4KB per instance
+        let matrix_size = size_of::<Matrix4<f32>>();
+        assert_eq!(super::instances_per_draw(64, matrix_size, super::GL_MIN_UNIFORM_BLOCK_SIZE), 4);
+        assert_eq!(super::instances_per_draw(64, matrix_size, 65536), 16);
+
+        // Dense rigs still draw several instances per call where the limit allows, or none where
+        // even one palette doesn't fit
+        assert_eq!(super::instances_per_draw(300, matrix_size, 65536), 3);
+        assert_eq!(super::instances_per_draw(300, size_of::<DualQuaternion<f32>>(), 65536), 6);
+        assert_eq!(super::instances_per_draw(300, matrix_size, super::GL_MIN_UNIFORM_BLOCK_SIZE), 0);
+    }
+
+    #[test]
+    fn test_instanced_draws() {
+
+        // One draw call per batch for every `instances_per_draw` instances, the last partial
+        let draws = super::instanced_draws(10, 4);
+        assert_eq!(draws, vec![0 .. 4, 4 .. 8, 8 .. 10]);
+        assert_eq!(draws.len(), (10 + 4 - 1) / 4);
+
+        assert_eq!(super::instanced_draws(8, 4).len(), 2);
+        assert_eq!(super::instanced_draws(3, 16), vec![0 .. 3]);
+        assert!(super::instanced_draws(0, 4).is_empty());
+    }
+
+    #[test]
+    fn test_write_instance_palettes() {
+
+        let inverse_bind_poses = [mat4_id(), mat4_rotate_z(0.5)];
+        let instance_poses = |x: f32| {
+            let mut poses = [mat4_rotate_z(1.0); 2];
+            for pose in poses.iter_mut() {
+                pose.set_translation([x, 0.0, 0.0]);
+            }
+            poses
+        };
+        let (poses_1, poses_2) = (instance_poses(1.0), instance_poses(2.0));
+        let instances: [&[Matrix4<f32>]; 2] = [&poses_1, &poses_2];
+
+        // Matches packing freshly computed palettes, when rewritten into a reused buffer
+        let mut packed = vec![mat4_id(); 20];
+        super::write_instance_palettes(&inverse_bind_poses, &instances[..1], 4, &mut packed);
+        super::write_instance_palettes(&inverse_bind_poses, &instances, 4, &mut packed);
+
+        let palettes: Vec<Vec<Matrix4<f32>>> = instances.iter().map(|global_poses| {
+            global_poses.iter().zip(inverse_bind_poses.iter()).map(|(&global_pose, &inverse_bind_pose)| {
+                global_pose.concat(inverse_bind_pose)
+            }).collect()
+        }).collect();
+        assert_eq!(packed, super::pack_instance_palettes(&palettes, 4));
+    }
+
+    #[test]
+    fn test_palette_sized_for_skeleton() {
+        use std::str;
+        use skinned_renderer::HasShaderSources;
+
+        let skeleton = Skeleton::from_joints((0 .. 300).map(|i| {
+            let parent_index = if i == 0 { ROOT_JOINT_PARENT_INDEX } else { i - 1 };
+            Joint::new(&format!("joint_{}", i), parent_index, mat4_id())
+        }).collect());
+        assert_eq!(super::palette_size(&skeleton), 300);
+        assert_eq!(super::palette_size(&Skeleton::from_joints(Vec::new())), 1);
+
+        for &source in [
+            Matrix4::<f32>::vertex_shader_source(),
+            Matrix4::<f32>::instanced_vertex_shader_source(),
+            DualQuaternion::<f32>::vertex_shader_source(),
+            DualQuaternion::<f32>::instanced_vertex_shader_source(),
+        ].iter() {
+            let resized = super::with_constant(source, "MAX_JOINTS", super::SOURCE_MAX_JOINTS, 300);
+            let resized = str::from_utf8(&resized).unwrap();
+            assert!(resized.contains("const int MAX_JOINTS = 300;"));
+            assert!(!resized.contains("const int MAX_JOINTS = 64;"));
+        }
+    }
+
+    #[test]
+    fn test_instanced_shader_layout() {
+        use std::str;
+        use skinned_renderer::HasShaderSources;
+
+        // The instanced shaders must agree with the renderer on the palette layout
+        let max_instances = format!("const int MAX_INSTANCES = {};", super::SOURCE_MAX_INSTANCES);
+        for &source in [Matrix4::<f32>::instanced_vertex_shader_source(), DualQuaternion::<f32>::instanced_vertex_shader_source()].iter() {
+            let resized = super::with_constant(source, "MAX_INSTANCES", super::SOURCE_MAX_INSTANCES, 3);
+            let source = str::from_utf8(source).unwrap();
+            assert!(source.contains(&max_instances[..]));
+            assert!(source.contains("skinning_transforms[MAX_JOINTS * MAX_INSTANCES]"));
+            assert!(source.contains("gl_InstanceID"));
+            assert!(str::from_utf8(&resized).unwrap().contains("const int MAX_INSTANCES = 3;"));
+        }
+
+        let init = super::pipe_init();
+        assert_eq!(init.u_skinning_transforms, "u_skinning_transforms");
+    }
+
+    #[test]
+    fn test_skinned_objects() {
+        use collada;
+
+        let object = |name: &str, weighted: bool| collada::Object {
+            id: name.to_string(),
+            name: name.to_string(),
+            vertices: vec![collada::Vertex { x: 0.0, y: 0.0, z: 0.0 }; 3],
+            joint_weights: if weighted {
+                vec![collada::JointWeights { joints: [0; 4], weights: [1.0, 0.0, 0.0, 0.0] }; 3]
+            } else {
+                Vec::new()
+            },
+            tex_vertices: Vec::new(),
+            normals: Vec::new(),
+            geometry: Vec::new(),
+        };
+
+        let objects = [object("ground", false), object("body", true), object("sword", false), object("head", true)];
+        let skinned: Vec<(usize, &str)> = super::skinned_objects(&objects).into_iter().map(|(i, o)| (i, &o.name[..])).collect();
+        assert_eq!(skinned, vec![(1, "body"), (3, "head")]);
+
+        // All objects are rendered, however many there are
+        let objects: Vec<collada::Object> = (0 .. 9).map(|i| object(&format!("material_{}", i), true)).collect();
+        let skinned: Vec<usize> = super::skinned_objects(&objects).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(skinned, (0 .. 9).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_object_texture() {
+        let texture_paths = ["body.png", "head.png"];
+        assert_eq!(*super::object_texture(&texture_paths, 0), "body.png");
+        assert_eq!(*super::object_texture(&texture_paths, 1), "head.png");
+        assert_eq!(*super::object_texture(&texture_paths, 8), "head.png");
+
+        // Batches for objects 1, 3 and 4 share the last texture
+        let groups = super::batches_by_texture(&["ground.png", "body.png", "sword.png"], &[1, 3, 4]);
+        assert_eq!(groups, vec![("body.png".to_string(), vec![0]), ("sword.png".to_string(), vec![1, 2])]);
+    }
+
+    #[test]
+    fn test_deferred_textures() {
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+
+        let (start_sender, start_receiver) = mpsc::channel::<()>();
+
+        let mut textures = super::DeferredTextures::new();
+        textures.push(vec![0, 2], move || {
+            start_receiver.recv().unwrap();
+            Ok("body")
+        });
+        textures.push(vec![1], || Err("missing".to_string()));
+
+        // Until its load finishes, a texture isn't ready, so its batches keep the fallback
+        let mut finished = Vec::new();
+        for _ in 0 .. 100 {
+            finished.extend(textures.poll());
+            if !finished.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(finished, vec![(vec![1], Err("missing".to_string()))]);
+        assert!(!textures.is_empty());
+        assert!(textures.poll().is_empty());
+
+        start_sender.send(()).unwrap();
+        let mut finished = Vec::new();
+        for _ in 0 .. 100 {
+            finished.extend(textures.poll());
+            if textures.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(finished, vec![(vec![0, 2], Ok("body"))]);
+        assert!(textures.is_empty());
     }
 }
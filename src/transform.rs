@@ -1,6 +1,29 @@
 use interpolation;
+use rustc_serialize::{Decodable, Decoder};
+
 use math::*;
 
+/// The space in which the translation of a local joint transform is interpolated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TranslationSpace {
+    /// Interpolate translation components directly, in the parent joint's space (as `lerp` does)
+    Parent,
+    /// Interpolate translation in each transform's own rotated frame, then rotate the result
+    /// by the interpolated rotation. Translations that turn with the joint's rotation then keep
+    /// their length, rather than cutting the corner between the two endpoints.
+    Joint,
+}
+
+impl Decodable for TranslationSpace {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<TranslationSpace, D::Error> {
+        match &decoder.read_str()?[..] {
+            "parent" => Ok(TranslationSpace::Parent),
+            "joint" => Ok(TranslationSpace::Joint),
+            space => Err(decoder.error(&format!("Unknown translation space: {}", space))),
+        }
+    }
+}
+
 pub trait Transform: Copy {
     fn identity() -> Self;
     fn concat(self, other: Self) -> Self;
@@ -13,6 +36,33 @@ pub trait Transform: Copy {
     fn get_rotation(self) -> Quaternion<f32>;
     fn set_translation(&mut self, translation: Vector3<f32>);
     fn get_translation(self) -> Vector3<f32>;
+
+    /// Number of floats in the fixed layout used by `write_floats` and `read_floats`
+    const FLOAT_COUNT: usize;
+
+    /// Identifier of the layout used by `write_floats` and `read_floats`, distinct for each
+    /// implementation, so that layouts with the same `FLOAT_COUNT` aren't confused
+    const LAYOUT_ID: u8;
+
+    /// Append the transform to `out` as exactly `FLOAT_COUNT` floats
+    fn write_floats(self, out: &mut Vec<f32>);
+
+    /// Read a transform from the first `FLOAT_COUNT` floats written by `write_floats`
+    fn read_floats(floats: &[f32]) -> Self;
+
+    /// Interpolate as with `lerp`, but with translation interpolated in the given space
+    fn lerp_in_space(self, other: Self, parameter: f32, space: TranslationSpace) -> Self {
+        let mut blended = self.lerp(other, parameter);
+        if space == TranslationSpace::Joint {
+            let joint_translation = |t: Self| {
+                quaternion::rotate_vector(quaternion_conj(t.get_rotation()), t.get_translation())
+            };
+            let translation = interpolation::lerp(&joint_translation(self), &joint_translation(other), &parameter);
+            let rotation = blended.get_rotation();
+            blended.set_translation(quaternion::rotate_vector(rotation, translation));
+        }
+        blended
+    }
 }
 
 /// Transformation represented by separate scaling, translation, and rotation factors.
@@ -72,14 +122,21 @@ impl Transform for QVTransform {
         }
     }
 
+    /// Scales, then rotates, then translates the vector, as with `to_matrix`
     fn transform_vector(self, v: Vector3<f32>) -> Vector3<f32> {
+        let v = vec3_scale(v, self.scale);
         let v = quaternion::rotate_vector(self.rotation, v);
-        let v = vec3_add(v, self.translation);
-        vec3_scale(v, self.scale)
+        vec3_add(v, self.translation)
     }
 
     fn to_matrix(self) -> Matrix4<f32> {
-        let mut m = quaternion_to_matrix(self.rotation);
+        let mut m = mat4_transposed(quaternion_to_matrix(self.rotation));
+
+        for row in m.iter_mut().take(3) {
+            for value in row.iter_mut().take(3) {
+                *value *= self.scale;
+            }
+        }
 
         m[0][3] = self.translation[0];
         m[1][3] = self.translation[1];
@@ -88,9 +145,21 @@ impl Transform for QVTransform {
         m
     }
 
+    /// Assumes the matrix has uniform scale, taken from the length of its first column
     fn from_matrix(m: Matrix4<f32>) -> QVTransform {
 
-        let rotation = matrix_to_quaternion(&m);
+        let scale = vec3_len([m[0][0], m[1][0], m[2][0]]);
+
+        let mut unscaled = m;
+        if scale > 0.0 {
+            for row in unscaled.iter_mut().take(3) {
+                for value in row.iter_mut().take(3) {
+                    *value /= scale;
+                }
+            }
+        }
+
+        let rotation = matrix_to_quaternion(&mat4_transposed(unscaled));
 
         let translation = [m[0][3],
                            m[1][3],
@@ -98,11 +167,39 @@ impl Transform for QVTransform {
 
         QVTransform {
             rotation: rotation,
-            scale: 1.0,
+            scale,
             translation: translation,
         }
     }
 
+    /// Rotation (w, x, y, z), then translation, then scale
+    const FLOAT_COUNT: usize = 8;
+
+    const LAYOUT_ID: u8 = 1;
+
+    fn write_floats(self, out: &mut Vec<f32>) {
+        write_quaternion(self.rotation, out);
+        out.extend_from_slice(&self.translation);
+        out.push(self.scale);
+    }
+
+    fn read_floats(floats: &[f32]) -> QVTransform {
+        QVTransform {
+            rotation: read_quaternion(&floats[0 .. 4]),
+            translation: [floats[4], floats[5], floats[6]],
+            scale: floats[7],
+        }
+    }
+
+}
+
+fn write_quaternion(q: Quaternion<f32>, out: &mut Vec<f32>) {
+    out.push(q.0);
+    out.extend_from_slice(&q.1);
+}
+
+fn read_quaternion(floats: &[f32]) -> Quaternion<f32> {
+    (floats[0], [floats[1], floats[2], floats[3]])
 }
 
 impl Transform for DualQuaternion<f32> {
@@ -172,6 +269,20 @@ impl Transform for DualQuaternion<f32> {
         dual_quaternion::from_rotation_and_translation(rotation, translation)
 
     }
+
+    /// Real part (w, x, y, z), then dual part (w, x, y, z)
+    const FLOAT_COUNT: usize = 8;
+
+    const LAYOUT_ID: u8 = 2;
+
+    fn write_floats(self, out: &mut Vec<f32>) {
+        write_quaternion(self.0, out);
+        write_quaternion(self.1, out);
+    }
+
+    fn read_floats(floats: &[f32]) -> DualQuaternion<f32> {
+        (read_quaternion(&floats[0 .. 4]), read_quaternion(&floats[4 .. 8]))
+    }
 }
 
 impl Transform for Matrix4<f32> {
@@ -235,6 +346,25 @@ impl Transform for Matrix4<f32> {
     fn to_matrix(self) -> Matrix4<f32> { self }
 
     fn from_matrix(m: Matrix4<f32>) -> Matrix4<f32> { m }
+
+    /// Row-major
+    const FLOAT_COUNT: usize = 16;
+
+    const LAYOUT_ID: u8 = 3;
+
+    fn write_floats(self, out: &mut Vec<f32>) {
+        for row in self.iter() {
+            out.extend_from_slice(row);
+        }
+    }
+
+    fn read_floats(floats: &[f32]) -> Matrix4<f32> {
+        let mut m = mat4_id();
+        for (i, row) in m.iter_mut().enumerate() {
+            row.copy_from_slice(&floats[i * 4 .. i * 4 + 4]);
+        }
+        m
+    }
 }
 
 pub trait FromTransform<T: Transform> {
@@ -258,7 +388,7 @@ mod test {
     use quaternion;
     use dual_quaternion;
 
-    use super::Transform;
+    use super::{Transform, QVTransform, TranslationSpace};
 
     static EPSILON: f32 = 0.000001;
 
@@ -299,4 +429,47 @@ mod test {
         assert!(vecmath::vec3_len(vecmath::vec3_sub([1.0, 1.0, 0.0],
                                                     dq.transform_vector(b))) < EPSILON);
     }
+
+    #[test]
+    fn test_qv_transform_to_matrix() {
+
+        let qv = QVTransform {
+            translation: [1.0, 2.0, 3.0],
+            scale: 2.0,
+            rotation: quaternion::axis_angle([0.0, 0.0, 1.0], ::std::f32::consts::PI / 2.0),
+        };
+
+        let m = qv.to_matrix();
+
+        // Scaled by 2, rotated a quarter turn about z, then translated
+        for &v in [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.5, -1.0, 2.0]].iter() {
+            assert!(vecmath::vec3_len(vecmath::vec3_sub(m.transform_vector(v), qv.transform_vector(v))) < EPSILON);
+        }
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(qv.transform_vector([1.0, 0.0, 0.0]), [1.0, 4.0, 3.0])) < EPSILON);
+
+        // Round trip keeps the scale
+        let round_trip = QVTransform::from_matrix(m);
+        assert!((round_trip.scale - 2.0).abs() < EPSILON);
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(round_trip.transform_vector([0.5, -1.0, 2.0]), qv.transform_vector([0.5, -1.0, 2.0]))) < 0.01);
+    }
+
+    #[test]
+    fn test_lerp_in_space() {
+
+        // The same joint-space offset, with and without a quarter turn about z
+        let mut a = QVTransform::identity();
+        a.set_translation([1.0, 0.0, 0.0]);
+        let mut b = QVTransform::identity();
+        b.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], ::std::f32::consts::PI / 2.0));
+        b.set_translation([0.0, 1.0, 0.0]);
+
+        // Parent space cuts the corner between the two translations
+        let parent = a.lerp_in_space(b, 0.5, TranslationSpace::Parent).get_translation();
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(parent, [0.5, 0.5, 0.0])) < 0.0001);
+
+        // Joint space swings the translation around with the rotation
+        let half = ::std::f32::consts::FRAC_1_SQRT_2;
+        let joint = a.lerp_in_space(b, 0.5, TranslationSpace::Joint).get_translation();
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(joint, [half, half, 0.0])) < 0.0001);
+    }
 }